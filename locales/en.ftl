@@ -0,0 +1,445 @@
+### Сообщения rcp. Новые сообщения добавляются сюда и в переводы; отсутствующие в переводе
+### берутся отсюда
+
+error = Error:
+warning = Warning:
+subcommand-shadows-path = `{ $name }` is run as `rcp { $name }`, though a file with that name exists here; to copy it, use `rcp -- { $name } ...` or `rcp ./{ $name } ...`
+
+## Копирование
+
+copying-files = Copying { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    }...
+copying-files-to = Copying { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } to { $destination }...
+copying-files-to-session = Copying { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } to { $destination } (session { $session })...
+copy-completed = Copy completed successfully!
+extraction-completed = Extraction completed successfully!
+no-files-to-copy = No files to copy
+destination-snapshot-taken = Snapshot of the destination taken at { $path }
+session-skipping = Skipping { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } already copied in session { $session }
+duplicates-found = Found { $count ->
+        [one] { $count } duplicate file; it
+       *[other] { $count } duplicate files; they
+    } will be linked to the first copy
+worker-copy-failed = Worker { $worker }: Error copying { $path }: { $error }
+files-not-copied = { $count ->
+        [one] { $count } file was
+       *[other] { $count } files were
+    } not copied
+files-not-copied-resume = { $count ->
+        [one] { $count } file was
+       *[other] { $count } files were
+    } not copied; resume with --session { $session }
+rsync-destination-unsupported = rsync:// destinations are not supported
+source-missing = Source path does not exist: { $path }
+needs-local-destination = { $options } can only be used with a local destination
+needs-local-source = { $options } can only be used with a local source
+needs-local-source-and-destination = { $options } can only be used with a local source and destination
+remote-source-unsupported = { $options } cannot be used with remote sources
+remote-destination-unsupported = { $options } cannot be used with a remote destination
+rsync-source-unsupported = { $options } cannot be used with rsync:// sources
+rsync-source-combined = An rsync:// source cannot be combined with other sources
+remote-to-remote-unsupported = Copying between two remote hosts is not supported
+dedup-cache-needs-index = --dedup-cache needs --dedup-dest or --dedupe-blocks
+copied-list-failed = Failed to create the list of copied files: { $path }
+copy-stopped = Copy was stopped before all files were copied
+copy-thread-panicked = Copy thread panicked
+transfer-stalled = Transfer stalled for { $seconds } s, giving up
+copy-cancelled = Copy of { $path } was cancelled
+copy-file-failed = Failed to copy { $path }
+open-source-failed = Failed to open source file: { $path }
+read-source-failed = Failed to read source file: { $path }
+open-destination-failed = Failed to open destination file: { $path }
+create-destination-failed = Failed to create destination file: { $path }
+replace-destination-failed = Failed to replace destination file: { $path }
+create-destination-directory-failed = Failed to create destination directory: { $path }
+create-parent-failed = Failed to create parent directory: { $path }
+write-file-failed = Failed to write file: { $path }
+sync-directory-failed = Failed to sync directory: { $path }
+sync-file-failed = Failed to sync the file
+set-length-failed = Failed to set the file length
+allocate-failed = Failed to allocate { $size } bytes for { $path }
+file-shrank-during-copy = The file became shorter while it was being copied
+read-symlink-failed = Failed to read symlink: { $path }
+create-symlink-failed = Failed to create symlink: { $path }
+read-mtime-failed = Failed to read modification time: { $path }
+set-mtime-failed = Failed to set modification time: { $path }
+compressor-init-failed = Failed to initialize compressor
+decompressor-init-failed = Failed to initialize decompressor: { $path }
+decompress-failed = Failed to decompress { $path }: { $error }
+finish-compressed-failed = Failed to finish compressed file: { $path }
+finish-encrypted-failed = Failed to finish encrypted file: { $path }
+verify-open-failed = Failed to open file for verification: { $path }
+verify-read-failed = Failed to read file for verification: { $path }
+checksum-mismatch = Checksum mismatch for { $path }: expected { $expected }, got { $actual }
+checksum-mismatch-ranges = Checksum mismatch for { $path } in bytes { $ranges }
+invalid-source-pattern = Invalid source pattern: { $path }
+expand-pattern-failed = Failed to expand source pattern: { $path }
+no-files-match-pattern = No files match source pattern: { $path }
+cannot-derive-name = Cannot derive a file name from { $path }; give a file as the destination
+resolve-source-failed = Failed to resolve source path: { $path }
+resolve-path-failed = Failed to resolve path: { $path }
+same-file = Source and destination are the same file: { $source } and { $destination }
+same-directory = Source and destination are the same directory: { $path }
+destination-inside-source = Destination { $destination } lies inside source { $source }
+
+## Архивы
+
+archiving-files = Archiving { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    }...
+archive-item-failed = Error archiving { $path }: { $error }
+archive-written = Archive written to { $path }
+archive-skip-cpio-entry = Skipping unsupported cpio entry { $path } (mode { $mode })
+archive-skip-tar-entry = Skipping unsupported tar entry { $path } ({ $kind })
+archive-skip-unsafe-zip-entry = Skipping zip entry with unsafe path: { $path }
+archive-compressor-failed = Failed to initialize compressor
+archive-add-directory-failed = Failed to add directory to archive: { $path }
+archive-add-symlink-failed = Failed to add symlink to archive: { $path }
+archive-add-file-failed = Failed to add file to archive: { $path }
+archive-cpio-too-large = file is too large for cpio (4 GiB limit): { $path }
+archive-file-shrank = file shrank while archiving, padded with zeros: { $path }
+archive-finish-failed = Failed to finish the { $format } archive
+archive-finish-compressed-failed = Failed to finish compressed stream
+archive-flush-failed = Failed to flush archive
+archive-truncated = Unexpected end of { $format } stream
+archive-bad-cpio-magic = Invalid cpio header magic
+archive-bad-cpio-number = Invalid number in cpio header
+archive-read-failed = Failed to read { $format } archive
+archive-read-entry-failed = Failed to read { $format } entry
+archive-bad-path = Invalid path in { $format } entry
+archive-bad-link-target = Invalid link target in { $format } entry
+archive-read-symlink-failed = Failed to read symlink target: { $path }
+
+## rcp bisync
+
+conflict = Conflict:
+conflict-deleted-in-first = deleted in A, changed in B
+conflict-deleted-in-second = changed in A, deleted in B
+conflict-changed-on-both-sides = changed on both sides
+conflict-keeping = keeping the version from { $side }
+conflict-kept-as = kept as { $names }
+conflict-left-untouched = left untouched
+conflict-question = { $path }: { $reason }. Keep [a] A, [b] B, [r] both renamed, or [s] skip?{" "}
+already-in-sync = Already in sync
+removed = Removed { $path }
+bisync-side-summary = { $root }: { $copied ->
+        [one] { $copied } file
+       *[other] { $copied } files
+    } copied in, { $removed } removed
+
+## Файлы
+
+skipping-pseudo-fs = Skipping virtual filesystem { $path } ({ $fs_type }), use --include-pseudo-fs to copy it
+write-failed = Error writing file { $path }: { $error }
+copy-failed = Error copying { $path }: { $error }
+
+## btrfs
+
+btrfs-sending-changes = Sending changes since { $parent } to { $receiver }
+btrfs-sending = Sending { $source } to { $receiver }
+btrfs-sent = Sent { $source } as { $name } ({ $size })
+
+## Дедупликация
+
+dedup-cannot-index = cannot index { $path }: { $error }
+dedupe-blocks-unsupported = the destination file system cannot share blocks between files
+dedupe-blocks-shared = Shared the blocks of { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } with identical data ({ $size })
+reflinks-unsupported-copying = the destination file system does not support reflinks; copying files instead
+reflinks-unsupported-duplicates = the destination file system does not support reflinks; duplicates were copied instead
+dedup-duplicates = { $mode ->
+        [hardlink] Linked
+       *[reflink] Cloned
+    } { $count ->
+        [one] { $count } duplicate file
+       *[other] { $count } duplicate files
+    }
+dedup-existing = { $mode ->
+        [hardlink] Linked
+       *[reflink] Cloned
+    } { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } already present in the destination
+extracting = Extracting { $path }...
+extract-failed = Error extracting: { $error }
+skipping-unsafe-entry = Skipping archive entry with unsafe path: { $path }
+skipping-unsafe-link = Skipping hard link with unsafe target: { $target }
+hard-link-failed = Failed to create hard link { $path }: { $error }
+limits-left-out = { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } ({ $size }) left out by --max-files/--max-total-bytes:
+resuming = { $error }; resuming { $path }
+resuming-at = { $error }; resuming at byte { $offset }
+
+## Передача по сети
+
+receive-waiting = Waiting for files in { $directory } as "{ $name }" (port { $port })
+confirmation-code = Confirmation code: { $code }
+confirmation-code-prompt = Confirmation code shown by { $name }:{" "}
+received-from = Received files from { $peer }
+sending-to = Sending to { $name } ({ $destination })
+send-to-prompt = Send to:{" "}
+serving = Serving { $root } on { $address }{ $tls ->
+        [yes] {" "}(TLS)
+       *[no] {""}
+    }{ $quic ->
+        [yes] {" "}and QUIC
+       *[no] {""}
+    }
+serve-unencrypted = { $quic ->
+        [yes] TCP transfers
+       *[no] transfers
+    } are not encrypted or authenticated; use --tls-cert/--tls-key or --psk-file
+accept-failed = Failed to accept connection: { $error }
+quic-accept-failed = Failed to accept QUIC connection from { $peer }: { $error }
+relaying = Relaying on { $address }
+
+## Списки и каталоги-образцы
+
+paths-with-newline-left-out = { $count ->
+        [one] { $count } path contains
+       *[other] { $count } paths contain
+    } a newline and { $count ->
+        [one] was
+       *[other] were
+    } left out of { $path }; use --log-copied0
+reference-dir-missing = { $flag } directory does not exist: { $path }
+reference-no-times = { $source } does not report modification times, so { $flag } cannot match its files
+reference-linked = Linked { $count ->
+        [one] { $count } unchanged file
+       *[other] { $count } unchanged files
+    }
+reference-compared = Skipped { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } unchanged in --compare-dest
+reference-copied = Copied { $count ->
+        [one] { $count } unchanged file
+       *[other] { $count } unchanged files
+    } from --copy-dest
+not-enough-space = Not enough free space on { $path }: need { $required }, available { $available }
+
+## rcp watch и rcp snapshot
+
+watching = Watching { $path } for changes (Ctrl-C to stop)
+watch-remote-deletes = deletions are not mirrored to remote destinations
+watch-overflow = too many changes at once, syncing everything
+snapshot-continuing = Continuing interrupted snapshot { $name }
+snapshot-created = Snapshot { $path } created
+snapshot-removed = Removed snapshot { $name }
+
+## Передача через ретранслятор
+
+wormhole-code = Code: { $code }
+wormhole-code-prompt = Code:{" "}
+wormhole-code-hint = On the other machine run `rcp receive --relay { $relay }` and type the code
+wormhole-direct = Sending directly to { $address }
+wormhole-relayed = Sending through relay { $relay }
+wormhole-receiving = Receiving into { $directory }...
+wormhole-received = Received files
+
+## rsync
+
+copying-files-from = Copying { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } from { $host }...
+rsync-unsafe-path = Skipping unsafe path from rsync server: { $path }
+rsync-special-file = Skipping special file from rsync server: { $path }
+symlink-failed = Failed to create symlink { $path }: { $error }
+
+## --stats, --slowest и --interval
+
+slowest-files = Slowest { $count ->
+        [one] file
+       *[other] { $count } files
+    }:
+lowest-throughput = Lowest throughput (files of { $size } or more):
+stats-total = Copied { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    }, { $size } in { $seconds }s ({ $rate }/s)
+file-sizes = File sizes:
+files-column = files
+run-started = Run { $number } started at { $time }
+run-finished = Run { $number } finished in { $seconds }s
+run-failed = Run { $number } failed in { $seconds }s
+next-run = Next run at { $time }
+
+## Прогресс
+
+overall-progress = Overall progress
+progress-keys = Keys: p pause, s skip the slowest file, q stop, +/- raise or lower the speed limit
+all-files-copied = All files copied successfully!
+state-stopping = stopping
+state-draining = finishing started files
+state-paused = paused
+worker-failed-count = , { $count } failed
+worker-finished = finished
+file-copied = { $path } { $size } in { $seconds }s ({ $rate }/s)
+
+## Интерфейс --tui
+
+tui-keys = ↑↓ select  space pause file  p pause all  s skip  t copy next  e copy last  o sort  r reverse  q stop
+tui-errors = Errors ({ $count })
+tui-failed = { $count } failed
+tui-skipped = { $count } skipped
+tui-draining = finishing started files, q again to stop now
+tui-column-file = File
+tui-column-size = Size
+tui-column-done = Done
+tui-column-speed = Speed
+tui-files = Files: { $copying } copying, { $queued } queued
+tui-idle = idle
+tui-workers = Workers
+tui-summary = { $done } of { $total } files copied, { $failed } failed, { $skipped } skipped
+
+## --failure-report
+
+failure-report-written = Run status written to { $path }
+
+## Блокировка назначения
+
+destination-locked = Another rcp (pid { $pid }) is copying into { $destination }; use --wait-for-lock to wait for it or --no-lock to copy anyway
+waiting-for-lock = Waiting for another rcp (pid { $pid }) copying into { $destination }...
+
+## rcp bench
+
+bench-preparing = Preparing the workload…
+bench-workload = Workload: { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    }, { $size }
+bench-jobs = JOBS
+bench-buffer = BUFFER
+bench-time = TIME
+bench-rate = SPEED
+bench-fastest = Fastest: { $flags }
+
+## rcp doctor
+
+doctor-source = SOURCE
+doctor-destination = DESTINATION
+doctor-fs = File system
+doctor-reflink = Reflinks
+doctor-sparse = Sparse files
+doctor-xattr = User xattrs
+doctor-acl = POSIX ACLs
+doctor-symlink = Symbolic links
+doctor-hardlink = Hard links
+doctor-fallocate = fallocate
+doctor-timestamps = Time precision
+doctor-yes = yes
+doctor-no = no
+doctor-unknown = ?
+doctor-read-only = { $path } is read-only; only checks that don't write were run
+doctor-hardlinks-ok = --link-dest, --dedup and --dedup-dest can hard-link files
+doctor-hardlinks-no = --link-dest, --dedup and --dedup-dest: the destination has no hard links
+doctor-reflinks-ok = --dedup=reflink, --dedup-dest=reflink and --dedupe-blocks can share blocks between files
+doctor-reflinks-no = --dedup=reflink, --dedup-dest=reflink and --dedupe-blocks: the destination cannot share blocks between files
+doctor-fake-super-ok = --fake-super can keep owners, modes and times in user.rcp.* xattrs
+doctor-fake-super-no = --fake-super: the destination has no user xattrs
+doctor-fallocate-ok = --split-above reserves the space of big files before copying them
+doctor-fallocate-no = --split-above: no fallocate, big files grow without reserved space
+doctor-symlinks-no = symbolic links cannot be created in the destination
+doctor-ownership-ok = --preserve ownership works (running as root)
+doctor-ownership-fake-super = --preserve ownership needs root; add --fake-super to keep owners in xattrs
+doctor-ownership-no = --preserve ownership needs root, and --fake-super has no xattrs to use here
+doctor-modify-window = the destination keeps modification times to { $precision }: use --modify-window { $seconds } for incremental copies and --link-dest
+doctor-times-ok = modification times are kept as precisely as the source has them
+
+## rcp du
+
+du-total = { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } in { $directories ->
+        [one] { $directories } directory
+       *[other] { $directories } directories
+    }, { $size }
+
+## rcp --dbus
+
+dbus-unavailable = Progress is not exported over D-Bus: { $error }
+dbus-copying = Copying files
+dbus-source = Source
+dbus-destination = Destination
+dbus-stopped = Copy was stopped
+dbus-failed = Copy failed
+
+## systemd (sd_notify)
+
+systemd-synced = Synced { $count ->
+        [one] { $files } file
+       *[other] { $files } files
+    }, { $size }, { $errors ->
+        [one] { $errors } error
+       *[other] { $errors } errors
+    }
+systemd-copying = Copying { $count ->
+        [one] { $files } file
+       *[other] { $files } files
+    }: { $percent }%
+systemd-daemon = Jobs: { $running } running, { $queued } queued, { $finished } finished, { $failed } failed
+
+## rcp daemon, submit, list, cancel, attach
+
+daemon-listening = Waiting for jobs on { $socket }, running { $jobs ->
+        [1] one job
+       *[other] up to { $jobs } jobs
+    } at a time
+job-queued = Queued job { $id }
+job-started = Job { $id } started: { $command }
+job-start-failed = Job { $id } failed to start: { $error }
+job-ended = Job { $id } { $state } with exit code { $code }
+job-stopping = Stopping job { $id }
+job-cancelled = Cancelled job { $id }
+no-jobs = No jobs
+job-id = ID
+job-state-column = STATE
+job-priority = PRI
+job-exit = EXIT
+job-command = COMMAND
+job-state = { $state ->
+        [queued] queued
+        [running] running
+        [finished] finished
+        [failed] failed
+       *[cancelled] cancelled
+    }
+
+## Атрибуты копий
+
+birthtime-unsupported = creation times cannot be set on this system; add --fake-super to keep them in user.rcp.birthtime xattrs
+
+## Диски и сетевые файловые системы
+
+hdd-limited = spinning disks ({ $disks }): copying at most { $count ->
+    [one] one file
+   *[other] { $count } files
+} at a time on each
+stale-retry = { $path }: stale file handle, copying again (attempt { $attempt })
+fd-limited = open file limit ({ $limit }): copying at most { $jobs ->
+    [one] one file
+   *[other] { $jobs } files
+} at a time
+memory-limited = --max-memory: { $size } buffers, { $jobs ->
+    [one] one file
+   *[other] { $jobs } files
+} at a time
@@ -0,0 +1,434 @@
+error = Ошибка:
+warning = Предупреждение:
+subcommand-shadows-path = `{ $name }` запускается как `rcp { $name }`, хотя здесь есть файл с таким именем; чтобы скопировать его, используйте `rcp -- { $name } ...` или `rcp ./{ $name } ...`
+
+## Копирование
+
+copying-files = Копирование { $count ->
+        [one] { $count } файла
+       *[other] { $count } файлов
+    }...
+copying-files-to = Копирование { $count ->
+        [one] { $count } файла
+       *[other] { $count } файлов
+    } в { $destination }...
+copying-files-to-session = Копирование { $count ->
+        [one] { $count } файла
+       *[other] { $count } файлов
+    } в { $destination } (сессия { $session })...
+copy-completed = Копирование успешно завершено!
+extraction-completed = Распаковка успешно завершена!
+no-files-to-copy = Нечего копировать
+destination-snapshot-taken = Снимок назначения сделан в { $path }
+session-skipping = Пропуск { $count ->
+        [one] { $count } файла, уже скопированного
+       *[other] { $count } файлов, уже скопированных
+    } в сессии { $session }
+duplicates-found = { $count ->
+        [one] Найден { $count } дубликат; он станет
+        [few] Найдено { $count } дубликата; они станут
+       *[many] Найдено { $count } дубликатов; они станут
+    } ссылкой на первую копию
+worker-copy-failed = Поток { $worker }: ошибка копирования { $path }: { $error }
+files-not-copied = { $count ->
+        [one] { $count } файл не скопирован
+        [few] { $count } файла не скопированы
+       *[many] { $count } файлов не скопированы
+    }
+files-not-copied-resume = { $count ->
+        [one] { $count } файл не скопирован
+        [few] { $count } файла не скопированы
+       *[many] { $count } файлов не скопированы
+    }; продолжить можно с --session { $session }
+rsync-destination-unsupported = Назначение rsync:// не поддерживается
+source-missing = Исходный путь не существует: { $path }
+needs-local-destination = { $options } можно использовать только с локальным назначением
+needs-local-source = { $options } можно использовать только с локальным источником
+needs-local-source-and-destination = { $options } можно использовать только с локальными источником и назначением
+remote-source-unsupported = { $options } нельзя использовать с удаленными источниками
+remote-destination-unsupported = { $options } нельзя использовать с удаленным назначением
+rsync-source-unsupported = { $options } нельзя использовать с источниками rsync://
+rsync-source-combined = Источник rsync:// нельзя сочетать с другими источниками
+remote-to-remote-unsupported = Копирование между двумя удаленными хостами не поддерживается
+dedup-cache-needs-index = --dedup-cache требует --dedup-dest или --dedupe-blocks
+copied-list-failed = Не удалось создать список скопированных файлов: { $path }
+copy-stopped = Копирование остановлено до того, как были скопированы все файлы
+copy-thread-panicked = Поток копирования аварийно завершился
+transfer-stalled = Передача не продвигается { $seconds } с, копирование прервано
+copy-cancelled = Копирование { $path } отменено
+copy-file-failed = Не удалось скопировать { $path }
+open-source-failed = Не удалось открыть исходный файл: { $path }
+read-source-failed = Не удалось прочитать исходный файл: { $path }
+open-destination-failed = Не удалось открыть целевой файл: { $path }
+create-destination-failed = Не удалось создать целевой файл: { $path }
+replace-destination-failed = Не удалось заменить целевой файл: { $path }
+create-destination-directory-failed = Не удалось создать целевой каталог: { $path }
+create-parent-failed = Не удалось создать родительский каталог: { $path }
+write-file-failed = Не удалось записать файл: { $path }
+sync-directory-failed = Не удалось синхронизировать каталог: { $path }
+sync-file-failed = Не удалось синхронизировать файл
+set-length-failed = Не удалось задать длину файла
+allocate-failed = Не удалось выделить { $size } байт для { $path }
+file-shrank-during-copy = Файл стал короче во время копирования
+read-symlink-failed = Не удалось прочитать символическую ссылку: { $path }
+create-symlink-failed = Не удалось создать символическую ссылку: { $path }
+read-mtime-failed = Не удалось прочитать время изменения: { $path }
+set-mtime-failed = Не удалось установить время изменения: { $path }
+compressor-init-failed = Не удалось инициализировать сжатие
+decompressor-init-failed = Не удалось инициализировать распаковку: { $path }
+decompress-failed = Не удалось распаковать { $path }: { $error }
+finish-compressed-failed = Не удалось завершить сжатый файл: { $path }
+finish-encrypted-failed = Не удалось завершить зашифрованный файл: { $path }
+verify-open-failed = Не удалось открыть файл для проверки: { $path }
+verify-read-failed = Не удалось прочитать файл для проверки: { $path }
+checksum-mismatch = Контрольная сумма { $path } не совпадает: ожидалась { $expected }, получена { $actual }
+checksum-mismatch-ranges = Контрольная сумма { $path } не совпадает в байтах { $ranges }
+invalid-source-pattern = Неверный шаблон источника: { $path }
+expand-pattern-failed = Не удалось раскрыть шаблон источника: { $path }
+no-files-match-pattern = Ни один файл не подходит под шаблон источника: { $path }
+cannot-derive-name = Не удалось получить имя файла из { $path }; укажите файл в качестве назначения
+resolve-source-failed = Не удалось разрешить исходный путь: { $path }
+resolve-path-failed = Не удалось разрешить путь: { $path }
+same-file = Источник и назначение — один и тот же файл: { $source } и { $destination }
+same-directory = Источник и назначение — один и тот же каталог: { $path }
+destination-inside-source = Назначение { $destination } находится внутри источника { $source }
+
+## Архивы
+
+archiving-files = Архивирование { $count ->
+        [one] { $count } файла
+       *[other] { $count } файлов
+    }...
+archive-item-failed = Ошибка архивирования { $path }: { $error }
+archive-written = Архив записан в { $path }
+archive-skip-cpio-entry = Пропуск неподдерживаемой записи cpio { $path } (режим { $mode })
+archive-skip-tar-entry = Пропуск неподдерживаемой записи tar { $path } ({ $kind })
+archive-skip-unsafe-zip-entry = Пропуск записи zip с небезопасным путем: { $path }
+archive-compressor-failed = Не удалось запустить сжатие
+archive-add-directory-failed = Не удалось добавить директорию в архив: { $path }
+archive-add-symlink-failed = Не удалось добавить символическую ссылку в архив: { $path }
+archive-add-file-failed = Не удалось добавить файл в архив: { $path }
+archive-cpio-too-large = файл слишком велик для cpio (предел 4 ГиБ): { $path }
+archive-file-shrank = файл уменьшился во время архивирования, конец дополнен нулями: { $path }
+archive-finish-failed = Не удалось завершить архив { $format }
+archive-finish-compressed-failed = Не удалось завершить сжатый поток
+archive-flush-failed = Не удалось дописать архив
+archive-truncated = Неожиданный конец потока { $format }
+archive-bad-cpio-magic = Неверная сигнатура заголовка cpio
+archive-bad-cpio-number = Неверное число в заголовке cpio
+archive-read-failed = Не удалось прочитать архив { $format }
+archive-read-entry-failed = Не удалось прочитать запись { $format }
+archive-bad-path = Неверный путь в записи { $format }
+archive-bad-link-target = Неверная цель ссылки в записи { $format }
+archive-read-symlink-failed = Не удалось прочитать цель символической ссылки: { $path }
+
+## rcp bisync
+
+conflict = Конфликт:
+conflict-deleted-in-first = удален в A, изменен в B
+conflict-deleted-in-second = изменен в A, удален в B
+conflict-changed-on-both-sides = изменен с обеих сторон
+conflict-keeping = остается версия из { $side }
+conflict-kept-as = сохранен как { $names }
+conflict-left-untouched = оставлен как есть
+conflict-question = { $path }: { $reason }. Оставить [a] A, [b] B, [r] обе с новыми именами или [s] пропустить?{" "}
+already-in-sync = Уже синхронизировано
+removed = Удален { $path }
+bisync-side-summary = { $root }: скопировано { $copied ->
+        [one] { $copied } файл
+        [few] { $copied } файла
+       *[many] { $copied } файлов
+    }, удалено { $removed }
+
+## Файлы
+
+skipping-pseudo-fs = Пропуск виртуальной файловой системы { $path } ({ $fs_type }); скопировать ее можно с --include-pseudo-fs
+write-failed = Ошибка записи файла { $path }: { $error }
+copy-failed = Ошибка копирования { $path }: { $error }
+
+## btrfs
+
+btrfs-sending-changes = Передача изменений с { $parent } в { $receiver }
+btrfs-sending = Передача { $source } в { $receiver }
+btrfs-sent = { $source } передан как { $name } ({ $size })
+
+## Дедупликация
+
+dedup-cannot-index = не удалось проиндексировать { $path }: { $error }
+dedupe-blocks-unsupported = файловая система назначения не умеет делить блоки между файлами
+dedupe-blocks-shared = Общие блоки у { $count ->
+        [one] { $count } файла
+       *[other] { $count } файлов
+    } с одинаковыми данными ({ $size })
+reflinks-unsupported-copying = файловая система назначения не поддерживает reflink; файлы копируются
+reflinks-unsupported-duplicates = файловая система назначения не поддерживает reflink; дубликаты скопированы
+dedup-duplicates = { $mode ->
+        [hardlink] Связано ссылками
+       *[reflink] Склонировано
+    } дубликатов: { $count }
+dedup-existing = { $mode ->
+        [hardlink] Связано ссылками
+       *[reflink] Склонировано
+    } файлов, уже лежащих в назначении: { $count }
+extracting = Распаковка { $path }...
+extract-failed = Ошибка распаковки: { $error }
+skipping-unsafe-entry = Пропуск записи архива с небезопасным путем: { $path }
+skipping-unsafe-link = Пропуск жесткой ссылки с небезопасной целью: { $target }
+hard-link-failed = Не удалось создать жесткую ссылку { $path }: { $error }
+limits-left-out = { $count ->
+        [one] { $count } файл
+        [few] { $count } файла
+       *[many] { $count } файлов
+    } ({ $size }) не вошли в --max-files/--max-total-bytes:
+resuming = { $error }; продолжение { $path }
+resuming-at = { $error }; продолжение с байта { $offset }
+
+## Передача по сети
+
+receive-waiting = Ожидание файлов в { $directory } под именем "{ $name }" (порт { $port })
+confirmation-code = Код подтверждения: { $code }
+confirmation-code-prompt = Код подтверждения, показанный { $name }:{" "}
+received-from = Файлы получены от { $peer }
+sending-to = Отправка на { $name } ({ $destination })
+send-to-prompt = Отправить на:{" "}
+serving = Раздача { $root } на { $address }{ $tls ->
+        [yes] {" "}(TLS)
+       *[no] {""}
+    }{ $quic ->
+        [yes] {" "}и по QUIC
+       *[no] {""}
+    }
+serve-unencrypted = передача{ $quic ->
+        [yes] {" "}по TCP
+       *[no] {""}
+    } не шифруется и не проверяет клиентов; используйте --tls-cert/--tls-key или --psk-file
+accept-failed = Не удалось принять соединение: { $error }
+quic-accept-failed = Не удалось принять соединение QUIC от { $peer }: { $error }
+relaying = Ретрансляция на { $address }
+
+## Списки и каталоги-образцы
+
+paths-with-newline-left-out = { $count ->
+        [one] { $count } путь содержит
+        [few] { $count } пути содержат
+       *[many] { $count } путей содержат
+    } перевод строки и не попали в { $path }; используйте --log-copied0
+reference-dir-missing = директория { $flag } не существует: { $path }
+reference-no-times = { $source } не сообщает время изменения файлов, поэтому { $flag } не может их сопоставить
+reference-linked = Неизменных файлов связано ссылками: { $count }
+reference-compared = Пропущено файлов, не изменившихся относительно --compare-dest: { $count }
+reference-copied = Скопировано неизменных файлов из --copy-dest: { $count }
+not-enough-space = Недостаточно места на { $path }: нужно { $required }, доступно { $available }
+
+## rcp watch и rcp snapshot
+
+watching = Слежение за изменениями в { $path } (Ctrl-C - остановить)
+watch-remote-deletes = удаления не переносятся в удаленные назначения
+watch-overflow = слишком много изменений сразу, синхронизируется все
+snapshot-continuing = Продолжение прерванной копии { $name }
+snapshot-created = Копия { $path } создана
+snapshot-removed = Удалена копия { $name }
+
+## Передача через ретранслятор
+
+wormhole-code = Код: { $code }
+wormhole-code-prompt = Код:{" "}
+wormhole-code-hint = На другой машине запустите `rcp receive --relay { $relay }` и введите код
+wormhole-direct = Отправка напрямую на { $address }
+wormhole-relayed = Отправка через ретранслятор { $relay }
+wormhole-receiving = Прием в { $directory }...
+wormhole-received = Файлы получены
+
+## rsync
+
+copying-files-from = Копирование { $count ->
+        [one] { $count } файла
+       *[other] { $count } файлов
+    } с { $host }...
+rsync-unsafe-path = Пропуск небезопасного пути от сервера rsync: { $path }
+rsync-special-file = Пропуск специального файла от сервера rsync: { $path }
+symlink-failed = Не удалось создать символическую ссылку { $path }: { $error }
+
+## --stats, --slowest и --interval
+
+slowest-files = Самые долгие файлы ({ $count }):
+lowest-throughput = Самая низкая скорость (файлы от { $size }):
+stats-total = Скопировано { $count ->
+        [one] { $count } файл
+        [few] { $count } файла
+       *[many] { $count } файлов
+    }, { $size } за { $seconds } с ({ $rate }/s)
+file-sizes = Размеры файлов:
+files-column = файлов
+run-started = Запуск { $number } начат в { $time }
+run-finished = Запуск { $number } завершен за { $seconds } с
+run-failed = Запуск { $number } завершился ошибкой за { $seconds } с
+next-run = Следующий запуск в { $time }
+
+## Прогресс
+
+overall-progress = Общий прогресс
+progress-keys = Клавиши: p пауза, s пропустить самый медленный файл, q остановить, +/- поднять или опустить ограничение скорости
+all-files-copied = Все файлы успешно скопированы!
+state-stopping = остановка
+state-draining = докопируются начатые файлы
+state-paused = пауза
+worker-failed-count = , ошибок: { $count }
+worker-finished = готово
+file-copied = { $path } { $size } за { $seconds } с ({ $rate }/s)
+
+## Интерфейс --tui
+
+tui-keys = ↑↓ выбор  пробел пауза файла  p пауза всего  s пропустить  t копировать следующим  e копировать последним  o сортировка  r обратный порядок  q стоп
+tui-errors = Ошибки ({ $count })
+tui-failed = ошибок: { $count }
+tui-skipped = пропущено: { $count }
+tui-draining = докопируются начатые файлы, q еще раз - остановить сразу
+tui-column-file = Файл
+tui-column-size = Размер
+tui-column-done = Готово
+tui-column-speed = Скорость
+tui-files = Файлы: копируются { $copying }, в очереди { $queued }
+tui-idle = простой
+tui-workers = Потоки
+tui-summary = Скопировано { $done } из { $total }, ошибок: { $failed }, пропущено: { $skipped }
+
+## --failure-report
+
+failure-report-written = Состояние запуска записано в { $path }
+
+## Блокировка назначения
+
+destination-locked = Другой rcp (pid { $pid }) копирует в { $destination }; используйте --wait-for-lock, чтобы дождаться его, или --no-lock, чтобы копировать без блокировки
+waiting-for-lock = Ожидание другого rcp (pid { $pid }), копирующего в { $destination }...
+
+## rcp bench
+
+bench-preparing = Подготовка набора файлов…
+bench-workload = Набор: { $count ->
+        [one] { $count } файл
+        [few] { $count } файла
+       *[other] { $count } файлов
+    }, { $size }
+bench-jobs = ФАЙЛОВ
+bench-buffer = БУФЕР
+bench-time = ВРЕМЯ
+bench-rate = СКОРОСТЬ
+bench-fastest = Быстрее всего: { $flags }
+
+## rcp doctor
+
+doctor-source = ИСТОЧНИК
+doctor-destination = НАЗНАЧЕНИЕ
+doctor-fs = Файловая система
+doctor-reflink = Reflink
+doctor-sparse = Разреженные файлы
+doctor-xattr = xattr user.*
+doctor-acl = POSIX ACL
+doctor-symlink = Символические ссылки
+doctor-hardlink = Жесткие ссылки
+doctor-fallocate = fallocate
+doctor-timestamps = Точность времени
+doctor-yes = да
+doctor-no = нет
+doctor-unknown = ?
+doctor-read-only = { $path } только для чтения; проверено лишь то, что не требует записи
+doctor-hardlinks-ok = --link-dest, --dedup и --dedup-dest могут делать жесткие ссылки
+doctor-hardlinks-no = --link-dest, --dedup и --dedup-dest: в назначении нет жестких ссылок
+doctor-reflinks-ok = --dedup=reflink, --dedup-dest=reflink и --dedupe-blocks могут делить блоки между файлами
+doctor-reflinks-no = --dedup=reflink, --dedup-dest=reflink и --dedupe-blocks: назначение не умеет делить блоки между файлами
+doctor-fake-super-ok = --fake-super может хранить владельца, права и время в xattr user.rcp.*
+doctor-fake-super-no = --fake-super: в назначении нет xattr user.*
+doctor-fallocate-ok = --split-above заранее резервирует место под большие файлы
+doctor-fallocate-no = --split-above: fallocate нет, большие файлы растут без резерва места
+doctor-symlinks-no = в назначении нельзя создать символические ссылки
+doctor-ownership-ok = --preserve ownership работает (запуск от root)
+doctor-ownership-fake-super = --preserve ownership требует root; добавьте --fake-super, чтобы хранить владельца в xattr
+doctor-ownership-no = --preserve ownership требует root, а для --fake-super здесь нет xattr
+doctor-modify-window = назначение хранит время изменения с точностью { $precision }: для инкрементальных копий и --link-dest используйте --modify-window { $seconds }
+doctor-times-ok = время изменения сохраняется с той же точностью, что у источника
+
+## rcp du
+
+du-total = { $count ->
+        [one] { $count } файл
+        [few] { $count } файла
+       *[other] { $count } файлов
+    } в { $directories ->
+        [one] { $directories } директории
+       *[other] { $directories } директориях
+    }, { $size }
+
+## rcp --dbus
+
+dbus-unavailable = Прогресс не выставлен на D-Bus: { $error }
+dbus-copying = Копирование файлов
+dbus-source = Откуда
+dbus-destination = Куда
+dbus-stopped = Копирование остановлено
+dbus-failed = Копирование не удалось
+
+## systemd (sd_notify)
+
+systemd-synced = Синхронизировано { $count ->
+        [one] { $files } файл
+        [few] { $files } файла
+       *[many] { $files } файлов
+    }, { $size }, { $errors ->
+        [one] { $errors } ошибка
+        [few] { $errors } ошибки
+       *[many] { $errors } ошибок
+    }
+systemd-copying = Копируется { $count ->
+        [one] { $files } файл
+        [few] { $files } файла
+       *[many] { $files } файлов
+    }: { $percent }%
+systemd-daemon = Задания: выполняется { $running }, в очереди { $queued }, завершено { $finished }, с ошибкой { $failed }
+
+## rcp daemon, submit, list, cancel, attach
+
+daemon-listening = Ожидание заданий на { $socket }, одновременно { $jobs ->
+        [1] одно задание
+        [one] до { $jobs } задания
+       *[other] до { $jobs } заданий
+    }
+job-queued = Задание { $id } поставлено в очередь
+job-started = Задание { $id } запущено: { $command }
+job-start-failed = Задание { $id } не запустилось: { $error }
+job-ended = Задание { $id }: { $state }, код выхода { $code }
+job-stopping = Задание { $id } останавливается
+job-cancelled = Задание { $id } отменено
+no-jobs = Заданий нет
+job-id = ID
+job-state-column = СОСТОЯНИЕ
+job-priority = ПРИ
+job-exit = КОД
+job-command = КОМАНДА
+job-state = { $state ->
+        [queued] в очереди
+        [running] идет
+        [finished] готово
+        [failed] ошибка
+       *[cancelled] отменено
+    }
+
+## Атрибуты копий
+
+birthtime-unsupported = в этой системе время создания установить нельзя; добавьте --fake-super, чтобы сохранить его в xattr user.rcp.birthtime
+
+## Диски и сетевые файловые системы
+
+hdd-limited = жесткие диски ({ $disks }): на каждом одновременно копируется не больше { $count ->
+    [one] { $count } файла
+   *[other] { $count } файлов
+}
+stale-retry = { $path }: устаревший дескриптор файла, копирование заново (попытка { $attempt })
+fd-limited = предел открытых файлов ({ $limit }): одновременно копируется не больше { $jobs ->
+    [one] { $jobs } файла
+   *[other] { $jobs } файлов
+}
+memory-limited = --max-memory: буферы по { $size }, одновременно { $jobs ->
+    [one] { $jobs } файл
+   *[other] { $jobs } файлов
+}
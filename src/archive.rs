@@ -0,0 +1,321 @@
+mod cpio;
+mod tar;
+mod zip;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufWriter, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+use crate::cli::ChecksumAlgorithm;
+use crate::collect::CopyItem;
+use crate::compression::Compression;
+use crate::hash::Hasher;
+use crate::i18n::t;
+use crate::progress::{spawn_progress_manager, BarStyle, ProgressThrottle, ProgressUpdate};
+
+use self::cpio::{CpioReader, CpioWriter};
+use self::tar::{TarReader, TarWriter};
+use self::zip::{ZipArchiveReader, ZipArchiveWriter};
+
+// Корень, относительно которого собираются имена записей архива
+pub const ARCHIVE_ROOT: &str = ".";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    Zip,
+    Cpio,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArchiveFormat {
+    pub kind: ArchiveKind,
+    pub compression: Compression,
+}
+
+// Формат задается как `tar`, `tar:zstd`, `zip`, `cpio` или `cpio:zstd`
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (kind, compression) = match value.split_once(':') {
+            Some((kind, compression)) => (kind, Some(compression)),
+            None => (value, None),
+        };
+
+        let kind = match kind {
+            "tar" => ArchiveKind::Tar,
+            "zip" => ArchiveKind::Zip,
+            "cpio" => ArchiveKind::Cpio,
+            _ => return Err(format!("unsupported archive format: {}", kind)),
+        };
+
+        let compression = match compression {
+            None => Compression::None,
+            Some("zstd") => Compression::Zstd,
+            Some(other) => return Err(format!("unsupported archive compression: {}", other)),
+        };
+
+        // zip сжимает каждую запись сам
+        if kind == ArchiveKind::Zip && compression != Compression::None {
+            return Err("zip archives do not support an outer compression".to_string());
+        }
+
+        Ok(ArchiveFormat { kind, compression })
+    }
+}
+
+// Общий интерфейс записи архивов: записи добавляются строго по очереди
+pub trait ArchiveWriter {
+    fn append_directory(&mut self, name: &Path, metadata: &Metadata) -> Result<()>;
+    fn append_symlink(&mut self, name: &Path, metadata: &Metadata, target: &Path) -> Result<()>;
+    fn append_file(&mut self, name: &Path, metadata: &Metadata, data: &mut dyn Read) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+// Файл архива или стандартный вывод: zip без возможности перемотки пишется потоком
+enum ArchiveOutput {
+    Stdout,
+    File(File),
+}
+
+#[derive(Debug)]
+pub enum EntryKind {
+    Directory,
+    File,
+    Symlink(PathBuf),
+    // Жесткая ссылка на ранее распакованную запись
+    HardLink(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mode: Option<u32>,
+    pub modified: Option<SystemTime>,
+}
+
+pub type EntryVisitor<'a> = dyn FnMut(ArchiveEntry, &mut dyn Read) -> Result<()> + 'a;
+
+// Чтение архивов: записи отдаются по очереди вместе с потоком их данных
+pub trait ArchiveReader {
+    fn for_each_entry(&mut self, visit: &mut EntryVisitor) -> Result<()>;
+}
+
+const ZIP_MAGIC: &[u8] = b"PK";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// Формат определяем по сигнатуре: zip, cpio (newc) или tar, последние два - в том
+// числе сжатые zstd. `-` - стандартный ввод (zip из потока не читается, ему нужен
+// произвольный доступ)
+pub fn open_reader(source: &Path) -> Result<Box<dyn ArchiveReader>> {
+    let from_stdin = source == Path::new("-");
+    let mut input: Box<dyn Read> = if from_stdin {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(source).with_context(|| format!("Failed to open archive: {}", source.display()))?)
+    };
+
+    let mut head = Vec::with_capacity(8);
+    (&mut input).take(8).read_to_end(&mut head)?;
+
+    if head.starts_with(ZIP_MAGIC) {
+        if from_stdin {
+            anyhow::bail!("zip archives cannot be extracted from stdin, pass the archive file instead");
+        }
+        let file = File::open(source).with_context(|| format!("Failed to open archive: {}", source.display()))?;
+        return Ok(Box::new(ZipArchiveReader::new(file)?));
+    }
+
+    let mut stream: Box<dyn Read> = Box::new(io::Cursor::new(head.clone()).chain(input));
+    if head.starts_with(ZSTD_MAGIC) {
+        let mut decoder: Box<dyn Read> =
+            Box::new(zstd::Decoder::new(stream).context("Failed to initialize zstd decoder")?);
+        // Сигнатуру содержимого смотрим уже в распакованном потоке
+        head.clear();
+        (&mut decoder).take(8).read_to_end(&mut head)?;
+        stream = Box::new(io::Cursor::new(head.clone()).chain(decoder));
+    }
+
+    if cpio::is_cpio(&head) {
+        return Ok(Box::new(CpioReader::new(stream)));
+    }
+    Ok(Box::new(TarReader::new(stream)))
+}
+
+fn open_writer(format: ArchiveFormat, output: ArchiveOutput) -> Result<Box<dyn ArchiveWriter>> {
+    match (format.kind, output) {
+        (ArchiveKind::Tar, ArchiveOutput::Stdout) => Ok(Box::new(TarWriter::new(
+            BufWriter::new(io::stdout().lock()),
+            format.compression,
+        )?)),
+        (ArchiveKind::Tar, ArchiveOutput::File(file)) => {
+            Ok(Box::new(TarWriter::new(BufWriter::new(file), format.compression)?))
+        }
+        (ArchiveKind::Zip, ArchiveOutput::Stdout) => Ok(Box::new(ZipArchiveWriter::new(
+            ::zip::ZipWriter::new_stream(BufWriter::new(io::stdout().lock())),
+        ))),
+        (ArchiveKind::Zip, ArchiveOutput::File(file)) => Ok(Box::new(ZipArchiveWriter::new(
+            ::zip::ZipWriter::new(BufWriter::new(file)),
+        ))),
+        (ArchiveKind::Cpio, ArchiveOutput::Stdout) => Ok(Box::new(CpioWriter::new(
+            BufWriter::new(io::stdout().lock()),
+            format.compression,
+        )?)),
+        (ArchiveKind::Cpio, ArchiveOutput::File(file)) => {
+            Ok(Box::new(CpioWriter::new(BufWriter::new(file), format.compression)?))
+        }
+    }
+}
+
+// Упаковываем собранные файлы в архив (`-` - стандартный вывод) вместо
+// копирования по отдельности. Возвращает контрольные суммы, если они нужны
+pub fn create_archive(
+    directories: &[CopyItem],
+    files: &[CopyItem],
+    format: ArchiveFormat,
+    output: &Path,
+    checksum: Option<ChecksumAlgorithm>,
+    style: BarStyle,
+) -> Result<Vec<(CopyItem, String)>> {
+    let (output_stream, output_identity) = if output == Path::new("-") {
+        (ArchiveOutput::Stdout, None)
+    } else {
+        let file = File::create(output)
+            .with_context(|| format!("Failed to create archive: {}", output.display()))?;
+        let metadata = file.metadata()?;
+        (ArchiveOutput::File(file), Some((metadata.dev(), metadata.ino())))
+    };
+
+    let mut writer = open_writer(format, output_stream)?;
+
+    for directory in directories {
+        if let Some(name) = entry_name(&directory.destination) {
+            let metadata = fs::metadata(&directory.source)
+                .with_context(|| format!("Failed to read metadata: {}", directory.source))?;
+            writer.append_directory(name, &metadata)?;
+        }
+    }
+
+    eprintln!("{}", t!("archiving-files", count = files.len()));
+    let (progress_sender, manager_handle) = spawn_progress_manager(files.len(), style, None, None);
+    let mut checksums = Vec::new();
+
+    for (file_id, item) in files.iter().enumerate() {
+        let file_id = file_id as u32;
+        match append_item(writer.as_mut(), item, output_identity, checksum, &progress_sender, file_id) {
+            Ok(Some(digest)) => checksums.push((item.clone(), digest)),
+            Ok(None) => {}
+            Err(e) => {
+                let error = t!("archive-item-failed", path = item.source.as_str(), error = e.to_string());
+                eprintln!("{}", error);
+                let _ = progress_sender.send(ProgressUpdate::Failed { id: file_id, error });
+            }
+        }
+    }
+
+    drop(progress_sender);
+    manager_handle.join().expect("Progress manager panicked")?;
+    writer.finish()?;
+
+    Ok(checksums)
+}
+
+fn entry_name(destination: &Path) -> Option<&Path> {
+    destination
+        .strip_prefix(ARCHIVE_ROOT)
+        .ok()
+        .filter(|name| !name.as_os_str().is_empty())
+}
+
+fn append_item(
+    writer: &mut dyn ArchiveWriter,
+    item: &CopyItem,
+    output_identity: Option<(u64, u64)>,
+    checksum: Option<ChecksumAlgorithm>,
+    progress_sender: &mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+) -> Result<Option<String>> {
+    let source = Path::new(&item.source);
+    let name = entry_name(&item.destination).unwrap_or(&item.destination);
+    let metadata = fs::symlink_metadata(source)
+        .with_context(|| format!("Failed to read metadata: {}", item.source))?;
+
+    // Архив, который пишется внутрь исходного дерева, в себя не упаковываем
+    if output_identity == Some((metadata.dev(), metadata.ino())) {
+        anyhow::bail!("file is the archive being written, skipping");
+    }
+
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: item.source.clone(),
+        size: metadata.len().max(1),
+        id: file_id,
+    });
+
+    let digest = if metadata.is_symlink() {
+        let target = fs::read_link(source)
+            .with_context(|| format!("Failed to read symlink: {}", item.source))?;
+        writer.append_symlink(name, &metadata, &target)?;
+        None
+    } else {
+        let file = File::open(source)
+            .with_context(|| format!("Failed to open source file: {}", item.source))?;
+        let mut reader = ProgressReader {
+            inner: file,
+            hasher: checksum.map(Hasher::new),
+            progress_sender,
+            throttle: ProgressThrottle::default(),
+            file_id,
+            total_read: 0,
+        };
+        writer.append_file(name, &metadata, &mut reader)?;
+        reader.hasher.map(Hasher::finalize_hex)
+    };
+
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+    Ok(digest)
+}
+
+// Источник, который по ходу чтения сообщает прогресс и считает контрольную сумму
+struct ProgressReader<'a> {
+    inner: File,
+    hasher: Option<Hasher>,
+    progress_sender: &'a mpsc::Sender<ProgressUpdate>,
+    throttle: ProgressThrottle,
+    file_id: u32,
+    total_read: u64,
+}
+
+impl Read for ProgressReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..bytes_read]);
+        }
+        self.total_read += bytes_read as u64;
+        if self.throttle.ready() {
+            let _ = self.progress_sender.send(ProgressUpdate::Progress {
+                id: self.file_id,
+                bytes_copied: self.total_read,
+            });
+        }
+        Ok(bytes_read)
+    }
+}
+
+pub fn report_archive_created(output: &Path) {
+    let target = if output == Path::new("-") {
+        PathBuf::from("stdout")
+    } else {
+        output.to_path_buf()
+    };
+    eprintln!("{}", t!("archive-written", path = target.display().to_string()).green());
+}
@@ -0,0 +1,272 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::Metadata;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{ArchiveEntry, ArchiveReader, ArchiveWriter, EntryKind, EntryVisitor};
+use crate::compression::{CompressedWriter, Compression};
+use crate::i18n::t;
+
+// Формат newc ("070701"), который ядро понимает в initramfs; "070702" - тот же
+// формат с контрольной суммой данных, читаем его так же
+const NEWC_MAGIC: &[u8] = b"070701";
+const CRC_MAGIC: &[u8] = b"070702";
+const HEADER_LEN: usize = 110;
+const TRAILER_NAME: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+struct NewcHeader {
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u32,
+    file_size: u32,
+}
+
+// Заголовок, имя и данные выравниваются по 4 байта
+fn padding(len: u64) -> usize {
+    ((4 - len % 4) % 4) as usize
+}
+
+pub fn is_cpio(head: &[u8]) -> bool {
+    head.starts_with(NEWC_MAGIC) || head.starts_with(CRC_MAGIC)
+}
+
+pub struct CpioWriter<W: Write> {
+    output: CompressedWriter<W>,
+    // Номера inode в архиве - просто порядковые, реальные с исходной ФС не нужны
+    next_ino: u32,
+}
+
+impl<W: Write> CpioWriter<W> {
+    pub fn new(output: W, compression: Compression) -> Result<Self> {
+        let output = CompressedWriter::new(output, compression).with_context(|| t!("archive-compressor-failed"))?;
+        Ok(CpioWriter { output, next_ino: 1 })
+    }
+
+    fn write_header(&mut self, name: &Path, header: NewcHeader) -> io::Result<()> {
+        let name = name.as_os_str().as_bytes();
+        let fields = [
+            header.ino,
+            header.mode,
+            header.uid,
+            header.gid,
+            header.nlink,
+            header.mtime,
+            header.file_size,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            name.len() as u32 + 1,
+            0, // check
+        ];
+
+        let mut block = Vec::with_capacity(HEADER_LEN + name.len() + 4);
+        block.extend_from_slice(NEWC_MAGIC);
+        for field in fields {
+            block.extend_from_slice(format!("{:08x}", field).as_bytes());
+        }
+        block.extend_from_slice(name);
+        block.push(0);
+        block.resize(block.len() + padding(block.len() as u64), 0);
+        self.output.write_all(&block)
+    }
+
+    fn header_for(&mut self, metadata: &Metadata, file_type: u32, file_size: u32) -> NewcHeader {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        NewcHeader {
+            ino,
+            mode: file_type | (metadata.mode() & 0o7777),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            nlink: if file_type == S_IFDIR { 2 } else { 1 },
+            mtime: u32::try_from(metadata.mtime()).unwrap_or(0),
+            file_size,
+        }
+    }
+}
+
+// Поле размера в newc 32-битное
+fn entry_size(name: &Path, len: u64) -> Result<u32> {
+    u32::try_from(len).map_err(|_| anyhow::anyhow!("{}", t!("archive-cpio-too-large", path = name.display().to_string())))
+}
+
+impl<W: Write> ArchiveWriter for CpioWriter<W> {
+    fn append_directory(&mut self, name: &Path, metadata: &Metadata) -> Result<()> {
+        let header = self.header_for(metadata, S_IFDIR, 0);
+        self.write_header(name, header)
+            .with_context(|| t!("archive-add-directory-failed", path = name.display().to_string()))
+    }
+
+    fn append_symlink(&mut self, name: &Path, metadata: &Metadata, target: &Path) -> Result<()> {
+        // Цель ссылки хранится как данные записи
+        let target = target.as_os_str().as_bytes();
+        let header = self.header_for(metadata, S_IFLNK, entry_size(name, target.len() as u64)?);
+        self.write_header(name, header)
+            .and_then(|_| self.output.write_all(target))
+            .and_then(|_| self.output.write_all(&[0; 3][..padding(target.len() as u64)]))
+            .with_context(|| t!("archive-add-symlink-failed", path = name.display().to_string()))
+    }
+
+    fn append_file(&mut self, name: &Path, metadata: &Metadata, data: &mut dyn Read) -> Result<()> {
+        let size = entry_size(name, metadata.len())?;
+        let header = self.header_for(metadata, S_IFREG, size);
+        self.write_header(name, header)
+            .with_context(|| t!("archive-add-file-failed", path = name.display().to_string()))?;
+
+        // Файл мог измениться во время чтения - пишем ровно заявленный размер,
+        // недостающее добиваем нулями, чтобы поток остался корректным
+        let copied = io::copy(&mut data.take(u64::from(size)), &mut self.output)
+            .with_context(|| t!("archive-add-file-failed", path = name.display().to_string()))?;
+        let missing = u64::from(size) - copied;
+        io::copy(&mut io::repeat(0).take(missing + padding(u64::from(size)) as u64), &mut self.output)
+            .with_context(|| t!("archive-add-file-failed", path = name.display().to_string()))?;
+        if missing > 0 {
+            anyhow::bail!("{}", t!("archive-file-shrank", path = name.display().to_string()));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let trailer = NewcHeader {
+            ino: 0,
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            mtime: 0,
+            file_size: 0,
+        };
+        self.write_header(Path::new(TRAILER_NAME), trailer)
+            .with_context(|| t!("archive-finish-failed", format = "cpio"))?;
+        let mut output = self.output.finish().with_context(|| t!("archive-finish-compressed-failed"))?;
+        output.flush().with_context(|| t!("archive-flush-failed"))
+    }
+}
+
+pub struct CpioReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> CpioReader<R> {
+    pub fn new(input: R) -> Self {
+        CpioReader { input }
+    }
+
+    fn read_header(&mut self) -> Result<(NewcHeader, PathBuf)> {
+        let mut raw = [0; HEADER_LEN];
+        self.input.read_exact(&mut raw).with_context(|| t!("archive-truncated", format = "cpio"))?;
+        if !is_cpio(&raw) {
+            anyhow::bail!("{}", t!("archive-bad-cpio-magic"));
+        }
+
+        let field = |index: usize| -> Result<u32> {
+            let start = NEWC_MAGIC.len() + index * 8;
+            std::str::from_utf8(&raw[start..start + 8])
+                .ok()
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .with_context(|| t!("archive-bad-cpio-number"))
+        };
+
+        let header = NewcHeader {
+            ino: field(0)?,
+            mode: field(1)?,
+            uid: field(2)?,
+            gid: field(3)?,
+            nlink: field(4)?,
+            mtime: field(5)?,
+            file_size: field(6)?,
+        };
+        let name_size = field(11)? as usize;
+
+        let mut name = vec![0; name_size + padding((HEADER_LEN + name_size) as u64)];
+        self.input.read_exact(&mut name).with_context(|| t!("archive-truncated", format = "cpio"))?;
+        name.truncate(name_size.saturating_sub(1));
+        Ok((header, PathBuf::from(OsStr::from_bytes(&name))))
+    }
+
+    fn skip(&mut self, len: u64) -> Result<()> {
+        io::copy(&mut (&mut self.input).take(len), &mut io::sink()).with_context(|| t!("archive-truncated", format = "cpio"))?;
+        Ok(())
+    }
+}
+
+impl<R: Read> ArchiveReader for CpioReader<R> {
+    fn for_each_entry(&mut self, visit: &mut EntryVisitor) -> Result<()> {
+        // Жесткие ссылки в newc: все имена, кроме последнего, идут с нулевым размером,
+        // данные приходят с последним - откладываем имена до него
+        let mut pending_links: HashMap<u32, Vec<ArchiveEntry>> = HashMap::new();
+
+        loop {
+            let (header, path) = self.read_header()?;
+            if path == Path::new(TRAILER_NAME) {
+                break;
+            }
+
+            let size = u64::from(header.file_size);
+            let data_padding = padding(size) as u64;
+            let entry = ArchiveEntry {
+                path,
+                kind: EntryKind::File,
+                size,
+                mode: Some(header.mode & 0o7777),
+                modified: Some(UNIX_EPOCH + Duration::from_secs(u64::from(header.mtime))),
+            };
+
+            match header.mode & S_IFMT {
+                S_IFDIR => {
+                    visit(ArchiveEntry { kind: EntryKind::Directory, ..entry }, &mut io::empty())?;
+                    self.skip(size + data_padding)?;
+                }
+                S_IFLNK => {
+                    let mut target = vec![0; size as usize];
+                    self.input.read_exact(&mut target).with_context(|| t!("archive-truncated", format = "cpio"))?;
+                    self.skip(data_padding)?;
+                    let target = PathBuf::from(OsStr::from_bytes(&target));
+                    visit(ArchiveEntry { kind: EntryKind::Symlink(target), ..entry }, &mut io::empty())?;
+                }
+                S_IFREG if header.nlink > 1 && size == 0 => {
+                    pending_links.entry(header.ino).or_default().push(entry);
+                }
+                S_IFREG => {
+                    let path = entry.path.clone();
+                    let mut data = (&mut self.input).take(size);
+                    visit(entry, &mut data)?;
+                    // Остаток данных, который обработчик не дочитал
+                    io::copy(&mut data, &mut io::sink()).with_context(|| t!("archive-truncated", format = "cpio"))?;
+                    self.skip(data_padding)?;
+
+                    for link in pending_links.remove(&header.ino).unwrap_or_default() {
+                        let link = ArchiveEntry { kind: EntryKind::HardLink(path.clone()), ..link };
+                        visit(link, &mut io::empty())?;
+                    }
+                }
+                _ => {
+                    let (path, mode) = (entry.path.display().to_string(), format!("{:o}", header.mode));
+                    eprintln!("{}", t!("archive-skip-cpio-entry", path = path, mode = mode));
+                    self.skip(size + data_padding)?;
+                }
+            }
+        }
+
+        // Ссылки, для которых данные так и не пришли, - пустые файлы
+        for entry in pending_links.into_values().flatten() {
+            visit(entry, &mut io::empty())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use std::fs::Metadata;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{ArchiveEntry, ArchiveReader, ArchiveWriter, EntryKind, EntryVisitor};
+use crate::compression::{CompressedWriter, Compression};
+use crate::i18n::t;
+
+pub struct TarWriter<W: Write> {
+    builder: tar::Builder<CompressedWriter<W>>,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(output: W, compression: Compression) -> Result<Self> {
+        let writer = CompressedWriter::new(output, compression).with_context(|| t!("archive-compressor-failed"))?;
+        let mut builder = tar::Builder::new(writer);
+        builder.mode(tar::HeaderMode::Complete);
+        Ok(TarWriter { builder })
+    }
+}
+
+fn header_for(metadata: &Metadata, entry_type: tar::EntryType) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(metadata, tar::HeaderMode::Complete);
+    header.set_entry_type(entry_type);
+    header
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn append_directory(&mut self, name: &Path, metadata: &Metadata) -> Result<()> {
+        let mut header = header_for(metadata, tar::EntryType::Directory);
+        header.set_size(0);
+        self.builder
+            .append_data(&mut header, name, std::io::empty())
+            .with_context(|| t!("archive-add-directory-failed", path = name.display().to_string()))
+    }
+
+    fn append_symlink(&mut self, name: &Path, metadata: &Metadata, target: &Path) -> Result<()> {
+        let mut header = header_for(metadata, tar::EntryType::Symlink);
+        header.set_size(0);
+        self.builder
+            .append_link(&mut header, name, target)
+            .with_context(|| t!("archive-add-symlink-failed", path = name.display().to_string()))
+    }
+
+    fn append_file(&mut self, name: &Path, metadata: &Metadata, data: &mut dyn Read) -> Result<()> {
+        let mut header = header_for(metadata, tar::EntryType::Regular);
+        // Файл мог вырасти во время чтения - пишем ровно заявленный в заголовке размер
+        self.builder
+            .append_data(&mut header, name, data.take(metadata.len()))
+            .with_context(|| t!("archive-add-file-failed", path = name.display().to_string()))
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let writer = self.builder.into_inner().with_context(|| t!("archive-finish-failed", format = "tar"))?;
+        let mut output = writer.finish().with_context(|| t!("archive-finish-compressed-failed"))?;
+        output.flush().with_context(|| t!("archive-flush-failed"))
+    }
+}
+
+pub struct TarReader<R: Read> {
+    archive: tar::Archive<R>,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(input: R) -> Self {
+        TarReader {
+            archive: tar::Archive::new(input),
+        }
+    }
+}
+
+impl<R: Read> ArchiveReader for TarReader<R> {
+    fn for_each_entry(&mut self, visit: &mut EntryVisitor) -> Result<()> {
+        for entry in self.archive.entries().with_context(|| t!("archive-read-failed", format = "tar"))? {
+            let mut entry = entry.with_context(|| t!("archive-read-entry-failed", format = "tar"))?;
+            let header = entry.header();
+            let path = entry.path().with_context(|| t!("archive-bad-path", format = "tar"))?.into_owned();
+
+            let kind = match header.entry_type() {
+                tar::EntryType::Directory => EntryKind::Directory,
+                tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+                tar::EntryType::Symlink | tar::EntryType::Link => {
+                    let target = entry
+                        .link_name()
+                        .with_context(|| t!("archive-bad-link-target", format = "tar"))?
+                        .map(|target| target.into_owned())
+                        .unwrap_or_default();
+                    if header.entry_type() == tar::EntryType::Symlink {
+                        EntryKind::Symlink(target)
+                    } else {
+                        EntryKind::HardLink(target)
+                    }
+                }
+                other => {
+                    eprintln!("{}", t!("archive-skip-tar-entry", path = path.display().to_string(), kind = format!("{:?}", other)));
+                    continue;
+                }
+            };
+
+            let archive_entry = ArchiveEntry {
+                path,
+                kind,
+                size: header.size().unwrap_or(0),
+                mode: header.mode().ok(),
+                modified: header.mtime().ok().map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime)),
+            };
+            visit(archive_entry, &mut entry)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use std::fs::Metadata;
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+use super::{ArchiveEntry, ArchiveReader, ArchiveWriter, EntryKind, EntryVisitor};
+use crate::i18n::t;
+
+pub struct ZipArchiveWriter<W: Write + Seek> {
+    writer: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> ZipArchiveWriter<W> {
+    pub fn new(writer: ZipWriter<W>) -> Self {
+        ZipArchiveWriter { writer }
+    }
+}
+
+// Права и время изменения переносим в те поля, что есть в формате zip
+fn options_for(metadata: &Metadata) -> SimpleFileOptions {
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(metadata.permissions().mode() & 0o7777)
+        .large_file(metadata.len() >= u32::MAX as u64);
+
+    match modification_time(metadata) {
+        Some(mtime) => options.last_modified_time(mtime),
+        None => options,
+    }
+}
+
+fn modification_time(metadata: &Metadata) -> Option<DateTime> {
+    let seconds = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time_of_day = seconds.rem_euclid(86400);
+
+    DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month,
+        day,
+        (time_of_day / 3600) as u8,
+        (time_of_day % 3600 / 60) as u8,
+        (time_of_day % 60) as u8,
+    )
+    .ok()
+}
+
+// Дни от 1970-01-01 в дату григорианского календаря (алгоритм Говарда Хиннанта)
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Обратное преобразование: дата григорианского календаря в дни от 1970-01-01
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn system_time(datetime: DateTime) -> Option<SystemTime> {
+    let days = days_from_civil(i64::from(datetime.year()), datetime.month(), datetime.day());
+    let seconds = days * 86400
+        + i64::from(datetime.hour()) * 3600
+        + i64::from(datetime.minute()) * 60
+        + i64::from(datetime.second());
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+impl<W: Write + Seek> ArchiveWriter for ZipArchiveWriter<W> {
+    fn append_directory(&mut self, name: &Path, metadata: &Metadata) -> Result<()> {
+        self.writer
+            .add_directory_from_path(name, options_for(metadata))
+            .with_context(|| t!("archive-add-directory-failed", path = name.display().to_string()))
+    }
+
+    fn append_symlink(&mut self, name: &Path, metadata: &Metadata, target: &Path) -> Result<()> {
+        self.writer
+            .add_symlink_from_path(name, target, options_for(metadata))
+            .with_context(|| t!("archive-add-symlink-failed", path = name.display().to_string()))
+    }
+
+    fn append_file(&mut self, name: &Path, metadata: &Metadata, data: &mut dyn Read) -> Result<()> {
+        self.writer
+            .start_file_from_path(name, options_for(metadata))
+            .with_context(|| t!("archive-add-file-failed", path = name.display().to_string()))?;
+        io::copy(&mut data.take(metadata.len()), &mut self.writer)
+            .with_context(|| t!("archive-add-file-failed", path = name.display().to_string()))?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let mut output = self.writer.finish().with_context(|| t!("archive-finish-failed", format = "zip"))?;
+        output.flush().with_context(|| t!("archive-flush-failed"))
+    }
+}
+
+pub struct ZipArchiveReader<R: Read + Seek> {
+    archive: ZipArchive<R>,
+}
+
+impl<R: Read + Seek> ZipArchiveReader<R> {
+    pub fn new(input: R) -> Result<Self> {
+        let archive = ZipArchive::new(input).with_context(|| t!("archive-read-failed", format = "zip"))?;
+        Ok(ZipArchiveReader { archive })
+    }
+}
+
+impl<R: Read + Seek> ArchiveReader for ZipArchiveReader<R> {
+    fn for_each_entry(&mut self, visit: &mut EntryVisitor) -> Result<()> {
+        for index in 0..self.archive.len() {
+            let mut file = self.archive.by_index(index).with_context(|| t!("archive-read-entry-failed", format = "zip"))?;
+
+            // enclosed_name отсекает абсолютные пути и выход за пределы назначения
+            let Some(path) = file.enclosed_name() else {
+                eprintln!("{}", t!("archive-skip-unsafe-zip-entry", path = file.name().unwrap_or_default()));
+                continue;
+            };
+
+            let kind = if file.is_dir() {
+                EntryKind::Directory
+            } else if file.is_symlink() {
+                let mut target = String::new();
+                file.read_to_string(&mut target)
+                    .with_context(|| t!("archive-read-symlink-failed", path = path.display().to_string()))?;
+                EntryKind::Symlink(PathBuf::from(target))
+            } else {
+                EntryKind::File
+            };
+
+            let entry = ArchiveEntry {
+                path,
+                kind,
+                size: file.size(),
+                mode: file.unix_mode(),
+                modified: file.last_modified().and_then(system_time),
+            };
+            visit(entry, &mut file)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::{Digest, Md5};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::http::{agent, describe_error, encode_path, percent_encode, send_with_retry};
+use crate::transport::{is_object_prefix, Sink, SinkFile};
+
+// Назначение в Azure Blob Storage: файл загружается блочным блобом - блоки отправляются
+// по очереди (Put Block) с MD5, который проверяет сервис, а блоб появляется только
+// после фиксации списка блоков (Put Block List). Доступ - по SAS-токену
+
+const API_VERSION: &str = "2023-11-03";
+
+// Блоков в блобе не больше 50 000, поэтому каждые 10 000 блоков размер блока удваивается:
+// 8 MiB хватает на 78 GiB, а всего получается около 2.4 TiB
+const BLOCK_SIZE: usize = 8 * 1024 * 1024;
+const BLOCKS_PER_SIZE: usize = 10_000;
+const MAX_BLOCKS: usize = 50_000;
+
+// `az://container/path`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AzureLocation {
+    pub container: String,
+    pub path: PathBuf,
+}
+
+impl AzureLocation {
+    pub fn parse(location: &Path) -> Option<Self> {
+        let rest = location.to_str()?.strip_prefix("az://")?;
+        let (container, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if container.is_empty() {
+            return None;
+        }
+        Some(AzureLocation {
+            container: container.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct AzureSession {
+    agent: ureq::Agent,
+    // URL контейнера без параметров
+    container_url: String,
+    container: String,
+    // SAS без ведущего `?`
+    sas: String,
+}
+
+impl AzureSession {
+    // Адрес сервиса - `https://ACCOUNT.blob.core.windows.net` или явный (эмулятор Azurite)
+    pub fn connect(container: &str, account: Option<&str>, endpoint: Option<&str>, sas: Option<&str>) -> Result<Self> {
+        let endpoint = match (endpoint, account) {
+            (Some(endpoint), _) => endpoint.trim_end_matches('/').to_string(),
+            (None, Some(account)) => format!("https://{}.blob.core.windows.net", account),
+            (None, None) => anyhow::bail!("Azure needs a storage account: pass --azure-account or set AZURE_STORAGE_ACCOUNT"),
+        };
+        let sas = sas
+            .map(|sas| sas.trim_start_matches('?').to_string())
+            .filter(|sas| !sas.is_empty())
+            .context("Azure needs a SAS token: pass --azure-sas or set AZURE_STORAGE_SAS_TOKEN")?;
+
+        let session = AzureSession {
+            agent: agent(),
+            container_url: format!("{}/{}", endpoint, percent_encode(container)),
+            container: container.to_string(),
+            sas,
+        };
+
+        // Проверяем токен и контейнер до начала копирования. Токену только на запись
+        // чтение свойств запрещено, но такой отказ значит, что подпись верна
+        let url = format!("{}?restype=container&{}", session.container_url, session.sas);
+        let mut response = session
+            .request(session.agent.get(&url))
+            .call()
+            .with_context(|| format!("Failed to reach {}", endpoint))?;
+        let permitted = matches!(
+            error_code(&response).as_deref(),
+            Some("AuthorizationPermissionMismatch" | "AuthorizationResourceTypeMismatch")
+        );
+        if response.status() != 200 && !permitted {
+            anyhow::bail!("Cannot access container az://{}: {}", container, describe_error(&mut response));
+        }
+        Ok(session)
+    }
+
+    fn request<B>(&self, request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+        request.header("x-ms-version", API_VERSION)
+    }
+
+    // Имя блоба - путь назначения без ведущего слеша; в URL кодируется по сегментам
+    fn blob_url(&self, path: &Path) -> Result<String> {
+        let name = path
+            .to_str()
+            .map(|name| name.trim_start_matches('/'))
+            .filter(|name| !name.is_empty())
+            .with_context(|| format!("Invalid Azure blob name: {}", path.display()))?;
+        Ok(format!("{}/{}", self.container_url, encode_path(name)))
+    }
+
+    pub fn create(&self, path: &Path) -> Result<AzureFile> {
+        Ok(AzureFile {
+            session: self.clone(),
+            blob_url: self.blob_url(path)?,
+            buffer: Vec::new(),
+            blocks: Vec::new(),
+            md5: Md5::new(),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+// Блобы именуются так же, как объекты GCS
+impl Sink for AzureSession {
+    fn name(&self) -> String {
+        format!("az://{}", self.container)
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(is_object_prefix(path))
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn create(&self, path: &Path, _modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        Ok(Box::new(AzureSession::create(self, path)?))
+    }
+}
+
+fn error_code(response: &ureq::http::Response<ureq::Body>) -> Option<String> {
+    response
+        .headers()
+        .get("x-ms-error-code")
+        .and_then(|code| code.to_str().ok())
+        .map(str::to_string)
+}
+
+// Блоб в процессе загрузки: данные копятся до целого блока и отправляются как
+// незафиксированные блоки; до close() прежнее содержимое блоба не меняется
+pub struct AzureFile {
+    session: AzureSession,
+    blob_url: String,
+    buffer: Vec<u8>,
+    // ID отправленных блоков по порядку
+    blocks: Vec<String>,
+    md5: Md5,
+    path: PathBuf,
+}
+
+impl AzureFile {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE << (self.blocks.len() / BLOCKS_PER_SIZE).min(4)
+    }
+
+    // ID блоков одного блоба должны быть одной длины
+    fn put_block(&mut self, len: usize) -> Result<()> {
+        if self.blocks.len() >= MAX_BLOCKS {
+            anyhow::bail!("File is too large for an Azure block blob: {}", self.path.display());
+        }
+        let id = STANDARD.encode(format!("{:08}", self.blocks.len()));
+        let url = format!(
+            "{}?comp=block&blockid={}&{}",
+            self.blob_url,
+            percent_encode(&id),
+            self.session.sas
+        );
+        let block = &self.buffer[..len];
+        let checksum = STANDARD.encode(Md5::digest(block));
+        send_with_retry("Upload to Azure", || {
+            self.session
+                .request(self.session.agent.put(&url))
+                .header("Content-MD5", &checksum)
+                .send(block)
+        })?;
+        self.buffer.drain(..len);
+        self.blocks.push(id);
+        Ok(())
+    }
+
+    // Фиксация списка блоков заменяет блоб целиком; MD5 файла сохраняется в его свойствах
+    pub fn close(mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.put_block(self.buffer.len())?;
+        }
+
+        let mut list = String::from(r#"<?xml version="1.0" encoding="utf-8"?><BlockList>"#);
+        for id in &self.blocks {
+            list.push_str(&format!("<Latest>{}</Latest>", id));
+        }
+        list.push_str("</BlockList>");
+        let url = format!("{}?comp=blocklist&{}", self.blob_url, self.session.sas);
+        let checksum = STANDARD.encode(self.md5.finalize_reset());
+        send_with_retry("Committing Azure blob", || {
+            self.session
+                .request(self.session.agent.put(&url))
+                .header("Content-Type", "application/xml")
+                .header("x-ms-blob-content-type", "application/octet-stream")
+                .header("x-ms-blob-content-md5", &checksum)
+                .send(list.as_bytes())
+        })
+        .map(drop)
+        .with_context(|| format!("Failed to store az://{}/{}", self.session.container, self.path.display()))
+    }
+}
+
+impl SinkFile for AzureFile {
+    fn close(self: Box<Self>) -> Result<()> {
+        AzureFile::close(*self)
+    }
+}
+
+impl Write for AzureFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.md5.update(buf);
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= self.block_size() {
+            self.put_block(self.block_size()).map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
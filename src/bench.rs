@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use indicatif::HumanBytes;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::cli::BenchArgs;
+use crate::i18n::t;
+use crate::progress::format_speed;
+
+// `rcp bench`: набор файлов копируется самим rcp с каждым сочетанием --jobs и --buffer-size.
+// Перед каждым прогоном набор вытесняется из страничного кеша, а время
+// считается до syncfs назначения, иначе копия меньше памяти измеряла бы только кеш
+
+// Генерируемые мелкие файлы раскладываются по директориям, как в настоящем дереве
+const FILES_PER_DIR: usize = 100;
+
+struct Workload {
+    files: usize,
+    bytes: u64,
+}
+
+struct Run {
+    jobs: usize,
+    buffer_size: u64,
+    elapsed: Duration,
+}
+
+pub fn run(args: &BenchArgs) -> Result<()> {
+    let root = args.dir.join(format!(".rcp-bench-{}", std::process::id()));
+    fs::create_dir_all(&root).with_context(|| format!("Failed to create directory: {}", root.display()))?;
+    let result = bench(args, &root);
+    let _ = fs::remove_dir_all(&root);
+    result
+}
+
+fn bench(args: &BenchArgs, root: &Path) -> Result<()> {
+    let workload_dir = root.join("workload");
+    println!("{}", t!("bench-preparing"));
+    let workload = match &args.source {
+        Some(source) => sample(source, &workload_dir, args.sample)?,
+        None => generate(&workload_dir, args)?,
+    };
+    if workload.files == 0 {
+        anyhow::bail!("No files to copy in {}", workload_dir.display());
+    }
+    sync(root)?;
+    println!("{}", t!("bench-workload", count = workload.files, size = HumanBytes(workload.bytes).to_string()));
+    println!(
+        "{:>5} {:>8} {:>9} {:>12}",
+        t!("bench-jobs"),
+        t!("bench-buffer"),
+        t!("bench-time"),
+        t!("bench-rate")
+    );
+
+    let destination = root.join("copy");
+    let mut runs = Vec::new();
+    for &jobs in &args.jobs {
+        for &buffer_size in &args.buffer_sizes {
+            forget(&workload_dir);
+            let elapsed = copy(&workload_dir, &destination, jobs, buffer_size)?;
+            fs::remove_dir_all(&destination)
+                .with_context(|| format!("Failed to remove directory: {}", destination.display()))?;
+            println!(
+                "{:>5} {:>8} {:>8.2}s {:>10}/s",
+                jobs,
+                size_flag(buffer_size),
+                elapsed.as_secs_f64(),
+                format_speed(workload.bytes as f64 / elapsed.as_secs_f64().max(0.001))
+            );
+            runs.push(Run { jobs, buffer_size, elapsed });
+        }
+    }
+
+    if let Some(fastest) = runs.iter().min_by_key(|run| run.elapsed) {
+        let flags = format!("--jobs {} --buffer-size {}", fastest.jobs, size_flag(fastest.buffer_size));
+        println!("{}", t!("bench-fastest", flags = flags).bold());
+    }
+    Ok(())
+}
+
+// Один прогон: копия набора отдельным процессом rcp, вывод которого не нужен
+fn copy(workload: &Path, destination: &Path, jobs: usize, buffer_size: u64) -> Result<Duration> {
+    let started = Instant::now();
+    let output = Command::new(std::env::current_exe()?)
+        .args(["--jobs", &jobs.to_string()])
+        // Иначе ограничение для жестких дисков сравняло бы разные --jobs
+        .args(["--jobs-per-hdd", &jobs.to_string()])
+        .args(["--buffer-size", &buffer_size.to_string()])
+        .arg("--no-lock")
+        .arg(workload)
+        .arg(destination)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .context("Failed to start rcp")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Copy with --jobs {} --buffer-size {} failed: {}",
+            jobs,
+            size_flag(buffer_size),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    sync(destination)?;
+    Ok(started.elapsed())
+}
+
+// Мелкие файлы --file-size и один большой --large-file со случайным содержимым
+fn generate(directory: &Path, args: &BenchArgs) -> Result<Workload> {
+    let mut block = vec![0u8; 1024 * 1024];
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    for chunk in block.chunks_mut(8) {
+        // xorshift64: сжатие и дедупликация файловой системы не должны влиять на замер
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        chunk.copy_from_slice(&state.to_le_bytes()[..chunk.len()]);
+    }
+
+    let mut workload = Workload { files: 0, bytes: 0 };
+    for index in 0..args.files {
+        let path = directory.join(format!("{:04}", index / FILES_PER_DIR)).join(format!("{:06}.bin", index));
+        write_file(&path, &block, args.file_size, index)?;
+        workload.files += 1;
+        workload.bytes += args.file_size;
+    }
+    if args.large_file > 0 {
+        write_file(&directory.join("large.bin"), &block, args.large_file, args.files)?;
+        workload.files += 1;
+        workload.bytes += args.large_file;
+    }
+    Ok(workload)
+}
+
+fn write_file(path: &Path, block: &[u8], size: u64, seed: usize) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let mut file = File::create(path).with_context(|| format!("Failed to create file: {}", path.display()))?;
+    // У каждого файла свой сдвиг в блоке, чтобы файлы не совпадали
+    let mut offset = seed * 4099 % block.len();
+    let mut left = size;
+    while left > 0 {
+        let len = (block.len() - offset).min(left as usize);
+        file.write_all(&block[offset..offset + len])
+            .with_context(|| format!("Failed to write file: {}", path.display()))?;
+        left -= len as u64;
+        offset = 0;
+    }
+    Ok(())
+}
+
+// Первые файлы SOURCE (по именам) общим размером до --sample, с тем же деревом директорий
+fn sample(source: &Path, directory: &Path, limit: u64) -> Result<Workload> {
+    let mut files = Vec::new();
+    walk(source, &mut files)?;
+    let mut workload = Workload { files: 0, bytes: 0 };
+    for path in files {
+        let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        if workload.files > 0 && workload.bytes + size > limit {
+            break;
+        }
+        let target = directory.join(path.strip_prefix(source).unwrap_or(&path));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        fs::copy(&path, &target).with_context(|| format!("Failed to copy {} to {}", path.display(), target.display()))?;
+        workload.files += 1;
+        workload.bytes += size;
+    }
+    Ok(workload)
+}
+
+// Обычные файлы дерева по порядку имен; ссылки пропускаются
+fn walk(directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(directory)
+        .with_context(|| format!("Failed to read directory: {}", directory.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_type().ok().map(|kind| (entry.path(), kind)))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, kind) in entries {
+        if kind.is_dir() {
+            walk(&path, files)?;
+        } else if kind.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Набор уже на диске (sync), поэтому его страницы можно выбросить из кеша
+fn forget(directory: &Path) {
+    let mut files = Vec::new();
+    let _ = walk(directory, &mut files);
+    for path in files {
+        if let Ok(file) = File::open(&path) {
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        }
+    }
+}
+
+fn sync(path: &Path) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    if unsafe { libc::syncfs(file.as_raw_fd()) } != 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("Failed to sync {}", path.display()));
+    }
+    Ok(())
+}
+
+// Размер в виде, который принимает --buffer-size
+fn size_flag(size: u64) -> String {
+    match size {
+        size if size % (1024 * 1024) == 0 => format!("{}M", size / (1024 * 1024)),
+        size if size % 1024 == 0 => format!("{}K", size / 1024),
+        size => size.to_string(),
+    }
+}
@@ -0,0 +1,481 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Args, BisyncArgs, ChecksumAlgorithm, ConflictStrategy};
+use crate::hash::hash_file;
+use crate::i18n::t;
+use crate::nearby::prompt;
+use crate::session::state_dir;
+use crate::snapshot::now_name;
+use crate::transport::Address;
+
+// `rcp bisync`: изменения переносятся в обе стороны. Для каждой пары директорий в
+// $XDG_STATE_HOME/rcp/bisync хранится, какими файлы были после прошлого запуска: размер, mtime
+// на каждой стороне и хеш содержимого. Сторона изменила файл, если он отличается от записанного;
+// если изменили обе стороны (или одна удалила, а другая изменила) - это конфликт, который
+// решается по --conflict
+
+// По размеру и mtime понимаем, что файл не трогали с прошлого запуска
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileState {
+    size: u64,
+    modified: (i64, i64),
+}
+
+impl FileState {
+    fn of(metadata: &fs::Metadata) -> Self {
+        FileState {
+            size: metadata.len(),
+            modified: (metadata.mtime(), metadata.mtime_nsec()),
+        }
+    }
+}
+
+struct Entry {
+    first: FileState,
+    second: FileState,
+    hash: String,
+}
+
+enum Change {
+    Unchanged,
+    Modified(String),
+    Deleted,
+    // Файла нет и не было
+    Absent,
+}
+
+#[derive(Default)]
+struct Plan {
+    to_first: Vec<PathBuf>,
+    to_second: Vec<PathBuf>,
+    delete_first: Vec<PathBuf>,
+    delete_second: Vec<PathBuf>,
+}
+
+// Файл изменили обе стороны; None - сторона его удалила, иначе хеш ее версии
+struct Conflict {
+    path: PathBuf,
+    first: Option<String>,
+    second: Option<String>,
+}
+
+impl Conflict {
+    fn reason(&self) -> String {
+        match (&self.first, &self.second) {
+            (None, _) => t!("conflict-deleted-in-first"),
+            (_, None) => t!("conflict-deleted-in-second"),
+            _ => t!("conflict-changed-on-both-sides"),
+        }
+    }
+}
+
+enum Resolution {
+    KeepFirst,
+    KeepSecond,
+    RenameBoth,
+    Skip,
+}
+
+pub fn run(args: &BisyncArgs) -> Result<()> {
+    let first = root(&args.first)?;
+    let second = root(&args.second)?;
+    if first.starts_with(&second) || second.starts_with(&first) {
+        anyhow::bail!("rcp bisync needs two separate directories: {} and {}", first.display(), second.display());
+    }
+    let state_path = state_dir()?.join("bisync").join(pair_id(&first, &second));
+    let previous = load(&state_path)?;
+    let mut first_files = walk(&first)?;
+    let mut second_files = walk(&second)?;
+
+    let paths: BTreeSet<&PathBuf> = previous.keys().chain(first_files.keys()).chain(second_files.keys()).collect();
+    let mut plan = Plan::default();
+    let mut conflicts = Vec::new();
+    let mut state = HashMap::new();
+    // Хеши файлов, которые копируем: запишем их в состояние после копирования
+    let mut copied_hashes = HashMap::new();
+    for path in paths {
+        let entry = previous.get(path);
+        let in_first = change(&first, path, first_files.get(path), entry.map(|entry| (entry.first, &entry.hash)))?;
+        let in_second = change(&second, path, second_files.get(path), entry.map(|entry| (entry.second, &entry.hash)))?;
+        match (in_first, in_second) {
+            (Change::Unchanged, Change::Unchanged) => {
+                let hash = entry.map(|entry| entry.hash.clone()).unwrap_or_default();
+                state.insert(path.clone(), Entry { first: first_files[path], second: second_files[path], hash });
+            }
+            (Change::Modified(hash), Change::Unchanged | Change::Absent) => {
+                plan.to_second.push(path.clone());
+                copied_hashes.insert(path.clone(), hash);
+            }
+            (Change::Unchanged | Change::Absent, Change::Modified(hash)) => {
+                plan.to_first.push(path.clone());
+                copied_hashes.insert(path.clone(), hash);
+            }
+            (Change::Modified(a), Change::Modified(b)) if a == b => {
+                state.insert(path.clone(), Entry { first: first_files[path], second: second_files[path], hash: a });
+            }
+            (Change::Modified(a), Change::Modified(b)) => conflicts.push(Conflict {
+                path: path.clone(),
+                first: Some(a),
+                second: Some(b),
+            }),
+            (Change::Deleted, Change::Unchanged) => plan.delete_second.push(path.clone()),
+            (Change::Unchanged, Change::Deleted) => plan.delete_first.push(path.clone()),
+            (Change::Deleted, Change::Modified(b)) => conflicts.push(Conflict {
+                path: path.clone(),
+                first: None,
+                second: Some(b),
+            }),
+            (Change::Modified(a), Change::Deleted) => conflicts.push(Conflict {
+                path: path.clone(),
+                first: Some(a),
+                second: None,
+            }),
+            // Удален с обеих сторон или не существует
+            _ => {}
+        }
+    }
+
+    let mut unresolved = 0;
+    for conflict in conflicts {
+        let resolution = resolve(&conflict, args.conflict, first_files.get(&conflict.path), second_files.get(&conflict.path))?;
+        let reason = conflict.reason();
+        let outcome = match resolution {
+            Resolution::KeepFirst => {
+                keep(&mut plan.to_second, &mut plan.delete_second, &mut copied_hashes, &conflict.path, conflict.first);
+                t!("conflict-keeping", side = "A")
+            }
+            Resolution::KeepSecond => {
+                keep(&mut plan.to_first, &mut plan.delete_first, &mut copied_hashes, &conflict.path, conflict.second);
+                t!("conflict-keeping", side = "B")
+            }
+            Resolution::RenameBoth => {
+                let mut renamed = Vec::new();
+                for (root, files, to_other, hash, side) in [
+                    (&first, &mut first_files, &mut plan.to_second, conflict.first, "A"),
+                    (&second, &mut second_files, &mut plan.to_first, conflict.second, "B"),
+                ] {
+                    let Some(hash) = hash else {
+                        continue;
+                    };
+                    let name = conflict_name(&conflict.path, side);
+                    let (from, to) = (root.join(&conflict.path), root.join(&name));
+                    fs::rename(&from, &to)
+                        .with_context(|| format!("Failed to rename {} to {}", from.display(), to.display()))?;
+                    // Переименование не меняет ни размер, ни mtime
+                    if let Some(state) = files.remove(&conflict.path) {
+                        files.insert(name.clone(), state);
+                    }
+                    renamed.push(name.display().to_string());
+                    to_other.push(name.clone());
+                    copied_hashes.insert(name, hash);
+                }
+                t!("conflict-kept-as", names = renamed.join(" / "))
+            }
+            Resolution::Skip => {
+                unresolved += 1;
+                // Запись остается прежней, чтобы следующий запуск снова заметил конфликт
+                if let Some(entry) = previous.get(&conflict.path) {
+                    state.insert(conflict.path.clone(), Entry { hash: entry.hash.clone(), ..*entry });
+                }
+                t!("conflict-left-untouched")
+            }
+        };
+        eprintln!("{} {}: {}, {}", t!("conflict").yellow().bold(), conflict.path.display(), reason, outcome);
+    }
+
+    if plan.to_first.is_empty()
+        && plan.to_second.is_empty()
+        && plan.delete_first.is_empty()
+        && plan.delete_second.is_empty()
+    {
+        save(&state_path, &first, &second, &state)?;
+        if unresolved == 0 {
+            println!("{}", t!("already-in-sync").green());
+            return Ok(());
+        }
+        anyhow::bail!("{} conflicts were left untouched; resolve them and run rcp bisync again", unresolved);
+    }
+
+    remove_all(&second, &plan.delete_second)?;
+    remove_all(&first, &plan.delete_first)?;
+    // При неудачном копировании состояние не сохраняем: следующий запуск увидит уже
+    // скопированное одинаковым с обеих сторон и досинхронизирует остальное
+    copy(&first, &second, &plan.to_second)?;
+    copy(&second, &first, &plan.to_first)?;
+    // Источник записываем таким, каким он был до копирования: если его поменяли во время
+    // копирования, следующий запуск это заметит
+    for path in &plan.to_second {
+        if let Ok(metadata) = fs::symlink_metadata(second.join(path)) {
+            let hash = copied_hashes.remove(path).unwrap_or_default();
+            state.insert(path.clone(), Entry { first: first_files[path], second: FileState::of(&metadata), hash });
+        }
+    }
+    for path in &plan.to_first {
+        if let Ok(metadata) = fs::symlink_metadata(first.join(path)) {
+            let hash = copied_hashes.remove(path).unwrap_or_default();
+            state.insert(path.clone(), Entry { first: FileState::of(&metadata), second: second_files[path], hash });
+        }
+    }
+
+    save(&state_path, &first, &second, &state)?;
+    report(&plan, &first, &second);
+    if unresolved > 0 {
+        anyhow::bail!("{} conflicts were left untouched; resolve them and run rcp bisync again", unresolved);
+    }
+    Ok(())
+}
+
+// Удаление против изменения при newer, larger и rename-both всегда решается в пользу
+// изменения: так ничьи правки не теряются
+fn resolve(
+    conflict: &Conflict,
+    strategy: ConflictStrategy,
+    first: Option<&FileState>,
+    second: Option<&FileState>,
+) -> Result<Resolution> {
+    let ordering = match (strategy, first, second) {
+        (ConflictStrategy::Skip, ..) => return Ok(Resolution::Skip),
+        (ConflictStrategy::Prompt, ..) => return ask(conflict),
+        (_, None, _) => return Ok(Resolution::KeepSecond),
+        (_, _, None) => return Ok(Resolution::KeepFirst),
+        (ConflictStrategy::RenameBoth, ..) => return Ok(Resolution::RenameBoth),
+        (ConflictStrategy::Newer, Some(first), Some(second)) => first.modified.cmp(&second.modified),
+        (ConflictStrategy::Larger, Some(first), Some(second)) => first.size.cmp(&second.size),
+    };
+    Ok(match ordering {
+        Ordering::Greater => Resolution::KeepFirst,
+        Ordering::Less => Resolution::KeepSecond,
+        Ordering::Equal => Resolution::Skip,
+    })
+}
+
+// Без терминала спросить некого - конфликт остается
+fn ask(conflict: &Conflict) -> Result<Resolution> {
+    if !io::stdin().is_terminal() {
+        return Ok(Resolution::Skip);
+    }
+    let question = t!("conflict-question", path = conflict.path.display().to_string(), reason = conflict.reason());
+    loop {
+        match prompt(&question)?.to_lowercase().as_str() {
+            "a" => return Ok(Resolution::KeepFirst),
+            "b" => return Ok(Resolution::KeepSecond),
+            "r" => return Ok(Resolution::RenameBoth),
+            "s" | "" => return Ok(Resolution::Skip),
+            _ => {}
+        }
+    }
+}
+
+// Версия стороны переносится на другую; удаленная - удаляется и там
+fn keep(
+    to_other: &mut Vec<PathBuf>,
+    delete_other: &mut Vec<PathBuf>,
+    copied_hashes: &mut HashMap<PathBuf, String>,
+    path: &Path,
+    hash: Option<String>,
+) {
+    match hash {
+        Some(hash) => {
+            to_other.push(path.to_path_buf());
+            copied_hashes.insert(path.to_path_buf(), hash);
+        }
+        None => delete_other.push(path.to_path_buf()),
+    }
+}
+
+// `report.txt` -> `report.conflict-A-2026-10-15_093000.txt`
+fn conflict_name(path: &Path, side: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}.conflict-{}-{}", stem, side, now_name());
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+fn root(path: &Path) -> Result<PathBuf> {
+    if Address::parse(path)?.is_some() {
+        anyhow::bail!("rcp bisync works with local directories: {}", path.display());
+    }
+    let root = fs::canonicalize(path).with_context(|| format!("Directory does not exist: {}", path.display()))?;
+    if !root.is_dir() {
+        anyhow::bail!("Not a directory: {}", root.display());
+    }
+    Ok(root)
+}
+
+fn pair_id(first: &Path, second: &Path) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(first.as_os_str().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(second.as_os_str().as_bytes());
+    hasher.finalize().to_hex()[..16].to_string()
+}
+
+// Обычные файлы дерева по относительным путям; ссылки и прочее не синхронизируются
+fn walk(root: &Path) -> Result<HashMap<PathBuf, FileState>> {
+    let mut files = HashMap::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let dir = root.join(&relative);
+        for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read directory: {}", dir.display()))? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let path = relative.join(entry.file_name());
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.is_file() && !path.as_os_str().as_bytes().contains(&b'\n') {
+                files.insert(path, FileState::of(&metadata));
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Что сделала с файлом одна сторона с прошлого запуска. Файл с новым mtime, но прежним
+// содержимым изменившимся не считается
+fn change(root: &Path, path: &Path, current: Option<&FileState>, recorded: Option<(FileState, &String)>) -> Result<Change> {
+    Ok(match (current, recorded) {
+        (None, None) => Change::Absent,
+        (None, Some(_)) => Change::Deleted,
+        (Some(current), Some((recorded, _))) if *current == recorded => Change::Unchanged,
+        (Some(_), recorded) => {
+            let full = root.join(path);
+            let hash = hash_file(&full, ChecksumAlgorithm::Blake3)
+                .with_context(|| format!("Failed to read {}", full.display()))?;
+            match recorded {
+                Some((_, recorded)) if *recorded == hash => Change::Unchanged,
+                _ => Change::Modified(hash),
+            }
+        }
+    })
+}
+
+fn copy(from: &Path, to: &Path, paths: &[PathBuf]) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let mut copy: Vec<OsString> = vec!["rcp".into(), "--".into()];
+    copy.extend(paths.iter().map(|path| from.join(path).into_os_string()));
+    copy.push(to.as_os_str().to_owned());
+    let mut copy = Args::parse_from(copy);
+    copy.relative_to = Some(from.to_path_buf());
+    crate::run(copy)
+}
+
+// Вместе с файлами убираем опустевшие после них директории
+fn remove_all(root: &Path, paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        let full = root.join(path);
+        match fs::remove_file(&full) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", full.display())),
+        }
+        for parent in full.ancestors().skip(1).take_while(|parent| *parent != root) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Строка состояния: `размер<TAB>секунды<TAB>наносекунды<TAB>секунды<TAB>наносекунды<TAB>хеш<TAB>путь`,
+// mtime первой и второй стороны
+fn load(path: &Path) -> Result<HashMap<PathBuf, Entry>> {
+    let mut entries = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open bisync state: {}", path.display())),
+    };
+    for line in BufReader::new(file).split(b'\n') {
+        let line = line.with_context(|| format!("Failed to read bisync state: {}", path.display()))?;
+        if line.starts_with(b"#") {
+            continue;
+        }
+        let fields: Vec<&[u8]> = line.splitn(7, |&byte| byte == b'\t').collect();
+        let [size, first_seconds, first_nanoseconds, second_seconds, second_nanoseconds, hash, relative] = fields[..]
+        else {
+            continue;
+        };
+        let number = |field: &[u8]| std::str::from_utf8(field).ok().and_then(|text| text.parse::<i64>().ok());
+        let (Some(size), Some(first_seconds), Some(first_nanoseconds), Some(second_seconds), Some(second_nanoseconds)) = (
+            number(size),
+            number(first_seconds),
+            number(first_nanoseconds),
+            number(second_seconds),
+            number(second_nanoseconds),
+        ) else {
+            continue;
+        };
+        let size = size as u64;
+        entries.insert(
+            PathBuf::from(OsStr::from_bytes(relative)),
+            Entry {
+                first: FileState {
+                    size,
+                    modified: (first_seconds, first_nanoseconds),
+                },
+                second: FileState {
+                    size,
+                    modified: (second_seconds, second_nanoseconds),
+                },
+                hash: String::from_utf8_lossy(hash).into_owned(),
+            },
+        );
+    }
+    Ok(entries)
+}
+
+fn save(path: &Path, first: &Path, second: &Path, state: &HashMap<PathBuf, Entry>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let mut contents = Vec::new();
+    writeln!(contents, "# rcp bisync {} {}", first.display(), second.display())?;
+    for (relative, entry) in state {
+        write!(
+            contents,
+            "{}\t{}\t{}\t{}\t{}\t{}\t",
+            entry.first.size,
+            entry.first.modified.0,
+            entry.first.modified.1,
+            entry.second.modified.0,
+            entry.second.modified.1,
+            entry.hash
+        )?;
+        contents.extend_from_slice(relative.as_os_str().as_bytes());
+        contents.push(b'\n');
+    }
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, contents)
+        .and_then(|()| fs::rename(&temporary, path))
+        .with_context(|| format!("Failed to write bisync state: {}", path.display()))
+}
+
+fn report(plan: &Plan, first: &Path, second: &Path) {
+    for (root, paths) in [(first, &plan.delete_first), (second, &plan.delete_second)] {
+        for path in paths {
+            println!("{}", t!("removed", path = root.join(path).display().to_string()));
+        }
+    }
+    for (root, copied, removed) in [
+        (first, plan.to_first.len(), plan.delete_first.len()),
+        (second, plan.to_second.len(), plan.delete_second.len()),
+    ] {
+        println!("{}", t!("bisync-side-summary", root = root.display().to_string(), copied = copied, removed = removed));
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use indicatif::HumanBytes;
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::cli::Args;
+use crate::i18n::t;
+use crate::sftp::{shell_quote, RemoteLocation};
+use crate::snapshot::now_name;
+use crate::transport::Address;
+
+// Подтома, снимки и send/receive btrfs; сами операции выполняет утилита `btrfs` из btrfs-progs
+
+const BTRFS_SUPER_MAGIC: u64 = 0x9123_683e;
+// У корня любого подтома номер inode один и тот же
+const SUBVOLUME_ROOT_INODE: u64 = 256;
+const SNAPSHOTS_DIR: &str = ".rcp-snapshots";
+const SEND_DIR: &str = ".rcp-send";
+
+fn is_btrfs(path: &Path) -> io::Result<bool> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    // Тип f_type зависит от платформы
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_type as u64 == BTRFS_SUPER_MAGIC)
+}
+
+// Подтом, в котором лежит путь (или окажется, если его еще нет)
+pub fn subvolume_of(path: &Path) -> Result<Option<PathBuf>> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let existing = fs::canonicalize(existing).with_context(|| format!("Failed to resolve {}", existing.display()))?;
+    if !is_btrfs(&existing).with_context(|| format!("Failed to stat file system of {}", existing.display()))? {
+        return Ok(None);
+    }
+
+    let device = fs::metadata(&existing)?.dev();
+    for ancestor in existing.ancestors() {
+        let metadata = fs::metadata(ancestor)?;
+        if metadata.dev() != device {
+            break;
+        }
+        if metadata.ino() == SUBVOLUME_ROOT_INODE {
+            return Ok(Some(ancestor.to_path_buf()));
+        }
+    }
+    Ok(None)
+}
+
+// --pre-snapshot: снимок только для чтения в `.rcp-snapshots` внутри самого подтома - так он
+// всегда на той же файловой системе, а в следующие снимки вложенные подтома не попадают
+pub fn pre_snapshot(destination: &Path) -> Result<PathBuf> {
+    let subvolume = subvolume_of(destination)?
+        .with_context(|| format!("--pre-snapshot needs a destination on a btrfs subvolume: {}", destination.display()))?;
+    read_only_snapshot(&subvolume, SNAPSHOTS_DIR)
+}
+
+fn read_only_snapshot(subvolume: &Path, dir: &str) -> Result<PathBuf> {
+    let snapshots = subvolume.join(dir);
+    fs::create_dir_all(&snapshots).with_context(|| format!("Failed to create directory: {}", snapshots.display()))?;
+    let snapshot = snapshots.join(now_name());
+    btrfs(
+        Command::new("btrfs").args(["subvolume", "snapshot", "-r"]).arg(subvolume).arg(&snapshot),
+        &format!("Failed to snapshot {}", subvolume.display()),
+    )?;
+    Ok(snapshot)
+}
+
+fn btrfs(command: &mut Command, failure: &str) -> Result<()> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run btrfs, is btrfs-progs installed?")?;
+    if !output.status.success() {
+        anyhow::bail!("{}: {}", failure, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+// --btrfs-send: подтом передается потоком `btrfs send` в `btrfs receive` на назначении
+// (локально или через --rsh). Для потока нужен снимок только для чтения: он ложится в
+// `.rcp-send` внутри подтома-источника и остается там как основа следующей передачи, которая
+// шлет только отличия от него. В назначении каждая передача - отдельный подтом с именем снимка
+pub fn replicate(args: &Args) -> Result<()> {
+    let [source] = &args.sources[..] else {
+        anyhow::bail!("--btrfs-send takes exactly one source subvolume");
+    };
+    let source = fs::canonicalize(source).with_context(|| format!("Source path does not exist: {}", source.display()))?;
+    if subvolume_of(&source)?.as_deref() != Some(source.as_path()) {
+        anyhow::bail!("--btrfs-send needs a btrfs subvolume as the source: {}", source.display());
+    }
+    let receiver = match Address::parse(&args.destination)? {
+        None => Receiver::Local(args.destination.clone()),
+        Some(Address::Sftp(location)) => Receiver::Remote {
+            rsh: args.rsh.clone(),
+            location,
+        },
+        Some(_) => anyhow::bail!("--btrfs-send needs a local or ssh destination"),
+    };
+    receiver.prepare()?;
+
+    let earlier = previous_snapshots(&source)?;
+    let parent = earlier.iter().rev().find(|name| receiver.has(name)).cloned();
+    let snapshot = read_only_snapshot(&source, SEND_DIR)?;
+
+    let mut send = Command::new("btrfs");
+    send.arg("send");
+    if let Some(parent) = &parent {
+        send.arg("-p").arg(source.join(SEND_DIR).join(parent));
+        println!("{}", t!("btrfs-sending-changes", parent = parent.as_str(), receiver = receiver.to_string()));
+    } else {
+        println!("{}", t!("btrfs-sending", source = source.display().to_string(), receiver = receiver.to_string()));
+    }
+    let mut send = send
+        .arg(&snapshot)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to run btrfs, is btrfs-progs installed?")?;
+    let mut receive = receiver.command()?.stdin(Stdio::piped()).spawn().context("Failed to start btrfs receive")?;
+
+    let copied = io::copy(
+        &mut send.stdout.take().context("btrfs send has no output")?,
+        &mut receive.stdin.take().context("btrfs receive has no input")?,
+    );
+    let send_status = send.wait()?;
+    let receive_status = receive.wait()?;
+    if !send_status.success() || !receive_status.success() || copied.is_err() {
+        // Неудачный снимок не должен стать основой следующей передачи
+        let _ = btrfs(
+            Command::new("btrfs").args(["subvolume", "delete"]).arg(&snapshot),
+            "Failed to delete snapshot",
+        );
+        match copied {
+            Err(e) => return Err(e).context("Failed to pass the btrfs stream"),
+            Ok(_) if !send_status.success() => anyhow::bail!("btrfs send failed: {}", send_status),
+            Ok(_) => anyhow::bail!("btrfs receive failed: {}", receive_status),
+        }
+    }
+    let sent = copied?;
+
+    // Прежние снимки больше не нужны: основой следующей передачи будет новый
+    for name in earlier {
+        let old = source.join(SEND_DIR).join(&name);
+        if let Err(e) = btrfs(Command::new("btrfs").args(["subvolume", "delete"]).arg(&old), "Failed to delete snapshot") {
+            eprintln!("{} {:#}", t!("warning").yellow().bold(), e);
+        }
+    }
+    let name = snapshot.file_name().unwrap_or_default().to_string_lossy();
+    println!(
+        "{}",
+        t!("btrfs-sent", source = source.display().to_string(), name = name.as_ref(), size = HumanBytes(sent).to_string()).green()
+    );
+    Ok(())
+}
+
+// Имена прежних снимков от старых к новым
+fn previous_snapshots(source: &Path) -> Result<Vec<String>> {
+    let dir = source.join(SEND_DIR);
+    let mut names = Vec::new();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(names),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read directory: {}", dir.display())),
+    };
+    for entry in entries {
+        if let Some(name) = entry?.file_name().to_str() {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+enum Receiver {
+    Local(PathBuf),
+    Remote { rsh: String, location: RemoteLocation },
+}
+
+impl Receiver {
+    fn prepare(&self) -> Result<()> {
+        match self {
+            Receiver::Local(dir) => {
+                fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {}", dir.display()))
+            }
+            Receiver::Remote { .. } => Ok(()),
+        }
+    }
+
+    // Снимок уже принят раньше - от него можно слать отличия
+    fn has(&self, name: &str) -> bool {
+        match self {
+            Receiver::Local(dir) => dir.join(name).is_dir(),
+            Receiver::Remote { location, .. } => self
+                .remote(&format!("test -d {}", shell_quote(&location.path.join(name))))
+                .and_then(|mut command| Ok(command.stdout(Stdio::null()).stderr(Stdio::null()).status()?))
+                .is_ok_and(|status| status.success()),
+        }
+    }
+
+    fn command(&self) -> Result<Command> {
+        match self {
+            Receiver::Local(dir) => {
+                let mut command = Command::new("btrfs");
+                command.arg("receive").arg(dir);
+                Ok(command)
+            }
+            Receiver::Remote { location, .. } => self.remote(&format!(
+                "mkdir -p {path} && btrfs receive {path}",
+                path = shell_quote(&location.path)
+            )),
+        }
+    }
+
+    fn remote(&self, script: &str) -> Result<Command> {
+        let Receiver::Remote { rsh, location } = self else {
+            unreachable!("only remote receivers run commands over --rsh");
+        };
+        let mut parts = rsh.split_whitespace();
+        let program = parts.next().context("Empty remote shell command")?;
+        let mut command = Command::new(program);
+        command.args(parts).arg("--").arg(&location.host).arg(script);
+        Ok(command)
+    }
+}
+
+impl fmt::Display for Receiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Receiver::Local(dir) => write!(f, "{}", dir.display()),
+            Receiver::Remote { location, .. } => write!(f, "{}:{}", location.host, location.path.display()),
+        }
+    }
+}
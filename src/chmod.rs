@@ -0,0 +1,177 @@
+// Преобразования прав в стиле rsync --chmod: D755,F644,ug+X,o-w. Правило с D действует
+// только на директории, с F - только на файлы; без префикса - на все. Правила применяются
+// к правам копии по порядку
+
+#[derive(Clone, Debug)]
+pub struct ChmodRule {
+    target: Target,
+    change: Change,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    All,
+    Directories,
+    Files,
+}
+
+#[derive(Clone, Debug)]
+enum Change {
+    Octal(u32),
+    // Кому (маска битов u, g, o) и последовательность +/-/= с правами
+    Symbolic(u32, Vec<(char, Vec<char>)>),
+}
+
+const USER: u32 = 0o4700;
+const GROUP: u32 = 0o2070;
+const OTHER: u32 = 0o1007;
+
+// RULE[,RULE...]
+pub fn parse_chmod(text: &str) -> Result<Vec<ChmodRule>, String> {
+    text.split(',').map(parse_rule).collect()
+}
+
+fn parse_rule(rule: &str) -> Result<ChmodRule, String> {
+    let (target, rest) = match rule.as_bytes().first() {
+        Some(b'D') => (Target::Directories, &rule[1..]),
+        Some(b'F') => (Target::Files, &rule[1..]),
+        _ => (Target::All, rule),
+    };
+    if rest.is_empty() {
+        return Err(format!("empty permission rule: {}", rule));
+    }
+    if rest.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Ok(ChmodRule {
+            target,
+            change: Change::Octal(parse_mode(rest)?),
+        });
+    }
+
+    let who_end = rest.find(|c| !"ugoa".contains(c)).unwrap_or(rest.len());
+    let who = match rest[..who_end].chars().fold(0, |who, c| who | who_bits(c)) {
+        // Без u/g/o - всем, как a (umask, в отличие от chmod, не учитывается)
+        0 => USER | GROUP | OTHER,
+        who => who,
+    };
+    let mut operations: Vec<(char, Vec<char>)> = Vec::new();
+    for c in rest[who_end..].chars() {
+        match c {
+            '+' | '-' | '=' => operations.push((c, Vec::new())),
+            'r' | 'w' | 'x' | 'X' | 's' | 't' => match operations.last_mut() {
+                Some((_, permissions)) => permissions.push(c),
+                None => return Err(format!("expected +, - or = in {}", rule)),
+            },
+            _ => return Err(format!("invalid permission rule: {}", rule)),
+        }
+    }
+    if operations.is_empty() {
+        return Err(format!("expected +, - or = in {}", rule));
+    }
+    Ok(ChmodRule {
+        target,
+        change: Change::Symbolic(who, operations),
+    })
+}
+
+// Восьмеричные права, например 0640 или 2775
+pub fn parse_mode(text: &str) -> Result<u32, String> {
+    u32::from_str_radix(text, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid mode: {}", text))
+}
+
+fn who_bits(c: char) -> u32 {
+    match c {
+        'u' => USER,
+        'g' => GROUP,
+        'o' => OTHER,
+        _ => USER | GROUP | OTHER,
+    }
+}
+
+// Права (без типа файла) после всех подходящих правил
+pub fn apply_chmod(rules: &[ChmodRule], mut mode: u32, is_dir: bool) -> u32 {
+    for rule in rules {
+        match rule.target {
+            Target::Directories if !is_dir => continue,
+            Target::Files if is_dir => continue,
+            _ => {}
+        }
+        mode = match &rule.change {
+            Change::Octal(octal) => *octal,
+            Change::Symbolic(who, operations) => operations.iter().fold(mode, |mode, (operation, permissions)| {
+                let bits = permissions
+                    .iter()
+                    .map(|permission| permission_bits(*permission, mode, is_dir))
+                    .fold(0, |bits, permission| bits | permission)
+                    & who;
+                match operation {
+                    '+' => mode | bits,
+                    '-' => mode & !bits,
+                    // = сбрасывает права этих u/g/o; setuid и setgid директорий остаются, как в chmod
+                    _ => (mode & !(who & if is_dir { 0o1777 } else { 0o7777 })) | bits,
+                }
+            }),
+        };
+    }
+    mode & 0o7777
+}
+
+// Биты права для u, g и o сразу; лишнее отсекает маска "кому"
+fn permission_bits(permission: char, mode: u32, is_dir: bool) -> u32 {
+    match permission {
+        'r' => 0o444,
+        'w' => 0o222,
+        'x' => 0o111,
+        // Исполнение только директориям и тому, что уже кем-то исполняется
+        'X' if is_dir || mode & 0o111 != 0 => 0o111,
+        's' => 0o6000,
+        't' => 0o1000,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(rules: &str, mode: u32, is_dir: bool) -> u32 {
+        apply_chmod(&parse_chmod(rules).unwrap(), mode, is_dir)
+    }
+
+    #[test]
+    fn octal_rules_apply_by_target() {
+        assert_eq!(apply("D755,F644", 0o700, true), 0o755);
+        assert_eq!(apply("D755,F644", 0o600, false), 0o644);
+        assert_eq!(apply("2775", 0o600, true), 0o2775);
+    }
+
+    #[test]
+    fn symbolic_rules_apply_in_order() {
+        assert_eq!(apply("o-w", 0o666, false), 0o664);
+        assert_eq!(apply("go=r", 0o777, false), 0o744);
+        assert_eq!(apply("a-x,u+x", 0o755, false), 0o744);
+        assert_eq!(apply("u+w-x", 0o555, false), 0o655);
+        assert_eq!(apply("u+s", 0o755, false), 0o4755);
+        // Без u/g/o - всем, включая sticky
+        assert_eq!(apply("+t", 0o777, true), 0o1777);
+        // = не снимает setgid директории
+        assert_eq!(apply("g=rx", 0o2775, true), 0o2755);
+    }
+
+    #[test]
+    fn capital_x_only_for_directories_and_executables() {
+        assert_eq!(apply("ug+X", 0o644, false), 0o644);
+        assert_eq!(apply("ug+X", 0o744, false), 0o754);
+        assert_eq!(apply("ug+X", 0o600, true), 0o710);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for rules in ["", "D", "F", "D755,,F644", "u", "urw", "x+r", "q+r", "u+q", "999", "17777"] {
+            assert!(parse_chmod(rules).is_err(), "{:?}", rules);
+        }
+        assert_eq!(parse_mode("0640"), Ok(0o640));
+    }
+}
@@ -0,0 +1,885 @@
+use clap::{Command, CommandFactory, Parser, ValueEnum};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::archive::ArchiveFormat;
+use crate::compression::FileCompression;
+use crate::chmod::{parse_chmod, parse_mode, ChmodRule};
+use crate::owner::{parse_groupmap, parse_usermap, Chown, IdRule};
+use crate::progress::DEFAULT_REFRESH_RATE;
+use crate::schedule::Schedule;
+use crate::tls::Psk;
+
+#[derive(Parser, Debug)]
+#[command(name = "rcp", version, about = "Parallel file copy with progress bars")]
+pub struct Args {
+    /// Source files or directories; quoted glob patterns are expanded by rcp. A first source
+    /// named like a subcommand (`serve`, `du`, ...) needs `--` before it: `rcp -- serve dest`
+    #[arg(required = true)]
+    pub sources: Vec<PathBuf>,
+
+    /// Destination file or directory
+    pub destination: PathBuf,
+
+    /// Also write every file to this destination (repeatable); sources are read once
+    #[arg(long, value_name = "DEST")]
+    pub also_to: Vec<PathBuf>,
+
+    /// Re-read every written file and compare it with the checksum taken while copying
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Flush the data of every copied local file to disk and, after copying, fsync every local destination
+    /// directory that got new entries, so the copied files and their names survive a crash
+    #[arg(long)]
+    pub fsync_dirs: bool,
+
+    /// Write a checksum manifest (`<hash>  <path>` lines) of the copied files
+    #[arg(long, value_name = "FILE")]
+    pub write_manifest: Option<PathBuf>,
+
+    /// Write the destination paths of successfully copied files to FILE, one per line
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["to_archive", "extract"])]
+    pub log_copied: Option<PathBuf>,
+
+    /// Separate the --log-copied paths with NUL instead of newlines, e.g. for `xargs -0`
+    #[arg(long, requires = "log_copied")]
+    pub log_copied0: bool,
+
+    /// Where to write the JSON status of a run that fails, is interrupted or leaves files uncopied [default: ~/.local/state/rcp/failure.json]
+    #[arg(long, value_name = "FILE")]
+    pub failure_report: Option<PathBuf>,
+
+    /// Copy even if another rcp holds the lock on the destination
+    #[arg(long, conflicts_with = "wait_for_lock")]
+    pub no_lock: bool,
+
+    /// Wait for another rcp copying into the same destination to finish instead of failing
+    #[arg(long)]
+    pub wait_for_lock: bool,
+
+    /// After the copy, print totals and a histogram of file sizes
+    #[arg(long)]
+    pub stats: bool,
+
+    /// After the copy, list the N files that took longest and the N with the lowest throughput
+    #[arg(long, value_name = "N")]
+    pub slowest: Option<usize>,
+
+    /// Checksum algorithm for --verify and --write-manifest
+    #[arg(long, value_enum, default_value_t = ChecksumAlgorithm::Blake3)]
+    pub checksum: ChecksumAlgorithm,
+
+    /// Pack the copy set into an archive at DESTINATION (`-` for stdout): tar, tar:zstd, zip, cpio or cpio:zstd
+    #[arg(long, value_name = "FORMAT", value_parser = str::parse::<ArchiveFormat>, conflicts_with_all = ["also_to", "verify"])]
+    pub to_archive: Option<ArchiveFormat>,
+
+    /// Compress each destination file and append the matching suffix: zstd, gzip, xz or with a level, e.g. zstd:19
+    #[arg(long, value_name = "ALGORITHM[:LEVEL]", value_parser = str::parse::<FileCompression>, conflicts_with_all = ["to_archive", "verify", "write_manifest"])]
+    pub compress: Option<FileCompression>,
+
+    /// Encrypt each destination file to an age recipient (age1...) and append .age; may be repeated
+    #[arg(long, value_name = "RECIPIENT", value_parser = str::parse::<age::x25519::Recipient>, conflicts_with_all = ["to_archive", "verify", "write_manifest"])]
+    pub encrypt_to: Vec<age::x25519::Recipient>,
+
+    /// Encrypt each destination file to an OpenPGP key via gpg and append .gpg; may be repeated
+    #[arg(long, value_name = "KEYID", conflicts_with_all = ["encrypt_to", "to_archive", "verify", "write_manifest"])]
+    pub encrypt_gpg: Vec<String>,
+
+    /// Carry these source attributes over to the copies (comma-separated)
+    #[arg(long, value_enum, value_name = "ATTR,...", value_delimiter = ',', conflicts_with_all = ["to_archive", "extract"])]
+    pub preserve: Vec<Preserve>,
+
+    /// Without root, keep the preserved owner, mode, flags, creation time and device numbers in user.rcp.* xattrs of the copies; as
+    /// root, or when copying such copies back, take them from those xattrs
+    #[arg(long, requires = "preserve")]
+    pub fake_super: bool,
+
+    /// Change the permissions of the copies with rsync-style rules: D755,F644,ug+X,o-w (D - directories only,
+    /// F - files only); applied after --preserve mode, --dest-mode and --dest-dir-mode
+    #[arg(long, value_name = "RULE,...", value_parser = parse_chmod, conflicts_with_all = ["to_archive", "extract"])]
+    pub chmod: Vec<Vec<ChmodRule>>,
+
+    /// Give copied files these permissions (octal) instead of what the umask leaves
+    #[arg(long, value_name = "MODE", value_parser = parse_mode, conflicts_with_all = ["to_archive", "extract"])]
+    pub dest_mode: Option<u32>,
+
+    /// Give copied directories these permissions (octal), e.g. 2775 for a shared group directory
+    #[arg(long, value_name = "MODE", value_parser = parse_mode, conflicts_with_all = ["to_archive", "extract"])]
+    pub dest_dir_mode: Option<u32>,
+
+    /// Give every copied file, directory and symlink this owner: USER:GROUP, USER or :GROUP (names or IDs)
+    #[arg(long, value_name = "USER:GROUP", value_parser = str::parse::<Chown>, conflicts_with_all = ["to_archive", "extract"])]
+    pub chown: Option<Chown>,
+
+    /// Keep the source owner, translated by FROM:TO rules (name, glob, ID or ID range N-M to a name or ID)
+    #[arg(long, value_name = "FROM:TO,...", value_parser = parse_usermap, conflicts_with_all = ["to_archive", "extract"])]
+    pub usermap: Vec<Vec<IdRule>>,
+
+    /// Keep the source group, translated by FROM:TO rules like --usermap
+    #[arg(long, value_name = "FROM:TO,...", value_parser = parse_groupmap, conflicts_with_all = ["to_archive", "extract"])]
+    pub groupmap: Vec<Vec<IdRule>>,
+
+    /// Carry owners and groups over as numeric IDs. By default a --fake-super copy records the user and group
+    /// names too, and copying it on another system gives the files the IDs those names have there
+    #[arg(long, conflicts_with_all = ["to_archive", "extract"])]
+    pub numeric_ids: bool,
+
+    /// Decompress .gz/.zst/.xz sources and write them without the suffix
+    #[arg(long, conflicts_with_all = ["to_archive", "extract"])]
+    pub decompress: bool,
+
+    /// Remote shell used to reach `[user@]host:path` sources and destinations over SFTP
+    #[arg(short = 'e', long, value_name = "COMMAND", default_value = "ssh", env = "RCP_RSH")]
+    pub rsh: String,
+
+    /// CA certificate (PEM) that an rcp:// or ftps:// server's TLS certificate must chain to
+    #[arg(long, value_name = "FILE")]
+    pub tls_ca: Option<PathBuf>,
+
+    /// Client certificate (PEM) for rcp:// servers that require one
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) for --tls-cert
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// File with a pre-shared key for TLS to an rcp:// server; the server need not have a CA-signed certificate
+    #[arg(long, value_name = "FILE")]
+    pub psk_file: Option<PathBuf>,
+
+    /// Talk to rcp:// servers over QUIC (UDP) instead of TCP; the server must run with --quic
+    #[arg(long)]
+    pub quic: bool,
+
+    /// Transfer files of 64 MiB and more as this many ranges over separate connections: uploads to
+    /// rcp:// servers and downloads from http(s):// URLs. Local files of --split-above and more are copied as
+    /// this many ranges in parallel threads
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=64))]
+    pub streams_per_file: u16,
+
+    /// Copy local files of this size and more in --streams-per-file ranges at once, so one huge file can use the
+    /// full bandwidth of a fast array
+    #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+    pub split_above: u64,
+
+    /// Start writing big copies out to disk every SIZE and wait for the previous SIZE, so dirty pages don't
+    /// pile up and the end of a file doesn't stall at 100%; 0 turns it off
+    #[arg(long, value_name = "SIZE", default_value = "16M", value_parser = parse_size)]
+    pub writeback_every: u64,
+
+    /// Drop each copied file from the page cache once it is done, so a big backup doesn't push the working set
+    /// of other programs out of memory; `both` also drops the source after reading
+    #[arg(long, value_name = "WHERE", value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "destination")]
+    pub drop_cache: Option<DropCache>,
+
+    /// Read and write local files copied as they are with O_DIRECT, past the page cache, through aligned
+    /// buffers (huge-page backed from 2M), also when they are split (--split-above); file systems without
+    /// O_DIRECT fall back to normal I/O
+    #[arg(long)]
+    pub direct_io: bool,
+
+    /// Compress data sent to rcp:// servers with zstd, skipping files that are already compressed
+    #[arg(long)]
+    pub wire_compress: bool,
+
+    /// Send only the changed blocks of files that already exist on the rcp:// server
+    #[arg(long)]
+    pub delta: bool,
+
+    /// ID of a resumable rcp:// transfer: rerunning with the same ID skips files already copied and continues partial ones
+    #[arg(long, value_name = "ID")]
+    pub session: Option<String>,
+
+    /// Service account key (JSON) for gs:// destinations
+    #[arg(long, value_name = "FILE", env = "GOOGLE_APPLICATION_CREDENTIALS")]
+    pub gcs_credentials: Option<PathBuf>,
+
+    /// Storage account for az:// destinations
+    #[arg(long, value_name = "NAME", env = "AZURE_STORAGE_ACCOUNT")]
+    pub azure_account: Option<String>,
+
+    /// Blob service URL for az:// destinations instead of the account's (e.g. an Azurite emulator)
+    #[arg(long, value_name = "URL", env = "AZURE_STORAGE_BLOB_ENDPOINT")]
+    pub azure_endpoint: Option<String>,
+
+    /// SAS token for az:// destinations (needs write permission on the container)
+    #[arg(long, value_name = "TOKEN", env = "AZURE_STORAGE_SAS_TOKEN", hide_env_values = true)]
+    pub azure_sas: Option<String>,
+
+    /// User for dav:// and davs:// sources and destinations (a user in the URL takes precedence)
+    #[arg(long, value_name = "USER", env = "RCP_DAV_USER")]
+    pub dav_user: Option<String>,
+
+    /// Password for dav:// and davs:// (e.g. a Nextcloud app password)
+    #[arg(long, value_name = "PASSWORD", env = "RCP_DAV_PASSWORD", hide_env_values = true)]
+    pub dav_password: Option<String>,
+
+    /// Password for ftp:// and ftps:// when it is not in the URL
+    #[arg(long, value_name = "PASSWORD", env = "RCP_FTP_PASSWORD", hide_env_values = true)]
+    pub ftp_password: Option<String>,
+
+    /// Treat each source as a tar/zip/cpio archive (`-` for stdin) and extract it into DESTINATION
+    #[arg(long, conflicts_with_all = ["to_archive", "also_to"])]
+    pub extract: bool,
+
+    /// How a trailing slash on a source directory is interpreted
+    #[arg(long, value_enum, default_value_t = TrailingSlash::Rsync)]
+    pub trailing_slash: TrailingSlash,
+
+    /// What to do when the destination lacks free space for the copy set
+    #[arg(long, value_enum, default_value_t = SpaceCheck::Refuse)]
+    pub space_check: SpaceCheck,
+
+    /// Stop dispatching files once this many bytes are planned (e.g. 500M, 20G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_total_bytes: Option<u64>,
+
+    /// Stop dispatching files once this many are planned
+    #[arg(long, value_name = "COUNT")]
+    pub max_files: Option<usize>,
+
+    /// Copy files matching this glob first, before --max-files/--max-total-bytes apply (repeatable, earlier
+    /// patterns go first); a pattern without `/` matches the file name
+    #[arg(long, value_name = "GLOB", value_parser = str::parse::<glob::Pattern>)]
+    pub prioritize: Vec<glob::Pattern>,
+
+    /// Fail a file whose transfer makes no progress for this many seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub file_timeout: Option<u64>,
+
+    /// Copy a file again from the start, up to N times, when a network file system (NFS) reports a stale file handle
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retry_stale: usize,
+
+    /// Descend into virtual filesystems such as /proc, /sys, /dev and /run
+    #[arg(long)]
+    pub include_pseudo_fs: bool,
+
+    /// Hard-link files that are unchanged (same size and modification time) in this earlier copy of the
+    /// destination instead of copying them; may be repeated. Relative paths are taken from the current
+    /// directory, unlike rsync. Copied files keep the source modification time so the next run can link them
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["to_archive", "extract", "also_to", "compress", "decompress", "encrypt_to", "encrypt_gpg", "compare_dest", "copy_dest"])]
+    pub link_dest: Vec<PathBuf>,
+
+    /// Skip files that are unchanged in this directory laid out like the destination; may be repeated
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["to_archive", "extract", "also_to", "compress", "decompress", "encrypt_to", "encrypt_gpg", "copy_dest"])]
+    pub compare_dest: Vec<PathBuf>,
+
+    /// Copy files that are unchanged in this local directory laid out like the destination from there
+    /// instead of transferring them from the source; may be repeated
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["to_archive", "extract", "also_to", "compress", "decompress", "encrypt_to", "encrypt_gpg"])]
+    pub copy_dest: Vec<PathBuf>,
+
+    /// Treat modification times that differ by at most this many seconds as equal when looking for unchanged
+    /// files, e.g. 2 for FAT or 1 for NFS servers that round times
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    pub modify_window: u64,
+
+    /// Before copying a file, look for a file with the same content anywhere in the local destination and
+    /// hard-link (default) or reflink it instead of writing the bytes again
+    #[arg(long, value_name = "MODE", value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "hardlink", conflicts_with_all = ["to_archive", "extract", "also_to", "compress", "decompress", "encrypt_to", "encrypt_gpg"])]
+    pub dedup_dest: Option<DedupMode>,
+
+    /// Copy identical files of the copy set once and make the others hard links (default) or reflinks to
+    /// that copy
+    #[arg(long, value_name = "MODE", value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "hardlink", conflicts_with_all = ["to_archive", "extract", "also_to", "compress", "decompress", "encrypt_to", "encrypt_gpg"])]
+    pub dedup: Option<DedupMode>,
+
+    /// After copying, let the file system share the blocks of each copied file with an identical file already
+    /// in the destination (FIDEDUPERANGE on btrfs and XFS)
+    #[arg(long, conflicts_with_all = ["to_archive", "extract", "also_to"])]
+    pub dedupe_blocks: bool,
+
+    /// Before writing into a btrfs subvolume, take a read-only snapshot of it in `.rcp-snapshots` inside the
+    /// subvolume so that a bad sync can be rolled back
+    #[arg(long, conflicts_with_all = ["to_archive", "extract"])]
+    pub pre_snapshot: bool,
+
+    /// Replicate a btrfs subvolume with `btrfs send` / `btrfs receive` instead of copying files; later runs
+    /// send only the changes since the previous one. The destination is a local or ssh directory on btrfs
+    #[arg(long, conflicts_with_all = ["to_archive", "extract", "also_to", "compress", "decompress", "encrypt_to", "encrypt_gpg", "link_dest", "compare_dest", "copy_dest", "dedup", "dedup_dest", "dedupe_blocks", "pre_snapshot"])]
+    pub btrfs_send: bool,
+
+    /// Keep the destination file hashes for --dedup-dest and --dedupe-blocks in this file between runs
+    #[arg(long, value_name = "FILE")]
+    pub dedup_cache: Option<PathBuf>,
+
+    /// How many files to copy at the same time. By default it depends on the local disks involved: 16 on NVMe, 8 on
+    /// other SSDs, 4 with a spinning disk and 10 otherwise
+    #[arg(short, long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1024))]
+    pub jobs: Option<usize>,
+
+    /// Copy at most this many files at a time on one spinning disk (HDD), whether it is read or written; set it
+    /// to --jobs to turn the limit off
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1024))]
+    pub jobs_per_hdd: usize,
+
+    /// Read and write files in blocks of SIZE, a power of two from 4K to 64M, instead of the size picked for
+    /// the disks involved (64 KiB to 1 MiB); `rcp bench` helps to choose
+    #[arg(long, value_name = "SIZE", value_parser = parse_buffer_size)]
+    pub buffer_size: Option<u64>,
+
+    /// Keep the copy buffers of all files together within SIZE (e.g. 256M): buffers shrink first, down to
+    /// 16 KiB, then fewer files are copied at a time
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_memory: Option<u64>,
+
+    /// Use the options of a [profile.NAME] section in config.toml; a profile that sets `source` and
+    /// `destination` needs no paths on the command line
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Keep running and repeat the copy on a schedule: an interval between runs (30s, 15m, 2h, 1d) or a
+    /// five-field cron line in local time, e.g. "0 3 * * 1-5"
+    #[arg(long, value_name = "SCHEDULE", value_parser = str::parse::<Schedule>)]
+    pub interval: Option<Schedule>,
+
+    /// Full-screen view of queued and running files with keys to pause, skip and reorder them
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Export progress on the session D-Bus (org.rcp.Transfer1) for desktop progress dialogs, and as a KDE job when available
+    #[arg(long)]
+    pub dbus: bool,
+
+    /// Limit the combined transfer rate to this many bytes per second (e.g. 10M); `+` and `-`
+    /// adjust it while copying
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    pub bwlimit: Option<u64>,
+
+    /// Replace a progress bar template with one in indicatif syntax: `file=TEMPLATE` for the
+    /// per-file bars (msg is the path), `worker=TEMPLATE` for --worker-bars, `overall=TEMPLATE`
+    /// for the total; may be repeated
+    #[arg(long, value_name = "KIND=TEMPLATE", value_parser = parse_bar_template)]
+    pub bar_style: Vec<BarTemplate>,
+
+    /// Draw progress bars with ASCII characters only, for terminals and fonts without block glyphs
+    #[arg(long)]
+    pub ascii: bool,
+
+    /// Print a line for every copied file (path, size, time, rate) above the progress bars
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Show one bar per worker (current file, bytes so far, rate) instead of one per file; easier to
+    /// follow when many small files fly by
+    #[arg(long, conflicts_with = "tui")]
+    pub worker_bars: bool,
+
+    /// How many times per second progress bars are redrawn; lower it for slow terminals and serial consoles
+    #[arg(long, value_name = "HZ", default_value_t = DEFAULT_REFRESH_RATE, value_parser = clap::value_parser!(u8).range(1..))]
+    pub refresh_rate: u8,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    // Ключ из кода подтверждения `rcp send`; в командной строке не задается
+    #[arg(skip)]
+    pub pairing: Option<Psk>,
+
+    // `rcp watch`: источники ложатся в назначение по пути от этой директории
+    #[arg(skip)]
+    pub relative_to: Option<PathBuf>,
+}
+
+// `rcp serve`: прием файлов от других экземпляров rcp по сети
+#[derive(Parser, Debug)]
+#[command(name = "rcp serve", bin_name = "rcp serve", version, about = "Receive files from rcp clients over TCP (rcp://host/path destinations)")]
+pub struct ServeArgs {
+    /// Directory that client paths are resolved against
+    pub root: PathBuf,
+
+    /// Address to listen on; only this machine by default, use e.g. 0.0.0.0:7300 to serve the network
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7300")]
+    pub listen: String,
+
+    /// Let clients create symlinks that point outside the served directory (absolute or climbing out with ..)
+    #[arg(long)]
+    pub allow_escaping_links: bool,
+
+    /// Server certificate chain (PEM); enables TLS
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) for --tls-cert
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Require client certificates signed by this CA (PEM)
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Require clients to prove this pre-shared key; enables TLS with a temporary self-signed certificate if no --tls-cert is given
+    #[arg(long, value_name = "FILE")]
+    pub psk_file: Option<PathBuf>,
+
+    /// Also accept QUIC connections on the same address over UDP; always encrypted
+    #[arg(long)]
+    pub quic: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp send` / `rcp receive`: передача между машинами одной сети без настройки
+#[derive(Parser, Debug)]
+#[command(name = "rcp send", bin_name = "rcp send", version, about = "Send files to a nearby `rcp receive` found over mDNS")]
+pub struct SendArgs {
+    /// Files or directories to send
+    #[arg(required = true)]
+    pub sources: Vec<PathBuf>,
+
+    /// Name of the receiver to send to when several are waiting
+    #[arg(long, value_name = "NAME")]
+    pub to: Option<String>,
+
+    /// Confirmation code shown by the receiver; asked for interactively if omitted. With --relay, the code
+    /// phrase to use instead of a random one
+    #[arg(long, value_name = "CODE", env = "RCP_CODE")]
+    pub code: Option<String>,
+
+    /// Pair through an `rcp relay` instead of mDNS: print a code phrase for `rcp receive --relay` to type;
+    /// files go directly when the receiver is reachable and through the relay otherwise
+    #[arg(long, value_name = "HOST[:PORT]", env = "RCP_RELAY", conflicts_with = "to")]
+    pub relay: Option<String>,
+
+    /// How long to look for receivers on the local network
+    #[arg(long, value_name = "SECS", default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub discover_timeout: u64,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "rcp receive", bin_name = "rcp receive", version, about = "Wait for files from `rcp send` on the local network")]
+pub struct ReceiveArgs {
+    /// Directory to store received files in
+    #[arg(default_value = ".")]
+    pub directory: PathBuf,
+
+    /// Name shown to senders; defaults to the host name
+    #[arg(long, value_name = "NAME", conflicts_with = "relay")]
+    pub name: Option<String>,
+
+    /// Pair through an `rcp relay` with the code phrase printed by `rcp send --relay`
+    #[arg(long, value_name = "HOST[:PORT]", env = "RCP_RELAY")]
+    pub relay: Option<String>,
+
+    /// Code phrase printed by `rcp send --relay`; asked for interactively if omitted
+    #[arg(long, value_name = "CODE", requires = "relay")]
+    pub code: Option<String>,
+
+    /// Address to listen on; the port is random by default
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:0")]
+    pub listen: String,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp relay`: точка встречи для `rcp send --relay` без прямого маршрута между машинами
+#[derive(Parser, Debug)]
+#[command(name = "rcp relay", bin_name = "rcp relay", version, about = "Connect `rcp send --relay` and `rcp receive --relay` that cannot reach each other")]
+pub struct RelayArgs {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:7301")]
+    pub listen: String,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp snapshot`: датированные резервные копии со ссылками на прошлую
+#[derive(Parser, Debug)]
+#[command(name = "rcp snapshot", bin_name = "rcp snapshot", version, about = "Make a dated snapshot that hard-links unchanged files to the previous one")]
+pub struct SnapshotArgs {
+    /// Files or directories to back up
+    #[arg(required = true)]
+    pub sources: Vec<PathBuf>,
+
+    /// Local directory that holds the snapshots
+    pub backup_root: PathBuf,
+
+    /// Keep the newest snapshot of each of the last N days that have snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_daily: Option<usize>,
+
+    /// Keep the newest snapshot of each of the last N weeks that have snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_weekly: Option<usize>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp watch`: зеркало директории, которое обновляется по мере изменений
+#[derive(Parser, Debug)]
+#[command(name = "rcp watch", bin_name = "rcp watch", version, about = "Copy a directory, then keep mirroring its changes to the destination")]
+pub struct WatchArgs {
+    /// Local directory to watch; its contents are mirrored
+    pub source: PathBuf,
+
+    /// Destination directory; deletions are mirrored only when it is local
+    pub destination: PathBuf,
+
+    /// Wait until the source has been quiet this long before syncing a batch of changes
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    pub debounce: u64,
+
+    /// Remote shell used to reach `[user@]host:path` destinations over SFTP
+    #[arg(short = 'e', long, value_name = "COMMAND", default_value = "ssh", env = "RCP_RSH")]
+    pub rsh: String,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp bisync`: двусторонняя синхронизация двух директорий
+#[derive(Parser, Debug)]
+#[command(name = "rcp bisync", bin_name = "rcp bisync", version, about = "Sync two directories both ways, flagging files changed on both sides")]
+pub struct BisyncArgs {
+    /// First local directory
+    #[arg(value_name = "A")]
+    pub first: PathBuf,
+
+    /// Second local directory
+    #[arg(value_name = "B")]
+    pub second: PathBuf,
+
+    /// How to settle files changed on both sides; renamed copies get a `.conflict-A-<time>` suffix
+    #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = ConflictStrategy::Skip)]
+    pub conflict: ConflictStrategy,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp completions`: скрипт автодополнения для оболочки
+#[derive(Parser, Debug)]
+#[command(name = "rcp completions", bin_name = "rcp completions", version, about = "Print a shell completion script for rcp and its subcommands")]
+pub struct CompletionsArgs {
+    /// Shell to complete in
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+// `rcp man`: справочник опций в формате man
+#[derive(Parser, Debug)]
+#[command(name = "rcp man", bin_name = "rcp man", version, about = "Print the rcp(1) man page, or write pages for rcp and every subcommand")]
+pub struct ManArgs {
+    /// Write rcp.1 and rcp-<subcommand>.1 into this directory instead of printing rcp.1
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+}
+
+// `rcp bench`: одна и та же копия с разными буферами и числом потоков
+#[derive(Parser, Debug)]
+#[command(name = "rcp bench", bin_name = "rcp bench", version, about = "Time copies of a test workload with different --jobs and --buffer-size and compare them")]
+pub struct BenchArgs {
+    /// Copy a sample of this directory instead of generated files
+    pub source: Option<PathBuf>,
+
+    /// Where to put the workload and the copies; use the disk you are going to copy to
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub dir: PathBuf,
+
+    /// Size of the sample taken from SOURCE
+    #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+    pub sample: u64,
+
+    /// Number of generated small files
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    pub files: usize,
+
+    /// Size of each generated small file
+    #[arg(long, value_name = "SIZE", default_value = "256K", value_parser = parse_size)]
+    pub file_size: u64,
+
+    /// Size of one generated large file next to the small ones; 0 for none
+    #[arg(long, value_name = "SIZE", default_value = "256M", value_parser = parse_size)]
+    pub large_file: u64,
+
+    /// Numbers of files copied at the same time to compare
+    #[arg(long, value_name = "N,...", value_delimiter = ',', default_value = "1,4,16", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1024))]
+    pub jobs: Vec<usize>,
+
+    /// Buffer sizes to compare
+    #[arg(long, value_name = "SIZE,...", value_delimiter = ',', default_value = "64K,1M", value_parser = parse_buffer_size)]
+    pub buffer_sizes: Vec<u64>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp doctor`: что умеют файловые системы источника и назначения
+#[derive(Parser, Debug)]
+#[command(name = "rcp doctor", bin_name = "rcp doctor", version, about = "Probe the source and destination file systems and tell which --preserve and optimization flags will work")]
+pub struct DoctorArgs {
+    /// Local source directory
+    pub source: PathBuf,
+
+    /// Local destination directory; one that does not exist yet is probed at its nearest existing parent
+    pub destination: PathBuf,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp du`: что скопирует копирование с теми же источниками и ограничениями
+#[derive(Parser, Debug)]
+#[command(name = "rcp du", bin_name = "rcp du", version, about = "List the files a copy of SOURCES would take, with sizes and totals, without copying anything")]
+pub struct DuArgs {
+    /// Local source files or directories, as they would be given to rcp
+    #[arg(required = true)]
+    pub sources: Vec<PathBuf>,
+
+    /// How a trailing slash on a source directory is interpreted
+    #[arg(long, value_enum, default_value_t = TrailingSlash::Rsync)]
+    pub trailing_slash: TrailingSlash,
+
+    /// Descend into virtual filesystems such as /proc, /sys, /dev and /run
+    #[arg(long)]
+    pub include_pseudo_fs: bool,
+
+    /// Leave out files once this many bytes are planned, like the copy would
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_total_bytes: Option<u64>,
+
+    /// Leave out files once this many are planned, like the copy would
+    #[arg(long, value_name = "COUNT")]
+    pub max_files: Option<usize>,
+
+    /// Take files matching this glob first, before --max-files/--max-total-bytes apply (repeatable)
+    #[arg(long, value_name = "GLOB", value_parser = str::parse::<glob::Pattern>)]
+    pub prioritize: Vec<glob::Pattern>,
+
+    /// Show directories as a tree with the size of everything under each
+    #[arg(long, conflicts_with = "json")]
+    pub tree: bool,
+
+    /// Print the files and totals as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// `rcp daemon`: очередь заданий копирования в фоне
+#[derive(Parser, Debug)]
+#[command(name = "rcp daemon", bin_name = "rcp daemon", version, about = "Run copy jobs queued with `rcp submit`, by default one at a time")]
+pub struct DaemonArgs {
+    /// How many jobs to run at the same time
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=64))]
+    pub max_jobs: usize,
+}
+
+// `rcp submit`: задание для `rcp daemon`
+#[derive(Parser, Debug)]
+#[command(name = "rcp submit", bin_name = "rcp submit", version, about = "Queue a copy job with `rcp daemon`, starting the daemon if it is not running")]
+pub struct SubmitArgs {
+    /// Jobs with a higher priority run first; one above every running job starts even when --max-jobs are busy
+    #[arg(long, value_name = "N", default_value_t = 0, allow_negative_numbers = true)]
+    pub priority: i32,
+
+    /// Options and paths of the copy, as they would follow `rcp`
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "ARGS")]
+    pub args: Vec<OsString>,
+}
+
+// `rcp list`: задания `rcp daemon`
+#[derive(Parser, Debug)]
+#[command(name = "rcp list", bin_name = "rcp list", version, about = "List queued, running and finished jobs of `rcp daemon`")]
+pub struct ListArgs {}
+
+// `rcp cancel`: снять задание с очереди или остановить
+#[derive(Parser, Debug)]
+#[command(name = "rcp cancel", bin_name = "rcp cancel", version, about = "Remove a queued job or stop a running one")]
+pub struct CancelArgs {
+    /// Job ID printed by `rcp submit`
+    pub id: u64,
+}
+
+// `rcp attach`: вывод задания
+#[derive(Parser, Debug)]
+#[command(name = "rcp attach", bin_name = "rcp attach", version, about = "Print the output of a job and follow it until the job ends")]
+pub struct AttachArgs {
+    /// Job ID printed by `rcp submit`
+    pub id: u64,
+}
+
+// Подкоманды разбираются каждая своей структурой; для автодополнения и man-страниц они
+// собираются в одну команду
+pub fn full_command() -> Command {
+    fn subcommand<T: CommandFactory>(name: &'static str) -> Command {
+        T::command().name(name).bin_name(format!("rcp {}", name))
+    }
+    Args::command()
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .disable_help_subcommand(true)
+        .subcommand(subcommand::<ServeArgs>("serve"))
+        .subcommand(subcommand::<SendArgs>("send"))
+        .subcommand(subcommand::<ReceiveArgs>("receive"))
+        .subcommand(subcommand::<RelayArgs>("relay"))
+        .subcommand(subcommand::<SnapshotArgs>("snapshot"))
+        .subcommand(subcommand::<WatchArgs>("watch"))
+        .subcommand(subcommand::<BisyncArgs>("bisync"))
+        .subcommand(subcommand::<BenchArgs>("bench"))
+        .subcommand(subcommand::<DoctorArgs>("doctor"))
+        .subcommand(subcommand::<DuArgs>("du"))
+        .subcommand(subcommand::<DaemonArgs>("daemon"))
+        .subcommand(subcommand::<SubmitArgs>("submit"))
+        .subcommand(subcommand::<ListArgs>("list"))
+        .subcommand(subcommand::<CancelArgs>("cancel"))
+        .subcommand(subcommand::<AttachArgs>("attach"))
+        .subcommand(subcommand::<CompletionsArgs>("completions"))
+        .subcommand(subcommand::<ManArgs>("man"))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// Keep the version with the later modification time
+    Newer,
+    /// Keep the bigger version
+    Larger,
+    /// Keep both versions under suffixed names on both sides
+    RenameBoth,
+    /// Ask on the terminal for each conflict
+    Prompt,
+    /// Leave conflicting files alone and report them
+    Skip,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropCache {
+    /// Only the written copies
+    Destination,
+    /// The copies and the source files
+    Both,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupMode {
+    /// Hard-link to the existing file; both names share one inode
+    Hardlink,
+    /// Share the data blocks (btrfs, XFS); the files stay independent
+    Reflink,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// `src/` copies the contents of src, `src` creates `dest/src`
+    Rsync,
+    /// Always copy the contents of a source directory into the destination
+    Ignore,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preserve {
+    /// Permission bits, including setuid, setgid and sticky
+    Mode,
+    /// Owner and group (needs root, or --fake-super)
+    Ownership,
+    /// Device nodes, FIFOs and sockets, which are otherwise skipped
+    Devices,
+    /// chattr flags: immutable and append-only (set last, need root), nodump, noatime, nocow, sync, dirsync
+    Flags,
+    /// Access times: the copies get the source atime, and the source atime is restored after reading
+    Atime,
+    /// Creation time of files, where the destination system can set it (macOS, Windows; elsewhere only with
+    /// --fake-super)
+    Birthtime,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceCheck {
+    /// Abort before copying anything
+    Refuse,
+    /// Print a warning and copy anyway
+    Warn,
+    /// Skip the check
+    Off,
+}
+
+// --color у rcp и у всех подкоманд, которые что-то печатают
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct OutputArgs {
+    /// When to color the output; NO_COLOR in the environment turns it off for `auto`
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when both stdout and stderr are terminals and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Debug)]
+pub enum BarTemplate {
+    Overall(String),
+    File(String),
+    Worker(String),
+}
+
+fn parse_bar_template(value: &str) -> Result<BarTemplate, String> {
+    let (kind, template) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected file=, worker= or overall=TEMPLATE, got `{}`", value))?;
+    // Ошибку в шаблоне лучше показать сразу, а не после сбора файлов
+    indicatif::ProgressStyle::with_template(template).map_err(|e| format!("invalid template: {}", e))?;
+    match kind {
+        "file" => Ok(BarTemplate::File(template.to_string())),
+        "worker" => Ok(BarTemplate::Worker(template.to_string())),
+        "overall" => Ok(BarTemplate::Overall(template.to_string())),
+        _ => Err(format!("unknown progress bar `{}`; use file, worker or overall", kind)),
+    }
+}
+
+// --buffer-size: степень двойки от 4K (выравнивание --direct-io) до 64M
+fn parse_buffer_size(value: &str) -> Result<u64, String> {
+    let size = parse_size(value)?;
+    if !size.is_power_of_two() || !(4096..=64 * 1024 * 1024).contains(&size) {
+        return Err(format!("{} is not a power of two between 4K and 64M", value));
+    }
+    Ok(size)
+}
+
+// Размер с необязательным двоичным суффиксом: 4096, 64K, 10MiB, 2G
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits_end);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {}", value))?;
+
+    let multiplier: u64 = match suffix.to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        "P" => 1 << 50,
+        _ => return Err(format!("invalid size suffix: {}", suffix)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size too large: {}", value))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Blake3,
+    Sha256,
+}
@@ -0,0 +1,402 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::cli::TrailingSlash;
+use crate::i18n::t;
+use crate::metadata::is_special_file;
+use crate::pseudofs::pseudo_fs_type;
+use crate::transport::{EntryKind, Source};
+
+#[derive(Clone, Copy, Debug)]
+pub struct CollectOptions<'a> {
+    pub trailing_slash: TrailingSlash,
+    pub skip_pseudo_fs: bool,
+    // false, когда назначение - не дерево на диске (например, архив):
+    // директории не создаются, пересечение с источником не проверяется
+    pub create_directories: bool,
+    // Источники внутри этой директории ложатся в назначение по своему пути от нее (`rcp watch`)
+    pub relative_to: Option<&'a Path>,
+    // Устройства, FIFO и сокеты (--preserve devices); иначе они пропускаются
+    pub special_files: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Collection {
+    pub files: Vec<CopyItem>,
+    // Директории в порядке обхода (родитель раньше потомков)
+    pub directories: Vec<CopyItem>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CopyItem {
+    pub source: String,
+    pub destination: PathBuf,
+    // Дополнительные назначения (--also-to), в которые пишутся те же данные
+    pub also_to: Vec<PathBuf>,
+    pub size: u64,
+}
+
+impl CopyItem {
+    fn new(source: &Path, destinations: Vec<PathBuf>) -> Self {
+        // Для символических ссылок размер данных не учитываем
+        let size = match fs::symlink_metadata(source) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => 0,
+        };
+
+        Self::with_size(source, destinations, size)
+    }
+
+    pub fn with_size(source: &Path, mut destinations: Vec<PathBuf>, size: u64) -> Self {
+        let destination = destinations.remove(0);
+        CopyItem {
+            source: source.to_string_lossy().into_owned(),
+            destination,
+            also_to: destinations,
+            size,
+        }
+    }
+}
+
+impl CopyItem {
+    // Основное назначение, затем дополнительные
+    pub fn destinations(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.destination.as_path()).chain(self.also_to.iter().map(PathBuf::as_path))
+    }
+}
+
+// Раскрываем шаблоны вида `logs/2024-*.gz`, которые не раскрыла оболочка
+pub fn expand_sources(sources: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+
+    for source in sources {
+        let pattern = source.to_string_lossy();
+        if source.exists() || source.is_symlink() || !is_glob_pattern(&pattern) {
+            expanded.push(source.clone());
+            continue;
+        }
+
+        let mut matches = glob::glob(&pattern)
+            .with_context(|| t!("invalid-source-pattern", path = pattern.as_ref()))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| t!("expand-pattern-failed", path = pattern.as_ref()))?;
+
+        if matches.is_empty() {
+            anyhow::bail!(t!("no-files-match-pattern", path = pattern.as_ref()));
+        }
+
+        matches.sort();
+        expanded.extend(matches);
+    }
+
+    Ok(expanded)
+}
+
+fn is_glob_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+// Первое назначение основное, остальные - из --also-to
+pub fn collect_files(
+    sources: &[PathBuf],
+    destinations: &[PathBuf],
+    options: CollectOptions,
+) -> Result<Collection> {
+    let mut collection = Collection::default();
+
+    // Проверяем все источники до того, как что-либо будет создано
+    if options.create_directories {
+        for source in sources {
+            for destination in destinations {
+                check_overlap(source, destination, options.trailing_slash, sources.len() > 1)?;
+            }
+        }
+    }
+
+    // Несколько источников всегда копируются внутрь директории назначения
+    if sources.len() > 1 && options.create_directories {
+        for destination in destinations {
+            fs::create_dir_all(destination)
+                .with_context(|| t!("create-destination-directory-failed", path = destination.display().to_string()))?;
+        }
+    }
+
+    for source in sources {
+        collect_source(source, destinations, sources.len() > 1, options, &mut collection)?;
+    }
+
+    Ok(collection)
+}
+
+// Удаленные источники (SFTP, WebDAV, FTP, URL): дерево обходится по листингам сервера,
+// размеры берутся из них же, поэтому лимиты отсекают лишнее еще до передачи
+pub fn collect_remote(
+    source: &dyn Source,
+    sources: &[PathBuf],
+    destinations: &[PathBuf],
+    options: CollectOptions,
+) -> Result<Collection> {
+    let mut collection = Collection::default();
+
+    if sources.len() > 1 && options.create_directories {
+        for destination in destinations {
+            fs::create_dir_all(destination)
+                .with_context(|| t!("create-destination-directory-failed", path = destination.display().to_string()))?;
+        }
+    }
+
+    for path in sources {
+        let entry = source
+            .stat(path)?
+            .with_context(|| t!("source-missing", path = source.describe(path)))?;
+        if entry.kind == EntryKind::Dir {
+            let dest_roots: Vec<PathBuf> = destinations
+                .iter()
+                .map(|destination| directory_destination(path, destination, options.trailing_slash))
+                .collect();
+            collect_remote_recursive(source, path, &dest_roots, options, &mut collection)?;
+            continue;
+        }
+
+        let mut dest_paths = Vec::new();
+        for destination in destinations {
+            if sources.len() == 1 && !destination.is_dir() {
+                dest_paths.push(destination.to_path_buf());
+                continue;
+            }
+            let name = source.file_name(path).with_context(|| {
+                t!("cannot-derive-name", path = source.describe(path))
+            })?;
+            dest_paths.push(destination.join(name));
+        }
+        collection.files.push(CopyItem::with_size(path, dest_paths, entry.size));
+    }
+
+    Ok(collection)
+}
+
+fn collect_remote_recursive(
+    source: &dyn Source,
+    path: &Path,
+    destinations: &[PathBuf],
+    options: CollectOptions,
+    collection: &mut Collection,
+) -> Result<()> {
+    if options.create_directories {
+        for destination in destinations {
+            fs::create_dir_all(destination)
+                .with_context(|| t!("create-destination-directory-failed", path = destination.display().to_string()))?;
+        }
+    }
+    collection.directories.push(CopyItem::with_size(path, destinations.to_vec(), 0));
+
+    for (source_path, entry) in source.read_dir(path)? {
+        let Some(name) = source_path.file_name() else {
+            continue;
+        };
+        let dest_paths: Vec<PathBuf> = destinations.iter().map(|destination| destination.join(name)).collect();
+
+        match entry.kind {
+            EntryKind::Dir => collect_remote_recursive(source, &source_path, &dest_paths, options, collection)?,
+            EntryKind::File | EntryKind::Symlink => {
+                collection.files.push(CopyItem::with_size(&source_path, dest_paths, entry.size))
+            }
+            EntryKind::Other => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Отказываемся копировать файл сам в себя и директорию внутрь самой себя:
+// иначе collect_files_recursive начнет копировать собственный вывод
+fn check_overlap(
+    source: &Path,
+    destination: &Path,
+    trailing_slash: TrailingSlash,
+    multiple_sources: bool,
+) -> Result<()> {
+    if source.is_file() || source.is_symlink() {
+        let dest_path = if multiple_sources || destination.is_dir() {
+            destination.join(source.file_name().unwrap_or_default())
+        } else {
+            destination.to_path_buf()
+        };
+
+        // Для ссылок сравниваем сами ссылки, для файлов - то, куда реально пойдет запись
+        let (source_meta, dest_meta) = if source.is_symlink() {
+            (fs::symlink_metadata(source), fs::symlink_metadata(&dest_path))
+        } else {
+            (fs::metadata(source), fs::metadata(&dest_path))
+        };
+
+        if let (Ok(source_meta), Ok(dest_meta)) = (source_meta, dest_meta)
+            && source_meta.dev() == dest_meta.dev()
+            && source_meta.ino() == dest_meta.ino()
+        {
+            anyhow::bail!(t!(
+                "same-file",
+                source = source.display().to_string(),
+                destination = dest_path.display().to_string()
+            ));
+        }
+    } else if source.is_dir() {
+        let dest_root = directory_destination(source, destination, trailing_slash);
+        let source_resolved = fs::canonicalize(source)
+            .with_context(|| t!("resolve-source-failed", path = source.display().to_string()))?;
+        let dest_resolved = resolve_path(&dest_root)?;
+
+        if dest_resolved == source_resolved {
+            anyhow::bail!(t!("same-directory", path = source_resolved.display().to_string()));
+        }
+        if dest_resolved.starts_with(&source_resolved) {
+            anyhow::bail!(t!(
+                "destination-inside-source",
+                destination = dest_root.display().to_string(),
+                source = source.display().to_string()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// canonicalize для пути, который может еще не существовать: разрешаем
+// ближайшего существующего предка и добавляем оставшиеся компоненты
+fn resolve_path(path: &Path) -> Result<PathBuf> {
+    let absolute = std::path::absolute(path)
+        .with_context(|| t!("resolve-path-failed", path = path.display().to_string()))?;
+
+    for ancestor in absolute.ancestors() {
+        if let Ok(resolved) = fs::canonicalize(ancestor) {
+            let rest = absolute.strip_prefix(ancestor).unwrap_or(Path::new(""));
+            return Ok(resolved.join(rest));
+        }
+    }
+
+    Ok(absolute)
+}
+
+// Директория назначения для нескольких источников на сервере еще может не существовать
+// локально, поэтому кроме проверки смотрим и на число источников
+fn collect_source(
+    source: &Path,
+    destinations: &[PathBuf],
+    multiple_sources: bool,
+    options: CollectOptions,
+    collection: &mut Collection,
+) -> Result<()> {
+    let relative = options.relative_to.and_then(|root| source.strip_prefix(root).ok());
+    if source.is_file() || source.is_symlink() || (options.special_files && is_special(source)) {
+        let dest_paths = destinations
+            .iter()
+            .map(|destination| {
+                if let Some(relative) = relative {
+                    destination.join(relative)
+                } else if multiple_sources || destination.is_dir() {
+                    destination.join(source.file_name().unwrap())
+                } else {
+                    destination.to_path_buf()
+                }
+            })
+            .collect();
+        collection.files.push(CopyItem::new(source, dest_paths));
+    } else if source.is_dir() {
+        if options.skip_pseudo_fs && is_pseudo_fs(source) {
+            return Ok(());
+        }
+        let dest_roots: Vec<PathBuf> = destinations
+            .iter()
+            .map(|destination| match relative {
+                Some(relative) => destination.join(relative),
+                None => directory_destination(source, destination, options.trailing_slash),
+            })
+            .collect();
+        collect_files_recursive(source, &dest_roots, options, collection)?;
+    }
+
+    Ok(())
+}
+
+// Как в rsync: `src/` копирует содержимое, `src` создает `dest/src`
+fn directory_destination(source: &Path, destination: &Path, trailing_slash: TrailingSlash) -> PathBuf {
+    if trailing_slash == TrailingSlash::Ignore || has_trailing_slash(source) {
+        return destination.to_path_buf();
+    }
+
+    // У `.`, `..` и `/` нет имени - копируем содержимое
+    match source.file_name() {
+        Some(name) => destination.join(name),
+        None => destination.to_path_buf(),
+    }
+}
+
+fn has_trailing_slash(path: &Path) -> bool {
+    let bytes = path.as_os_str().as_bytes();
+    bytes.ends_with(b"/") || bytes.ends_with(b"/.")
+}
+
+fn is_special(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| is_special_file(&metadata))
+}
+
+fn is_pseudo_fs(dir: &Path) -> bool {
+    match pseudo_fs_type(dir) {
+        Some(fs_type) => {
+            eprintln!(
+                "{}",
+                t!("skipping-pseudo-fs", path = dir.display().to_string(), fs_type = fs_type.to_string()).yellow()
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+fn collect_files_recursive(
+    source: &Path,
+    destinations: &[PathBuf],
+    options: CollectOptions,
+    collection: &mut Collection,
+) -> Result<()> {
+    if options.create_directories {
+        for destination in destinations {
+            fs::create_dir_all(destination)
+                .with_context(|| t!("create-destination-directory-failed", path = destination.display().to_string()))?;
+        }
+    }
+    collection.directories.push(CopyItem::new(source, destinations.to_vec()));
+
+    // Устройство текущей директории - смена st_dev у поддиректории означает точку монтирования
+    let source_dev = fs::metadata(source)?.dev();
+
+    // Директория читается целиком до спуска в поддиректории: иначе глубокое дерево держит открытым
+    // дескриптор на каждом уровне (его держит и DirEntry, поэтому запоминаются только имена)
+    let entries = fs::read_dir(source)?
+        .map(|entry| entry.map(|entry| (entry.path(), entry.file_name())))
+        .collect::<io::Result<Vec<_>>>()?;
+    for (source_path, name) in entries {
+        let dest_paths: Vec<PathBuf> = destinations
+            .iter()
+            .map(|destination| destination.join(&name))
+            .collect();
+
+        // Включаем символические ссылки в список для копирования
+        if source_path.is_file() || source_path.is_symlink() || (options.special_files && is_special(&source_path)) {
+            collection.files.push(CopyItem::new(&source_path, dest_paths));
+        } else if source_path.is_dir() {
+            let crosses_mount = fs::metadata(&source_path).is_ok_and(|metadata| metadata.dev() != source_dev);
+            if options.skip_pseudo_fs && crosses_mount && is_pseudo_fs(&source_path) {
+                continue;
+            }
+            collect_files_recursive(&source_path, &dest_paths, options, collection)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use anyhow::Result;
+use clap::builder::PossibleValue;
+use std::io;
+
+use crate::cli::{self, CompletionsArgs};
+use crate::config::Defaults;
+
+// Значения перечислений (--checksum, --color и т.п.) попадают в скрипт из clap, имена профилей
+// для --profile - из конфигурации на момент генерации
+pub fn print(args: &CompletionsArgs) -> Result<()> {
+    let defaults = Defaults::load()?;
+    let profiles: Vec<PossibleValue> = defaults.profile_names().map(|name| PossibleValue::new(name.to_string())).collect();
+    let mut command = cli::full_command()
+        .mut_arg("profile", |arg| if profiles.is_empty() { arg } else { arg.value_parser(profiles) });
+    clap_complete::generate(args.shell, &mut command, "rcp", &mut io::stdout());
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Gzip,
+    Xz,
+}
+
+impl Compression {
+    // Суффикс, который получает сжатый файл в назначении
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Zstd => ".zst",
+            Compression::Gzip => ".gz",
+            Compression::Xz => ".xz",
+        }
+    }
+
+    // Сжатие, которое угадывается по суффиксу имени файла (--decompress)
+    pub fn from_suffix(path: &Path) -> Compression {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("zst") => Compression::Zstd,
+            Some("gz") => Compression::Gzip,
+            Some("xz") => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+}
+
+// Форматы, которые уже сжаты: повторное сжатие при передаче по сети только тратит CPU
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "zst", "gz", "tgz", "xz", "txz", "bz2", "lz4", "lzma", "br", "zip", "7z", "rar", "jar", "apk", "deb", "rpm",
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif", "mp3", "ogg", "opus", "flac", "aac", "m4a", "mp4", "m4v",
+    "mkv", "webm", "mov", "avi", "docx", "xlsx", "pptx", "odt", "ods", "epub", "woff2", "age", "gpg",
+];
+
+pub fn is_compressed_format(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| COMPRESSED_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+// Поток чтения с распаковкой в соответствии с форматом
+pub fn decompressing_reader<'a, R: Read + 'a>(inner: R, compression: Compression) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(inner),
+        Compression::Zstd => Box::new(zstd::Decoder::new(inner)?),
+        // Склеенные gzip-потоки (cat a.gz b.gz) распаковываются целиком, как это делает gzip -d
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(inner)),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(inner)),
+    })
+}
+
+// Сжатие каждого файла по отдельности (--compress): `zstd`, `gzip`, `xz` или с уровнем, `zstd:19`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileCompression {
+    pub compression: Compression,
+    pub level: Option<u32>,
+}
+
+impl FromStr for FileCompression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, level) = match value.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (value, None),
+        };
+
+        let (compression, levels) = match name {
+            "zstd" => (Compression::Zstd, 1..=22),
+            "gzip" => (Compression::Gzip, 0..=9),
+            "xz" => (Compression::Xz, 0..=9),
+            _ => return Err(format!("unsupported compression: {}", name)),
+        };
+
+        let level = match level {
+            None => None,
+            Some(level) => {
+                let level: u32 = level.parse().map_err(|_| format!("invalid compression level: {}", level))?;
+                if !levels.contains(&level) {
+                    return Err(format!(
+                        "{} level must be between {} and {}",
+                        name,
+                        levels.start(),
+                        levels.end()
+                    ));
+                }
+                Some(level)
+            }
+        };
+
+        Ok(FileCompression { compression, level })
+    }
+}
+
+// Поток записи с необязательным сжатием; finish() обязательно вызывать,
+// иначе у сжатого потока не будет завершающего кадра
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(flate2::write::GzEncoder<W>),
+    Xz(xz2::write::XzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(inner: W, compression: Compression) -> io::Result<Self> {
+        Self::with_level(inner, compression, None)
+    }
+
+    // Без уровня - уровень по умолчанию для алгоритма
+    pub fn with_level(inner: W, compression: Compression, level: Option<u32>) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => CompressedWriter::Plain(inner),
+            Compression::Zstd => {
+                CompressedWriter::Zstd(zstd::Encoder::new(inner, level.map_or(0, |level| level as i32))?)
+            }
+            Compression::Gzip => CompressedWriter::Gzip(flate2::write::GzEncoder::new(
+                inner,
+                level.map_or_else(flate2::Compression::default, flate2::Compression::new),
+            )),
+            Compression::Xz => CompressedWriter::Xz(xz2::write::XzEncoder::new(inner, level.unwrap_or(6))),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressedWriter::Plain(inner) => Ok(inner),
+            CompressedWriter::Zstd(encoder) => encoder.finish(),
+            CompressedWriter::Gzip(encoder) => encoder.finish(),
+            CompressedWriter::Xz(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(inner) => inner.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Xz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(inner) => inner.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Xz(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use anyhow::{Context, Result};
+use clap::builder::BoolishValueParser;
+use clap::error::ErrorKind;
+use clap::{ArgAction, Command, CommandFactory, FromArgMatches};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Args;
+
+// Постоянные значения по умолчанию для опций копирования: /etc/rcp/config.toml, поверх него
+// ~/.config/rcp/config.toml. Ключ - длинное имя опции, значение становится ее значением по
+// умолчанию в clap, поэтому флаги и переменные окружения всегда важнее:
+//
+//     bwlimit = "20M"
+//     verify = true
+//     also-to = ["/mnt/mirror"]
+//
+// Секция [profile.NAME] - именованный набор опций поверх общих, включается `--profile NAME`.
+// Кроме опций в ней могут быть source (строка или список) и destination:
+//
+//     [profile.photos-backup]
+//     source = "/home/alice/Photos"
+//     destination = "nas:/backup/photos"
+//     verify = true
+pub struct Defaults {
+    values: Values,
+    // Имя профиля -> его значения; source и destination - под ID позиционных аргументов
+    profiles: Vec<(String, Values)>,
+}
+
+// ID аргумента clap -> значения
+type Values = Vec<(String, Vec<String>)>;
+
+impl Defaults {
+    pub fn load() -> Result<Self> {
+        let mut defaults = Defaults {
+            values: Vec::new(),
+            profiles: Vec::new(),
+        };
+        for path in config_paths() {
+            defaults.read(&path)?;
+        }
+        Ok(defaults)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<()> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config: {}", path.display())),
+        };
+        let table: toml::Table = text
+            .parse()
+            .with_context(|| format!("Failed to parse config: {}", path.display()))?;
+
+        let command = Args::command();
+        for (key, value) in table {
+            if key != "profile" {
+                let (id, values) = option(&command, &key, &value, false, path)?;
+                set(&mut self.values, id, values);
+                continue;
+            }
+            let toml::Value::Table(profiles) = value else {
+                anyhow::bail!("`profile` in {} must be made of [profile.NAME] sections", path.display());
+            };
+            for (name, options) in profiles {
+                let toml::Value::Table(options) = options else {
+                    anyhow::bail!("`profile.{}` in {} must be a [profile.{}] section", name, path.display(), name);
+                };
+                let index = match self.profiles.iter().position(|(known, _)| *known == name) {
+                    Some(index) => index,
+                    None => {
+                        self.profiles.push((name, Vec::new()));
+                        self.profiles.len() - 1
+                    }
+                };
+                for (key, value) in options {
+                    let (id, values) = option(&command, &key, &value, true, path)?;
+                    set(&mut self.profiles[index].1, id, values);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Как Args::parse, но со значениями из конфигурации вместо встроенных
+    pub fn parse(&self) -> Args {
+        self.parse_from(std::env::args_os())
+    }
+
+    pub fn parse_from(&self, args: impl IntoIterator<Item = OsString>) -> Args {
+        let mut command = Args::command();
+        for (id, values) in &self.values {
+            command = command.mut_arg(id, |arg| arg.default_values(values));
+        }
+        // RCP_<ОПЦИЯ> для опций без своей переменной: важнее конфигурации, но не флагов
+        let own: Vec<(String, String)> = command
+            .get_arguments()
+            .filter(|arg| arg.get_env().is_none())
+            .filter_map(|arg| Some((arg.get_id().to_string(), env_name(arg.get_long()?))))
+            .collect();
+        for (id, name) in own {
+            command = command.mut_arg(id, |arg| {
+                let arg = arg.env(name).hide_env(true);
+                // Флагу подходят и 1/0, yes/no, on/off
+                match arg.get_action() {
+                    ArgAction::SetTrue => arg.value_parser(BoolishValueParser::new()),
+                    _ => arg,
+                }
+            });
+        }
+        let mut args: Vec<OsString> = args.into_iter().collect();
+        if let Some(name) = requested_profile(&args) {
+            let profile = self.profile(&mut command, &name);
+            for (id, values) in profile.iter().filter(|(id, _)| id != "sources" && id != "destination") {
+                command = command.mut_arg(id, |arg| arg.default_values(values));
+            }
+            add_paths(&command, profile, &mut args);
+        }
+        let mut matches = command.get_matches_from(args);
+        Args::from_arg_matches_mut(&mut matches).unwrap_or_else(|e| e.exit())
+    }
+
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn profile(&self, command: &mut Command, name: &str) -> &[(String, Vec<String>)] {
+        let Some((_, profile)) = self.profiles.iter().find(|(known, _)| known == name) else {
+            let known: Vec<&str> = self.profiles.iter().map(|(known, _)| known.as_str()).collect();
+            let paths: Vec<String> = config_paths().iter().map(|path| path.display().to_string()).collect();
+            let known = if known.is_empty() {
+                "no profiles are defined".to_string()
+            } else {
+                format!("known profiles: {}", known.join(", "))
+            };
+            command
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("no [profile.{}] section in {} ({})", name, paths.join(" or "), known),
+                )
+                .exit();
+        };
+        let has = |id: &str| profile.iter().any(|(known, _)| known == id);
+        if has("sources") && !has("destination") {
+            command
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("profile `{}` sets `source` but not `destination`", name),
+                )
+                .exit();
+        }
+        profile
+    }
+}
+
+// Ключ конфигурации -> ID аргумента и значения; пути источника и назначения - только в профиле
+fn option(command: &Command, key: &str, value: &toml::Value, in_profile: bool, path: &Path) -> Result<(String, Vec<String>)> {
+    let id = match key {
+        "source" if in_profile => "sources".to_string(),
+        "destination" if in_profile => "destination".to_string(),
+        _ => command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key) && !arg.is_positional() && arg.get_id() != "profile")
+            .with_context(|| format!("Unknown option `{}` in {}", key, path.display()))?
+            .get_id()
+            .to_string(),
+    };
+    let values = match value {
+        toml::Value::Array(items) => items.iter().map(scalar).collect(),
+        value => scalar(value).map(|value| vec![value]),
+    }
+    .with_context(|| format!("Option `{}` in {} must be a string, number, boolean or a list of them", key, path.display()))?;
+    Ok((id, values))
+}
+
+// Более поздний файл заменяет значение более раннего
+fn set(values: &mut Values, id: String, value: Vec<String>) {
+    values.retain(|(known, _)| *known != id);
+    values.push((id, value));
+}
+
+// Пути профиля дописываются в конец командной строки, если в ней их нет; один путь в командной
+// строке - источник вместо источника профиля
+fn add_paths(command: &Command, profile: &[(String, Vec<String>)], args: &mut Vec<OsString>) {
+    let paths = |id: &str| profile.iter().find(|(known, _)| known == id).map(|(_, values)| values.as_slice());
+    let Some(destination) = paths("destination") else {
+        return;
+    };
+    // Пробный разбор только считает пути; ошибки покажет настоящий
+    let Ok(matches) = command.clone().ignore_errors(true).try_get_matches_from(args.iter()) else {
+        return;
+    };
+    let given: usize = ["sources", "destination"]
+        .iter()
+        .map(|id| matches.get_raw(id).map_or(0, |values| values.len()))
+        .sum();
+    let added: Vec<&String> = match (given, paths("sources")) {
+        (0, Some(sources)) => sources.iter().chain(destination).collect(),
+        (1, _) => destination.iter().collect(),
+        _ => return,
+    };
+    if !args.iter().skip(1).any(|arg| arg == "--") {
+        args.push("--".into());
+    }
+    args.extend(added.into_iter().map(OsString::from));
+}
+
+// --bwlimit -> RCP_BWLIMIT
+fn env_name(long: &str) -> String {
+    format!("RCP_{}", long.to_uppercase().replace('-', "_"))
+}
+
+// Профиль нужен до разбора аргументов: от него зависят значения по умолчанию. Флаг важнее
+// переменной, как и у остальных опций
+fn requested_profile(args: &[OsString]) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--profile" {
+            return args.next().map(|name| name.to_string_lossy().into_owned());
+        }
+        if let Some(name) = arg.to_str().and_then(|arg| arg.strip_prefix("--profile=")) {
+            return Some(name.to_string());
+        }
+    }
+    std::env::var("RCP_PROFILE").ok().filter(|name| !name.is_empty())
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(text) => Some(text.clone()),
+        toml::Value::Integer(number) => Some(number.to_string()),
+        toml::Value::Float(number) => Some(number.to_string()),
+        toml::Value::Boolean(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/etc/rcp/config.toml")];
+    let user = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    };
+    paths.extend(user.map(|dir| dir.join("rcp/config.toml")));
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Файлы конфигурации читаются по порядку, как /etc/rcp и ~/.config/rcp
+    fn defaults(name: &str, files: &[&str]) -> Defaults {
+        let mut defaults = Defaults {
+            values: Vec::new(),
+            profiles: Vec::new(),
+        };
+        for (index, text) in files.iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("rcp-config-{}-{}-{}.toml", name, index, std::process::id()));
+            fs::write(&path, text).unwrap();
+            let result = defaults.read(&path);
+            fs::remove_file(&path).unwrap();
+            result.unwrap();
+        }
+        defaults
+    }
+
+    fn parse(defaults: &Defaults, args: &[&str]) -> Args {
+        defaults.parse_from(std::iter::once("rcp").chain(args.iter().copied()).map(OsString::from))
+    }
+
+    #[test]
+    fn later_files_override_earlier_ones() {
+        let defaults = defaults("order", &["jobs = 2\nverify = true", "jobs = 8"]);
+        let args = parse(&defaults, &["a", "b"]);
+        assert_eq!(args.jobs, Some(8));
+        assert!(args.verify);
+    }
+
+    #[test]
+    fn flags_override_the_config() {
+        let defaults = defaults("flags", &["jobs = 8\nalso-to = [\"/mnt/m1\", \"/mnt/m2\"]"]);
+        let args = parse(&defaults, &["a", "b"]);
+        assert_eq!(args.jobs, Some(8));
+        assert_eq!(args.also_to, [PathBuf::from("/mnt/m1"), PathBuf::from("/mnt/m2")]);
+        let args = parse(&defaults, &["-j", "3", "--also-to", "/mnt/m3", "a", "b"]);
+        assert_eq!(args.jobs, Some(3));
+        assert_eq!(args.also_to, [PathBuf::from("/mnt/m3")]);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let mut defaults = defaults("unknown", &[]);
+        for text in ["no-such-option = 1", "source = \"/data\"", "jobs = { n = 1 }"] {
+            let path = std::env::temp_dir().join(format!("rcp-config-unknown-{}.toml", std::process::id()));
+            fs::write(&path, text).unwrap();
+            let result = defaults.read(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(result.is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn profile_options_override_the_general_ones() {
+        let general = "jobs = 2\nverify = true\n[profile.fast]\njobs = 16";
+        // Одноименная секция из более позднего файла дополняет профиль
+        let defaults = defaults("profile", &[general, "[profile.fast]\nalso-to = \"/mnt/mirror\""]);
+        let args = parse(&defaults, &["a", "b"]);
+        assert_eq!(args.jobs, Some(2));
+        assert!(args.also_to.is_empty());
+        let args = parse(&defaults, &["--profile", "fast", "a", "b"]);
+        assert_eq!(args.jobs, Some(16));
+        assert!(args.verify);
+        assert_eq!(args.also_to, [PathBuf::from("/mnt/mirror")]);
+        let args = parse(&defaults, &["--profile=fast", "-j", "4", "a", "b"]);
+        assert_eq!(args.jobs, Some(4));
+    }
+
+    #[test]
+    fn profile_paths_fill_in_missing_arguments() {
+        let text = "[profile.photos]\nsource = [\"/home/a/Photos\", \"/home/a/Videos\"]\ndestination = \"/mnt/backup\"";
+        let defaults = defaults("paths", &[text]);
+        let args = parse(&defaults, &["--profile", "photos"]);
+        assert_eq!(args.sources, [PathBuf::from("/home/a/Photos"), PathBuf::from("/home/a/Videos")]);
+        assert_eq!(args.destination, PathBuf::from("/mnt/backup"));
+        // Один путь в командной строке - источник вместо источников профиля
+        let args = parse(&defaults, &["--profile", "photos", "/home/a/Music"]);
+        assert_eq!(args.sources, [PathBuf::from("/home/a/Music")]);
+        assert_eq!(args.destination, PathBuf::from("/mnt/backup"));
+        let args = parse(&defaults, &["--profile", "photos", "/src", "/dst"]);
+        assert_eq!(args.sources, [PathBuf::from("/src")]);
+        assert_eq!(args.destination, PathBuf::from("/dst"));
+    }
+
+    #[test]
+    fn environment_sits_between_config_and_flags() {
+        let defaults = defaults("env", &["bwlimit = 1000\n[profile.slow]\nbwlimit = 2000"]);
+        // Эти переменные читает только этот тест
+        unsafe {
+            std::env::set_var("RCP_BWLIMIT", "3000");
+            std::env::set_var("RCP_NO_LOCK", "yes");
+        }
+        let from_env = parse(&defaults, &["--profile", "slow", "a", "b"]);
+        let from_flag = parse(&defaults, &["--profile", "slow", "--bwlimit", "4000", "a", "b"]);
+        unsafe {
+            std::env::remove_var("RCP_BWLIMIT");
+            std::env::remove_var("RCP_NO_LOCK");
+        }
+        let from_profile = parse(&defaults, &["--profile", "slow", "a", "b"]);
+        let from_file = parse(&defaults, &["a", "b"]);
+        assert_eq!(from_env.bwlimit, Some(3000));
+        assert!(from_env.no_lock);
+        assert_eq!(from_flag.bwlimit, Some(4000));
+        assert_eq!(from_profile.bwlimit, Some(2000));
+        assert!(!from_profile.no_lock);
+        assert_eq!(from_file.bwlimit, Some(1000));
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use crate::collect::CopyItem;
+use crate::copy::SMALL_FILE_SIZE;
+use crate::disks::DiskSlots;
+
+// Управление идущим копированием с клавиатуры: пауза, пропуск файлов, остановка и
+// ограничение скорости. Копирование сверяется с ним после каждого записанного блока
+#[derive(Default)]
+pub struct Control {
+    paused: AtomicBool,
+    // Новые файлы не начинаются, начатые докопируются
+    draining: AtomicBool,
+    stopped: AtomicBool,
+    paused_files: Mutex<HashSet<u32>>,
+    skipped_files: Mutex<HashSet<u32>>,
+    // Байт в секунду на все копирование, 0 - без ограничения
+    limit: AtomicU64,
+    // Начало отсчета и сколько передано с него
+    sent: Mutex<Option<(Instant, u64)>>,
+    // Всего записано байт, для прогресса вне терминала (--dbus)
+    written: AtomicU64,
+    // Когда поток закончил ждать паузы или --bwlimit; None - ждет сейчас. Это время
+    // --file-timeout не считает зависанием
+    waits: Mutex<HashMap<ThreadId, Option<Instant>>>,
+}
+
+// Пока жив, поток считается ждущим
+struct Waiting<'a>(&'a Control);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.waits.lock().unwrap().insert(thread::current().id(), Some(Instant::now()));
+    }
+}
+
+impl Control {
+    // Возвращает, стоит ли теперь копирование на паузе
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_file_pause(&self, id: u32) -> bool {
+        let mut paused = self.paused_files.lock().unwrap();
+        if paused.remove(&id) {
+            return false;
+        }
+        paused.insert(id);
+        true
+    }
+
+    pub fn is_file_paused(&self, id: u32) -> bool {
+        self.paused_files.lock().unwrap().contains(&id)
+    }
+
+    pub fn skip(&self, id: u32) {
+        self.skipped_files.lock().unwrap().insert(id);
+    }
+
+    // Первый раз - дождаться начатых файлов, второй - прервать и их
+    pub fn abort(&self) {
+        if self.draining.swap(true, Ordering::Relaxed) {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        Some(self.limit.load(Ordering::Relaxed)).filter(|&limit| limit > 0)
+    }
+
+    pub fn set_limit(&self, limit: Option<u64>) {
+        self.limit.store(limit.unwrap_or(0), Ordering::Relaxed);
+        // Новое ограничение действует с этого момента
+        *self.sent.lock().unwrap() = None;
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    // Притормаживает поток так, чтобы все потоки вместе не превышали ограничение
+    pub fn throttle(&self, bytes: u64) {
+        self.written.fetch_add(bytes, Ordering::Relaxed);
+        let Some(limit) = self.limit() else {
+            return;
+        };
+        let wait = {
+            let mut sent = self.sent.lock().unwrap();
+            let (start, total) = sent.get_or_insert_with(|| (Instant::now(), 0));
+            *total += bytes;
+            let due = Duration::from_secs_f64(*total as f64 / limit as f64);
+            let elapsed = start.elapsed();
+            // После простоя не копим запас больше чем на секунду
+            if elapsed > due + Duration::from_secs(1) {
+                *sent = Some((Instant::now(), 0));
+            }
+            due.saturating_sub(elapsed)
+        };
+        if !wait.is_zero() {
+            let _waiting = self.waiting();
+            thread::sleep(wait);
+        }
+    }
+
+    // Ждет, пока файл или все копирование на паузе; Some - файл надо бросить, с причиной
+    pub fn checkpoint(&self, id: u32) -> Option<&'static str> {
+        let mut waiting = None;
+        loop {
+            if self.is_stopped() {
+                return Some("Copy was stopped");
+            }
+            if self.skipped_files.lock().unwrap().contains(&id) {
+                return Some("Skipped");
+            }
+            if !self.is_paused() && !self.is_file_paused(id) {
+                return None;
+            }
+            waiting.get_or_insert_with(|| self.waiting());
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn waiting(&self) -> Waiting<'_> {
+        self.waits.lock().unwrap().insert(thread::current().id(), None);
+        Waiting(self)
+    }
+
+    // До какого момента поток ждал паузы или ограничения скорости; если ждет сейчас - до сих пор
+    pub fn waited_until(&self, thread: ThreadId) -> Option<Instant> {
+        self.waits
+            .lock()
+            .unwrap()
+            .get(&thread)
+            .map(|until| until.unwrap_or_else(Instant::now))
+    }
+
+    pub fn forget_waits(&self, thread: ThreadId) {
+        self.waits.lock().unwrap().remove(&thread);
+    }
+}
+
+// Сколько мелких файлов поток берет из очереди за раз
+const BATCH_FILES: usize = 64;
+
+// Общая очередь файлов: рабочие потоки берут из нее следующий файл, когда освобождаются,
+// поэтому порядок можно менять по ходу копирования. ID файла - его номер в исходном списке
+pub struct WorkQueue {
+    pending: Mutex<VecDeque<(u32, CopyItem)>>,
+    // Файл -> рабочий поток, который его копирует
+    running: Mutex<HashMap<u32, usize>>,
+    // То же, но без удаления: прогресс файла может дойти до интерфейса уже после его конца
+    assigned: Mutex<HashMap<u32, usize>>,
+    // Убранные из очереди до начала копирования
+    dropped: Mutex<usize>,
+    // Сколько файлов одновременно копируется на каждом HDD (--jobs-per-hdd)
+    disks: Mutex<Option<DiskSlots>>,
+    // Пачки мелких файлов - только при копировании с локального диска на локальный
+    batches: AtomicBool,
+}
+
+impl WorkQueue {
+    pub fn new(files: &[CopyItem]) -> Self {
+        WorkQueue {
+            pending: Mutex::new(files.iter().cloned().enumerate().map(|(id, item)| (id as u32, item)).collect()),
+            running: Mutex::new(HashMap::new()),
+            assigned: Mutex::new(HashMap::new()),
+            dropped: Mutex::new(0),
+            disks: Mutex::new(None),
+            batches: AtomicBool::new(false),
+        }
+    }
+
+    pub fn batch_small_files(&self) {
+        self.batches.store(true, Ordering::Relaxed);
+    }
+
+    pub fn limit_disks(&self, slots: DiskSlots) {
+        *self.disks.lock().unwrap() = Some(slots);
+    }
+
+    // Первый файл, диски которого не заняты; если заняты у всех, поток ждет, пока диск освободится
+    pub fn next(&self, worker: usize, control: &Control) -> Option<(u32, CopyItem)> {
+        loop {
+            if control.is_draining() {
+                return None;
+            }
+            let mut pending = self.pending.lock().unwrap();
+            if pending.is_empty() {
+                return None;
+            }
+            let index = match self.disks.lock().unwrap().as_mut() {
+                Some(slots) => pending.iter().position(|(id, _)| slots.try_acquire(*id)),
+                None => Some(0),
+            };
+            if let Some((id, item)) = index.and_then(|index| pending.remove(index)) {
+                self.running.lock().unwrap().insert(id, worker);
+                self.assigned.lock().unwrap().insert(id, worker);
+                return Some((id, item));
+            }
+            drop(pending);
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    // Мелкие файлы поток берет пачкой, чтобы не разбирать очередь ради каждого. С ограничением
+    // по HDD пачек нет: место на диске занимает каждый файл
+    pub fn next_batch(&self, worker: usize, control: &Control) -> Vec<(u32, CopyItem)> {
+        let Some(first) = self.next(worker, control) else {
+            return Vec::new();
+        };
+        let mut batch = vec![first];
+        if !self.batches.load(Ordering::Relaxed)
+            || batch[0].1.size > SMALL_FILE_SIZE
+            || self.disks.lock().unwrap().is_some()
+        {
+            return batch;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        while batch.len() < BATCH_FILES
+            && let Some((id, item)) = pending.pop_front_if(|(_, item)| item.size <= SMALL_FILE_SIZE)
+        {
+            self.running.lock().unwrap().insert(id, worker);
+            self.assigned.lock().unwrap().insert(id, worker);
+            batch.push((id, item));
+        }
+        batch
+    }
+
+    // Непочатые файлы пачки после остановки возвращаются в начало очереди
+    pub fn put_back(&self, files: Vec<(u32, CopyItem)>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (id, item) in files.into_iter().rev() {
+            self.running.lock().unwrap().remove(&id);
+            self.assigned.lock().unwrap().remove(&id);
+            pending.push_front((id, item));
+        }
+    }
+
+    pub fn finished(&self, id: u32) {
+        self.running.lock().unwrap().remove(&id);
+        if let Some(slots) = self.disks.lock().unwrap().as_mut() {
+            slots.release(id);
+        }
+    }
+
+    pub fn pending(&self) -> Vec<(u32, String, u64)> {
+        self.pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, item)| (*id, item.source.clone(), item.size))
+            .collect()
+    }
+
+    pub fn running(&self) -> HashMap<u32, usize> {
+        self.running.lock().unwrap().clone()
+    }
+
+    pub fn worker_of(&self, id: u32) -> Option<usize> {
+        self.assigned.lock().unwrap().get(&id).copied()
+    }
+
+    pub fn move_to_front(&self, id: u32) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.iter().position(|(queued, _)| *queued == id).and_then(|index| pending.remove(index)) {
+            Some(entry) => {
+                pending.push_front(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_to_back(&self, id: u32) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.iter().position(|(queued, _)| *queued == id).and_then(|index| pending.remove(index)) {
+            Some(entry) => {
+                pending.push_back(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&self, id: u32) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let removed = pending.iter().position(|(queued, _)| *queued == id).and_then(|index| pending.remove(index));
+        if removed.is_some() {
+            *self.dropped.lock().unwrap() += 1;
+        }
+        removed.is_some()
+    }
+
+    // Файлы, которые так и не начали копировать: убранные из очереди и оставшиеся в ней
+    // после остановки
+    pub fn left_out(&self) -> usize {
+        *self.dropped.lock().unwrap() + self.pending.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn queue() -> WorkQueue {
+        let files: Vec<_> =
+            (0..3).map(|n| CopyItem::with_size(Path::new("source"), vec![PathBuf::from(format!("copy{}", n))], 10)).collect();
+        WorkQueue::new(&files)
+    }
+
+    #[test]
+    fn small_files_are_batched_only_when_enabled() {
+        let control = Control::default();
+        assert_eq!(queue().next_batch(0, &control).len(), 1);
+
+        let queue = queue();
+        queue.batch_small_files();
+        assert_eq!(queue.next_batch(0, &control).len(), 3);
+    }
+}
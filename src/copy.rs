@@ -0,0 +1,1418 @@
+use anyhow::{Context, Result};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::ffi::CString;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, FileExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, LocalKey};
+use std::time::{Duration, Instant};
+
+use crate::cli::{ChecksumAlgorithm, DropCache};
+use crate::collect::CopyItem;
+use crate::compression::{decompressing_reader, CompressedWriter, Compression, FileCompression};
+use crate::control::Control;
+use crate::direct::{self, AlignedBuffer, DIRECT_IO_ALIGN};
+use crate::encrypt::{EncryptedWriter, Encryption};
+use crate::hash::{hash_file, hash_range, BackgroundHasher, Hasher};
+use crate::i18n::t;
+use crate::metadata::{open_source, Metadata, Stat};
+use crate::pipeline::PipelinedWriter;
+use crate::progress::{ProgressThrottle, ProgressUpdate};
+use crate::space::allocated_bytes;
+use crate::tee::Tee;
+use crate::transport::{EntryKind, Progress, Sink, SinkFile, Source};
+
+#[derive(Clone, Default)]
+pub struct CopyOptions {
+    pub file_timeout: Option<Duration>,
+    // Контрольная сумма считается по ходу копирования, если она нужна
+    pub checksum: Option<ChecksumAlgorithm>,
+    pub verify: bool,
+    // Сумма всего файла нужна для --write-manifest
+    pub manifest: bool,
+    // Сжимать каждый файл в назначении (--compress)
+    pub compress: Option<FileCompression>,
+    // Распаковывать источники .gz/.zst/.xz (--decompress)
+    pub decompress: bool,
+    // Шифровать каждый файл в назначении (--encrypt-to)
+    pub encryption: Option<Encryption>,
+    // Удаленный источник: пути источников - на нем
+    pub source: Option<Arc<dyn Source>>,
+    // Удаленное назначение: пути назначения - на нем
+    pub sink: Option<Arc<dyn Sink>>,
+    // Источник и назначение на одном сервере: файлы можно копировать на нем самом
+    pub same_server: bool,
+    // Сколько соединений отдавать одному большому файлу, если назначение или источник
+    // умеют передавать файл частями
+    pub streams_per_file: usize,
+    // С какого размера локальный файл копируется частями в несколько потоков (--split-above)
+    pub split_above: u64,
+    // Переносить время изменения источника на локальную копию (нужно --link-dest)
+    pub preserve_modified: bool,
+    // Владелец, права и специальные файлы (--preserve, --chown, --usermap, --groupmap)
+    pub metadata: Option<Metadata>,
+    // Каждые сколько байт большого файла отправлять на диск (--writeback-every), 0 - не отправлять
+    pub writeback_every: u64,
+    // fdatasync каждого файла до синхронизации директорий (--fsync-dirs)
+    pub fsync: bool,
+    // Локальные файлы как есть - с O_DIRECT (--direct-io)
+    pub direct_io: bool,
+    // Убирать скопированные файлы из страничного кеша (--drop-cache)
+    pub drop_cache: Option<DropCache>,
+    // Буфер чтения: больше для HDD и сетевых файловых систем (disks.rs)
+    pub buffer_size: usize,
+    // Уже созданные каталоги назначения, чтобы не проверять их для каждого файла; только на
+    // сетевой файловой системе, где каждая проверка - запрос к серверу
+    pub created_dirs: Option<Arc<Mutex<HashSet<PathBuf>>>>,
+    // Сколько раз начинать файл заново после ESTALE (--retry-stale)
+    pub stale_retries: usize,
+    // Пауза и пропуск файлов по ходу копирования (--tui)
+    pub control: Arc<Control>,
+}
+
+// Считает байты, прочитанные из источника до распаковки: по ним идет прогресс
+struct CountingReader {
+    inner: SourceFile,
+    bytes_read: Rc<Cell<u64>>,
+}
+
+// Источник - локальный файл или файл на удаленном источнике
+enum SourceFile {
+    Local(File),
+    Remote(Box<dyn Read>),
+}
+
+impl SourceFile {
+    // Вместе с файлом возвращает его размер
+    fn open(source: &str, options: &CopyOptions) -> Result<(Self, u64)> {
+        if let Some(remote) = &options.source {
+            let (reader, size) = remote.open(Path::new(source))?;
+            return Ok((SourceFile::Remote(reader), size));
+        }
+
+        let file = open_source(source).with_context(|| t!("open-source-failed", path = source))?;
+        let size = file.metadata()?.len();
+        Ok((SourceFile::Local(file), size))
+    }
+}
+
+impl Read for SourceFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SourceFile::Local(file) => file.read(buf),
+            SourceFile::Remote(file) => file.read(buf),
+        }
+    }
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.bytes_read.set(self.bytes_read.get() + bytes_read as u64);
+        Ok(bytes_read)
+    }
+}
+
+// Основное назначение и дополнительные (--also-to) как один поток записи:
+// сжатие, если оно включено, выполняется один раз для всех назначений
+struct Destinations {
+    file: DestinationFile,
+    tee: Tee,
+    writeback: Writeback,
+    // Нулевые блоки разреженного источника остаются дырами в основном назначении
+    sparse: bool,
+}
+
+// Блок, который при разреженной записи становится дырой, если он весь из нулей
+const SPARSE_BLOCK: usize = 4096;
+
+// Запись на диск по ходу копирования (--writeback-every): каждые every байт ядро начинает
+// писать новое окно и дожидается предыдущего. Грязные страницы не копятся до close(), и
+// прогресс не висит секундами на 100%
+struct Writeback {
+    every: u64,
+    // --fsync-dirs: в конце файла ждем, пока его данные дойдут до диска
+    fsync: bool,
+    written: u64,
+    // Начало окна, которое еще не отправлено на запись, и окна, которое пишется сейчас
+    window: u64,
+    previous: u64,
+}
+
+impl Writeback {
+    fn new(every: u64, fsync: bool) -> Self {
+        Writeback { every, fsync, written: 0, window: 0, previous: 0 }
+    }
+
+    // Запись в директории, которую потом сбросит --fsync-dirs, не должна пережить сбой без данных
+    fn finish(&self, file: &File) -> io::Result<()> {
+        match self.fsync {
+            true => file.sync_data(),
+            false => Ok(()),
+        }
+    }
+
+    fn advance(&mut self, file: &File, bytes: usize) {
+        self.written += bytes as u64;
+        if self.every == 0 || self.written - self.window < self.every {
+            return;
+        }
+        // Только подсказки ядру: ошибку записи покажут write и close
+        let fd = file.as_raw_fd();
+        unsafe {
+            libc::sync_file_range(
+                fd,
+                self.window as libc::off64_t,
+                (self.written - self.window) as libc::off64_t,
+                libc::SYNC_FILE_RANGE_WRITE,
+            );
+            if self.window > self.previous {
+                libc::sync_file_range(
+                    fd,
+                    self.previous as libc::off64_t,
+                    (self.window - self.previous) as libc::off64_t,
+                    libc::SYNC_FILE_RANGE_WAIT_BEFORE | libc::SYNC_FILE_RANGE_WRITE | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+                );
+            }
+        }
+        self.previous = self.window;
+        self.window = self.written;
+    }
+}
+
+// Основное назначение - локальный файл или файл на удаленном назначении
+enum DestinationFile {
+    Local(File),
+    Sink(Box<dyn SinkFile>),
+}
+
+impl DestinationFile {
+    fn create(source: &str, destination: &Path, options: &CopyOptions) -> Result<Self> {
+        if let Some(sink) = &options.sink {
+            // Время изменения известно только у локального источника
+            let modified = options
+                .source
+                .is_none()
+                .then(|| fs::metadata(source).and_then(|metadata| metadata.modified()).ok())
+                .flatten();
+            return Ok(DestinationFile::Sink(sink.create(destination, modified)?));
+        }
+
+        create_parent(destination, options)?;
+        // Файл с другими жесткими ссылками (--link-dest, --dedup-dest) не переписываем на месте,
+        // иначе вместе с ним изменятся и они
+        if fs::symlink_metadata(destination).is_ok_and(|metadata| metadata.is_file() && metadata.nlink() > 1) {
+            fs::remove_file(destination)
+                .with_context(|| t!("replace-destination-failed", path = destination.display().to_string()))?;
+        }
+        let file = File::create(destination)
+            .with_context(|| t!("create-destination-failed", path = destination.display().to_string()))?;
+        Ok(DestinationFile::Local(file))
+    }
+
+    // Удаленный файл считается записанным только после подтверждения всех блоков
+    fn close(self) -> Result<()> {
+        match self {
+            DestinationFile::Local(_) => Ok(()),
+            DestinationFile::Sink(file) => file.close(),
+        }
+    }
+}
+
+impl Destinations {
+    // Ошибки дополнительных назначений только показываются. После дыры в конце файла размер
+    // ставится явно: пропущенные нули его не меняют
+    fn close(self) -> Result<()> {
+        for (path, e) in self.tee.finish() {
+            eprintln!("{}", t!("write-failed", path = path.display().to_string(), error = e.to_string()));
+        }
+        if self.sparse
+            && let DestinationFile::Local(file) = &self.file
+        {
+            let mut file = file;
+            let end = file.stream_position()?;
+            file.set_len(end)?;
+        }
+        if let DestinationFile::Local(file) = &self.file {
+            self.writeback.finish(file)?;
+        }
+        self.file.close()
+    }
+}
+
+fn write_sparse(mut file: &File, buf: &[u8]) -> io::Result<()> {
+    for block in buf.chunks(SPARSE_BLOCK) {
+        if block.iter().all(|&byte| byte == 0) {
+            file.seek(SeekFrom::Current(block.len() as i64))?;
+        } else {
+            file.write_all(block)?;
+        }
+    }
+    Ok(())
+}
+
+impl Write for Destinations {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tee.write(buf);
+        match &mut self.file {
+            DestinationFile::Local(file) if self.sparse => {
+                write_sparse(file, buf)?;
+                self.writeback.advance(file, buf.len());
+            }
+            DestinationFile::Local(file) => {
+                file.write_all(buf)?;
+                self.writeback.advance(file, buf.len());
+            }
+            DestinationFile::Sink(file) => file.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            DestinationFile::Local(file) => file.flush(),
+            DestinationFile::Sink(file) => file.flush(),
+        }
+    }
+}
+
+// Возвращает контрольную сумму скопированных данных, если она включена
+pub fn copy_item_with_progress(
+    item: &CopyItem,
+    progress_sender: mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    options: &CopyOptions,
+) -> Result<Option<String>> {
+    // Устаревший дескриптор NFS (файл заменили на сервере) - файл копируется с начала
+    let mut attempt = 0;
+    // Кончились дескрипторы (их держат и другие процессы) - файл ждет, пока они освободятся
+    let mut fd_waits = 0;
+    loop {
+        let result = match options.file_timeout {
+            Some(timeout) => copy_with_timeout(item, progress_sender.clone(), file_id, timeout, options),
+            None => copy_item(item, progress_sender.clone(), file_id, options),
+        };
+        match result {
+            Err(e) if attempt < options.stale_retries && has_os_error(&e, &[libc::ESTALE]) => {
+                attempt += 1;
+                eprintln!("{}", t!("stale-retry", path = item.source.as_str(), attempt = attempt));
+            }
+            Err(e) if fd_waits < FD_WAIT_ATTEMPTS && has_os_error(&e, &[libc::EMFILE, libc::ENFILE]) => {
+                fd_waits += 1;
+                thread::sleep(FD_WAIT);
+            }
+            result => return result,
+        }
+    }
+}
+
+// До 10 секунд ожидания дескрипторов на файл
+const FD_WAIT: Duration = Duration::from_millis(100);
+const FD_WAIT_ATTEMPTS: usize = 100;
+
+fn has_os_error(error: &anyhow::Error, codes: &[i32]) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::raw_os_error)
+            .is_some_and(|code| codes.contains(&code))
+    })
+}
+
+fn create_parent(destination: &Path, options: &CopyOptions) -> Result<()> {
+    let Some(parent) = destination.parent() else {
+        return Ok(());
+    };
+    if let Some(created) = &options.created_dirs
+        && created.lock().unwrap().contains(parent)
+    {
+        return Ok(());
+    }
+    fs::create_dir_all(parent).with_context(|| t!("create-parent-failed", path = parent.display().to_string()))?;
+    if let Some(created) = &options.created_dirs {
+        created.lock().unwrap().insert(parent.to_path_buf());
+    }
+    Ok(())
+}
+
+// --drop-cache: файл больше не нужен в памяти. Грязные страницы ядро не выбрасывает, поэтому
+// копия сначала дописывается на диск. Это подсказка, ошибки не важны
+fn drop_from_cache(item: &CopyItem, drop_cache: DropCache, options: &CopyOptions) {
+    let source = (drop_cache == DropCache::Both && options.source.is_none()).then(|| Path::new(&item.source));
+    let destination = options.sink.is_none().then_some(item.destination.as_path());
+    for path in source.into_iter().chain(destination).chain(item.also_to.iter().map(PathBuf::as_path)) {
+        let Ok(file) = File::open(path) else {
+            continue;
+        };
+        let fd = file.as_raw_fd();
+        unsafe {
+            if Some(path) != source {
+                libc::sync_file_range(
+                    fd,
+                    0,
+                    0,
+                    libc::SYNC_FILE_RANGE_WAIT_BEFORE | libc::SYNC_FILE_RANGE_WRITE | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+                );
+            }
+            libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+}
+
+// --fsync-dirs: записи о файлах в директориях назначения сбрасываются на диск, иначе после
+// сбоя питания скопированный файл может остаться без имени. Каждая директория - один раз, от
+// глубоких к корню; файловые системы без fsync директорий пропускаются
+pub fn sync_directories<'a>(entries: impl IntoIterator<Item = &'a Path>) -> Result<()> {
+    let directories: BTreeSet<&Path> = entries
+        .into_iter()
+        .filter_map(|entry| entry.parent())
+        .map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent })
+        .collect();
+    for directory in directories.into_iter().rev() {
+        let result = File::open(directory).and_then(|directory| directory.sync_all());
+        match result {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::EOPNOTSUPP)) => {}
+            result => result.with_context(|| t!("sync-directory-failed", path = directory.display().to_string()))?,
+        }
+    }
+    Ok(())
+}
+
+// Копируем в отдельном потоке и следим за событиями прогресса: если их нет
+// дольше таймаута (например, завис read на NFS), бросаем файл и идем дальше
+fn copy_with_timeout(
+    item: &CopyItem,
+    progress_sender: mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    timeout: Duration,
+    options: &CopyOptions,
+) -> Result<Option<String>> {
+    let (local_sender, local_receiver) = mpsc::channel();
+    let copy_handle = thread::spawn({
+        let item = item.clone();
+        let options = options.clone();
+        move || copy_item(&item, local_sender, file_id, &options)
+    });
+    let copy_thread = copy_handle.thread().id();
+
+    let mut last_progress = Instant::now();
+    let mut started = false;
+    let result = loop {
+        match local_receiver.recv_timeout(timeout.saturating_sub(last_progress.elapsed())) {
+            Ok(update) => {
+                last_progress = Instant::now();
+                started = true;
+                let _ = progress_sender.send(update);
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                break copy_handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!(t!("copy-thread-panicked"))));
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // Пауза и --bwlimit - не зависание: отсчет идет заново с их конца
+                if let Some(waited) = options.control.waited_until(copy_thread)
+                    && waited > last_progress
+                {
+                    last_progress = waited;
+                    continue;
+                }
+                // Зависший поток не ждем: без получателя прогресса он прервется, как только
+                // проснется. Недописанный файл убираем, чтобы он не выглядел скопированным
+                drop(local_receiver);
+                if started && options.sink.is_none() {
+                    for path in item.destinations() {
+                        let _ = fs::remove_file(path);
+                    }
+                }
+                break Err(anyhow::anyhow!(t!("transfer-stalled", seconds = timeout.as_secs())));
+            }
+        }
+    };
+    options.control.forget_waits(copy_thread);
+    result
+}
+
+fn copy_item(
+    item: &CopyItem,
+    progress_sender: mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    options: &CopyOptions,
+) -> Result<Option<String>> {
+    let source_path = Path::new(&item.source);
+    if options.source.is_none()
+        && let Some(metadata) = &options.metadata
+        && let Some(stat) = metadata.special(source_path)?
+    {
+        copy_special(item, metadata, &stat, &progress_sender, file_id)?;
+        return Ok(None);
+    }
+    if options.sink.is_none()
+        && let Some(metadata) = &options.metadata
+    {
+        metadata.unseal(item.destinations())?;
+    }
+    let is_symlink = match &options.source {
+        Some(source) => source.stat(source_path)?.is_some_and(|entry| entry.kind == EntryKind::Symlink),
+        None => source_path.is_symlink(),
+    };
+
+    if is_symlink {
+        // Копируем символическую ссылку
+        copy_symlink(
+            source_path,
+            &item.destination,
+            &item.also_to,
+            options,
+            progress_sender,
+            file_id,
+        )?;
+        apply_metadata(item, options)?;
+        Ok(None)
+    } else if options.same_server
+        && is_pass_through(&item.also_to, options)
+        && copy_on_server(item, &progress_sender, file_id, options)?
+    {
+        Ok(None)
+    } else {
+        // Копируем обычный файл
+        let accessed = match (&options.source, &options.metadata) {
+            (None, Some(metadata)) => metadata.source_atime(source_path),
+            _ => None,
+        };
+        let digest =
+            copy_file_with_progress(&item.source, &item.destination, &item.also_to, progress_sender, file_id, options)?;
+        if let Some(metadata) = &options.metadata {
+            metadata.restore_atime(source_path, accessed);
+        }
+        if let Some(drop_cache) = options.drop_cache {
+            drop_from_cache(item, drop_cache, options);
+        }
+        if options.preserve_modified && options.sink.is_none() {
+            copy_modified(source_path, &item.destination, options)?;
+        }
+        apply_metadata(item, options)?;
+        Ok(digest)
+    }
+}
+
+fn copy_symlink(
+    source: &Path,
+    destination: &Path,
+    also_to: &[PathBuf],
+    options: &CopyOptions,
+    progress_sender: mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+) -> Result<()> {
+    // Получаем цель символической ссылки
+    let target = match &options.source {
+        Some(remote) => remote.read_link(source)?,
+        None => fs::read_link(source)
+            .with_context(|| t!("read-symlink-failed", path = source.display().to_string()))?,
+    };
+
+    // В объектном хранилище ссылок нет, а подменять ссылку содержимым цели - сюрприз
+    if let Some(sink) = &options.sink {
+        sink.symlink(&target, destination)?;
+    }
+
+    let local_destinations = options.sink.is_none().then_some(destination);
+    for destination in local_destinations.into_iter().chain(also_to.iter().map(PathBuf::as_path)) {
+        create_parent(destination, options)?;
+
+        // Удаляем существующий файл/ссылку, если он есть
+        let _ = std::fs::remove_file(destination);
+
+        // Создаем новую символическую ссылку
+        symlink(&target, destination)
+            .with_context(|| t!("create-symlink-failed", path = destination.display().to_string()))?;
+    }
+
+    // Для символических ссылок отправляем фиктивный размер и сразу завершаем
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: source.to_string_lossy().into_owned(),
+        size: 1, // Фиктивный размер для прогресс-бара
+        id: file_id,
+    });
+
+    let _ = progress_sender.send(ProgressUpdate::Progress {
+        id: file_id,
+        bytes_copied: 1,
+    });
+
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+
+    Ok(())
+}
+
+fn copy_file_with_progress(
+    source: &str,
+    destination: &Path,
+    also_to: &[PathBuf],
+    progress_sender: mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    options: &CopyOptions,
+) -> Result<Option<String>> {
+    // Файл как есть удаленная сторона может передать своим способом: с докачкой,
+    // частями в несколько соединений или дельтой
+    if is_pass_through(also_to, options) {
+        let sent = match (&options.source, &options.sink) {
+            (None, Some(sink)) => transfer_with_progress(source, &progress_sender, file_id, |on_start, on_progress| {
+                sink.send_file(Path::new(source), destination, options.streams_per_file, on_start, on_progress)
+            })?,
+            (Some(remote), None) if options.streams_per_file > 1 => {
+                transfer_with_progress(source, &progress_sender, file_id, |on_start, on_progress| {
+                    remote.download(Path::new(source), destination, options.streams_per_file, on_start, on_progress)
+                })?
+            }
+            _ => false,
+        };
+        if sent {
+            let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+            return Ok(None);
+        }
+    }
+
+    // Размер локального файла, который копируется байт в байт: по нему выбирается быстрый путь
+    let local_size = (options.source.is_none() && options.sink.is_none() && is_unchanged(also_to, options))
+        .then(|| fs::metadata(source).ok().map(|metadata| metadata.len()))
+        .flatten();
+    let split = |size| options.streams_per_file > 1 && size >= options.split_above;
+    if local_size.is_some_and(|size| size <= SMALL_FILE_SIZE && !split(size)) {
+        return copy_small_file(source, destination, &progress_sender, file_id, options);
+    }
+
+    // Огромный локальный файл - частями в несколько потоков (--split-above); контрольная сумма
+    // при этом считается по частям
+    if local_size.is_some_and(split) {
+        let mut digest = None;
+        transfer_with_progress(source, &progress_sender, file_id, |on_start, on_progress| {
+            digest = copy_in_ranges(source, destination, file_id, options, on_start, on_progress)?;
+            Ok(true)
+        })?;
+        let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+        return Ok(digest);
+    }
+
+    // --direct-io: мимо страничного кеша, через выровненный буфер
+    if options.direct_io
+        && let Some(size) = local_size
+    {
+        return copy_direct(source, destination, size, &progress_sender, file_id, options);
+    }
+
+    // Сюда локальный файл байт в байт доходит только целиком и в один поток, и его дыры
+    // можно сохранить (их и учитывает проверка места, см. keeps_holes)
+    let sparse = local_size.is_some()
+        && fs::metadata(source).is_ok_and(|metadata| allocated_bytes(&metadata) < metadata.len());
+
+    let (source_file, file_size) = SourceFile::open(source, options)?;
+
+    let source_compression = if options.decompress {
+        Compression::from_suffix(Path::new(source))
+    } else {
+        Compression::None
+    };
+    let source_read = Rc::new(Cell::new(0));
+    let mut reader = decompressing_reader(
+        CountingReader { inner: source_file, bytes_read: Rc::clone(&source_read) },
+        source_compression,
+    )
+    .with_context(|| t!("decompressor-init-failed", path = source))?;
+
+    let dest_file = DestinationFile::create(source, destination, options)?;
+    let tee = Tee::create(also_to, options.fsync)?;
+    let (compression, level) = options
+        .compress
+        .map_or((Compression::None, None), |compress| (compress.compression, compress.level));
+    // Сначала сжатие, затем шифрование: зашифрованные данные уже не сжимаются
+    let destinations =
+        Destinations { file: dest_file, tee, writeback: Writeback::new(options.writeback_every, options.fsync), sparse };
+    let encrypted = EncryptedWriter::new(destinations, options.encryption.as_ref())?;
+    let compressed =
+        CompressedWriter::with_level(encrypted, compression, level).with_context(|| t!("compressor-init-failed"))?;
+    // Файл больше пары буферов пишется и хешируется в своих потоках, одновременно с чтением
+    // следующего блока
+    let threaded = file_size > 2 * options.buffer_size as u64;
+    let mut writer = PipelinedWriter::new(compressed, threaded);
+
+    // Уведомляем о начале копирования
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: source.to_string(),
+        size: file_size,
+        id: file_id,
+    });
+
+    let mut buffer = vec![0; options.buffer_size];
+    let mut hasher = options.checksum.map(|algorithm| BackgroundHasher::new(algorithm, threaded));
+    let throttle = ProgressThrottle::default();
+
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            // Поврежденный сжатый поток - ошибка файла, а не молча обрезанный результат
+            Err(e) if source_compression != Compression::None => {
+                anyhow::bail!(t!("decompress-failed", path = source, error = e.to_string()));
+            }
+            // Недочитанный файл - ошибка, а не скопированная часть с суммой от нее
+            Err(e) => return Err(e).with_context(|| t!("read-source-failed", path = source)),
+        };
+
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..bytes_read]);
+        }
+        writer
+            .write_all(&buffer[..bytes_read])
+            .with_context(|| t!("write-file-failed", path = destination.display().to_string()))?;
+
+        // Обновляем прогресс; если получателя уже нет, копирование отменено
+        if throttle.ready()
+            && progress_sender
+                .send(ProgressUpdate::Progress {
+                    id: file_id,
+                    bytes_copied: source_read.get(),
+                })
+                .is_err()
+        {
+            anyhow::bail!(t!("copy-cancelled", path = source));
+        }
+        options.control.throttle(bytes_read as u64);
+        // Недописанный файл не оставляем: пропущенный файл не должен выглядеть скопированным
+        if let Some(reason) = options.control.checkpoint(file_id) {
+            drop(writer);
+            if options.sink.is_none() {
+                for path in std::iter::once(destination).chain(also_to.iter().map(PathBuf::as_path)) {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            anyhow::bail!("{}", reason);
+        }
+    }
+
+    let outputs = writer
+        .finish()
+        .with_context(|| t!("write-file-failed", path = destination.display().to_string()))?
+        .finish()
+        .with_context(|| t!("finish-compressed-failed", path = destination.display().to_string()))?
+        .finish()
+        .with_context(|| t!("finish-encrypted-failed", path = destination.display().to_string()))?;
+    outputs
+        .close()
+        .with_context(|| t!("write-file-failed", path = destination.display().to_string()))?;
+
+    let digest = hasher.map(BackgroundHasher::finalize_hex);
+
+    // Сверяем записанное с суммой, посчитанной при чтении источника
+    if options.verify
+        && let (Some(expected), Some(algorithm)) = (digest.as_deref(), options.checksum)
+    {
+        for path in std::iter::once(destination).chain(also_to.iter().map(PathBuf::as_path)) {
+            let actual = hash_file(path, algorithm)?;
+            if actual != expected {
+                anyhow::bail!(t!(
+                    "checksum-mismatch",
+                    path = path.display().to_string(),
+                    expected = expected,
+                    actual = actual.as_str()
+                ));
+            }
+        }
+    }
+
+    // Уведомляем о завершении
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+
+    Ok(digest)
+}
+
+// Атрибуты ставим только локальным копиям
+fn apply_metadata(item: &CopyItem, options: &CopyOptions) -> Result<()> {
+    let Some(metadata) = &options.metadata else {
+        return Ok(());
+    };
+    let local = match options.sink {
+        Some(_) => None,
+        None => Some(item.destination.as_path()),
+    };
+    metadata.apply(Path::new(&item.source), local.into_iter().chain(item.also_to.iter().map(PathBuf::as_path)))
+}
+
+// Устройство, FIFO или сокет (--preserve devices): данных нет, прогресс - как у ссылки
+fn copy_special(
+    item: &CopyItem,
+    metadata: &Metadata,
+    stat: &Stat,
+    progress_sender: &mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+) -> Result<()> {
+    for destination in item.destinations() {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| t!("create-parent-failed", path = parent.display().to_string()))?;
+        }
+        metadata.create_special(stat, destination)?;
+    }
+    metadata.apply(Path::new(&item.source), item.destinations())?;
+
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: item.source.clone(),
+        size: 1,
+        id: file_id,
+    });
+    let _ = progress_sender.send(ProgressUpdate::Progress {
+        id: file_id,
+        bytes_copied: 1,
+    });
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+    Ok(())
+}
+
+// Время изменения удаленного источника известно не всегда
+fn copy_modified(source: &Path, destination: &Path, options: &CopyOptions) -> Result<()> {
+    let modified = match &options.source {
+        Some(remote) => match remote.stat(source)?.and_then(|entry| entry.modified) {
+            Some(modified) => modified,
+            None => return Ok(()),
+        },
+        None => fs::metadata(source)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| t!("read-mtime-failed", path = source.display().to_string()))?,
+    };
+    File::options()
+        .write(true)
+        .open(destination)
+        .and_then(|file| file.set_modified(modified))
+        .with_context(|| t!("set-mtime-failed", path = destination.display().to_string()))
+}
+
+// Данные не идут через эту машину, поэтому прогресс - сразу весь файл
+fn copy_on_server(
+    item: &CopyItem,
+    progress_sender: &mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    options: &CopyOptions,
+) -> Result<bool> {
+    let Some(sink) = &options.sink else {
+        return Ok(false);
+    };
+    if !sink.copy_within(Path::new(&item.source), &item.destination)? {
+        return Ok(false);
+    }
+
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: item.source.clone(),
+        size: item.size,
+        id: file_id,
+    });
+    let _ = progress_sender.send(ProgressUpdate::Progress {
+        id: file_id,
+        bytes_copied: item.size,
+    });
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+    Ok(true)
+}
+
+// Мелкие файлы (до SMALL_FILE_SIZE) копируются одним чтением и одной записью без буфера на
+// файл, а открываются через openat от дескрипторов их директорий, которые поток держит между
+// файлами: файлы одной директории идут подряд (и пачками, см. WorkQueue::next_batch), и путь
+// не разбирается ядром заново для каждого
+pub const SMALL_FILE_SIZE: u64 = 128 * 1024;
+
+// Сохранит ли копирование дыры разреженного файла: только основной путь (не мелкий файл и
+// не частями) в единственное назначение. Остальные условия - локальная копия байт в байт
+// без --direct-io - проверяет вызывающий
+pub fn keeps_holes(item: &CopyItem, streams_per_file: usize, split_above: u64) -> bool {
+    item.also_to.is_empty()
+        && item.size > SMALL_FILE_SIZE
+        && !(streams_per_file > 1 && item.size >= split_above)
+}
+
+thread_local! {
+    // Директория последнего источника и последнего назначения этого потока
+    static SOURCE_DIR: RefCell<Option<(PathBuf, File)>> = const { RefCell::new(None) };
+    static DESTINATION_DIR: RefCell<Option<(PathBuf, File)>> = const { RefCell::new(None) };
+}
+
+fn copy_small_file(
+    source: &str,
+    destination: &Path,
+    progress_sender: &mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    options: &CopyOptions,
+) -> Result<Option<String>> {
+    let mut source_file = match open_at(&SOURCE_DIR, Path::new(source), libc::O_RDONLY | libc::O_NOATIME) {
+        // O_NOATIME разрешен только владельцу файла
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => open_at(&SOURCE_DIR, Path::new(source), libc::O_RDONLY),
+        result => result,
+    }
+    .with_context(|| t!("open-source-failed", path = source))?;
+    let mut data = Vec::with_capacity(SMALL_FILE_SIZE as usize);
+    source_file
+        .read_to_end(&mut data)
+        .with_context(|| t!("read-source-failed", path = source))?;
+    drop(source_file);
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: source.to_string(),
+        size: data.len() as u64,
+        id: file_id,
+    });
+
+    let flags = libc::O_WRONLY | libc::O_CREAT;
+    let mut destination_file = match open_at(&DESTINATION_DIR, destination, flags) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            create_parent(destination, options)?;
+            open_at(&DESTINATION_DIR, destination, flags)
+        }
+        result => result,
+    }
+    .with_context(|| t!("create-destination-failed", path = destination.display().to_string()))?;
+    // Файл с другими жесткими ссылками не переписываем на месте, как и DestinationFile::create.
+    // Устройство (/dev/null) только пишется: длины у него нет
+    let metadata = destination_file.metadata()?;
+    let regular = metadata.is_file();
+    if regular && metadata.nlink() > 1 {
+        fs::remove_file(destination)
+            .with_context(|| t!("replace-destination-failed", path = destination.display().to_string()))?;
+        destination_file = open_at(&DESTINATION_DIR, destination, flags)
+            .with_context(|| t!("create-destination-failed", path = destination.display().to_string()))?;
+    }
+    destination_file
+        .write_all(&data)
+        .and_then(|()| match regular {
+            true => destination_file.set_len(data.len() as u64),
+            false => Ok(()),
+        })
+        .and_then(|()| match regular && options.fsync {
+            true => destination_file.sync_data(),
+            false => Ok(()),
+        })
+        .with_context(|| t!("write-file-failed", path = destination.display().to_string()))?;
+    drop(destination_file);
+
+    options.control.throttle(data.len() as u64);
+    if let Some(reason) = options.control.checkpoint(file_id) {
+        let _ = fs::remove_file(destination);
+        anyhow::bail!("{}", reason);
+    }
+    let _ = progress_sender.send(ProgressUpdate::Progress {
+        id: file_id,
+        bytes_copied: data.len() as u64,
+    });
+
+    let digest = options.checksum.map(|algorithm| {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(&data);
+        hasher.finalize_hex()
+    });
+    if options.verify
+        && let (Some(expected), Some(algorithm)) = (digest.as_deref(), options.checksum)
+    {
+        let actual = hash_file(destination, algorithm)?;
+        if actual != expected {
+            anyhow::bail!(t!("checksum-mismatch", path = destination.display().to_string(), expected = expected, actual = actual.as_str()));
+        }
+    }
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+    Ok(digest)
+}
+
+// Локальный файл как есть с O_DIRECT (--direct-io). Чтение и запись идут блоками буфера,
+// кратными DIRECT_IO_ALIGN; короткий хвост в конце файла дописывается уже без O_DIRECT
+fn copy_direct(
+    source: &str,
+    destination: &Path,
+    size: u64,
+    progress_sender: &mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    options: &CopyOptions,
+) -> Result<Option<String>> {
+    let open = |flags: libc::c_int| fs::OpenOptions::new().read(true).custom_flags(flags).open(source);
+    let mut source_file = match open(libc::O_DIRECT | libc::O_NOATIME) {
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => open(libc::O_DIRECT),
+        result => result,
+    };
+    if let Err(e) = &source_file
+        && direct::is_unsupported(e)
+    {
+        source_file = open_source(source);
+    }
+    let mut source_file = source_file.with_context(|| t!("open-source-failed", path = source))?;
+
+    create_parent(destination, options)?;
+    if fs::symlink_metadata(destination).is_ok_and(|metadata| metadata.is_file() && metadata.nlink() > 1) {
+        fs::remove_file(destination)
+            .with_context(|| t!("replace-destination-failed", path = destination.display().to_string()))?;
+    }
+    let create = |flags: libc::c_int| {
+        fs::OpenOptions::new().write(true).create(true).truncate(true).custom_flags(flags).open(destination)
+    };
+    let mut destination_file = match create(libc::O_DIRECT) {
+        Err(e) if direct::is_unsupported(&e) => create(0),
+        result => result,
+    }
+    .with_context(|| t!("create-destination-failed", path = destination.display().to_string()))?;
+
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: source.to_string(),
+        size,
+        id: file_id,
+    });
+    let mut buffer = AlignedBuffer::new(options.buffer_size);
+    let mut hasher = options.checksum.map(|algorithm| BackgroundHasher::new(algorithm, size > 2 * buffer.len() as u64));
+    let throttle = ProgressThrottle::default();
+    let mut copied = 0;
+    loop {
+        let bytes_read = match source_file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            // Короткое чтение посреди файла сбило выравнивание смещения: дочитываем через кеш
+            Err(e) if direct::is_unsupported(&e) => {
+                direct::clear_direct(&source_file).with_context(|| t!("read-source-failed", path = source))?;
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| t!("read-source-failed", path = source)),
+        };
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..bytes_read]);
+        }
+        // Целые блоки - напрямую, неполный последний - через кеш
+        let aligned = bytes_read - bytes_read % DIRECT_IO_ALIGN;
+        let mut result = destination_file.write_all(&buffer[..aligned]);
+        if result.is_ok() && aligned < bytes_read {
+            result = direct::clear_direct(&destination_file)
+                .and_then(|()| destination_file.write_all(&buffer[aligned..bytes_read]));
+        }
+        result.with_context(|| t!("write-file-failed", path = destination.display().to_string()))?;
+        copied += bytes_read as u64;
+
+        if throttle.ready()
+            && progress_sender.send(ProgressUpdate::Progress { id: file_id, bytes_copied: copied }).is_err()
+        {
+            anyhow::bail!(t!("copy-cancelled", path = source));
+        }
+        options.control.throttle(bytes_read as u64);
+        if let Some(reason) = options.control.checkpoint(file_id) {
+            drop(destination_file);
+            let _ = fs::remove_file(destination);
+            anyhow::bail!("{}", reason);
+        }
+    }
+    // Длина - ровно прочитанное, каким бы путем ни записался хвост
+    destination_file
+        .set_len(copied)
+        .and_then(|()| match options.fsync {
+            true => destination_file.sync_data(),
+            false => Ok(()),
+        })
+        .with_context(|| t!("write-file-failed", path = destination.display().to_string()))?;
+    drop(destination_file);
+
+    let digest = hasher.map(BackgroundHasher::finalize_hex);
+    if options.verify
+        && let (Some(expected), Some(algorithm)) = (digest.as_deref(), options.checksum)
+    {
+        let actual = hash_file(destination, algorithm)?;
+        if actual != expected {
+            anyhow::bail!(t!("checksum-mismatch", path = destination.display().to_string(), expected = expected, actual = actual.as_str()));
+        }
+    }
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+    Ok(digest)
+}
+
+// openat от дескриптора директории файла; дескриптор остается до файла из другой директории
+fn open_at(
+    directory: &'static LocalKey<RefCell<Option<(PathBuf, File)>>>,
+    path: &Path,
+    flags: libc::c_int,
+) -> io::Result<File> {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let name = path.file_name().ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let name = CString::new(name.as_bytes())?;
+    directory.with_borrow_mut(|cached| {
+        if cached.as_ref().is_none_or(|(cached, _)| cached != parent) {
+            let opened = File::open(if parent.as_os_str().is_empty() { Path::new(".") } else { parent })?;
+            *cached = Some((parent.to_path_buf(), opened));
+        }
+        let (_, opened) = cached.as_ref().expect("directory is open");
+        let fd = unsafe { libc::openat(opened.as_raw_fd(), name.as_ptr(), flags | libc::O_CLOEXEC, 0o666) };
+        match fd {
+            -1 => Err(io::Error::last_os_error()),
+            fd => Ok(unsafe { File::from_raw_fd(fd) }),
+        }
+    })
+}
+
+// Большой локальный файл копируется частями по SPLIT_CHUNK_SIZE в несколько потоков: pread и
+// pwrite в заранее выделенный файл назначения. С --verify у каждой части своя сумма: проверка
+// копии тоже идет параллельно и называет испорченные диапазоны
+const SPLIT_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+// Сумма всего файла (для манифеста)
+fn copy_in_ranges(
+    source: &str,
+    destination: &Path,
+    file_id: u32,
+    options: &CopyOptions,
+    on_start: &dyn Fn(u64),
+    on_progress: Progress,
+) -> Result<Option<String>> {
+    let source_file = open_source(source).with_context(|| t!("open-source-failed", path = source))?;
+    let size = source_file.metadata()?.len();
+    let DestinationFile::Local(destination_file) = DestinationFile::create(source, destination, options)? else {
+        unreachable!("split copies have a local destination");
+    };
+    preallocate(&destination_file, size)
+        .with_context(|| t!("allocate-failed", size = size.to_string(), path = destination.display().to_string()))?;
+    let direct = match options.direct_io {
+        true => open_direct_pair(source, destination)?,
+        false => None,
+    };
+    on_start(size);
+
+    let copied = AtomicU64::new(0);
+    let verified = options.checksum.filter(|_| options.verify);
+    // Начало части -> ее сумма
+    let digests = Mutex::new(HashMap::new());
+    let result = for_each_chunk(size, options.streams_per_file, |range| {
+        let start = range.start;
+        let files = (&source_file, &destination_file, direct.as_ref());
+        let digest = copy_range(files, range.clone(), file_id, options, verified, &copied, on_progress)?;
+        // Готовая часть сразу уходит на диск, не дожидаясь конца файла
+        if options.writeback_every > 0 {
+            unsafe {
+                libc::sync_file_range(
+                    destination_file.as_raw_fd(),
+                    range.start as libc::off64_t,
+                    (range.end - range.start) as libc::off64_t,
+                    libc::SYNC_FILE_RANGE_WRITE,
+                );
+            }
+        }
+        if let Some(digest) = digest {
+            digests.lock().unwrap().insert(start, digest);
+        }
+        Ok(())
+    });
+    // Хвост с O_DIRECT записан целым блоком: лишнее отрезаем
+    let result = result.and_then(|()| match direct {
+        Some(_) => destination_file.set_len(size).with_context(|| t!("set-length-failed")),
+        None => Ok(()),
+    });
+    let result = result.and_then(|()| match options.fsync {
+        true => destination_file.sync_data().with_context(|| t!("sync-file-failed")),
+        false => Ok(()),
+    });
+    drop(destination_file);
+    // Недописанный файл не оставляем, как и при обычном копировании
+    if let Err(e) = result {
+        let _ = fs::remove_file(destination);
+        return Err(e).with_context(|| t!("copy-file-failed", path = source));
+    }
+
+    if let Some(algorithm) = verified {
+        verify_chunks(destination, size, options.streams_per_file, algorithm, &digests.into_inner().unwrap())?;
+    }
+    match options.checksum.filter(|_| options.manifest) {
+        Some(algorithm) => hash_file(destination, algorithm).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Части по очереди разбирают streams потоков; ошибка в одном останавливает остальные
+fn for_each_chunk(size: u64, streams: usize, work: impl Fn(Range<u64>) -> Result<()> + Sync) -> Result<()> {
+    let chunks = size.div_ceil(SPLIT_CHUNK_SIZE);
+    let next = AtomicU64::new(0);
+    let failed = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..streams.min(chunks as usize))
+            .map(|_| {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= chunks || failed.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    let start = index * SPLIT_CHUNK_SIZE;
+                    if let Err(e) = work(start..(start + SPLIT_CHUNK_SIZE).min(size)) {
+                        failed.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap_or_else(|_| Err(anyhow::anyhow!(t!("copy-thread-panicked")))))
+    })
+}
+
+// --direct-io при копировании частями: у частей свои дескрипторы источника и назначения с
+// O_DIRECT рядом с обычными. None - файловая система без O_DIRECT, части идут через кеш
+fn open_direct_pair(source: &str, destination: &Path) -> Result<Option<(File, File)>> {
+    let open = |flags: libc::c_int| fs::OpenOptions::new().read(true).custom_flags(flags).open(source);
+    let source_file = match open(libc::O_DIRECT | libc::O_NOATIME) {
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => open(libc::O_DIRECT),
+        result => result,
+    };
+    let destination_file = fs::OpenOptions::new().write(true).custom_flags(libc::O_DIRECT).open(destination);
+    match (source_file, destination_file) {
+        (Ok(source_file), Ok(destination_file)) => Ok(Some((source_file, destination_file))),
+        (Err(e), _) if direct::is_unsupported(&e) => Ok(None),
+        (_, Err(e)) if direct::is_unsupported(&e) => Ok(None),
+        (Err(e), _) => Err(e).with_context(|| t!("open-source-failed", path = source)),
+        (_, Err(e)) => Err(e).with_context(|| t!("open-destination-failed", path = destination.display().to_string())),
+    }
+}
+
+// files - источник, назначение и их пара с O_DIRECT. Начала частей кратны SPLIT_CHUNK_SIZE, так
+// что с O_DIRECT идут все целые блоки; неполный блок в конце файла пишется целым (лишнее
+// отрезает copy_in_ranges), а после короткого чтения посреди части - через кеш
+fn copy_range(
+    (source, destination, direct): (&File, &File, Option<&(File, File)>),
+    range: Range<u64>,
+    file_id: u32,
+    options: &CopyOptions,
+    checksum: Option<ChecksumAlgorithm>,
+    copied: &AtomicU64,
+    on_progress: Progress,
+) -> Result<Option<String>> {
+    let (mut aligned, mut plain);
+    let buffer: &mut [u8] = match direct {
+        Some(_) => {
+            aligned = AlignedBuffer::new(options.buffer_size);
+            &mut aligned
+        }
+        None => {
+            plain = vec![0; options.buffer_size];
+            &mut plain
+        }
+    };
+    let mut hasher = checksum.map(Hasher::new);
+    let mut position = range.start;
+    while position < range.end {
+        let length = buffer.len().min((range.end - position) as usize);
+        let direct = direct.filter(|_| position.is_multiple_of(DIRECT_IO_ALIGN as u64));
+        let bytes_read = match direct {
+            Some((source, _)) => source.read_at(&mut buffer[..length.next_multiple_of(DIRECT_IO_ALIGN)], position)?.min(length),
+            None => source.read_at(&mut buffer[..length], position)?,
+        };
+        if bytes_read == 0 {
+            anyhow::bail!(t!("file-shrank-during-copy"));
+        }
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..bytes_read]);
+        }
+        let whole = match direct {
+            Some(_) if position + bytes_read as u64 == range.end => bytes_read.next_multiple_of(DIRECT_IO_ALIGN),
+            Some(_) => bytes_read - bytes_read % DIRECT_IO_ALIGN,
+            None => 0,
+        };
+        if let Some((_, direct_destination)) = direct {
+            if whole > bytes_read {
+                buffer[bytes_read..whole].fill(0);
+            }
+            direct_destination.write_all_at(&buffer[..whole], position)?;
+        }
+        if whole < bytes_read {
+            destination.write_all_at(&buffer[whole..bytes_read], position + whole as u64)?;
+        }
+        position += bytes_read as u64;
+        on_progress(copied.fetch_add(bytes_read as u64, Ordering::Relaxed) + bytes_read as u64)?;
+        options.control.throttle(bytes_read as u64);
+        if let Some(reason) = options.control.checkpoint(file_id) {
+            anyhow::bail!("{}", reason);
+        }
+    }
+    Ok(hasher.map(Hasher::finalize_hex))
+}
+
+// Перечитывает части копии параллельно; в ошибке - все части, чья сумма не совпала
+fn verify_chunks(
+    path: &Path,
+    size: u64,
+    streams: usize,
+    algorithm: ChecksumAlgorithm,
+    digests: &HashMap<u64, String>,
+) -> Result<()> {
+    let file = open_source(path).with_context(|| t!("verify-open-failed", path = path.display().to_string()))?;
+    let mismatched = Mutex::new(Vec::new());
+    for_each_chunk(size, streams, |range| {
+        let actual = hash_range(&file, range.clone(), algorithm)
+            .with_context(|| t!("verify-read-failed", path = path.display().to_string()))?;
+        if digests.get(&range.start) != Some(&actual) {
+            mismatched.lock().unwrap().push(range);
+        }
+        Ok(())
+    })?;
+    let mut mismatched = mismatched.into_inner().unwrap();
+    if mismatched.is_empty() {
+        return Ok(());
+    }
+    mismatched.sort_by_key(|range| range.start);
+    let ranges: Vec<String> = mismatched
+        .iter()
+        .map(|range| format!("{}-{}", range.start, range.end - 1))
+        .collect();
+    anyhow::bail!(t!("checksum-mismatch-ranges", path = path.display().to_string(), ranges = ranges.join(", ")));
+}
+
+// Место под весь файл сразу: экстенты идут подряд, и записи из разных потоков не дробят файл
+fn preallocate(file: &File, size: u64) -> io::Result<()> {
+    if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, size as libc::off_t) } != 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EOPNOTSUPP) {
+            return Err(error);
+        }
+    }
+    file.set_len(size)
+}
+
+// Своим способом передается только файл как есть: без преобразований и без
+// контрольной суммы копии
+fn is_pass_through(also_to: &[PathBuf], options: &CopyOptions) -> bool {
+    options.checksum.is_none() && is_unchanged(also_to, options)
+}
+
+// Копия байт в байт в одно назначение
+fn is_unchanged(also_to: &[PathBuf], options: &CopyOptions) -> bool {
+    also_to.is_empty() && options.compress.is_none() && options.encryption.is_none() && !options.decompress
+}
+
+// Прогресс передачи, которую ведет сама удаленная сторона (возможно, из нескольких потоков)
+fn transfer_with_progress(
+    source: &str,
+    progress_sender: &mpsc::Sender<ProgressUpdate>,
+    file_id: u32,
+    transfer: impl FnOnce(&dyn Fn(u64), Progress) -> Result<bool>,
+) -> Result<bool> {
+    let on_start = |size| {
+        let _ = progress_sender.send(ProgressUpdate::NewFile {
+            path: source.to_string(),
+            size,
+            id: file_id,
+        });
+    };
+    let progress = Mutex::new((progress_sender.clone(), ProgressThrottle::default()));
+    let on_progress = |bytes_copied| {
+        let (progress_sender, throttle) = &*progress.lock().unwrap();
+        if !throttle.ready() {
+            return Ok(());
+        }
+        progress_sender
+            .send(ProgressUpdate::Progress { id: file_id, bytes_copied })
+            .map_err(|_| anyhow::anyhow!(t!("copy-cancelled", path = source)))
+    };
+    transfer(&on_start, &on_progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_errors_fail_the_copy() {
+        let source = std::env::temp_dir().join(format!("rcp-copy-full-{}", std::process::id()));
+        fs::write(&source, vec![7; 2 * SMALL_FILE_SIZE as usize]).unwrap();
+        let options = CopyOptions { buffer_size: 4096, ..Default::default() };
+        let (sender, _receiver) = mpsc::channel();
+
+        // Запись в /dev/full всегда кончается ENOSPC: файл не должен считаться скопированным
+        let result = copy_file_with_progress(source.to_str().unwrap(), Path::new("/dev/full"), &[], sender, 0, &options);
+        fs::remove_file(&source).unwrap();
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains(&t!("write-file-failed", path = "/dev/full")), "{}", error);
+    }
+
+    #[test]
+    fn pause_does_not_count_towards_the_file_timeout() {
+        let dir = std::env::temp_dir().join(format!("rcp-copy-pause-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source");
+        fs::write(&source, vec![7; 4 * SMALL_FILE_SIZE as usize]).unwrap();
+        let item = CopyItem::with_size(&source, vec![dir.join("copy")], 4 * SMALL_FILE_SIZE);
+        let options = CopyOptions { buffer_size: 4096, ..Default::default() };
+        let (sender, _receiver) = mpsc::channel();
+
+        // Пауза дольше таймаута: файл должен докопироваться, когда ее снимут
+        options.control.toggle_pause();
+        let control = Arc::clone(&options.control);
+        let resume = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(2500));
+            control.toggle_pause();
+        });
+        let result = copy_with_timeout(&item, sender, 0, Duration::from_secs(1), &options);
+        resume.join().unwrap();
+        assert!(result.is_ok(), "{:#}", result.unwrap_err());
+        assert_eq!(fs::read(dir.join("copy")).unwrap(), fs::read(&source).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sparse_file_keeps_its_holes() {
+        let dir = std::env::temp_dir().join(format!("rcp-copy-sparse-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source");
+        // Дыра, немного данных и дыра в конце
+        let file = File::create(&source).unwrap();
+        file.write_all_at(&[7; 8192], 1 << 20).unwrap();
+        file.set_len(3 << 20).unwrap();
+        let metadata = file.metadata().unwrap();
+        if allocated_bytes(&metadata) == metadata.len() {
+            // Файловая система без дыр
+            return fs::remove_dir_all(&dir).unwrap();
+        }
+        let options = CopyOptions { buffer_size: 64 * 1024, ..Default::default() };
+        let (sender, _receiver) = mpsc::channel();
+
+        let item = CopyItem::with_size(&source, vec![dir.join("copy")], 3 << 20);
+        copy_item(&item, sender, 0, &options).unwrap();
+        let copy = fs::metadata(dir.join("copy")).unwrap();
+        assert_eq!(copy.len(), 3 << 20);
+        assert!(allocated_bytes(&copy) < 1 << 20, "{} bytes allocated", allocated_bytes(&copy));
+        assert_eq!(fs::read(dir.join("copy")).unwrap(), fs::read(&source).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn small_file_can_be_written_to_a_device() {
+        let source = std::env::temp_dir().join(format!("rcp-copy-device-{}", std::process::id()));
+        fs::write(&source, b"data").unwrap();
+        let item = CopyItem::with_size(&source, vec![PathBuf::from("/dev/null")], 4);
+        let (sender, _receiver) = mpsc::channel();
+
+        let result = copy_item(&item, sender, 0, &CopyOptions::default());
+        fs::remove_file(&source).unwrap();
+        assert!(result.is_ok(), "{:#}", result.unwrap_err());
+    }
+
+    #[test]
+    fn direct_io_copies_keep_the_exact_length() {
+        let dir = std::env::temp_dir().join(format!("rcp-copy-direct-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source");
+        let data: Vec<u8> = (0..(1 << 20) + 123).map(|n: u32| (n % 251) as u8).collect();
+        fs::write(&source, &data).unwrap();
+
+        // Один поток (copy_direct) и частями (copy_in_ranges)
+        for streams_per_file in [1, 2] {
+            let options = CopyOptions { direct_io: true, streams_per_file, split_above: 0, buffer_size: 64 * 1024, ..Default::default() };
+            let item = CopyItem::with_size(&source, vec![dir.join("copy")], data.len() as u64);
+            let (sender, _receiver) = mpsc::channel();
+            copy_item(&item, sender, 0, &options).unwrap();
+            assert!(fs::read(dir.join("copy")).unwrap() == data, "{} streams", streams_per_file);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,520 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde_json::{json, Value};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cli::{AttachArgs, CancelArgs, DaemonArgs, ListArgs, SubmitArgs};
+use crate::config::Defaults;
+use crate::i18n::t;
+use crate::session::state_dir;
+use crate::systemd;
+
+// Фоновая очередь заданий копирования, как task-spooler. `rcp daemon` слушает Unix-сокет;
+// `rcp submit` присылает аргументы задания вместе со своим рабочим каталогом и окружением,
+// демон запускает с ними `rcp`, не больше --max-jobs сразу, и пишет вывод в
+// $XDG_STATE_HOME/rcp/jobs/<ID>.log. Запрос и ответ - по строке JSON; за ответом на attach
+// идет вывод задания, пока оно не закончится
+
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+impl State {
+    fn as_str(self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Running => "running",
+            State::Finished => "finished",
+            State::Failed => "failed",
+            State::Cancelled => "cancelled",
+        }
+    }
+
+    fn is_over(self) -> bool {
+        !matches!(self, State::Queued | State::Running)
+    }
+}
+
+struct Job {
+    id: u64,
+    args: Vec<String>,
+    directory: PathBuf,
+    env: Vec<(String, String)>,
+    priority: i32,
+    state: State,
+    exit_code: Option<i32>,
+    pid: Option<u32>,
+    // Отмененное на ходу задание после выхода считается отмененным, а не упавшим
+    cancelling: bool,
+    log: PathBuf,
+}
+
+impl Job {
+    fn describe(&self) -> Value {
+        json!({
+            "id": self.id,
+            "state": self.state.as_str(),
+            "priority": self.priority,
+            "exit_code": self.exit_code,
+            "args": self.args,
+            "directory": self.directory,
+            "log": self.log,
+        })
+    }
+}
+
+struct Queue {
+    jobs: Vec<Job>,
+    next_id: u64,
+    max_jobs: usize,
+    jobs_dir: PathBuf,
+}
+
+// Очередь и сигнал планировщику, что в ней что-то изменилось
+type Shared = Arc<(Mutex<Queue>, Condvar)>;
+
+// $XDG_RUNTIME_DIR/rcp/daemon.sock, без него - рядом с остальным состоянием
+fn socket_path() -> Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("rcp"),
+        None => state_dir()?,
+    };
+    Ok(dir.join("daemon.sock"))
+}
+
+pub fn serve(args: &DaemonArgs) -> Result<()> {
+    let path = socket_path()?;
+    if UnixStream::connect(&path).is_ok() {
+        anyhow::bail!("rcp daemon is already running on {}", path.display());
+    }
+    if let Some(dir) = path.parent() {
+        // В сокет приходит окружение заданий, поэтому он только для владельца
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).with_context(|| format!("Failed to listen on {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    let jobs_dir = state_dir()?.join("jobs");
+    fs::create_dir_all(&jobs_dir).with_context(|| format!("Failed to create directory: {}", jobs_dir.display()))?;
+    println!("{}", t!("daemon-listening", socket = path.display().to_string(), jobs = args.max_jobs));
+
+    let shared: Shared = Arc::new((
+        Mutex::new(Queue {
+            jobs: Vec::new(),
+            next_id: 1,
+            max_jobs: args.max_jobs,
+            jobs_dir,
+        }),
+        Condvar::new(),
+    ));
+    {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || schedule(&shared));
+    }
+    // Задания - отдельные процессы, поэтому в строке состояния только их число по состояниям
+    systemd::ready({
+        let shared = Arc::clone(&shared);
+        move || {
+            let queue = shared.0.lock().unwrap();
+            let count = |state: State| queue.jobs.iter().filter(|job| job.state == state).count();
+            t!(
+                "systemd-daemon",
+                running = count(State::Running),
+                queued = count(State::Queued),
+                finished = count(State::Finished),
+                failed = count(State::Failed)
+            )
+        }
+    });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{}", t!("accept-failed", error = e.to_string()));
+                continue;
+            }
+        };
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &shared) {
+                eprintln!("{} {:#}", t!("error").red().bold(), e);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Запускает задания из очереди, старшие по приоритету первыми, при равном - по порядку,
+// пока не занято --max-jobs. Задание приоритетнее всех идущих не ждет, пока те закончатся
+fn schedule(shared: &Shared) {
+    let (queue, changed) = &**shared;
+    let mut queue = queue.lock().unwrap();
+    loop {
+        let running: Vec<i32> = queue
+            .jobs
+            .iter()
+            .filter(|job| job.state == State::Running)
+            .map(|job| job.priority)
+            .collect();
+        let next = queue
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.state == State::Queued)
+            .max_by_key(|(index, job)| (job.priority, std::cmp::Reverse(*index)))
+            .map(|(index, job)| (index, job.priority));
+        match next {
+            Some((index, priority))
+                if running.len() < queue.max_jobs || running.iter().all(|&running| priority > running) =>
+            {
+                start(shared, &mut queue.jobs[index])
+            }
+            _ => queue = changed.wait(queue).unwrap(),
+        }
+    }
+}
+
+fn start(shared: &Shared, job: &mut Job) {
+    // Первый источник с именем режима (`rcp submit serve dest`) иначе запустил бы этот режим
+    let mut args = job.args.clone();
+    if let Some(first) = args.first_mut()
+        && crate::is_subcommand(OsStr::new(first.as_str()))
+    {
+        *first = format!("./{}", first);
+    }
+    let child = File::create(&job.log).and_then(|log| {
+        let mut command = Command::new(std::env::current_exe()?);
+        command
+            .args(&args)
+            .current_dir(&job.directory)
+            .env_clear()
+            .envs(job.env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+        // У каждого задания свой отчет о сбое, а не общий на всех
+        if !job.env.iter().any(|(name, _)| name == "RCP_FAILURE_REPORT") {
+            let report = job.log.with_extension("failure.json");
+            command.env("RCP_FAILURE_REPORT", report);
+        }
+        command.spawn()
+    });
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("{}", t!("job-start-failed", id = job.id, error = e.to_string()));
+            job.state = State::Failed;
+            return;
+        }
+    };
+    println!("{}", t!("job-started", id = job.id, command = command_line(&job.args)));
+    job.state = State::Running;
+    job.pid = Some(child.id());
+
+    let id = job.id;
+    let shared = Arc::clone(shared);
+    thread::spawn(move || {
+        let status = child.wait();
+        let (queue, changed) = &*shared;
+        let mut queue = queue.lock().unwrap();
+        if let Some(job) = queue.jobs.iter_mut().find(|job| job.id == id) {
+            let code = match status {
+                Ok(status) => status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1),
+                Err(_) => 1,
+            };
+            job.exit_code = Some(code);
+            job.pid = None;
+            job.state = match (job.cancelling, code) {
+                (true, _) => State::Cancelled,
+                (false, 0) => State::Finished,
+                (false, _) => State::Failed,
+            };
+            println!("{}", t!("job-ended", id = id, state = t!("job-state", state = job.state.as_str()), code = code));
+        }
+        changed.notify_all();
+    });
+}
+
+fn handle(mut stream: UnixStream, shared: &Shared) -> Result<()> {
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut line)?;
+    let request: Value = serde_json::from_str(&line).context("Malformed request")?;
+    let id = request["id"].as_u64();
+    let reply = match (request["command"].as_str(), id) {
+        (Some("submit"), _) => add_job(&request, shared),
+        (Some("list"), _) => {
+            let queue = shared.0.lock().unwrap();
+            Ok(json!({ "jobs": queue.jobs.iter().map(Job::describe).collect::<Vec<_>>() }))
+        }
+        (Some("cancel"), Some(id)) => cancel_job(id, shared),
+        (Some("attach"), Some(id)) => return follow_job(id, stream, shared),
+        _ => Err(anyhow::anyhow!("Unknown request")),
+    };
+    let reply = reply.unwrap_or_else(|e| json!({ "error": format!("{:#}", e) }));
+    writeln!(stream, "{}", reply)?;
+    Ok(())
+}
+
+fn add_job(request: &Value, shared: &Shared) -> Result<Value> {
+    let strings = |value: &Value| -> Option<Vec<String>> {
+        value.as_array()?.iter().map(|item| item.as_str().map(str::to_string)).collect()
+    };
+    let args = strings(&request["args"]).context("Malformed job arguments")?;
+    let directory = PathBuf::from(request["directory"].as_str().context("Malformed job directory")?);
+    let priority = request["priority"].as_i64().and_then(|priority| i32::try_from(priority).ok()).unwrap_or(0);
+    let env = request["env"]
+        .as_object()
+        .context("Malformed job environment")?
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+        .collect();
+
+    let (queue, changed) = &**shared;
+    let mut queue = queue.lock().unwrap();
+    let id = queue.next_id;
+    queue.next_id += 1;
+    let log = queue.jobs_dir.join(format!("{}.log", id));
+    queue.jobs.push(Job {
+        id,
+        args,
+        directory,
+        env,
+        priority,
+        state: State::Queued,
+        exit_code: None,
+        pid: None,
+        cancelling: false,
+        log,
+    });
+    changed.notify_all();
+    Ok(json!({ "id": id }))
+}
+
+fn cancel_job(id: u64, shared: &Shared) -> Result<Value> {
+    let (queue, changed) = &**shared;
+    let mut queue = queue.lock().unwrap();
+    let job = queue.jobs.iter_mut().find(|job| job.id == id).with_context(|| format!("No job {}", id))?;
+    match job.state {
+        State::Queued => {
+            job.state = State::Cancelled;
+            changed.notify_all();
+        }
+        // Задание останавливается так же, как по Ctrl-C, и пишет отчет о сбое
+        State::Running => {
+            job.cancelling = true;
+            if let Some(pid) = job.pid {
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            }
+        }
+        state => anyhow::bail!("Job {} is already {}", id, state.as_str()),
+    }
+    Ok(json!({ "id": id, "state": job.state.as_str() }))
+}
+
+// Вывод задания с начала и дальше по мере появления, пока задание не закончится
+fn follow_job(id: u64, mut stream: UnixStream, shared: &Shared) -> Result<()> {
+    let log = {
+        let queue = shared.0.lock().unwrap();
+        match queue.jobs.iter().find(|job| job.id == id) {
+            Some(job) => job.log.clone(),
+            None => {
+                writeln!(stream, "{}", json!({ "error": format!("No job {}", id) }))?;
+                return Ok(());
+            }
+        }
+    };
+    writeln!(stream, "{}", json!({ "id": id }))?;
+    let mut position = 0;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let over = {
+            let queue = shared.0.lock().unwrap();
+            queue.jobs.iter().find(|job| job.id == id).is_none_or(|job| job.state.is_over())
+        };
+        // Задание в очереди журнала еще не завело
+        if let Ok(mut file) = File::open(&log) {
+            file.seek(SeekFrom::Start(position))?;
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                stream.write_all(&buffer[..read])?;
+                position += read as u64;
+            }
+        }
+        if over {
+            return Ok(());
+        }
+        thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+// Без запущенного демона submit запускает его сам, отвязанным от терминала
+fn connect(start: bool) -> Result<UnixStream> {
+    let path = socket_path()?;
+    if let Ok(stream) = UnixStream::connect(&path) {
+        return Ok(stream);
+    }
+    if !start {
+        anyhow::bail!("rcp daemon is not running (no socket at {})", path.display());
+    }
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("daemon.log"))
+        .context("Failed to open the daemon log")?;
+    let mut command = Command::new(std::env::current_exe()?);
+    command.arg("daemon").stdin(Stdio::null()).stdout(log.try_clone()?).stderr(log);
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    command.spawn().context("Failed to start rcp daemon")?;
+
+    let started = Instant::now();
+    loop {
+        match UnixStream::connect(&path) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if started.elapsed() > START_TIMEOUT => {
+                return Err(e).with_context(|| format!("rcp daemon did not start; see {}", dir.join("daemon.log").display()));
+            }
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+// Ответ демона и соединение, в котором после ответа может идти вывод задания
+fn request(stream: UnixStream, request: Value) -> Result<(Value, BufReader<UnixStream>)> {
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "{}", request).context("Failed to send the request to rcp daemon")?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).context("Failed to read the reply of rcp daemon")?;
+    let reply: Value = serde_json::from_str(&line).context("Malformed reply from rcp daemon")?;
+    if let Some(error) = reply["error"].as_str() {
+        anyhow::bail!("{}", error);
+    }
+    Ok((reply, reader))
+}
+
+pub fn submit(args: &SubmitArgs) -> Result<()> {
+    // Ошибку в аргументах лучше показать сразу, а не в журнале задания
+    Defaults::load()?.parse_from(std::iter::once(OsString::from("rcp")).chain(args.args.iter().cloned()));
+    let job_args = args
+        .args
+        .iter()
+        .map(|arg| arg.to_str().map(str::to_string).context("Job arguments must be valid UTF-8"))
+        .collect::<Result<Vec<_>>>()?;
+    let directory = std::env::current_dir().context("Failed to get the current directory")?;
+    let env: serde_json::Map<String, Value> = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, Value::from(value.into_string().ok()?))))
+        .collect();
+    let (reply, _) = request(
+        connect(true)?,
+        json!({ "command": "submit", "args": job_args, "directory": directory, "env": env, "priority": args.priority }),
+    )?;
+    println!("{}", t!("job-queued", id = reply["id"].as_u64().unwrap_or_default()));
+    Ok(())
+}
+
+pub fn list(_args: &ListArgs) -> Result<()> {
+    let (reply, _) = request(connect(false)?, json!({ "command": "list" }))?;
+    let jobs = reply["jobs"].as_array().cloned().unwrap_or_default();
+    if jobs.is_empty() {
+        println!("{}", t!("no-jobs"));
+        return Ok(());
+    }
+    println!(
+        "{:>4}  {:<10} {:>4} {:>4}  {}",
+        t!("job-id"),
+        t!("job-state-column"),
+        t!("job-priority"),
+        t!("job-exit"),
+        t!("job-command")
+    );
+    for job in &jobs {
+        let state = job["state"].as_str().unwrap_or_default();
+        let exit = job["exit_code"].as_i64().map(|code| code.to_string()).unwrap_or_default();
+        let args: Vec<String> = job["args"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|arg| arg.as_str().map(str::to_string))
+            .collect();
+        println!(
+            "{:>4}  {:<10} {:>4} {:>4}  {}",
+            job["id"].as_u64().unwrap_or_default(),
+            t!("job-state", state = state),
+            job["priority"].as_i64().unwrap_or_default(),
+            exit,
+            command_line(&args)
+        );
+    }
+    Ok(())
+}
+
+pub fn cancel(args: &CancelArgs) -> Result<()> {
+    let (reply, _) = request(connect(false)?, json!({ "command": "cancel", "id": args.id }))?;
+    let state = reply["state"].as_str().unwrap_or_default();
+    match state {
+        "running" => println!("{}", t!("job-stopping", id = args.id)),
+        _ => println!("{}", t!("job-cancelled", id = args.id)),
+    }
+    Ok(())
+}
+
+// Выходит с кодом задания, чтобы `rcp attach ID && ...` ждал его и проверял итог
+pub fn attach(args: &AttachArgs) -> Result<()> {
+    let (_, mut output) = request(connect(false)?, json!({ "command": "attach", "id": args.id }))?;
+    io::copy(&mut output, &mut io::stdout()).context("Failed to read the job output")?;
+    let (reply, _) = request(connect(false)?, json!({ "command": "list" }))?;
+    let code = reply["jobs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|job| job["id"].as_u64() == Some(args.id))
+        .and_then(|job| job["exit_code"].as_i64())
+        .unwrap_or(0);
+    if code != 0 {
+        std::process::exit(code as i32);
+    }
+    Ok(())
+}
+
+fn command_line(args: &[String]) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| match arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"\\$`".contains(c)) {
+            true => format!("'{}'", arg.replace('\'', "'\\''")),
+            false => arg.clone(),
+        })
+        .collect();
+    format!("rcp {}", args.join(" "))
+}
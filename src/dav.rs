@@ -0,0 +1,530 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::io::{self, PipeWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+use ureq::http::{Method, Request, Response};
+use ureq::{AsSendBody, Body, BodyReader, SendBody};
+
+use crate::http::{agent, describe_error, encode_path, percent_decode, send_with_retry};
+use crate::transport::{Entry, Sink, SinkFile, Source};
+
+// WebDAV (Nextcloud, ownCloud, SharePoint и т.п.): дерево обходится по PROPFIND, файл
+// загружается одним PUT с потоковым телом. Nextcloud вместо этого получает файл частями
+// через коллекцию загрузки, а время изменения передается заголовком X-OC-Mtime, который
+// понимают Nextcloud и ownCloud; остальные серверы его игнорируют
+
+// Nextcloud принимает не больше 10 000 частей не меньше 5 MiB, поэтому каждые 2 000
+// частей размер части удваивается: всего получается около 1 TiB
+const CHUNK_SIZE: usize = 16 * 1024 * 1024;
+const CHUNKS_PER_SIZE: usize = 2_000;
+const MAX_CHUNKS: usize = 10_000;
+
+// Листинг большой директории легко перерастает ограничение ureq по умолчанию
+const MAX_LISTING_SIZE: u64 = 256 * 1024 * 1024;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/></d:prop></d:propfind>"#;
+
+// `dav://[user@]host[:port]/path` (HTTP) или `davs://...` (HTTPS)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DavLocation {
+    // `scheme://host[:port]`
+    pub server: String,
+    pub user: Option<String>,
+    pub path: PathBuf,
+}
+
+impl DavLocation {
+    pub fn parse(location: &Path) -> Option<Self> {
+        let location = location.to_str()?;
+        let (scheme, rest) = match location.strip_prefix("dav://") {
+            Some(rest) => ("http", rest),
+            None => ("https", location.strip_prefix("davs://")?),
+        };
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |slash| rest.split_at(slash));
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host),
+            None => (None, authority),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(DavLocation {
+            server: format!("{}://{}", scheme, host),
+            user,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DavEntry {
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+#[derive(Clone)]
+pub struct DavSession {
+    agent: ureq::Agent,
+    server: String,
+    authorization: Option<String>,
+    // Коллекция для частей загрузки, если сервер - Nextcloud
+    uploads: Option<PathBuf>,
+}
+
+impl DavSession {
+    // Пользователь из адреса важнее --dav-user; пароль - только из параметров
+    pub fn connect(location: &DavLocation, user: Option<&str>, password: Option<&str>) -> Result<Self> {
+        let user = location.user.as_deref().or(user);
+        let authorization = user.map(|user| {
+            let credentials = format!("{}:{}", user, password.unwrap_or_default());
+            format!("Basic {}", STANDARD.encode(credentials))
+        });
+
+        // Файлы пользователя Nextcloud лежат в .../remote.php/dav/files/USER/,
+        // а части загрузок - в .../remote.php/dav/uploads/USER/
+        let path = location.path.to_string_lossy();
+        let uploads = path.split_once("/remote.php/dav/files/").and_then(|(prefix, rest)| {
+            let user = rest.split('/').next().filter(|user| !user.is_empty())?;
+            Some(PathBuf::from(format!("{}/remote.php/dav/uploads/{}", prefix, user)))
+        });
+
+        let session = DavSession {
+            agent: agent(),
+            server: location.server.clone(),
+            authorization,
+            uploads,
+        };
+        // Проверяем адрес и учетные данные до начала копирования
+        session.stat(&location.path)?;
+        Ok(session)
+    }
+
+    fn url(&self, path: &Path) -> String {
+        format!("{}{}", self.server, encode_path(&path.to_string_lossy()))
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &Path,
+        headers: &[(&str, String)],
+        body: impl AsSendBody,
+    ) -> Result<Response<Body>, ureq::Error> {
+        let mut builder = Request::builder()
+            .method(Method::from_bytes(method.as_bytes()).expect("valid HTTP method"))
+            .uri(self.url(path));
+        if let Some(authorization) = &self.authorization {
+            builder = builder.header("Authorization", authorization);
+        }
+        for (name, value) in headers {
+            builder = builder.header(*name, value);
+        }
+        self.agent.run(builder.body(body)?)
+    }
+
+    // None, если такого пути нет
+    fn propfind(&self, path: &Path, depth: &str) -> Result<Option<Vec<(PathBuf, DavEntry)>>> {
+        let headers = [("Depth", depth.to_string()), ("Content-Type", "application/xml".to_string())];
+        let mut response = self
+            .request("PROPFIND", path, &headers, PROPFIND_BODY.as_bytes())
+            .with_context(|| format!("Failed to reach {}", self.server))?;
+        match response.status().as_u16() {
+            207 => {}
+            404 => return Ok(None),
+            _ => anyhow::bail!("Failed to list {}{}: {}", self.server, path.display(), describe_error(&mut response)),
+        }
+        let listing = response
+            .body_mut()
+            .with_config()
+            .limit(MAX_LISTING_SIZE)
+            .read_to_vec()
+            .context("Failed to read WebDAV listing")?;
+        parse_multistatus(&listing).map(Some)
+    }
+
+    pub fn stat(&self, path: &Path) -> Result<Option<DavEntry>> {
+        Ok(self
+            .propfind(path, "0")?
+            .and_then(|entries| entries.into_iter().next())
+            .map(|(_, entry)| entry))
+    }
+
+    // Содержимое коллекции без нее самой
+    pub fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, DavEntry)>> {
+        let entries = self
+            .propfind(path, "1")?
+            .with_context(|| format!("Remote directory not found: {}", path.display()))?;
+        let own = path.to_string_lossy().trim_end_matches('/').to_string();
+        Ok(entries
+            .into_iter()
+            .filter(|(href, _)| href.to_string_lossy().trim_end_matches('/') != own)
+            .collect())
+    }
+
+    // Вместе с потоком возвращает размер файла
+    pub fn open(&self, path: &Path) -> Result<(BodyReader<'static>, u64)> {
+        let mut response = self
+            .request("GET", path, &[], ())
+            .with_context(|| format!("Failed to reach {}", self.server))?;
+        if response.status() != 200 {
+            anyhow::bail!("Failed to download {}: {}", path.display(), describe_error(&mut response));
+        }
+        let size = response
+            .headers()
+            .get("content-length")
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+        Ok((response.into_body().into_reader(), size))
+    }
+
+    // Недостающие родительские коллекции (ответ 409) создаются по очереди вверх
+    pub fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mkcol = || {
+            self.request("MKCOL", path, &[], ())
+                .with_context(|| format!("Failed to reach {}", self.server))
+        };
+        let mut response = mkcol()?;
+        if response.status() == 409
+            && let Some(parent) = path.parent()
+        {
+            self.create_dir_all(parent)?;
+            response = mkcol()?;
+        }
+        // 405 - коллекция уже есть
+        match response.status().as_u16() {
+            201 | 405 => Ok(()),
+            _ => anyhow::bail!("Failed to create remote directory {}: {}", path.display(), describe_error(&mut response)),
+        }
+    }
+
+    // COPY на сервере; 501 и 405 - сервер так не умеет
+    pub fn copy_file(&self, source: &Path, destination: &Path) -> Result<bool> {
+        let headers = [("Destination", self.url(destination)), ("Overwrite", "T".to_string())];
+        let mut response = self
+            .request("COPY", source, &headers, ())
+            .with_context(|| format!("Failed to reach {}", self.server))?;
+        match response.status().as_u16() {
+            201 | 204 => Ok(true),
+            405 | 501 => Ok(false),
+            _ => anyhow::bail!("Failed to copy {} on the server: {}", source.display(), describe_error(&mut response)),
+        }
+    }
+
+    pub fn create(&self, path: &Path, modified: Option<SystemTime>) -> Result<DavFile> {
+        let mtime = modified
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|modified| ("X-OC-Mtime", modified.as_secs().to_string()));
+        let upload = match &self.uploads {
+            Some(uploads) => Upload::Chunked {
+                collection: uploads.join(transfer_id(path)),
+                buffer: Vec::new(),
+                chunks: 0,
+                total: 0,
+            },
+            None => self.start_put(path, mtime.clone())?,
+        };
+        Ok(DavFile {
+            session: self.clone(),
+            path: path.to_path_buf(),
+            mtime,
+            upload,
+        })
+    }
+
+    // Тело запроса читается из канала, в который пишет копирование
+    fn start_put(&self, path: &Path, mtime: Option<(&'static str, String)>) -> Result<Upload> {
+        let (reader, writer) = io::pipe().context("Failed to create pipe")?;
+        let session = self.clone();
+        let path = path.to_path_buf();
+        let request = thread::spawn(move || {
+            let headers: Vec<_> = mtime.into_iter().collect();
+            let mut response = session
+                .request("PUT", &path, &headers, SendBody::from_owned_reader(reader))
+                .with_context(|| format!("Failed to reach {}", session.server))?;
+            if !response.status().is_success() {
+                anyhow::bail!("Upload to WebDAV failed: {}", describe_error(&mut response));
+            }
+            Ok(())
+        });
+        Ok(Upload::Stream {
+            pipe: Some(writer),
+            request: Some(request),
+        })
+    }
+}
+
+// Имя коллекции загрузки: разное для разных файлов и запусков
+fn entry(entry: &DavEntry) -> Entry {
+    if entry.is_dir { Entry::dir() } else { Entry::file(entry.size) }
+}
+
+// Символических ссылок в WebDAV нет
+impl Source for DavSession {
+    fn describe(&self, path: &Path) -> String {
+        format!("{}{}", self.server, path.display())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Option<Entry>> {
+        Ok(DavSession::stat(self, path)?.as_ref().map(entry))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Entry)>> {
+        Ok(DavSession::read_dir(self, path)?
+            .into_iter()
+            .map(|(path, dav_entry)| (path, entry(&dav_entry)))
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> Result<(Box<dyn Read>, u64)> {
+        let (reader, size) = DavSession::open(self, path)?;
+        Ok((Box::new(reader), size))
+    }
+}
+
+impl Sink for DavSession {
+    fn name(&self) -> String {
+        self.server.clone()
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(DavSession::stat(self, path)?.is_some_and(|entry| entry.is_dir))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        DavSession::create_dir_all(self, path)
+    }
+
+    fn create(&self, path: &Path, modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        Ok(Box::new(DavSession::create(self, path, modified)?))
+    }
+
+    fn copy_within(&self, source: &Path, destination: &Path) -> Result<bool> {
+        self.copy_file(source, destination)
+    }
+}
+
+fn transfer_id(path: &Path) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&now.to_le_bytes());
+    hasher.update(&std::process::id().to_le_bytes());
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    format!("rcp-{}", &hasher.finalize().to_hex()[..16])
+}
+
+// Ответ 207 на PROPFIND: пары href и свойств. Префиксы пространств имен у серверов
+// разные, поэтому смотрим только на локальные имена элементов
+fn parse_multistatus(xml: &[u8]) -> Result<Vec<(PathBuf, DavEntry)>> {
+    let mut reader = Reader::from_reader(xml);
+    let mut entries = Vec::new();
+    let mut current: Option<(String, DavEntry)> = None;
+    let mut element = String::new();
+    let mut text = String::new();
+    let mut buffer = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buffer).context("Invalid WebDAV listing")? {
+            Event::Start(start) => {
+                element = start.local_name().as_ref().to_string();
+                text.clear();
+                match element.as_str() {
+                    "response" => current = Some((String::new(), DavEntry::default())),
+                    "collection" => current.iter_mut().for_each(|(_, entry)| entry.is_dir = true),
+                    _ => {}
+                }
+            }
+            Event::Empty(empty) if empty.local_name().as_ref() == "collection" => {
+                current.iter_mut().for_each(|(_, entry)| entry.is_dir = true);
+            }
+            Event::Text(content) => text.push_str(&content.xml10_content()),
+            Event::GeneralRef(reference) => {
+                if let Some(char) = reference.resolve_char_ref().context("Invalid WebDAV listing")? {
+                    text.push(char);
+                } else if let Some(value) = resolve_predefined_entity(&reference.xml10_content()) {
+                    text.push_str(value);
+                }
+            }
+            Event::End(end) => {
+                if let Some((href, entry)) = current.as_mut() {
+                    match end.local_name().as_ref() {
+                        "href" if element == "href" => *href = text.trim().to_string(),
+                        "getcontentlength" => entry.size = text.trim().parse().unwrap_or(0),
+                        "response" => {
+                            let (href, entry) = current.take().unwrap();
+                            entries.push((href_path(&href), entry));
+                        }
+                        _ => {}
+                    }
+                }
+                element.clear();
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    Ok(entries)
+}
+
+// href бывает и полным URL, и путем; в листинге он закодирован
+fn href_path(href: &str) -> PathBuf {
+    let path = match href.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |slash| &rest[slash..]),
+        None => href,
+    };
+    PathBuf::from(percent_decode(path))
+}
+
+enum Upload {
+    // Один PUT; тело передается по мере копирования (chunked transfer encoding)
+    Stream {
+        pipe: Option<PipeWriter>,
+        request: Option<JoinHandle<Result<()>>>,
+    },
+    // Nextcloud: части уходят в коллекцию загрузки, а в конце собираются MOVE
+    Chunked {
+        collection: PathBuf,
+        buffer: Vec<u8>,
+        chunks: usize,
+        total: u64,
+    },
+}
+
+pub struct DavFile {
+    session: DavSession,
+    path: PathBuf,
+    mtime: Option<(&'static str, String)>,
+    upload: Upload,
+}
+
+impl DavFile {
+    // Ошибка потокового PUT видна только в ответе сервера, а не в обрыве канала
+    fn finish_stream(request: &mut Option<JoinHandle<Result<()>>>) -> Result<()> {
+        match request.take() {
+            Some(request) => request.join().unwrap_or_else(|_| anyhow::bail!("WebDAV upload thread panicked")),
+            None => Ok(()),
+        }
+    }
+
+    fn chunk_size(chunks: usize) -> usize {
+        CHUNK_SIZE << (chunks / CHUNKS_PER_SIZE).min(3)
+    }
+
+    fn put_chunk(&mut self, data: &[u8]) -> Result<()> {
+        let Upload::Chunked { collection, chunks, total, .. } = &mut self.upload else {
+            return Ok(());
+        };
+        if *chunks >= MAX_CHUNKS {
+            anyhow::bail!("File is too large for a chunked upload: {}", self.path.display());
+        }
+        let destination = [("Destination", self.session.url(&self.path))];
+        if *chunks == 0 {
+            send_with_retry("Creating WebDAV upload", || {
+                self.session.request("MKCOL", collection, &destination, ())
+            })?;
+        }
+        let chunk = collection.join(format!("{:05}", *chunks + 1));
+        send_with_retry("Upload to WebDAV", || self.session.request("PUT", &chunk, &destination, data))?;
+        *chunks += 1;
+        *total += data.len() as u64;
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<()> {
+        let chunks = match &mut self.upload {
+            Upload::Stream { pipe, request } => {
+                // Конец тела запроса
+                drop(pipe.take());
+                return Self::finish_stream(request);
+            }
+            Upload::Chunked { chunks, .. } => *chunks,
+        };
+        // Маленький файл проще отправить одним запросом
+        if chunks == 0 {
+            let headers: Vec<_> = self.mtime.iter().cloned().collect();
+            let body = self.take_buffer();
+            return send_with_retry("Upload to WebDAV", || {
+                self.session.request("PUT", &self.path, &headers, body.as_slice())
+            })
+            .map(drop);
+        }
+
+        let rest = self.take_buffer();
+        if !rest.is_empty() {
+            self.put_chunk(&rest)?;
+        }
+        let Upload::Chunked { collection, total, .. } = &self.upload else {
+            unreachable!()
+        };
+        let mut headers = vec![
+            ("Destination", self.session.url(&self.path)),
+            ("OC-Total-Length", total.to_string()),
+            ("Overwrite", "T".to_string()),
+        ];
+        headers.extend(self.mtime.iter().cloned());
+        let mut response = self
+            .session
+            .request("MOVE", &collection.join(".file"), &headers, ())
+            .with_context(|| format!("Failed to reach {}", self.session.server))?;
+        if !response.status().is_success() {
+            let error = describe_error(&mut response);
+            let _ = self.session.request("DELETE", collection, &[], ());
+            anyhow::bail!("Failed to assemble chunked upload: {}", error);
+        }
+        Ok(())
+    }
+
+    fn take_buffer(&mut self) -> Vec<u8> {
+        match &mut self.upload {
+            Upload::Chunked { buffer, .. } => std::mem::take(buffer),
+            Upload::Stream { .. } => Vec::new(),
+        }
+    }
+}
+
+impl SinkFile for DavFile {
+    fn close(self: Box<Self>) -> Result<()> {
+        DavFile::close(*self)
+    }
+}
+
+impl Write for DavFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.upload {
+            Upload::Stream { pipe, request } => {
+                let Some(writer) = pipe.as_mut() else {
+                    return Err(io::Error::other("WebDAV upload already finished"));
+                };
+                if let Err(e) = writer.write_all(buf) {
+                    drop(pipe.take());
+                    Self::finish_stream(request).map_err(io::Error::other)?;
+                    return Err(e);
+                }
+            }
+            Upload::Chunked { buffer, .. } => buffer.extend_from_slice(buf),
+        }
+        while let Upload::Chunked { buffer, chunks, .. } = &mut self.upload
+            && buffer.len() >= Self::chunk_size(*chunks)
+        {
+            let rest = buffer.split_off(Self::chunk_size(*chunks));
+            let chunk = std::mem::replace(buffer, rest);
+            self.put_chunk(&chunk).map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,878 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::collect::CopyItem;
+use crate::control::Control;
+use crate::i18n::t;
+
+// --dbus: прогресс копирования на сессионной шине, чтобы оболочка рабочего стола или файловый
+// менеджер показали свое окно прогресса для rcp, запущенного из скрипта. Объект
+// /org/rcp/Transfer с интерфейсом org.rcp.Transfer1 на имени org.rcp.Transfer.p<pid>:
+// свойства с PropertiesChanged раз в секунду, методы Pause, Resume и Cancel, сигнал Finished.
+// Если на шине есть kuiserver (KDE), копирование появляется и в его списке заданий.
+// Протокол шины простой, и клиент для него написан здесь же: только little-endian и только
+// те типы, что нужны
+
+const OBJECT_PATH: &str = "/org/rcp/Transfer";
+const INTERFACE: &str = "org.rcp.Transfer1";
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+const KUISERVER: &str = "org.kde.kuiserver";
+const JOB_VIEW: &str = "org.kde.JobViewV2";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.rcp.Transfer1">
+    <property name="Source" type="s" access="read"/>
+    <property name="Destination" type="s" access="read"/>
+    <property name="TotalFiles" type="t" access="read"/>
+    <property name="CopiedFiles" type="t" access="read"/>
+    <property name="FailedFiles" type="t" access="read"/>
+    <property name="TotalBytes" type="t" access="read"/>
+    <property name="CopiedBytes" type="t" access="read"/>
+    <property name="Progress" type="d" access="read"/>
+    <property name="Speed" type="t" access="read"/>
+    <property name="State" type="s" access="read"/>
+    <method name="Pause"/>
+    <method name="Resume"/>
+    <method name="Cancel"/>
+    <signal name="Finished">
+      <arg name="state" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed" type="a{sv}"/>
+      <arg name="invalidated" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="data" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+// Свойства рассылаются раз в секунду, а вызовы и завершение копирования замечаются быстрее
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Типы сообщений, флаги и поля заголовка из спецификации D-Bus
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 1;
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+// RequestName: не вставать в очередь за чужим владельцем имени
+const DO_NOT_QUEUE: u32 = 4;
+// requestView: задание можно остановить и поставить на паузу
+const JOB_KILLABLE_SUSPENDABLE: i32 = 3;
+// Больше сообщение на этой шине быть не может (128M по спецификации)
+const MAX_MESSAGE_SIZE: usize = 128 * 1024 * 1024;
+
+// Копирование, выставленное на шину; поток шины живет до finish
+pub struct Transfer {
+    shared: Arc<Shared>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Shared {
+    source: String,
+    destination: String,
+    total_files: u64,
+    total_bytes: u64,
+    copied_files: AtomicU64,
+    failed_files: AtomicU64,
+    // Байт в секунду за последний интервал
+    speed: AtomicU64,
+    control: Arc<Control>,
+    // Итог копирования: поток шины рассылает его и выходит
+    result: Mutex<Option<&'static str>>,
+}
+
+impl Transfer {
+    pub fn export(source: String, destination: String, files: &[CopyItem], control: &Arc<Control>) -> Result<Self> {
+        let mut connection = Connection::open().context("Failed to connect to the session bus")?;
+        let name = format!("org.rcp.Transfer.p{}", std::process::id());
+        let request = Body::default().arg(Value::Str(name.clone())).arg(Value::U32(DO_NOT_QUEUE));
+        connection
+            .call(BUS_NAME, BUS_PATH, BUS_NAME, "RequestName", request)
+            .with_context(|| format!("Failed to own bus name {}", name))?;
+
+        let shared = Arc::new(Shared {
+            source,
+            destination,
+            total_files: files.len() as u64,
+            total_bytes: files.iter().map(|item| item.size).sum(),
+            copied_files: AtomicU64::new(0),
+            failed_files: AtomicU64::new(0),
+            speed: AtomicU64::new(0),
+            control: Arc::clone(control),
+            result: Mutex::new(None),
+        });
+        // Без KDE окна заданий нет, и это не ошибка
+        let job_view = JobView::request(&mut connection, &shared).ok();
+        let handle = thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || {
+                let _ = serve(connection, job_view, &shared);
+            }
+        });
+        Ok(Transfer { shared, handle: Mutex::new(Some(handle)) })
+    }
+
+    pub fn copied(&self) {
+        self.shared.copied_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.shared.failed_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // state: finished, stopped или failed; failed - не скопировано файлов всего, вместе с
+    // оставшимися в очереди после остановки
+    pub fn finish(&self, state: &'static str, failed: usize) {
+        self.shared.result.lock().unwrap().get_or_insert(state);
+        self.shared.failed_files.fetch_max(failed as u64, Ordering::Relaxed);
+        if let Some(handle) = self.handle.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Выход по ошибке до finish: на шине копирование тоже завершается, а не пропадает молча
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.finish("failed", 0);
+    }
+}
+
+impl Shared {
+    fn copied_bytes(&self) -> u64 {
+        self.control.written().min(self.total_bytes)
+    }
+
+    fn progress(&self) -> f64 {
+        match (self.total_bytes, self.total_files) {
+            (0, 0) => 1.0,
+            (0, files) => self.copied_files.load(Ordering::Relaxed) as f64 / files as f64,
+            (bytes, _) => self.copied_bytes() as f64 / bytes as f64,
+        }
+    }
+
+    fn state(&self) -> &'static str {
+        if let Some(result) = *self.result.lock().unwrap() {
+            return result;
+        }
+        match (self.control.is_draining(), self.control.is_paused()) {
+            (true, _) => "stopping",
+            (false, true) => "paused",
+            (false, false) => "copying",
+        }
+    }
+
+    fn properties(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("Source", Value::Str(self.source.clone())),
+            ("Destination", Value::Str(self.destination.clone())),
+            ("TotalFiles", Value::U64(self.total_files)),
+            ("CopiedFiles", Value::U64(self.copied_files.load(Ordering::Relaxed))),
+            ("FailedFiles", Value::U64(self.failed_files.load(Ordering::Relaxed))),
+            ("TotalBytes", Value::U64(self.total_bytes)),
+            ("CopiedBytes", Value::U64(self.copied_bytes())),
+            ("Progress", Value::F64(self.progress())),
+            ("Speed", Value::U64(self.speed.load(Ordering::Relaxed))),
+            ("State", Value::Str(self.state().to_string())),
+        ]
+    }
+
+    fn pause(&self, paused: bool) {
+        if self.control.is_paused() != paused {
+            self.control.toggle_pause();
+        }
+    }
+}
+
+// Поток шины: отвечает на вызовы и раз в секунду рассылает прогресс
+fn serve(mut connection: Connection, job_view: Option<JobView>, shared: &Shared) -> io::Result<()> {
+    let mut last_update = Instant::now();
+    let mut last_written = shared.control.written();
+    loop {
+        let result = *shared.result.lock().unwrap();
+        let elapsed = last_update.elapsed();
+        if elapsed >= UPDATE_INTERVAL || result.is_some() {
+            let written = shared.control.written();
+            let speed = written.saturating_sub(last_written) as f64 / elapsed.as_secs_f64().max(0.001);
+            shared.speed.store(speed as u64, Ordering::Relaxed);
+            (last_update, last_written) = (Instant::now(), written);
+
+            let changed = Body::default().arg(Value::Str(INTERFACE.to_string())).properties(&shared.properties()).no_strings();
+            connection.signal(OBJECT_PATH, PROPERTIES, "PropertiesChanged", changed)?;
+            if let Some(job_view) = &job_view {
+                job_view.update(&mut connection, shared)?;
+            }
+        }
+        if let Some(result) = result {
+            connection.signal(OBJECT_PATH, INTERFACE, "Finished", Body::default().arg(Value::Str(result.to_string())))?;
+            if let Some(job_view) = &job_view {
+                job_view.terminate(&mut connection, shared, result)?;
+            }
+            return Ok(());
+        }
+        if let Some(message) = connection.receive(POLL_INTERVAL)? {
+            handle(&mut connection, &message, job_view.as_ref(), shared)?;
+        }
+    }
+}
+
+fn handle(connection: &mut Connection, message: &Message, job_view: Option<&JobView>, shared: &Shared) -> io::Result<()> {
+    // Кнопки в окне заданий KDE приходят сигналами
+    if message.kind == SIGNAL {
+        if job_view.is_some_and(|job_view| message.path.as_deref() == Some(job_view.path.as_str())) {
+            match message.member.as_deref() {
+                Some("cancelRequested") => shared.control.abort(),
+                Some("suspendRequested") => shared.pause(true),
+                Some("resumeRequested") => shared.pause(false),
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+    if message.kind != METHOD_CALL {
+        return Ok(());
+    }
+
+    let path = message.path.as_deref().unwrap_or_default();
+    let reply = match (path, message.interface.as_deref(), message.member.as_deref().unwrap_or_default()) {
+        (_, _, "Ping") => Ok(Body::default()),
+        (OBJECT_PATH, _, "Introspect") => Ok(Body::default().arg(Value::Str(INTROSPECTION.to_string()))),
+        // Родительские узлы пути, чтобы объект нашелся обходом дерева от "/"
+        (path, _, "Introspect") if OBJECT_PATH.starts_with(&format!("{}/", path.trim_end_matches('/'))) => {
+            let rest = &OBJECT_PATH[path.trim_end_matches('/').len() + 1..];
+            let child = rest.split('/').next().unwrap_or(rest);
+            Ok(Body::default().arg(Value::Str(format!("<node>\n  <node name=\"{}\"/>\n</node>\n", child))))
+        }
+        (OBJECT_PATH, Some(PROPERTIES) | None, "Get") => {
+            let args = message.strings();
+            let name = args.get(1).map(String::as_str).unwrap_or_default();
+            match shared.properties().into_iter().find(|(property, _)| *property == name) {
+                Some((_, value)) => Ok(Body::default().variant(value)),
+                None => Err(("org.freedesktop.DBus.Error.UnknownProperty", format!("No such property: {}", name))),
+            }
+        }
+        (OBJECT_PATH, Some(PROPERTIES) | None, "GetAll") => {
+            let properties = match message.strings().first().map(String::as_str) {
+                Some(INTERFACE) | Some("") | None => shared.properties(),
+                Some(_) => Vec::new(),
+            };
+            Ok(Body::default().properties(&properties))
+        }
+        (OBJECT_PATH, Some(PROPERTIES) | None, "Set") => {
+            Err(("org.freedesktop.DBus.Error.PropertyReadOnly", "Properties are read-only".to_string()))
+        }
+        (OBJECT_PATH, Some(INTERFACE) | None, "Pause") => {
+            shared.pause(true);
+            Ok(Body::default())
+        }
+        (OBJECT_PATH, Some(INTERFACE) | None, "Resume") => {
+            shared.pause(false);
+            Ok(Body::default())
+        }
+        // Как Ctrl+C: первый раз начатые файлы докопируются, второй - прерываются
+        (OBJECT_PATH, Some(INTERFACE) | None, "Cancel") => {
+            shared.control.abort();
+            Ok(Body::default())
+        }
+        (OBJECT_PATH, _, member) => Err(("org.freedesktop.DBus.Error.UnknownMethod", format!("No such method: {}", member))),
+        (path, _, _) => Err(("org.freedesktop.DBus.Error.UnknownObject", format!("No such object: {}", path))),
+    };
+    if message.flags & NO_REPLY_EXPECTED != 0 {
+        return Ok(());
+    }
+    match reply {
+        Ok(body) => connection.reply(message, body),
+        Err((name, text)) => connection.error(message, name, &text),
+    }
+}
+
+// Задание в kuiserver (org.kde.JobViewServer): KDE показывает его в уведомлениях с полосой
+// прогресса и кнопками паузы и остановки
+struct JobView {
+    path: String,
+}
+
+impl JobView {
+    fn request(connection: &mut Connection, shared: &Shared) -> io::Result<Self> {
+        let request = Body::default()
+            .arg(Value::Str("rcp".to_string()))
+            .arg(Value::Str("edit-copy".to_string()))
+            .arg(Value::I32(JOB_KILLABLE_SUSPENDABLE));
+        let reply = connection.call(KUISERVER, "/JobViewServer", "org.kde.JobViewServer", "requestView", request)?;
+        let path = reply.strings().into_iter().next().ok_or_else(|| invalid("requestView returned no job path"))?;
+        let rule = format!("type='signal',interface='{}',path='{}'", JOB_VIEW, path);
+        connection.call(BUS_NAME, BUS_PATH, BUS_NAME, "AddMatch", Body::default().arg(Value::Str(rule)))?;
+
+        let job_view = JobView { path };
+        job_view.send(connection, "setInfoMessage", Body::default().arg(Value::Str(t!("dbus-copying"))))?;
+        let fields = [(t!("dbus-source"), &shared.source), (t!("dbus-destination"), &shared.destination)];
+        for (number, (label, value)) in fields.into_iter().enumerate() {
+            let field = Body::default().arg(Value::U32(number as u32)).arg(Value::Str(label)).arg(Value::Str(value.clone()));
+            job_view.send(connection, "setDescriptionField", field)?;
+        }
+        Ok(job_view)
+    }
+
+    fn update(&self, connection: &mut Connection, shared: &Shared) -> io::Result<()> {
+        let amounts = [
+            ("setTotalAmount", shared.total_bytes, "bytes"),
+            ("setProcessedAmount", shared.copied_bytes(), "bytes"),
+            ("setTotalAmount", shared.total_files, "files"),
+            ("setProcessedAmount", shared.copied_files.load(Ordering::Relaxed), "files"),
+        ];
+        for (method, amount, unit) in amounts {
+            self.send(connection, method, Body::default().arg(Value::U64(amount)).arg(Value::Str(unit.to_string())))?;
+        }
+        let percent = (shared.progress() * 100.0) as u32;
+        self.send(connection, "setPercent", Body::default().arg(Value::U32(percent.min(100))))?;
+        self.send(connection, "setSpeed", Body::default().arg(Value::U64(shared.speed.load(Ordering::Relaxed))))?;
+        self.send(connection, "setSuspended", Body::default().arg(Value::Bool(shared.control.is_paused())))
+    }
+
+    // Пустое сообщение - задание выполнено, иначе KDE показывает его как ошибку
+    fn terminate(&self, connection: &mut Connection, shared: &Shared, result: &str) -> io::Result<()> {
+        let failed = shared.failed_files.load(Ordering::Relaxed);
+        let message = match result {
+            "stopped" => t!("dbus-stopped"),
+            _ if failed > 0 => t!("files-not-copied", count = failed),
+            "failed" => t!("dbus-failed"),
+            _ => String::new(),
+        };
+        self.send(connection, "terminate", Body::default().arg(Value::Str(message)))
+    }
+
+    fn send(&self, connection: &mut Connection, method: &str, body: Body) -> io::Result<()> {
+        connection.send(METHOD_CALL, NO_REPLY_EXPECTED, &call_fields(KUISERVER, &self.path, JOB_VIEW, method), body)?;
+        Ok(())
+    }
+}
+
+// Соединение с сессионной шиной
+struct Connection {
+    stream: UnixStream,
+    serial: u32,
+    // Прочитанное, но еще не разобранное
+    input: Vec<u8>,
+    // Пришедшее, пока ждали ответа на свой вызов
+    queue: VecDeque<Message>,
+}
+
+impl Connection {
+    fn open() -> io::Result<Self> {
+        let mut stream = connect()?;
+        authenticate(&mut stream)?;
+        let mut connection = Connection { stream, serial: 0, input: Vec::new(), queue: VecDeque::new() };
+        connection.call(BUS_NAME, BUS_PATH, BUS_NAME, "Hello", Body::default())?;
+        Ok(connection)
+    }
+
+    // Вызов с ожиданием ответа; ответ-ошибка становится io::Error
+    fn call(&mut self, destination: &str, path: &str, interface: &str, member: &str, body: Body) -> io::Result<Message> {
+        let serial = self.send(METHOD_CALL, 0, &call_fields(destination, path, interface, member), body)?;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("No reply to {}.{}", interface, member)));
+            }
+            let Some(message) = self.read(left)? else {
+                continue;
+            };
+            if message.reply_serial != Some(serial) {
+                self.queue.push_back(message);
+                continue;
+            }
+            if message.kind == ERROR {
+                let name = message.error_name.clone().unwrap_or_default();
+                let text = message.strings().into_iter().next().unwrap_or_default();
+                return Err(io::Error::other(format!("{}: {}", name, text)));
+            }
+            return Ok(message);
+        }
+    }
+
+    fn signal(&mut self, path: &str, interface: &str, member: &str, body: Body) -> io::Result<()> {
+        let fields = [
+            (FIELD_PATH, Value::Path(path.to_string())),
+            (FIELD_INTERFACE, Value::Str(interface.to_string())),
+            (FIELD_MEMBER, Value::Str(member.to_string())),
+        ];
+        self.send(SIGNAL, NO_REPLY_EXPECTED, &fields, body)?;
+        Ok(())
+    }
+
+    fn reply(&mut self, call: &Message, body: Body) -> io::Result<()> {
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Value::U32(call.serial))];
+        fields.extend(call.sender.clone().map(|sender| (FIELD_DESTINATION, Value::Str(sender))));
+        self.send(METHOD_RETURN, NO_REPLY_EXPECTED, &fields, body)?;
+        Ok(())
+    }
+
+    fn error(&mut self, call: &Message, name: &str, text: &str) -> io::Result<()> {
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Value::U32(call.serial)), (FIELD_ERROR_NAME, Value::Str(name.to_string()))];
+        fields.extend(call.sender.clone().map(|sender| (FIELD_DESTINATION, Value::Str(sender))));
+        self.send(ERROR, NO_REPLY_EXPECTED, &fields, Body::default().arg(Value::Str(text.to_string())))?;
+        Ok(())
+    }
+
+    // Возвращает серийный номер отправленного сообщения
+    fn send(&mut self, kind: u8, flags: u8, fields: &[(u8, Value)], body: Body) -> io::Result<u32> {
+        self.serial += 1;
+        let mut writer = Writer::default();
+        writer.buf.extend([b'l', kind, flags, 1]);
+        writer.u32(body.writer.buf.len() as u32);
+        writer.u32(self.serial);
+        let signature = (!body.signature.is_empty()).then(|| (FIELD_SIGNATURE, Value::Signature(body.signature.clone())));
+        writer.array(8, |writer| {
+            for (code, value) in fields.iter().chain(&signature) {
+                writer.align(8);
+                writer.buf.push(*code);
+                writer.variant(value);
+            }
+        });
+        writer.align(8);
+        writer.buf.extend(&body.writer.buf);
+        self.stream.write_all(&writer.buf)?;
+        Ok(self.serial)
+    }
+
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<Message>> {
+        match self.queue.pop_front() {
+            Some(message) => Ok(Some(message)),
+            None => self.read(timeout),
+        }
+    }
+
+    // Следующее сообщение с сокета; None - за timeout целиком не пришло
+    fn read(&mut self, timeout: Duration) -> io::Result<Option<Message>> {
+        if let Some(message) = take_message(&mut self.input)? {
+            return Ok(Some(message));
+        }
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut buffer = [0u8; 64 * 1024];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Session bus closed the connection")),
+            Ok(read) => {
+                self.input.extend_from_slice(&buffer[..read]);
+                take_message(&mut self.input)
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn call_fields(destination: &str, path: &str, interface: &str, member: &str) -> [(u8, Value); 4] {
+    [
+        (FIELD_PATH, Value::Path(path.to_string())),
+        (FIELD_INTERFACE, Value::Str(interface.to_string())),
+        (FIELD_MEMBER, Value::Str(member.to_string())),
+        (FIELD_DESTINATION, Value::Str(destination.to_string())),
+    ]
+}
+
+// Адрес из DBUS_SESSION_BUS_ADDRESS (unix:path=... или unix:abstract=..., несколько через ';'),
+// без него - $XDG_RUNTIME_DIR/bus, как у systemd
+fn connect() -> io::Result<UnixStream> {
+    let address = match std::env::var("DBUS_SESSION_BUS_ADDRESS") {
+        Ok(address) => address,
+        Err(_) => match std::env::var("XDG_RUNTIME_DIR") {
+            Ok(runtime) => format!("unix:path={}/bus", runtime),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, "DBUS_SESSION_BUS_ADDRESS is not set")),
+        },
+    };
+    let mut error = io::Error::new(io::ErrorKind::NotFound, format!("No supported address in {}", address));
+    for entry in address.split(';') {
+        let Some(params) = entry.strip_prefix("unix:") else {
+            continue;
+        };
+        for param in params.split(',') {
+            let result = if let Some(path) = param.strip_prefix("path=") {
+                UnixStream::connect(unescape(path))
+            } else if let Some(name) = param.strip_prefix("abstract=") {
+                SocketAddr::from_abstract_name(unescape(name).as_bytes()).and_then(|address| UnixStream::connect_addr(&address))
+            } else {
+                continue;
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = e,
+            }
+        }
+    }
+    Err(error)
+}
+
+// Значения в адресе экранируются как %XX
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                result.push(byte);
+                index += 3;
+            }
+            None => {
+                result.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+// SASL EXTERNAL: шина узнает uid по сокету, клиент называет его же десятичной строкой в hex
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    let uid = unsafe { libc::getuid() }.to_string();
+    let hex: String = uid.bytes().map(|byte| format!("{:02x}", byte)).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    // По байту: после строки ответа сразу пойдут сообщения
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = String::from_utf8_lossy(&line);
+    if !line.starts_with("OK ") {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Session bus rejected authentication: {}", line.trim())));
+    }
+    stream.write_all(b"BEGIN\r\n")
+}
+
+fn invalid(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text.to_string())
+}
+
+// Значения, которые rcp пишет на шину
+enum Value {
+    Str(String),
+    Path(String),
+    Signature(String),
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    U64(u64),
+    F64(f64),
+}
+
+impl Value {
+    fn signature(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "s",
+            Value::Path(_) => "o",
+            Value::Signature(_) => "g",
+            Value::Bool(_) => "b",
+            Value::I32(_) => "i",
+            Value::U32(_) => "u",
+            Value::U64(_) => "t",
+            Value::F64(_) => "d",
+        }
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        match self {
+            Value::Str(value) | Value::Path(value) => writer.string(value),
+            Value::Signature(value) => writer.signature(value),
+            Value::Bool(value) => writer.u32(*value as u32),
+            Value::I32(value) => writer.u32(*value as u32),
+            Value::U32(value) => writer.u32(*value),
+            Value::U64(value) => writer.u64(*value),
+            Value::F64(value) => writer.u64(value.to_bits()),
+        }
+    }
+}
+
+// Сериализация little-endian; выравнивание считается от начала буфера, а тело сообщения
+// начинается с границы 8 байт, поэтому его можно собирать отдельно
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, alignment: usize) {
+        self.buf.resize(self.buf.len().next_multiple_of(alignment), 0);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend(value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.align(8);
+        self.buf.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.buf.push(value.len() as u8);
+        self.buf.extend(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn variant(&mut self, value: &Value) {
+        self.signature(value.signature());
+        value.write(self);
+    }
+
+    // Длина массива не включает выравнивание перед первым элементом
+    fn array(&mut self, alignment: usize, elements: impl FnOnce(&mut Writer)) {
+        self.u32(0);
+        let at = self.buf.len() - 4;
+        self.align(alignment);
+        let start = self.buf.len();
+        elements(self);
+        let length = (self.buf.len() - start) as u32;
+        self.buf[at..at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+}
+
+// Аргументы сообщения вместе с их сигнатурой
+#[derive(Default)]
+struct Body {
+    signature: String,
+    writer: Writer,
+}
+
+impl Body {
+    fn arg(mut self, value: Value) -> Self {
+        self.signature.push_str(value.signature());
+        value.write(&mut self.writer);
+        self
+    }
+
+    fn variant(mut self, value: Value) -> Self {
+        self.signature.push('v');
+        self.writer.variant(&value);
+        self
+    }
+
+    fn properties(mut self, properties: &[(&str, Value)]) -> Self {
+        self.signature.push_str("a{sv}");
+        self.writer.array(8, |writer| {
+            for (name, value) in properties {
+                writer.align(8);
+                writer.string(name);
+                writer.variant(value);
+            }
+        });
+        self
+    }
+
+    // Пустой массив строк (invalidated у PropertiesChanged)
+    fn no_strings(mut self) -> Self {
+        self.signature.push_str("as");
+        self.writer.array(4, |_| {});
+        self
+    }
+}
+
+// Принятое сообщение: заголовок разобран, тело читается по требованию
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    signature: String,
+    data: Vec<u8>,
+    body: usize,
+    big_endian: bool,
+}
+
+impl Message {
+    // Строковые аргументы от начала тела (s и o), до первого аргумента другого типа
+    fn strings(&self) -> Vec<String> {
+        let mut reader = Reader { data: &self.data, pos: self.body, big_endian: self.big_endian };
+        self.signature
+            .chars()
+            .take_while(|kind| matches!(kind, 's' | 'o'))
+            .map_while(|_| reader.string().ok())
+            .collect()
+    }
+}
+
+// Забирает из буфера первое сообщение, если оно пришло целиком
+fn take_message(input: &mut Vec<u8>) -> io::Result<Option<Message>> {
+    if input.len() < 16 {
+        return Ok(None);
+    }
+    let big_endian = input[0] == b'B';
+    let header = Reader { data: input, pos: 0, big_endian };
+    let body_length = header.u32_at(4)? as usize;
+    let fields_length = header.u32_at(12)? as usize;
+    let body = (16 + fields_length).next_multiple_of(8);
+    let total = body + body_length;
+    if total > MAX_MESSAGE_SIZE {
+        return Err(invalid("Message from the session bus is too large"));
+    }
+    if input.len() < total {
+        return Ok(None);
+    }
+    let data: Vec<u8> = input.drain(..total).collect();
+
+    let mut reader = Reader { data: &data, pos: 16, big_endian };
+    let mut message = Message {
+        kind: data[1],
+        flags: data[2],
+        serial: reader.u32_at(8)?,
+        path: None,
+        interface: None,
+        member: None,
+        error_name: None,
+        reply_serial: None,
+        sender: None,
+        signature: String::new(),
+        data: Vec::new(),
+        body,
+        big_endian,
+    };
+    while reader.pos < 16 + fields_length {
+        reader.align(8);
+        let code = reader.byte()?;
+        match reader.signature()?.as_str() {
+            "s" | "o" => {
+                let value = Some(reader.string()?);
+                match code {
+                    FIELD_PATH => message.path = value,
+                    FIELD_INTERFACE => message.interface = value,
+                    FIELD_MEMBER => message.member = value,
+                    FIELD_ERROR_NAME => message.error_name = value,
+                    FIELD_SENDER => message.sender = value,
+                    _ => {}
+                }
+            }
+            "g" => {
+                let value = reader.signature()?;
+                if code == FIELD_SIGNATURE {
+                    message.signature = value;
+                }
+            }
+            "u" => {
+                let value = reader.u32()?;
+                if code == FIELD_REPLY_SERIAL {
+                    message.reply_serial = Some(value);
+                }
+            }
+            _ => return Err(invalid("Unexpected header field type from the session bus")),
+        }
+    }
+    message.data = data;
+    Ok(Some(message))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn align(&mut self, alignment: usize) {
+        self.pos = self.pos.next_multiple_of(alignment);
+    }
+
+    fn bytes(&mut self, length: usize) -> io::Result<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos + length).ok_or_else(|| invalid("Truncated message from the session bus"))?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32_at(&self, at: usize) -> io::Result<u32> {
+        let bytes: [u8; 4] = self
+            .data
+            .get(at..at + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("Truncated message from the session bus"))?;
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.align(4);
+        let value = self.u32_at(self.pos)?;
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.u32()? as usize;
+        let value = String::from_utf8_lossy(self.bytes(length)?).into_owned();
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn signature(&mut self) -> io::Result<String> {
+        let length = self.byte()? as usize;
+        let value = String::from_utf8_lossy(self.bytes(length)?).into_owned();
+        self.pos += 1;
+        Ok(value)
+    }
+}
@@ -0,0 +1,494 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use indicatif::HumanBytes;
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{ChecksumAlgorithm, DedupMode};
+use crate::collect::CopyItem;
+use crate::hash::hash_file;
+use crate::i18n::t;
+use crate::reference::{link, prepare_destination};
+
+// --dedup-dest: перед копированием ищем в назначении файл с тем же содержимым и вместо записи
+// делаем на него жесткую ссылку или reflink. Хешируются лениво только файлы назначения того же
+// размера, что и источник; хеши можно хранить между запусками в --dedup-cache
+
+// По этим полям понимаем, что файл не менялся с тех пор, как его хешировали
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileState {
+    size: u64,
+    modified: (i64, i64),
+    inode: u64,
+}
+
+impl FileState {
+    fn of(metadata: &Metadata) -> Self {
+        FileState {
+            size: metadata.len(),
+            modified: (metadata.mtime(), metadata.mtime_nsec()),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+pub struct DestinationIndex {
+    files: HashMap<PathBuf, FileState>,
+    by_size: HashMap<u64, Vec<PathBuf>>,
+    hashes: HashMap<PathBuf, String>,
+    cache: Option<PathBuf>,
+    reflinks_unsupported: bool,
+}
+
+impl DestinationIndex {
+    // Reflink на сетевой файловой системе не пробуется
+    pub fn without_reflinks(&mut self) {
+        self.reflinks_unsupported = true;
+    }
+
+    pub fn build(root: &Path, cache: Option<&Path>) -> Result<Self> {
+        let mut index = DestinationIndex {
+            files: HashMap::new(),
+            by_size: HashMap::new(),
+            hashes: HashMap::new(),
+            cache: cache.map(Path::to_path_buf),
+            reflinks_unsupported: false,
+        };
+        if root.is_dir() {
+            index.walk(root)?;
+        }
+        if let Some(cache) = cache {
+            index.load(cache)?;
+        }
+        Ok(index)
+    }
+
+    fn walk(&mut self, root: &Path) -> Result<()> {
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if dir != root => {
+                    eprintln!("{} {}", t!("warning").yellow().bold(), t!("dedup-cannot-index", path = dir.display().to_string(), error = e.to_string()));
+                    continue;
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to read directory: {}", dir.display())),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let path = entry.path();
+                let metadata = entry.metadata()?;
+                // Пустые файлы связывать незачем
+                if metadata.is_dir() {
+                    pending.push(path);
+                } else if metadata.is_file() && metadata.len() > 0 {
+                    self.by_size.entry(metadata.len()).or_default().push(path.clone());
+                    self.files.insert(path, FileState::of(&metadata));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Строка кэша: `хеш<TAB>размер<TAB>секунды<TAB>наносекунды<TAB>inode<TAB>путь`; записи
+    // об изменившихся и пропавших файлах отбрасываются
+    fn load(&mut self, cache: &Path) -> Result<()> {
+        let file = match File::open(cache) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open dedup cache: {}", cache.display())),
+        };
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line.with_context(|| format!("Failed to read dedup cache: {}", cache.display()))?;
+            let fields: Vec<&[u8]> = line.splitn(6, |&byte| byte == b'\t').collect();
+            let [hash, size, seconds, nanoseconds, inode, path] = fields[..] else {
+                continue;
+            };
+            let number = |field: &[u8]| std::str::from_utf8(field).ok().and_then(|text| text.parse::<i64>().ok());
+            let (Some(size), Some(seconds), Some(nanoseconds), Some(inode)) =
+                (number(size), number(seconds), number(nanoseconds), number(inode))
+            else {
+                continue;
+            };
+            let path = PathBuf::from(std::ffi::OsStr::from_bytes(path));
+            let state = FileState {
+                size: size as u64,
+                modified: (seconds, nanoseconds),
+                inode: inode as u64,
+            };
+            if self.files.get(&path) == Some(&state) {
+                self.hashes.insert(path, String::from_utf8_lossy(hash).into_owned());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+        if let Some(parent) = cache.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let mut contents = Vec::new();
+        for (path, hash) in &self.hashes {
+            let (Some(state), false) = (self.files.get(path), path.as_os_str().as_bytes().contains(&b'\n')) else {
+                continue;
+            };
+            write!(
+                contents,
+                "{}\t{}\t{}\t{}\t{}\t",
+                hash, state.size, state.modified.0, state.modified.1, state.inode
+            )?;
+            contents.extend_from_slice(path.as_os_str().as_bytes());
+            contents.push(b'\n');
+        }
+        let temporary = cache.with_extension("tmp");
+        fs::write(&temporary, contents)
+            .and_then(|()| fs::rename(&temporary, cache))
+            .with_context(|| format!("Failed to write dedup cache: {}", cache.display()))
+    }
+
+    // Файл, только что записанный в назначение
+    fn insert(&mut self, path: &Path, metadata: &Metadata) {
+        let state = FileState::of(metadata);
+        if let Some(previous) = self.files.insert(path.to_path_buf(), state) {
+            if previous == state {
+                return;
+            }
+            self.hashes.remove(path);
+            if let Some(paths) = self.by_size.get_mut(&previous.size) {
+                paths.retain(|known| known != path);
+            }
+        }
+        self.by_size.entry(state.size).or_default().push(path.to_path_buf());
+    }
+
+    // --dedupe-blocks: после копирования просим файловую систему разделить блоки каждой новой
+    // копии с одинаковым файлом, который уже был в назначении или скопирован раньше
+    pub fn dedupe_blocks(&mut self, copied: &[PathBuf]) -> Result<()> {
+        let mut files = 0;
+        let mut bytes = 0;
+        for path in copied {
+            let Some(metadata) = fs::symlink_metadata(path).ok().filter(|metadata| metadata.is_file() && metadata.len() > 0)
+            else {
+                continue;
+            };
+            self.insert(path, &metadata);
+            let candidates: Vec<PathBuf> = self.by_size[&metadata.len()]
+                .iter()
+                .filter(|candidate| {
+                    *candidate != path && self.files.get(*candidate).is_some_and(|state| state.inode != metadata.ino())
+                })
+                .cloned()
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+            let Some(hash) = self.hash(path) else {
+                continue;
+            };
+            for candidate in candidates {
+                if self.hash(&candidate).as_deref() != Some(hash.as_str()) {
+                    continue;
+                }
+                match dedupe_range(&candidate, path, metadata.len())? {
+                    Some(0) => continue,
+                    Some(shared) => {
+                        files += 1;
+                        bytes += shared;
+                        break;
+                    }
+                    None => {
+                        eprintln!("{} {}", t!("warning").yellow().bold(), t!("dedupe-blocks-unsupported"));
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        if files > 0 {
+            println!("{}", t!("dedupe-blocks-shared", count = files, size = HumanBytes(bytes).to_string()));
+        }
+        Ok(())
+    }
+
+    fn hash(&mut self, path: &Path) -> Option<String> {
+        if let Some(hash) = self.hashes.get(path) {
+            return Some(hash.clone());
+        }
+        // Файл могли удалить или изменить после обхода - тогда он просто не подходит
+        let before = fs::symlink_metadata(path).ok().map(|metadata| FileState::of(&metadata))?;
+        let hash = hash_file(path, ChecksumAlgorithm::Blake3).ok()?;
+        if self.files.get(path) != Some(&before) {
+            return None;
+        }
+        self.hashes.insert(path.to_path_buf(), hash.clone());
+        Some(hash)
+    }
+
+    // Убирает из списка на копирование файлы, содержимое которых уже есть в назначении,
+    // и возвращает их
+    pub fn apply(&mut self, files: &mut Vec<CopyItem>, mode: DedupMode) -> Result<Vec<CopyItem>> {
+        let mut handled = Vec::new();
+        let mut rest = Vec::new();
+        for item in files.drain(..) {
+            let source = Path::new(&item.source);
+            let candidates = match self.by_size.get(&item.size) {
+                Some(candidates) if item.size > 0 && !source.is_symlink() => candidates.clone(),
+                _ => {
+                    rest.push(item);
+                    continue;
+                }
+            };
+            // Ошибку чтения источника покажет обычное копирование
+            let Ok(source_hash) = hash_file(source, ChecksumAlgorithm::Blake3) else {
+                rest.push(item);
+                continue;
+            };
+            let found = candidates
+                .into_iter()
+                .find(|candidate| self.hash(candidate).as_deref() == Some(source_hash.as_str()));
+            let done = match found {
+                Some(existing) => self.reuse(&existing, &item.destination, mode)?,
+                None => false,
+            };
+            if done {
+                handled.push(item);
+            } else {
+                rest.push(item);
+            }
+        }
+        *files = rest;
+        Ok(handled)
+    }
+
+    fn reuse(&mut self, existing: &Path, destination: &Path, mode: DedupMode) -> Result<bool> {
+        let metadata = fs::symlink_metadata(existing)
+            .with_context(|| format!("Failed to stat destination file: {}", existing.display()))?;
+        match mode {
+            DedupMode::Hardlink => link(existing, &metadata, destination),
+            DedupMode::Reflink if self.reflinks_unsupported => Ok(false),
+            DedupMode::Reflink => {
+                let cloned = reflink(existing, &metadata, destination)?;
+                if !cloned {
+                    self.reflinks_unsupported = true;
+                    eprintln!("{} {}", t!("warning").yellow().bold(), t!("reflinks-unsupported-copying"));
+                }
+                Ok(cloned)
+            }
+        }
+    }
+}
+
+// duplicates - одинаковые файлы внутри набора, иначе - уже лежащие в назначении
+pub fn report(count: usize, mode: DedupMode, duplicates: bool) {
+    let mode = match mode {
+        DedupMode::Hardlink => "hardlink",
+        DedupMode::Reflink => "reflink",
+    };
+    if duplicates {
+        println!("{}", t!("dedup-duplicates", mode = mode, count = count));
+    } else {
+        println!("{}", t!("dedup-existing", mode = mode, count = count));
+    }
+}
+
+// --dedup: одинаковые файлы внутри копируемого набора (сначала по размеру, потом по хешу)
+// копируются один раз, а остальные после копирования становятся ссылками на копию
+pub struct Duplicate {
+    pub item: CopyItem,
+    pub original: PathBuf,
+}
+
+pub fn split_duplicates(files: &mut Vec<CopyItem>) -> Vec<Duplicate> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, item) in files.iter().enumerate() {
+        if item.size > 0 && !Path::new(&item.source).is_symlink() {
+            by_size.entry(item.size).or_default().push(index);
+        }
+    }
+
+    // Индекс повтора -> индекс файла, который копируется
+    let mut originals: HashMap<usize, usize> = HashMap::new();
+    for indices in by_size.values().filter(|indices| indices.len() > 1) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for &index in indices {
+            let Ok(hash) = hash_file(Path::new(&files[index].source), ChecksumAlgorithm::Blake3) else {
+                continue;
+            };
+            match seen.get(&hash) {
+                Some(&original) => {
+                    originals.insert(index, original);
+                }
+                None => {
+                    seen.insert(hash, index);
+                }
+            }
+        }
+    }
+
+    let mut duplicates = Vec::new();
+    let mut rest = Vec::new();
+    let destinations: Vec<PathBuf> = files.iter().map(|item| item.destination.clone()).collect();
+    for (index, item) in files.drain(..).enumerate() {
+        match originals.get(&index) {
+            Some(&original) => duplicates.push(Duplicate {
+                item,
+                original: destinations[original].clone(),
+            }),
+            None => rest.push(item),
+        }
+    }
+    *files = rest;
+    duplicates
+}
+
+// Вызывается после копирования; если не удалось скопировать сам оригинал, повтор тоже не создается
+// На сетевой файловой системе reflink не пробуется: повторы сразу копируются
+pub fn link_duplicates(duplicates: Vec<Duplicate>, mode: DedupMode, network: bool) -> (Vec<CopyItem>, usize) {
+    let mut done = Vec::new();
+    let mut linked = 0;
+    let mut failed = 0;
+    let mut reflinks_unsupported = network;
+    for Duplicate { item, original } in duplicates {
+        match link_duplicate(&original, &item, mode, &mut reflinks_unsupported) {
+            Ok(was_linked) => {
+                linked += usize::from(was_linked);
+                done.push(item);
+            }
+            Err(e) => {
+                eprintln!("{}", t!("copy-failed", path = item.source.as_str(), error = format!("{:#}", e)));
+                failed += 1;
+            }
+        }
+    }
+    if reflinks_unsupported {
+        eprintln!("{} {}", t!("warning").yellow().bold(), t!("reflinks-unsupported-duplicates"));
+    }
+    if linked > 0 {
+        report(linked, mode, true);
+    }
+    (done, failed)
+}
+
+// true - ссылка, false - пришлось скопировать
+fn link_duplicate(original: &Path, item: &CopyItem, mode: DedupMode, reflinks_unsupported: &mut bool) -> Result<bool> {
+    let metadata = fs::symlink_metadata(original)
+        .ok()
+        .filter(|metadata| metadata.is_file() && metadata.len() == item.size)
+        .with_context(|| format!("{} was not copied", original.display()))?;
+    let done = match mode {
+        DedupMode::Hardlink => link(original, &metadata, &item.destination)?,
+        DedupMode::Reflink if *reflinks_unsupported => false,
+        DedupMode::Reflink => {
+            let cloned = reflink(original, &metadata, &item.destination)?;
+            *reflinks_unsupported = !cloned;
+            cloned
+        }
+    };
+    // Без ссылок копируем уже записанную копию: источник второй раз не читаем
+    if !done {
+        prepare_destination(&metadata, &item.destination)?;
+        fs::copy(original, &item.destination)
+            .with_context(|| format!("Failed to copy {} to {}", original.display(), item.destination.display()))?;
+    }
+    Ok(done)
+}
+
+// FICLONE: новый файл делит блоки данных с существующим; false - файловая система не умеет
+pub fn reflink(existing: &Path, metadata: &Metadata, destination: &Path) -> Result<bool> {
+    if !prepare_destination(metadata, destination)? {
+        return Ok(true);
+    }
+    let source = File::open(existing).with_context(|| format!("Failed to open {}", existing.display()))?;
+    let target = File::create(destination)
+        .with_context(|| format!("Failed to create destination file: {}", destination.display()))?;
+    let result = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    drop(target);
+    let _ = fs::remove_file(destination);
+    match error.raw_os_error() {
+        Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY) => Ok(false),
+        _ => Err(error).with_context(|| format!("Failed to clone {} to {}", existing.display(), destination.display())),
+    }
+}
+
+// struct file_dedupe_range из linux/fs.h с одним получателем
+#[repr(C)]
+struct FileDedupeRange {
+    src_offset: u64,
+    src_length: u64,
+    dest_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    info: FileDedupeRangeInfo,
+}
+
+#[repr(C)]
+struct FileDedupeRangeInfo {
+    dest_fd: i64,
+    dest_offset: u64,
+    bytes_deduped: u64,
+    status: i32,
+    reserved: u32,
+}
+
+// В номер ioctl входит размер заголовка без массива получателей
+const FIDEDUPERANGE: libc::Ioctl = libc::_IOWR::<[u64; 3]>(0x94, 54);
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+// btrfs не берет за раз больше 16 МиБ
+const MAX_DEDUPE_LENGTH: u64 = 16 * 1024 * 1024;
+
+// Сколько байт copy теперь делит с existing; None - файловая система не умеет. Содержимое
+// сравнивает ядро, так что совпадение хешей здесь только подсказка
+fn dedupe_range(existing: &Path, copy: &Path, size: u64) -> Result<Option<u64>> {
+    let source = File::open(existing).with_context(|| format!("Failed to open {}", existing.display()))?;
+    let target = File::open(copy).with_context(|| format!("Failed to open {}", copy.display()))?;
+    let unsupported = |errno: i32| matches!(errno, libc::EOPNOTSUPP | libc::EINVAL | libc::ENOTTY);
+
+    let mut shared = 0;
+    while shared < size {
+        let mut range = FileDedupeRange {
+            src_offset: shared,
+            src_length: (size - shared).min(MAX_DEDUPE_LENGTH),
+            dest_count: 1,
+            reserved1: 0,
+            reserved2: 0,
+            info: FileDedupeRangeInfo {
+                dest_fd: target.as_raw_fd().into(),
+                dest_offset: shared,
+                bytes_deduped: 0,
+                status: 0,
+                reserved: 0,
+            },
+        };
+        if unsafe { libc::ioctl(source.as_raw_fd(), FIDEDUPERANGE, &mut range) } != 0 {
+            let error = io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(errno) if unsupported(errno) => Ok(None),
+                Some(libc::EXDEV) => Ok(Some(shared)),
+                _ => Err(error).with_context(|| format!("Failed to share blocks of {} with {}", copy.display(), existing.display())),
+            };
+        }
+        match range.info.status {
+            FILE_DEDUPE_RANGE_DIFFERS => break,
+            status if status < 0 && unsupported(-status) => return Ok(None),
+            status if status < 0 => {
+                return Err(io::Error::from_raw_os_error(-status))
+                    .with_context(|| format!("Failed to share blocks of {} with {}", copy.display(), existing.display()));
+            }
+            _ if range.info.bytes_deduped == 0 => break,
+            _ => shared += range.info.bytes_deduped,
+        }
+    }
+    Ok(Some(shared))
+}
@@ -0,0 +1,71 @@
+use std::alloc::{self, Layout};
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::fd::AsRawFd;
+use std::ptr::NonNull;
+
+// С O_DIRECT адрес буфера, длина и смещение в файле кратны размеру блока устройства;
+// 4K подходит и дискам с сектором 512 байт
+pub const DIRECT_IO_ALIGN: usize = 4096;
+
+// Буфер от 2M выравнивается по огромной странице и просит ее у ядра (transparent huge pages):
+// меньше промахов TLB при DMA в большой буфер
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+// Буфер чтения и записи для --direct-io: vec![0; n] выровнен только по 8-16 байт
+pub struct AlignedBuffer {
+    data: NonNull<u8>,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    // Размер округляется вверх до кратного DIRECT_IO_ALIGN
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1).next_multiple_of(DIRECT_IO_ALIGN);
+        let align = if size >= HUGE_PAGE_SIZE { HUGE_PAGE_SIZE } else { DIRECT_IO_ALIGN };
+        let layout = Layout::from_size_align(size, align).expect("buffer layout");
+        let data = NonNull::new(unsafe { alloc::alloc_zeroed(layout) }).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        if align == HUGE_PAGE_SIZE {
+            // Только подсказка: без THP буфер останется на обычных страницах
+            unsafe { libc::madvise(data.as_ptr().cast(), size, libc::MADV_HUGEPAGE) };
+        }
+        AlignedBuffer { data, layout }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.data.as_ptr(), self.layout) };
+    }
+}
+
+// Последний неполный блок файла с O_DIRECT не записать: флаг снимается, и хвост идет через
+// страничный кеш
+pub fn clear_direct(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Файловые системы без O_DIRECT (tmpfs, часть FUSE) отвечают EINVAL при открытии
+pub fn is_unsupported(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EINVAL)
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::collect::CopyItem;
+use crate::metadata::open_source;
+use crate::{BUFFER_SIZE, MAX_CONCURRENT_FILES};
+
+// Шпиндельные диски (HDD): десяток одновременных потоков на одном диске гоняет головки между
+// файлами, и копирование идет медленнее, чем в один-два потока. Диск файла находится по
+// st_dev через /sys/dev/block; на SSD, в сети и на виртуальных файловых системах число
+// потоков не ограничивается
+pub struct DiskSlots {
+    // Имена дисков (sda) для сообщения
+    names: Vec<String>,
+    // Файл -> индексы дисков, которые он читает или пишет
+    files: HashMap<u32, Vec<usize>>,
+    // Сколько файлов сейчас копируется на каждом диске
+    active: Vec<usize>,
+    limit: usize,
+}
+
+impl DiskSlots {
+    pub fn detect(files: &[CopyItem], local_source: bool, local_destination: bool, limit: usize) -> Self {
+        let mut slots = DiskSlots {
+            names: Vec::new(),
+            files: HashMap::new(),
+            active: Vec::new(),
+            limit,
+        };
+        let mut devices: HashMap<u64, Option<usize>> = HashMap::new();
+        let mut directories: HashMap<PathBuf, Option<u64>> = HashMap::new();
+        for (id, item) in files.iter().enumerate() {
+            let mut found = Vec::new();
+            if local_source && let Ok(metadata) = fs::symlink_metadata(&item.source) {
+                found.push(metadata.dev());
+            }
+            if local_destination {
+                for destination in item.destinations() {
+                    let directory = destination.parent().unwrap_or(Path::new(".")).to_path_buf();
+                    let device = *directories.entry(directory).or_insert_with_key(|directory| existing_device(directory));
+                    found.extend(device);
+                }
+            }
+            let mut disks: Vec<usize> = found
+                .into_iter()
+                .filter_map(|device| *devices.entry(device).or_insert_with(|| rotational_disk(device).map(|name| slots.add(name))))
+                .collect();
+            disks.sort_unstable();
+            disks.dedup();
+            if !disks.is_empty() {
+                slots.files.insert(id as u32, disks);
+            }
+        }
+        slots
+    }
+
+    fn add(&mut self, name: String) -> usize {
+        match self.names.iter().position(|known| *known == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name);
+                self.active.push(0);
+                self.names.len() - 1
+            }
+        }
+    }
+
+    pub fn disks(&self) -> &[String] {
+        &self.names
+    }
+
+    // false - один из дисков файла уже занят до предела
+    pub fn try_acquire(&mut self, id: u32) -> bool {
+        let Some(disks) = self.files.get(&id) else {
+            return true;
+        };
+        if disks.iter().any(|disk| self.active[*disk] >= self.limit) {
+            return false;
+        }
+        for disk in disks {
+            self.active[*disk] += 1;
+        }
+        true
+    }
+
+    pub fn release(&mut self, id: u32) {
+        if let Some(disks) = self.files.get(&id) {
+            for disk in disks {
+                self.active[*disk] = self.active[*disk].saturating_sub(1);
+            }
+        }
+    }
+}
+
+// Файлы источника на HDD копируются в порядке их расположения на диске (первый экстент по
+// FIEMAP, без него - номер inode), чтобы чтение шло почти подряд, а не скачками головок.
+// Остальные файлы идут первыми в прежнем порядке
+pub fn sort_by_disk_position(files: &mut Vec<CopyItem>) {
+    let mut rotational: HashMap<u64, bool> = HashMap::new();
+    let mut keyed: Vec<_> = files
+        .drain(..)
+        .map(|item| {
+            let key = fs::symlink_metadata(&item.source).ok().and_then(|metadata| {
+                let device = metadata.dev();
+                let hdd = *rotational.entry(device).or_insert_with(|| rotational_disk(device).is_some());
+                let offset = metadata.is_file().then(|| physical_offset(Path::new(&item.source))).flatten();
+                hdd.then_some((device, offset, metadata.ino()))
+            });
+            (key, item)
+        })
+        .collect();
+    if keyed.iter().any(|(key, _)| key.is_some()) {
+        keyed.sort_by_key(|(key, _)| *key);
+    }
+    files.extend(keyed.into_iter().map(|(_, item)| item));
+}
+
+// struct fiemap из linux/fiemap.h с местом для одного экстента
+#[repr(C)]
+#[derive(Default)]
+struct Fiemap {
+    start: u64,
+    length: u64,
+    flags: u32,
+    mapped_extents: u32,
+    extent_count: u32,
+    reserved: u32,
+    extent: FiemapExtent,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct FiemapExtent {
+    logical: u64,
+    physical: u64,
+    length: u64,
+    reserved64: [u64; 2],
+    flags: u32,
+    reserved: [u32; 3],
+}
+
+// _IOWR('f', 11, struct fiemap)
+const FS_IOC_FIEMAP: libc::Ioctl = 0xc020660b;
+
+// Физическое смещение начала файла; пустые и встроенные в inode файлы его не имеют
+fn physical_offset(path: &Path) -> Option<u64> {
+    let file = open_source(path).ok()?;
+    let mut map = Fiemap {
+        length: u64::MAX,
+        extent_count: 1,
+        ..Fiemap::default()
+    };
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP, &mut map) } != 0 || map.mapped_extents == 0 {
+        return None;
+    }
+    Some(map.extent.physical)
+}
+
+// Назначение может еще не существовать: устройство берется у ближайшего существующего предка
+fn existing_device(path: &Path) -> Option<u64> {
+    path.ancestors().find_map(|ancestor| {
+        let ancestor = if ancestor.as_os_str().is_empty() { Path::new(".") } else { ancestor };
+        fs::metadata(ancestor).ok().map(|metadata| metadata.dev())
+    })
+}
+
+// Число файлов, копируемых одновременно, и размер буфера, когда --jobs не задан: NVMe
+// выдерживает много параллельных запросов, HDD лучше читать немногими длинными потоками.
+// На сетевой файловой системе (NFS, SMB, FUSE) каждый запрос идет к серверу: буфер больше,
+// а лишние проверки каталогов не повторяются
+pub struct Tuning {
+    pub jobs: usize,
+    pub buffer_size: usize,
+    pub network: bool,
+}
+
+impl Default for Tuning {
+    // Удаленные источники и назначения, tmpfs и неизвестные устройства
+    fn default() -> Self {
+        Tuning {
+            jobs: MAX_CONCURRENT_FILES,
+            buffer_size: BUFFER_SIZE,
+            network: false,
+        }
+    }
+}
+
+const NETWORK_BUFFER_SIZE: usize = 1024 * 1024;
+
+// Порядок - от медленных к быстрым: настройку определяет самый медленный диск
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum DeviceClass {
+    Hdd,
+    Ssd,
+    Nvme,
+}
+
+pub fn tune<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Tuning {
+    let paths: Vec<&Path> = paths.into_iter().collect();
+    let mut tuning = match paths.iter().filter_map(|path| device_class(path)).min() {
+        Some(DeviceClass::Hdd) => Tuning {
+            jobs: 4,
+            buffer_size: 1024 * 1024,
+            network: false,
+        },
+        Some(DeviceClass::Ssd) => Tuning {
+            jobs: 8,
+            buffer_size: 256 * 1024,
+            network: false,
+        },
+        Some(DeviceClass::Nvme) => Tuning {
+            jobs: 16,
+            buffer_size: 256 * 1024,
+            network: false,
+        },
+        None => Tuning::default(),
+    };
+    if paths.iter().any(|path| network_fs(path).is_some()) {
+        tuning.buffer_size = tuning.buffer_size.max(NETWORK_BUFFER_SIZE);
+        tuning.network = true;
+    }
+    tuning
+}
+
+// Тип сетевой файловой системы по f_type из statfs
+pub fn network_fs(path: &Path) -> Option<&'static str> {
+    let path = path.ancestors().find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())?;
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    match stat.f_type as u32 {
+        0x6969 => Some("nfs"),
+        0x517b => Some("smb"),
+        0xff534d42 => Some("cifs"),
+        0xfe534d42 => Some("smb2"),
+        0x65735546 => Some("fuse"),
+        0x00c36400 => Some("ceph"),
+        0x01021997 => Some("9p"),
+        _ => None,
+    }
+}
+
+fn device_class(path: &Path) -> Option<DeviceClass> {
+    let disk = block_disk(existing_device(path)?)?;
+    if is_rotational(&disk) {
+        return Some(DeviceClass::Hdd);
+    }
+    // LVM и RAID (dm-0, md0) - по дискам под ними
+    let mut names: Vec<String> = fs::read_dir(disk.join("slaves"))
+        .map(|entries| entries.flatten().map(|entry| entry.file_name().to_string_lossy().into_owned()).collect())
+        .unwrap_or_default();
+    if names.is_empty() {
+        names.push(disk.file_name()?.to_string_lossy().into_owned());
+    }
+    match names.iter().all(|name| name.starts_with("nvme")) {
+        true => Some(DeviceClass::Nvme),
+        false => Some(DeviceClass::Ssd),
+    }
+}
+
+// Имя диска, если это HDD
+fn rotational_disk(device: u64) -> Option<String> {
+    let disk = block_disk(device)?;
+    is_rotational(&disk).then(|| disk.file_name()?.to_str().map(str::to_string))?
+}
+
+// Каталог диска в /sys; раздел (sda1) сводится к диску (sda)
+fn block_disk(device: u64) -> Option<PathBuf> {
+    let path = format!("/sys/dev/block/{}:{}", libc::major(device), libc::minor(device));
+    let path = fs::canonicalize(path).ok()?;
+    match path.join("partition").exists() {
+        true => Some(path.parent()?.to_path_buf()),
+        false => Some(path),
+    }
+}
+
+fn is_rotational(disk: &Path) -> bool {
+    fs::read_to_string(disk.join("queue/rotational")).is_ok_and(|rotational| rotational.trim() == "1")
+}
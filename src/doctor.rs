@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::ffi::CString;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::cli::DoctorArgs;
+use crate::i18n::t;
+use crate::pseudofs::fs_type;
+
+// `rcp doctor`: возможности файловой системы проверяются делом во временной директории
+// `.rcp-doctor-<pid>` - создать ссылку, клон, xattr, дыру в файле, - а не по ее имени:
+// тот же ext4 без user_xattr или NFS разных версий умеют разное
+
+// Размер файла-пробы для дыр: заметно больше блока любой файловой системы
+const SPARSE_PROBE_SIZE: u64 = 16 * 1024 * 1024;
+
+// Нечетная секунда и все девять знаков наносекунд: по прочитанному обратно видно шаг времени,
+// в том числе двухсекундный у FAT
+const PROBE_TIME: Duration = Duration::new(1_700_000_001, 123_456_789);
+
+// None - проверить не удалось (директория только для чтения)
+#[derive(Default)]
+struct Capabilities {
+    fs_type: Option<String>,
+    reflink: Option<bool>,
+    sparse: Option<bool>,
+    xattr: Option<bool>,
+    acl: Option<bool>,
+    symlink: Option<bool>,
+    hardlink: Option<bool>,
+    fallocate: Option<bool>,
+    // Шаг, с которым хранится время изменения
+    precision: Option<Duration>,
+}
+
+enum Advice {
+    Works,
+    Warning,
+    Fails,
+}
+
+pub fn run(args: &DoctorArgs) -> Result<()> {
+    if !args.source.is_dir() {
+        anyhow::bail!("{} is not a directory", args.source.display());
+    }
+    let destination = args
+        .destination
+        .ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.is_dir())
+        .unwrap_or(Path::new("."));
+    let source = probe(&args.source)?;
+    let target = probe(destination)?;
+
+    println!("{:<22} {:<14} {}", "", t!("doctor-source").bold(), t!("doctor-destination").bold());
+    let unknown = || t!("doctor-unknown");
+    let name = |caps: &Capabilities| caps.fs_type.clone().unwrap_or_else(unknown);
+    println!("{:<22} {:<14} {}", t!("doctor-fs"), name(&source), name(&target));
+    let rows = [
+        (t!("doctor-reflink"), source.reflink, target.reflink),
+        (t!("doctor-sparse"), source.sparse, target.sparse),
+        (t!("doctor-xattr"), source.xattr, target.xattr),
+        (t!("doctor-acl"), source.acl, target.acl),
+        (t!("doctor-symlink"), source.symlink, target.symlink),
+        (t!("doctor-hardlink"), source.hardlink, target.hardlink),
+        (t!("doctor-fallocate"), source.fallocate, target.fallocate),
+    ];
+    let answer = |supported: Option<bool>| match supported {
+        Some(true) => t!("doctor-yes"),
+        Some(false) => t!("doctor-no"),
+        None => unknown(),
+    };
+    for (label, source, target) in rows {
+        println!("{:<22} {:<14} {}", label, answer(source), answer(target));
+    }
+    let precision = |caps: &Capabilities| caps.precision.map_or_else(unknown, format_precision);
+    println!("{:<22} {:<14} {}", t!("doctor-timestamps"), precision(&source), precision(&target));
+
+    println!();
+    for (advice, text) in advise(&source, &target) {
+        let mark = match advice {
+            Advice::Works => "✓".green(),
+            Advice::Warning => "!".yellow(),
+            Advice::Fails => "✗".red(),
+        };
+        println!("{} {}", mark.bold(), text);
+    }
+    Ok(())
+}
+
+// Что из этого следует для флагов rcp; важно в основном назначение
+fn advise(source: &Capabilities, target: &Capabilities) -> Vec<(Advice, String)> {
+    let mut advice = Vec::new();
+    let mut check = |supported: Option<bool>, works: String, fails: String| match supported {
+        Some(true) => advice.push((Advice::Works, works)),
+        Some(false) => advice.push((Advice::Fails, fails)),
+        None => {}
+    };
+    check(target.hardlink, t!("doctor-hardlinks-ok"), t!("doctor-hardlinks-no"));
+    check(target.reflink, t!("doctor-reflinks-ok"), t!("doctor-reflinks-no"));
+    check(target.xattr, t!("doctor-fake-super-ok"), t!("doctor-fake-super-no"));
+    check(target.fallocate, t!("doctor-fallocate-ok"), t!("doctor-fallocate-no"));
+    if target.symlink == Some(false) {
+        advice.push((Advice::Fails, t!("doctor-symlinks-no")));
+    }
+
+    if unsafe { libc::geteuid() } == 0 {
+        advice.push((Advice::Works, t!("doctor-ownership-ok")));
+    } else if target.xattr == Some(true) {
+        advice.push((Advice::Warning, t!("doctor-ownership-fake-super")));
+    } else if target.xattr == Some(false) {
+        advice.push((Advice::Fails, t!("doctor-ownership-no")));
+    }
+
+    // Грубее источника: время копии не совпадет, и без --modify-window каждый файл будет
+    // выглядеть измененным
+    match (source.precision, target.precision) {
+        (Some(source), Some(target)) if target > source && target >= Duration::from_secs(1) => {
+            let text = t!("doctor-modify-window", precision = format_precision(target), seconds = target.as_secs());
+            advice.push((Advice::Warning, text));
+        }
+        (Some(_), Some(_)) => advice.push((Advice::Works, t!("doctor-times-ok"))),
+        _ => {}
+    }
+    advice
+}
+
+fn probe(directory: &Path) -> Result<Capabilities> {
+    let fs_type = fs_type(directory);
+    // Поддержку ACL видно без записи: ENODATA - ACL нет у этого файла, EOPNOTSUPP - нет вообще
+    let acl = match get_xattr(directory, "system.posix_acl_access") {
+        Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Some(false),
+        _ => Some(true),
+    };
+
+    let scratch = directory.join(format!(".rcp-doctor-{}", std::process::id()));
+    if let Err(e) = fs::create_dir(&scratch) {
+        if matches!(e.raw_os_error(), Some(libc::EROFS | libc::EACCES | libc::EPERM)) {
+            println!("{} {}", t!("warning").yellow().bold(), t!("doctor-read-only", path = directory.display().to_string()));
+            return Ok(Capabilities { fs_type, acl, ..Capabilities::default() });
+        }
+        return Err(e).with_context(|| format!("Failed to create directory: {}", scratch.display()));
+    }
+    let result = probe_in(&scratch);
+    let _ = fs::remove_dir_all(&scratch);
+    Ok(Capabilities { fs_type, acl, ..result? })
+}
+
+fn probe_in(scratch: &Path) -> Result<Capabilities> {
+    let path = scratch.join("file");
+    let mut file = create(&path)?;
+    file.write_all(b"rcp doctor\n").with_context(|| format!("Failed to write file: {}", path.display()))?;
+
+    let clone = create(&scratch.join("clone"))?;
+    let reflink = unsafe { libc::ioctl(clone.as_raw_fd(), libc::FICLONE, file.as_raw_fd()) } == 0;
+
+    let allocated = create(&scratch.join("allocated"))?;
+    let fallocate = unsafe { libc::fallocate(allocated.as_raw_fd(), 0, 0, 4096) } == 0;
+
+    // Файл из одной дыры: там, где дыр нет, set_len записывает нули
+    let sparse = create(&scratch.join("sparse"))?;
+    let sparse = sparse
+        .set_len(SPARSE_PROBE_SIZE)
+        .and_then(|()| sparse.metadata())
+        .is_ok_and(|metadata| metadata.blocks() * 512 < SPARSE_PROBE_SIZE);
+
+    let xattr = set_xattr(&path, "user.rcp.doctor", b"1").is_ok();
+    let symlink = symlink("file", scratch.join("link")).is_ok();
+    let hardlink = fs::hard_link(&path, scratch.join("hardlink")).is_ok();
+
+    file.set_times(FileTimes::new().set_modified(SystemTime::UNIX_EPOCH + PROBE_TIME))
+        .with_context(|| format!("Failed to set modification time of {}", path.display()))?;
+    let modified = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read modification time of {}", path.display()))?;
+    let modified = modified.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+
+    Ok(Capabilities {
+        reflink: Some(reflink),
+        sparse: Some(sparse),
+        xattr: Some(xattr),
+        symlink: Some(symlink),
+        hardlink: Some(hardlink),
+        fallocate: Some(fallocate),
+        precision: Some(precision(modified)),
+        ..Capabilities::default()
+    })
+}
+
+// Самый мелкий шаг, с которым прочитанное время сходится с записанным (обрезка или округление)
+fn precision(modified: Duration) -> Duration {
+    let expected = PROBE_TIME.as_nanos();
+    let actual = modified.as_nanos();
+    [1, 10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000, 100_000_000, 1_000_000_000, 2_000_000_000u64]
+        .into_iter()
+        .map(Duration::from_nanos)
+        .find(|step| actual.is_multiple_of(step.as_nanos()) && actual.abs_diff(expected) < step.as_nanos())
+        .unwrap_or(Duration::from_secs(2))
+}
+
+fn format_precision(step: Duration) -> String {
+    match step.as_nanos() {
+        nanos if nanos < 1_000 => format!("{} ns", nanos),
+        nanos if nanos < 1_000_000 => format!("{} µs", nanos / 1_000),
+        nanos if nanos < 1_000_000_000 => format!("{} ms", nanos / 1_000_000),
+        _ => format!("{} s", step.as_secs()),
+    }
+}
+
+fn create(path: &Path) -> Result<File> {
+    File::create(path).with_context(|| format!("Failed to create file: {}", path.display()))
+}
+
+fn get_xattr(path: &Path, name: &str) -> io::Result<usize> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name)?;
+    match unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        length => Ok(length as usize),
+    }
+}
+
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name)?;
+    if unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+use colored::Colorize;
+use indicatif::HumanBytes;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive::ARCHIVE_ROOT;
+use crate::cli::DuArgs;
+use crate::collect::{collect_files, expand_sources, CollectOptions, CopyItem};
+use crate::disks::sort_by_disk_position;
+use crate::i18n::t;
+use crate::limits::{apply_limits, prioritize, report_left_out};
+use crate::transport::{local_path, parse_sources};
+
+// `rcp du`: только сбор файлов, в том же порядке и с теми же ограничениями, что у копирования,
+// поэтому --max-files и --max-total-bytes оставляют за бортом те же файлы. Назначение
+// условное, как у архива: директории не создаются
+
+// Директория дерева (--tree): размер и число файлов всего, что под ней
+#[derive(Default)]
+struct Node {
+    size: u64,
+    files: usize,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn add(&mut self, path: &Path, size: u64) {
+        self.size += size;
+        self.files += 1;
+        let mut node = self;
+        for component in path.components() {
+            node = node.children.entry(component.as_os_str().to_string_lossy().into_owned()).or_default();
+            node.size += size;
+            node.files += 1;
+        }
+    }
+
+    fn print(&self, prefix: &str) {
+        let last = self.children.len().saturating_sub(1);
+        for (index, (name, child)) in self.children.iter().enumerate() {
+            let (branch, indent) = match index == last {
+                true => ("└── ", "    "),
+                false => ("├── ", "│   "),
+            };
+            let name = match child.children.is_empty() {
+                true => name.clone(),
+                false => format!("{}/", name).bold().to_string(),
+            };
+            println!("{:>10}  {}{}{}", HumanBytes(child.size).to_string(), prefix, branch, name);
+            child.print(&format!("{}{}", prefix, indent));
+        }
+    }
+}
+
+pub fn run(args: &DuArgs) -> Result<()> {
+    if parse_sources(&args.sources)?.is_some() {
+        anyhow::bail!("rcp du lists local sources only");
+    }
+    let sources = expand_sources(&args.sources.iter().map(|source| local_path(source)).collect::<Vec<_>>())?;
+    for source in &sources {
+        if !source.exists() && !source.is_symlink() {
+            anyhow::bail!("Source path does not exist: {}", source.display());
+        }
+    }
+
+    let options = CollectOptions {
+        trailing_slash: args.trailing_slash,
+        skip_pseudo_fs: !args.include_pseudo_fs,
+        create_directories: false,
+        relative_to: None,
+        special_files: false,
+    };
+    let collection = collect_files(&sources, &[PathBuf::from(ARCHIVE_ROOT)], options)?;
+    let mut files = collection.files;
+    sort_by_disk_position(&mut files);
+    prioritize(&mut files, &args.prioritize);
+    let (files, left_out) = apply_limits(files, args.max_total_bytes, args.max_files);
+    let bytes: u64 = files.iter().map(|item| item.size).sum();
+
+    if args.json {
+        let list = |items: &[CopyItem]| -> Vec<_> {
+            items.iter().map(|item| json!({ "path": item.source, "size": item.size })).collect()
+        };
+        let report = json!({
+            "files": list(&files),
+            "directories": collection.directories.len(),
+            "total_files": files.len(),
+            "total_bytes": bytes,
+            "left_out": list(&left_out),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if args.tree {
+        for source in &sources {
+            let mut root = Node::default();
+            for item in &files {
+                if let Ok(relative) = Path::new(&item.source).strip_prefix(source) {
+                    root.add(relative, item.size);
+                }
+            }
+            if root.files > 0 {
+                println!("{:>10}  {}", HumanBytes(root.size).to_string(), source.display().to_string().bold());
+                root.print("");
+            }
+        }
+    } else {
+        for item in &files {
+            println!("{:>10}  {}", HumanBytes(item.size).to_string(), item.source);
+        }
+    }
+    let total = t!(
+        "du-total",
+        count = files.len(),
+        directories = collection.directories.len(),
+        size = HumanBytes(bytes).to_string()
+    );
+    println!("{}", total.bold());
+    report_left_out(&left_out);
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+// Шифрование файлов в назначении: получатели age (--encrypt-to) или ключи OpenPGP
+// (--encrypt-gpg), для которых вызывается системный gpg
+#[derive(Clone)]
+pub enum Encryption {
+    Age(Vec<age::x25519::Recipient>),
+    Gpg(Vec<String>),
+}
+
+impl Encryption {
+    // Суффикс, который получает зашифрованный файл в назначении
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Encryption::Age(_) => ".age",
+            Encryption::Gpg(_) => ".gpg",
+        }
+    }
+}
+
+// Проверяем до начала копирования, что gpg доступен и знает все ключи,
+// иначе ошибка повторилась бы на каждом файле
+pub fn check_gpg_recipients(recipients: &[String]) -> Result<()> {
+    for recipient in recipients {
+        let status = Command::new("gpg")
+            .args(["--batch", "--quiet", "--list-keys", recipient])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("Failed to run gpg, is GnuPG installed?")?;
+        if !status.success() {
+            anyhow::bail!("gpg does not know a public key for recipient: {}", recipient);
+        }
+    }
+    Ok(())
+}
+
+// gpg шифрует поток: данные пишутся в его stdin, отдельный поток
+// перекладывает зашифрованный stdout в назначение
+pub struct GpgWriter<W: Write> {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    pump: JoinHandle<io::Result<W>>,
+}
+
+impl<W: Write + Send + 'static> GpgWriter<W> {
+    fn spawn(mut inner: W, recipients: &[String]) -> Result<Self> {
+        let mut command = Command::new("gpg");
+        command.args(["--batch", "--yes", "--quiet", "--trust-model", "always", "--encrypt"]);
+        for recipient in recipients {
+            command.args(["--recipient", recipient]);
+        }
+        let mut child = command
+            .args(["--output", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run gpg, is GnuPG installed?")?;
+
+        let stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().context("Failed to capture gpg output")?;
+        let pump = thread::spawn(move || {
+            io::copy(&mut stdout, &mut inner)?;
+            Ok(inner)
+        });
+
+        Ok(GpgWriter { child, stdin, pump })
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        let inner = self
+            .pump
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("gpg output thread panicked")))?;
+        if !status.success() {
+            return Err(io::Error::other(format!("gpg exited with {}", status)));
+        }
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for GpgWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.write(buf),
+            None => Err(io::Error::other("gpg input is closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Поток записи с необязательным шифрованием; как и у сжатия, finish() обязателен -
+// без него у потока age не будет последнего блока, а gpg не завершится
+pub enum EncryptedWriter<W: Write> {
+    Plain(W),
+    Age(age::stream::StreamWriter<W>),
+    Gpg(GpgWriter<W>),
+}
+
+impl<W: Write + Send + 'static> EncryptedWriter<W> {
+    pub fn new(inner: W, encryption: Option<&Encryption>) -> Result<Self> {
+        Ok(match encryption {
+            None => EncryptedWriter::Plain(inner),
+            Some(Encryption::Age(recipients)) => {
+                let encryptor = age::Encryptor::with_recipients(
+                    recipients.iter().map(|recipient| recipient as &dyn age::Recipient),
+                )
+                .context("Failed to initialize age encryption")?;
+                EncryptedWriter::Age(encryptor.wrap_output(inner).context("Failed to write age header")?)
+            }
+            Some(Encryption::Gpg(recipients)) => EncryptedWriter::Gpg(GpgWriter::spawn(inner, recipients)?),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            EncryptedWriter::Plain(inner) => Ok(inner),
+            EncryptedWriter::Age(writer) => writer.finish(),
+            EncryptedWriter::Gpg(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EncryptedWriter::Plain(inner) => inner.write(buf),
+            EncryptedWriter::Age(writer) => writer.write(buf),
+            EncryptedWriter::Gpg(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EncryptedWriter::Plain(inner) => inner.flush(),
+            EncryptedWriter::Age(writer) => writer.flush(),
+            EncryptedWriter::Gpg(writer) => writer.flush(),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::thread;
+
+use crate::collect::CopyItem;
+use crate::control::{Control, WorkQueue};
+
+// Как файлы из очереди раздаются на копирование: --jobs рабочих потоков, каждый берет
+// следующую пачку, когда освобождается. copy получает номер рабочего места (для
+// --worker-bars и --tui), файл и итог этого места
+pub fn run<R, F>(jobs: usize, queue: &Arc<WorkQueue>, control: &Arc<Control>, copy: F) -> Vec<R>
+where
+    R: Default + Send + 'static,
+    F: Fn(usize, u32, CopyItem, &mut R) + Send + Sync + 'static,
+{
+    let copy = Arc::new(copy);
+    let handles: Vec<_> = (0..jobs)
+        .map(|worker_id| {
+            let (queue, control, copy) = (Arc::clone(queue), Arc::clone(control), Arc::clone(&copy));
+            thread::spawn(move || {
+                let mut report = R::default();
+                loop {
+                    let batch = queue.next_batch(worker_id, &control);
+                    if batch.is_empty() {
+                        break;
+                    }
+                    copy_batch(worker_id, batch, &queue, &control, &*copy, &mut report);
+                }
+                report
+            })
+        })
+        .collect();
+    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+}
+
+// Файлы пачки (WorkQueue::next_batch) по порядку; после остановки непочатые возвращаются в
+// очередь и считаются нескопированными
+fn copy_batch<R, F>(place: usize, batch: Vec<(u32, CopyItem)>, queue: &WorkQueue, control: &Control, copy: &F, report: &mut R)
+where
+    F: Fn(usize, u32, CopyItem, &mut R),
+{
+    let mut files = batch.into_iter();
+    while let Some((file_id, item)) = files.next() {
+        copy(place, file_id, item, report);
+        queue.finished(file_id);
+        if control.is_draining() {
+            queue.put_back(files.collect());
+            break;
+        }
+    }
+}
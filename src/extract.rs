@@ -0,0 +1,437 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, FileTimes};
+use std::io::{Read, Write};
+use std::fs::OpenOptions;
+use std::os::unix::fs::{symlink, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+use crate::archive::{open_reader, ArchiveEntry, EntryKind};
+use crate::i18n::t;
+use crate::progress::{spawn_progress_manager, BarStyle, ProgressThrottle, ProgressUpdate};
+use crate::BUFFER_SIZE;
+
+// Сколько блоков данных одной записи может ждать своего рабочего потока
+const CHUNK_QUEUE_DEPTH: usize = 4;
+
+// Файл из архива: поток чтения архива передает данные рабочему потоку по каналу,
+// поэтому распаковка следующей записи идет параллельно с записью предыдущих
+struct ExtractJob {
+    id: u32,
+    path: PathBuf,
+    size: u64,
+    mode: Option<u32>,
+    modified: Option<SystemTime>,
+    chunks: Receiver<Vec<u8>>,
+}
+
+// Распаковываем архивы (tar, tar+zstd, zip или `-` для stdin) в назначение
+pub fn extract_archives(sources: &[PathBuf], destination: &Path, jobs: usize, style: BarStyle) -> Result<()> {
+    for source in sources {
+        extract_archive(source, destination, jobs, style.clone())?;
+    }
+    Ok(())
+}
+
+fn extract_archive(source: &Path, destination: &Path, jobs: usize, style: BarStyle) -> Result<()> {
+    let mut reader = open_reader(source)?;
+    fs::create_dir_all(destination)
+        .with_context(|| format!("Failed to create destination directory: {}", destination.display()))?;
+
+    println!("{}", t!("extracting", path = source.display().to_string()));
+    let (progress_sender, manager_handle) = spawn_progress_manager(0, style, None, None);
+
+    let (job_sender, job_receiver) = mpsc::sync_channel::<ExtractJob>(jobs);
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+    // Файлы, которые не удалось записать: распаковка с ними не считается успешной
+    let failures = Arc::new(AtomicUsize::new(0));
+    let mut worker_handles = Vec::new();
+    for _ in 0..jobs {
+        let job_receiver = Arc::clone(&job_receiver);
+        let progress_sender = progress_sender.clone();
+        let failures = Arc::clone(&failures);
+        worker_handles.push(thread::spawn(move || {
+            loop {
+                let job = job_receiver.lock().unwrap().recv();
+                let Ok(job) = job else { break };
+                let id = job.id;
+                if let Err(e) = write_job(job, &progress_sender) {
+                    let error = t!("extract-failed", error = e.to_string());
+                    eprintln!("{}", error);
+                    failures.fetch_add(1, Ordering::Relaxed);
+                    let _ = progress_sender.send(ProgressUpdate::Failed { id, error });
+                }
+            }
+        }));
+    }
+
+    let mut directories = Vec::new();
+    let mut hard_links = Vec::new();
+    let mut symlinks = Vec::new();
+    let mut next_id = 0;
+
+    let result = reader.for_each_entry(&mut |entry: ArchiveEntry, data: &mut dyn Read| {
+        let Some(relative) = sanitize_entry_path(&entry.path) else {
+            eprintln!("{}", t!("skipping-unsafe-entry", path = entry.path.display().to_string()));
+            return Ok(());
+        };
+        // Запись `./` - это сам корень назначения
+        if relative.as_os_str().is_empty() {
+            return Ok(());
+        }
+        // Ссылка, оставшаяся в назначении от прошлой распаковки, тоже не должна уводить запись наружу
+        let target = match join_inside(destination, &relative) {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("{}", t!("extract-failed", error = format!("{:#}", e)));
+                failures.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        };
+
+        match entry.kind {
+            EntryKind::Directory => {
+                fs::create_dir_all(&target)
+                    .with_context(|| format!("Failed to create directory: {}", target.display()))?;
+                directories.push((target, entry.mode, entry.modified));
+            }
+            // Ссылки создаются после всех файлов: иначе запись `a -> /etc` и за ней `a/passwd`
+            // записала бы файл за пределами назначения
+            EntryKind::Symlink(link_target) => symlinks.push((relative, link_target)),
+            EntryKind::HardLink(link_target) => {
+                // Цель может еще записываться - связываем после окончания распаковки
+                match sanitize_entry_path(&link_target).map(|relative| join_inside(destination, &relative)) {
+                    Some(Ok(link_target)) => hard_links.push((link_target, target)),
+                    _ => {
+                        eprintln!("{}", t!("skipping-unsafe-link", target = link_target.display().to_string()));
+                        failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            EntryKind::File => {
+                let (chunk_sender, chunk_receiver) = mpsc::sync_channel(CHUNK_QUEUE_DEPTH);
+                let _ = progress_sender.send(ProgressUpdate::Queued { files: 1 });
+                let job = ExtractJob {
+                    id: next_id,
+                    path: target,
+                    size: entry.size,
+                    mode: entry.mode,
+                    modified: entry.modified,
+                    chunks: chunk_receiver,
+                };
+                next_id += 1;
+                if job_sender.send(job).is_err() {
+                    anyhow::bail!("Extraction workers stopped");
+                }
+                stream_entry(data, &chunk_sender)?;
+            }
+        }
+
+        Ok(())
+    });
+
+    drop(job_sender);
+    for handle in worker_handles {
+        handle.join().unwrap();
+    }
+
+    for (link_target, path) in hard_links {
+        create_parent(&path)?;
+        let _ = fs::remove_file(&path);
+        if let Err(e) = fs::hard_link(&link_target, &path) {
+            eprintln!("{}", t!("hard-link-failed", path = path.display().to_string(), error = e.to_string()));
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Ссылка внутри директории-ссылки (`a -> /tmp`, затем `a/b`) тоже легла бы снаружи
+    for (relative, link_target) in symlinks {
+        let created = join_inside(destination, &relative).and_then(|target| {
+            create_parent(&target)?;
+            let _ = fs::remove_file(&target);
+            symlink(&link_target, &target).with_context(|| format!("Failed to create symlink: {}", target.display()))
+        });
+        if let Err(e) = created {
+            eprintln!("{}", t!("extract-failed", error = format!("{:#}", e)));
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Права и время директорий выставляем последними: запись файлов меняет mtime
+    for (path, mode, modified) in directories.into_iter().rev() {
+        apply_metadata(&path, mode, modified)?;
+    }
+
+    drop(progress_sender);
+    manager_handle.join().expect("Progress manager panicked")?;
+
+    result?;
+    let failures = failures.load(Ordering::Relaxed);
+    if failures > 0 {
+        anyhow::bail!("{} entries of {} could not be extracted", failures, source.display());
+    }
+    Ok(())
+}
+
+fn stream_entry(data: &mut dyn Read, chunk_sender: &SyncSender<Vec<u8>>) -> Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let bytes_read = data.read(&mut buffer).context("Failed to read archive data")?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        // Рабочий поток отказался от файла (ошибка записи) - остаток записи пропускаем
+        if chunk_sender.send(buffer[..bytes_read].to_vec()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn write_job(job: ExtractJob, progress_sender: &mpsc::Sender<ProgressUpdate>) -> Result<()> {
+    create_parent(&job.path)?;
+    // Ссылку на месте файла (от прошлой распаковки) заменяем, а не пишем по ней
+    if job.path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        let _ = fs::remove_file(&job.path);
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&job.path)
+        .with_context(|| format!("Failed to create destination file: {}", job.path.display()))?;
+
+    let _ = progress_sender.send(ProgressUpdate::NewFile {
+        path: job.path.to_string_lossy().into_owned(),
+        size: job.size.max(1),
+        id: job.id,
+    });
+
+    let mut total_written = 0;
+    let throttle = ProgressThrottle::default();
+    for chunk in &job.chunks {
+        file.write_all(&chunk)
+            .with_context(|| format!("Failed to write file: {}", job.path.display()))?;
+        total_written += chunk.len() as u64;
+        if throttle.ready() {
+            let _ = progress_sender.send(ProgressUpdate::Progress {
+                id: job.id,
+                bytes_copied: total_written,
+            });
+        }
+    }
+    drop(file);
+
+    apply_metadata(&job.path, job.mode, job.modified)?;
+    let _ = progress_sender.send(ProgressUpdate::Finished { id: job.id });
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+pub fn apply_metadata(path: &Path, mode: Option<u32>, modified: Option<SystemTime>) -> Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+            .with_context(|| format!("Failed to set permissions: {}", path.display()))?;
+    }
+    if let Some(modified) = modified
+        && let Ok(file) = File::open(path)
+    {
+        let _ = file.set_times(FileTimes::new().set_modified(modified));
+    }
+    Ok(())
+}
+
+// Запрещаем выход за пределы назначения: абсолютные пути делаем относительными,
+// записи с `..` отбрасываем
+pub fn sanitize_entry_path(path: &Path) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => result.push(part),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+            Component::ParentDir => return None,
+        }
+    }
+    Some(result)
+}
+
+// relative внутри root, если ни одна уже существующая директория на пути к нему не символическая
+// ссылка: через ссылку `a -> /etc` запись `a/passwd` ушла бы за пределы root. Последний
+// компонент не проверяется - его заменяют целиком или открывают с O_NOFOLLOW
+pub fn join_inside(root: &Path, relative: &Path) -> Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    let mut components = relative.components().peekable();
+    // Пока путь существует; дальше ссылок быть уже не может
+    let mut existing = true;
+    while let Some(component) = components.next() {
+        path.push(component);
+        if !existing || components.peek().is_none() {
+            continue;
+        }
+        match path.symlink_metadata() {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                anyhow::bail!("Path goes through a symbolic link: {}", path.display())
+            }
+            Ok(_) => {}
+            Err(_) => existing = false,
+        }
+    }
+    Ok(path)
+}
+
+// Ссылка link (относительно корня) на target остается внутри корня: цель не абсолютная,
+// и `..` в ней не поднимается выше корня
+pub fn link_stays_inside(link: &Path, target: &Path) -> bool {
+    let mut depth = link.components().count().saturating_sub(1);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rcp-extract-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    enum Entry<'a> {
+        Link(&'a str, &'a Path),
+        File(&'a str, &'a [u8]),
+    }
+
+    fn tar(path: &Path, entries: &[Entry]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            match entry {
+                Entry::Link(name, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, name, target).unwrap();
+                }
+                Entry::File(name, data) => {
+                    header.set_size(data.len() as u64);
+                    builder.append_data(&mut header, name, *data).unwrap();
+                }
+            }
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn file_behind_symlink_stays_in_destination() {
+        let dir = scratch("file-behind-link");
+        let outside = scratch("file-behind-link-outside");
+        let archive = dir.join("evil.tar");
+        tar(&archive, &[Entry::Link("a", &outside), Entry::File("a/payload", b"evil")]);
+
+        let destination = dir.join("out");
+        // Ссылка `a` не создается: на ее месте уже директория с файлом
+        assert!(extract_archive(&archive, &destination, 2, BarStyle::default()).is_err());
+        assert!(!outside.join("payload").exists());
+        assert_eq!(fs::read(destination.join("a/payload")).unwrap(), b"evil");
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn symlink_behind_symlink_is_refused() {
+        let dir = scratch("link-behind-link");
+        let outside = scratch("link-behind-link-outside");
+        let archive = dir.join("evil.tar");
+        tar(&archive, &[Entry::Link("a", &outside), Entry::Link("a/b", Path::new("target"))]);
+
+        let result = extract_archive(&archive, &dir.join("out"), 2, BarStyle::default());
+        assert!(result.is_err());
+        assert!(outside.join("b").symlink_metadata().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn existing_symlink_is_replaced_not_followed() {
+        let dir = scratch("replace-link");
+        let victim = dir.join("victim");
+        fs::write(&victim, b"keep").unwrap();
+        let destination = dir.join("out");
+        fs::create_dir_all(&destination).unwrap();
+        symlink(&victim, destination.join("file")).unwrap();
+        let archive = dir.join("plain.tar");
+        tar(&archive, &[Entry::File("file", b"new")]);
+
+        extract_archive(&archive, &destination, 2, BarStyle::default()).unwrap();
+        assert_eq!(fs::read(&victim).unwrap(), b"keep");
+        assert_eq!(fs::read(destination.join("file")).unwrap(), b"new");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symlink_left_in_destination_is_not_followed() {
+        let dir = scratch("old-link");
+        let outside = scratch("old-link-outside");
+        let destination = dir.join("out");
+        fs::create_dir_all(&destination).unwrap();
+        symlink(&outside, destination.join("a")).unwrap();
+        let archive = dir.join("evil.tar");
+        tar(&archive, &[Entry::File("a/payload", b"evil")]);
+
+        assert!(extract_archive(&archive, &destination, 2, BarStyle::default()).is_err());
+        assert!(!outside.join("payload").exists());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_fail_the_extraction() {
+        let dir = scratch("failed-write");
+        let destination = dir.join("out");
+        // На месте директории для файла - обычный файл
+        fs::create_dir_all(&destination).unwrap();
+        fs::write(destination.join("a"), b"file").unwrap();
+        let archive = dir.join("blocked.tar");
+        tar(&archive, &[Entry::File("a/payload", b"data"), Entry::File("b", b"data")]);
+
+        assert!(extract_archive(&archive, &destination, 2, BarStyle::default()).is_err());
+        assert_eq!(fs::read(destination.join("b")).unwrap(), b"data");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entry_paths_are_sanitized() {
+        assert_eq!(sanitize_entry_path(Path::new("/etc/passwd")), Some(PathBuf::from("etc/passwd")));
+        assert_eq!(sanitize_entry_path(Path::new("./a/./b")), Some(PathBuf::from("a/b")));
+        assert_eq!(sanitize_entry_path(Path::new("a/../../b")), None);
+    }
+
+    #[test]
+    fn link_targets_are_checked_against_the_root() {
+        assert!(link_stays_inside(Path::new("a/link"), Path::new("../b")));
+        assert!(link_stays_inside(Path::new("a/link"), Path::new("./c/../d")));
+        assert!(!link_stays_inside(Path::new("link"), Path::new("../b")));
+        assert!(!link_stays_inside(Path::new("a/link"), Path::new("/etc")));
+    }
+}
@@ -0,0 +1,831 @@
+use anyhow::{Context, Result};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::http::percent_decode;
+use crate::i18n::t;
+use crate::tls::ClientTls;
+use crate::transport::{Entry, Progress, Sink, SinkFile, Source};
+
+// FTP и FTPS (явный TLS по AUTH TLS): пассивный режим (EPSV, затем PASV), листинги
+// MLSD/MLST (RFC 3659) и докачка по REST после обрыва. Каждый рабочий поток берет
+// свое управляющее соединение, передача идет по отдельному соединению данных
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// Зависший сервер не должен держать копирование вечно
+const IO_TIMEOUT: Duration = Duration::from_secs(120);
+const RESUME_ATTEMPTS: u32 = 5;
+const RESUME_DELAY: Duration = Duration::from_secs(2);
+
+// `ftp://[user[:password]@]host[:port]/path` или `ftps://...`. Как и у curl, путь
+// отсчитывается от директории входа, а `ftp://host//srv/file` - абсолютный путь
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FtpLocation {
+    // `host:port`
+    pub address: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    pub path: PathBuf,
+}
+
+impl FtpLocation {
+    pub fn parse(location: &Path) -> Option<Self> {
+        let location = location.to_str()?;
+        let (tls, rest) = match location.strip_prefix("ftp://") {
+            Some(rest) => (false, rest),
+            None => (true, location.strip_prefix("ftps://")?),
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (credentials, host) = match authority.rsplit_once('@') {
+            Some((credentials, host)) => (Some(credentials), host),
+            None => (None, authority),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let (user, password) = match credentials.map(|credentials| credentials.split_once(':')) {
+            Some(Some((user, password))) => (Some(percent_decode(user)), Some(percent_decode(password))),
+            Some(None) => (credentials.map(percent_decode), None),
+            None => (None, None),
+        };
+        let has_port = host.rsplit_once(':').is_some_and(|(name, port)| {
+            !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()) && (!name.contains(':') || name.ends_with(']'))
+        });
+        Some(FtpLocation {
+            address: if has_port { host.to_string() } else { format!("{}:21", host) },
+            user,
+            password,
+            tls,
+            path: PathBuf::from(if path.is_empty() { ".".to_string() } else { percent_decode(path) }),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FtpEntry {
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+// Строка фактов MLSD/MLST: `type=file;size=12;modify=...; name`
+fn parse_facts(line: &str) -> Option<(&str, FtpEntry)> {
+    let (facts, name) = line.split_once(' ')?;
+    let mut kind = None;
+    let mut entry = FtpEntry::default();
+    for fact in facts.split(';') {
+        let Some((key, value)) = fact.split_once('=') else {
+            continue;
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "type" => kind = Some(value.to_ascii_lowercase()),
+            "size" => entry.size = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    // cdir/pdir - сама директория и родитель, ссылки и прочее не копируем
+    match kind.as_deref() {
+        Some("file") => Some((name, entry)),
+        Some("dir") => Some((name, FtpEntry { is_dir: true, ..entry })),
+        _ => None,
+    }
+}
+
+fn command_path(path: &Path) -> Result<String> {
+    let path = path.to_string_lossy();
+    if path.contains(['\r', '\n']) {
+        anyhow::bail!("Path cannot be used over FTP: {}", path);
+    }
+    Ok(path.into_owned())
+}
+
+// Соединение управления или данных, с TLS или без
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    fn connect(address: SocketAddr) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        Ok(stream)
+    }
+
+    fn tls(stream: TcpStream, config: &Arc<ClientConfig>, server_name: &ServerName<'static>) -> io::Result<Self> {
+        let connection = ClientConnection::new(Arc::clone(config), server_name.clone()).map_err(io::Error::other)?;
+        Ok(Stream::Tls(Box::new(StreamOwned::new(connection, stream))))
+    }
+
+    // Конец передачи: серверу FTPS нужен close_notify, иначе файл считается оборванным
+    fn close(self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.shutdown(Shutdown::Write),
+            Stream::Tls(mut stream) => {
+                while stream.conn.is_handshaking() {
+                    stream.conn.complete_io(&mut stream.sock)?;
+                }
+                stream.conn.send_close_notify();
+                stream.flush()?;
+                stream.sock.shutdown(Shutdown::Write)?;
+                // Ждем, пока сервер закроет соединение: непрочитанные данные (билеты сессии TLS)
+                // при закрытии сокета дают RST, и сервер может потерять конец файла
+                match io::copy(&mut stream, &mut io::sink()) {
+                    Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            // Многие серверы закрывают соединение данных без close_notify; полноту
+            // передачи подтверждает ответ на управляющем соединении
+            Stream::Tls(stream) => match stream.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+                result => result,
+            },
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    fn is_ok(&self) -> bool {
+        self.code < 400
+    }
+
+    fn is_preliminary(&self) -> bool {
+        (100..200).contains(&self.code)
+    }
+
+    // 4xx - временный отказ (обрыв передачи, нет свободных соединений): можно повторить
+    fn is_transient(&self) -> bool {
+        (400..500).contains(&self.code)
+    }
+
+    fn error(&self, action: &str) -> anyhow::Error {
+        anyhow::anyhow!("{}: {}", action, self.lines.last().map_or("", |line| line.trim()))
+    }
+}
+
+struct Connection {
+    control: BufReader<Stream>,
+    peer: IpAddr,
+    tls: Option<(Arc<ClientConfig>, ServerName<'static>)>,
+    // Сервер не знает EPSV - сразу просим PASV
+    pasv_only: bool,
+}
+
+impl Connection {
+    fn open(session: &FtpSession) -> Result<Self> {
+        let failed = || format!("Failed to connect to FTP server {}", session.address);
+        let address = session
+            .address
+            .to_socket_addrs()
+            .with_context(failed)?
+            .next()
+            .with_context(failed)?;
+        let stream = Stream::connect(address).with_context(failed)?;
+        let mut connection = Connection {
+            control: BufReader::new(Stream::Plain(stream)),
+            peer: address.ip(),
+            tls: None,
+            pasv_only: false,
+        };
+
+        let greeting = connection.reply().with_context(failed)?;
+        if !greeting.is_ok() {
+            return Err(greeting.error("FTP server refused the connection")).with_context(failed);
+        }
+
+        if let Some(config) = &session.tls {
+            let reply = connection.command("AUTH TLS")?;
+            if reply.code != 234 {
+                return Err(reply.error("FTP server does not support TLS")).with_context(failed);
+            }
+            let Stream::Plain(stream) = connection.control.into_inner() else {
+                unreachable!()
+            };
+            let mut stream = Stream::tls(stream, config, &session.server_name)?;
+            if let Stream::Tls(tls) = &mut stream {
+                while tls.conn.is_handshaking() {
+                    tls.conn
+                        .complete_io(&mut tls.sock)
+                        .with_context(|| format!("TLS handshake with {} failed", session.address))?;
+                }
+            }
+            connection.control = BufReader::new(stream);
+            connection.tls = Some((Arc::clone(config), session.server_name.clone()));
+        }
+
+        let user = session.user.as_deref().unwrap_or("anonymous");
+        let mut reply = connection.command(&format!("USER {}", user))?;
+        if reply.code == 331 {
+            let password = session.password.as_deref().unwrap_or("anonymous@");
+            reply = connection.command(&format!("PASS {}", password))?;
+        }
+        if !reply.is_ok() {
+            return Err(reply.error("FTP login failed")).with_context(|| format!("{}@{}", user, session.address));
+        }
+
+        if connection.tls.is_some() {
+            connection.command("PBSZ 0")?;
+            let reply = connection.command("PROT P")?;
+            if !reply.is_ok() {
+                return Err(reply.error("FTP server refused to encrypt data connections"));
+            }
+        }
+        let reply = connection.command("TYPE I")?;
+        if !reply.is_ok() {
+            return Err(reply.error("FTP server refused binary mode"));
+        }
+        let _ = connection.command("OPTS UTF8 ON")?;
+        Ok(connection)
+    }
+
+    fn command(&mut self, line: &str) -> io::Result<Reply> {
+        let stream = self.control.get_mut();
+        stream.write_all(line.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.reply()
+    }
+
+    // Многострочный ответ: `250-...` и так до строки `250 ...`
+    fn reply(&mut self) -> io::Result<Reply> {
+        let mut lines: Vec<String> = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.control.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "FTP server closed the connection"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']).to_string();
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            let last = match (lines.first(), code) {
+                (None, None) => return Err(io::Error::other(format!("Invalid FTP reply: {}", line))),
+                (None, Some(_)) => line.as_bytes().get(3) != Some(&b'-'),
+                (Some(first), Some(_)) => line.starts_with(&first[..3]) && line.as_bytes().get(3) == Some(&b' '),
+                (Some(_), None) => false,
+            };
+            lines.push(line);
+            if last {
+                let code = lines[0][..3].parse().unwrap();
+                return Ok(Reply { code, lines });
+            }
+        }
+    }
+
+    // Пассивный режим: адрес сервера из ответа PASV не используем - за NAT он бывает неверным
+    fn data(&mut self) -> io::Result<Stream> {
+        let mut port = None;
+        if !self.pasv_only {
+            let reply = self.command("EPSV")?;
+            if reply.code == 229 {
+                port = reply.lines[0]
+                    .split('|')
+                    .nth(3)
+                    .and_then(|port| port.parse::<u16>().ok());
+            } else {
+                self.pasv_only = true;
+            }
+        }
+        if port.is_none() {
+            let reply = self.command("PASV")?;
+            if reply.code != 227 {
+                return Err(io::Error::other(reply.error("FTP server refused passive mode")));
+            }
+            let numbers: Vec<u16> = reply.lines[0]
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|number| !number.is_empty())
+                .skip(1)
+                .filter_map(|number| number.parse().ok())
+                .collect();
+            if let [.., high, low] = numbers[..] {
+                port = Some(high * 256 + low);
+            }
+        }
+        let port = port.ok_or_else(|| io::Error::other("Invalid passive mode reply from FTP server"))?;
+
+        let stream = Stream::connect(SocketAddr::new(self.peer, port))?;
+        match &self.tls {
+            Some((config, server_name)) => Stream::tls(stream, config, server_name),
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+
+    // Соединение данных и ответ 1xx на команду передачи. Внешняя ошибка - обрыв или
+    // временный отказ (можно повторить), внутренняя - отказ сервера
+    fn transfer(&mut self, command: &str) -> io::Result<Result<Stream>> {
+        let data = self.data()?;
+        let reply = self.command(command)?;
+        if reply.is_preliminary() {
+            Ok(Ok(data))
+        } else if reply.is_transient() {
+            Err(io::Error::other(reply.error(command)))
+        } else {
+            Ok(Err(reply.error(command)))
+        }
+    }
+
+    // Ответ после закрытия соединения данных
+    fn finish(&mut self, action: &str) -> io::Result<Result<()>> {
+        let reply = self.reply()?;
+        if reply.is_ok() {
+            Ok(Ok(()))
+        } else if reply.is_transient() {
+            Err(io::Error::other(reply.error(action)))
+        } else {
+            Ok(Err(reply.error(action)))
+        }
+    }
+
+    fn size(&mut self, path: &str) -> io::Result<Option<u64>> {
+        let reply = self.command(&format!("SIZE {}", path))?;
+        Ok((reply.code == 213)
+            .then(|| reply.lines[0].get(4..).and_then(|size| size.trim().parse().ok()))
+            .flatten())
+    }
+}
+
+#[derive(Clone)]
+pub struct FtpSession {
+    address: String,
+    user: Option<String>,
+    password: Option<String>,
+    tls: Option<Arc<ClientConfig>>,
+    server_name: ServerName<'static>,
+    idle: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl FtpSession {
+    // Пароль из адреса важнее --ftp-password; сертификат FTPS проверяется по --tls-ca
+    // или по общедоступным корневым сертификатам
+    pub fn connect(location: &FtpLocation, password: Option<&str>, tls_ca: Option<&Path>) -> Result<Self> {
+        let host = ClientTls::server_host(&location.address);
+        let server_name = ServerName::try_from(host.to_string())
+            .with_context(|| format!("Invalid server name for TLS: {}", host))?;
+        let mut session = FtpSession {
+            address: location.address.clone(),
+            user: location.user.clone(),
+            password: location.password.clone().or_else(|| password.map(str::to_string)),
+            tls: location.tls.then(|| ClientTls::public(tls_ca)).transpose()?,
+            server_name,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        };
+        let connection = Connection::open(&session)?;
+        session.idle = Arc::new(Mutex::new(vec![connection]));
+        Ok(session)
+    }
+
+    fn take(&self) -> Result<Connection> {
+        if let Some(connection) = self.idle.lock().unwrap().pop() {
+            return Ok(connection);
+        }
+        Connection::open(self)
+    }
+
+    fn give_back(&self, connection: Connection) {
+        self.idle.lock().unwrap().push(connection);
+    }
+
+    // Соединение с ошибкой ввода-вывода в пул не возвращается
+    fn with_connection<T>(&self, action: impl FnOnce(&mut Connection) -> io::Result<T>) -> Result<T> {
+        let mut connection = self.take()?;
+        let result = action(&mut connection).with_context(|| format!("Connection to FTP server {} failed", self.address))?;
+        self.give_back(connection);
+        Ok(result)
+    }
+
+    pub fn stat(&self, path: &Path) -> Result<Option<FtpEntry>> {
+        let name = command_path(path)?;
+        let (reply, size) = self.with_connection(|connection| {
+            let reply = connection.command(&format!("MLST {}", name))?;
+            // Без MLST файл узнаем по SIZE, а директорию - по тому, что SIZE для нее не работает
+            let size = match reply.code {
+                500..=502 => connection.size(&name)?,
+                _ => None,
+            };
+            Ok((reply, size))
+        })?;
+        match reply.code {
+            250 => Ok(reply
+                .lines
+                .iter()
+                .filter_map(|line| line.strip_prefix(' '))
+                .find_map(parse_facts)
+                .map(|(_, entry)| entry)),
+            500..=502 => Ok(Some(FtpEntry { is_dir: size.is_none(), size: size.unwrap_or(0) })),
+            550 => Ok(None),
+            _ => Err(reply.error(&format!("Failed to stat {}", path.display()))),
+        }
+    }
+
+    pub fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, FtpEntry)>> {
+        let name = command_path(path)?;
+        let listing = self.with_connection(|connection| {
+            let mut data = match connection.transfer(&format!("MLSD {}", name))? {
+                Ok(data) => data,
+                Err(e) => return Ok(Err(e)),
+            };
+            let mut listing = String::new();
+            data.read_to_string(&mut listing)?;
+            let _ = data.close();
+            Ok(connection.finish("MLSD")?.map(|()| listing))
+        })?;
+        let listing = listing.with_context(|| format!("Failed to list remote directory: {}", path.display()))?;
+        Ok(listing
+            .lines()
+            .filter_map(parse_facts)
+            .map(|(name, entry)| (path.join(name), entry))
+            .collect())
+    }
+
+    pub fn create_dir_all(&self, path: &Path) -> Result<()> {
+        if matches!(path.to_str(), Some("" | "." | "/")) {
+            return Ok(());
+        }
+        let name = command_path(path)?;
+        let reply = self.with_connection(|connection| connection.command(&format!("MKD {}", name)))?;
+        if reply.is_ok() || self.stat(path)?.is_some_and(|entry| entry.is_dir) {
+            return Ok(());
+        }
+        // Родителя нет: создаем его и пробуем еще раз
+        if let Some(parent) = path.parent()
+            && self.stat(parent)?.is_none()
+        {
+            self.create_dir_all(parent)?;
+            let reply = self.with_connection(|connection| connection.command(&format!("MKD {}", name)))?;
+            if reply.is_ok() {
+                return Ok(());
+            }
+        }
+        Err(reply.error(&format!("Failed to create remote directory {}", path.display())))
+    }
+
+    // Копия на сервере командами SITE CPFR/CPTO (mod_copy у ProFTPD); у остальных
+    // серверов их нет
+    pub fn copy_file(&self, source: &Path, destination: &Path) -> Result<bool> {
+        let (from, to) = (command_path(source)?, command_path(destination)?);
+        let reply = self.with_connection(|connection| {
+            let reply = connection.command(&format!("SITE CPFR {}", from))?;
+            if reply.code != 350 {
+                return Ok(None);
+            }
+            connection.command(&format!("SITE CPTO {}", to)).map(Some)
+        })?;
+        match reply {
+            None => Ok(false),
+            Some(reply) if reply.is_ok() => Ok(true),
+            Some(reply) => Err(reply.error(&format!("Failed to copy {} on the server", source.display()))),
+        }
+    }
+
+    pub fn open(&self, path: &Path) -> Result<(FtpReader, u64)> {
+        let entry = self
+            .stat(path)?
+            .with_context(|| format!("Remote file not found: {}", path.display()))?;
+        let reader = FtpReader {
+            session: self.clone(),
+            path: path.to_path_buf(),
+            size: entry.size,
+            transfer: None,
+            offset: 0,
+            attempt: 0,
+            done: false,
+        };
+        Ok((reader, entry.size))
+    }
+
+    pub fn create(&self, path: &Path) -> Result<FtpFile> {
+        let name = command_path(path)?;
+        let mut connection = self.take()?;
+        let data = connection
+            .transfer(&format!("STOR {}", name))
+            .with_context(|| format!("Connection to FTP server {} failed", self.address))?
+            .with_context(|| format!("Failed to create remote file: {}", path.display()))?;
+        Ok(FtpFile {
+            session: self.clone(),
+            connection: Some(connection),
+            data: Some(data),
+        })
+    }
+
+    // Загрузка локального файла как есть: при обрыве переподключаемся и продолжаем
+    // с размера, который уже на сервере (REST + STOR)
+    pub fn send_resumable(
+        &self,
+        source: &Path,
+        destination: &Path,
+        size: u64,
+        mut on_progress: impl FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.try_send(source, destination, size, attempt > 0, &mut on_progress) {
+                Ok(result) => return result,
+                Err(e) if attempt < RESUME_ATTEMPTS => {
+                    attempt += 1;
+                    eprintln!("{}", t!("resuming", error = format!("{:#}", e), path = destination.display().to_string()));
+                    thread::sleep(RESUME_DELAY);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Внешняя ошибка - сбой соединения (можно повторить), внутренняя - все остальное
+    fn try_send(
+        &self,
+        source: &Path,
+        destination: &Path,
+        size: u64,
+        resume: bool,
+        on_progress: &mut impl FnMut(u64) -> Result<()>,
+    ) -> Result<Result<()>> {
+        let name = match command_path(destination) {
+            Ok(name) => name,
+            Err(e) => return Ok(Err(e)),
+        };
+        let mut connection = self.take()?;
+        let failed = || format!("Connection to FTP server {} failed", self.address);
+
+        let mut offset = if resume {
+            connection.size(&name).with_context(failed)?.unwrap_or(0).min(size)
+        } else {
+            0
+        };
+        let mut file = match File::open(source).and_then(|mut file| file.seek(SeekFrom::Start(offset)).map(|_| file)) {
+            Ok(file) => file,
+            Err(e) => {
+                self.give_back(connection);
+                return Ok(Err(anyhow::Error::new(e).context(format!("Failed to open source file: {}", source.display()))));
+            }
+        };
+        // Сервер без REST для загрузки - начинаем заново
+        if offset > 0 && connection.command(&format!("REST {}", offset)).with_context(failed)?.code != 350 {
+            offset = 0;
+            if let Err(e) = file.seek(SeekFrom::Start(0)) {
+                return Ok(Err(e.into()));
+            }
+        }
+        let mut data = match connection.transfer(&format!("STOR {}", name)).with_context(failed)? {
+            Ok(data) => data,
+            Err(e) => {
+                self.give_back(connection);
+                return Ok(Err(e));
+            }
+        };
+
+        let mut buffer = vec![0; crate::BUFFER_SIZE];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => return Ok(Err(anyhow::Error::new(e).context(format!("Failed to read file: {}", source.display())))),
+            };
+            data.write_all(&buffer[..read]).with_context(failed)?;
+            offset += read as u64;
+            if let Err(e) = on_progress(offset) {
+                return Ok(Err(e));
+            }
+        }
+        data.close().with_context(failed)?;
+        let result = connection.finish("Upload failed").with_context(failed)?;
+        self.give_back(connection);
+        Ok(result)
+    }
+}
+
+fn entry(entry: &FtpEntry) -> Entry {
+    if entry.is_dir { Entry::dir() } else { Entry::file(entry.size) }
+}
+
+// Символические ссылки по FTP не различить: листинг их пропускает
+impl Source for FtpSession {
+    fn describe(&self, path: &Path) -> String {
+        format!("{}/{}", self.address, path.display())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Option<Entry>> {
+        Ok(FtpSession::stat(self, path)?.as_ref().map(entry))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Entry)>> {
+        Ok(FtpSession::read_dir(self, path)?
+            .into_iter()
+            .map(|(path, ftp_entry)| (path, entry(&ftp_entry)))
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> Result<(Box<dyn Read>, u64)> {
+        let (reader, size) = FtpSession::open(self, path)?;
+        Ok((Box::new(reader), size))
+    }
+}
+
+impl Sink for FtpSession {
+    fn name(&self) -> String {
+        self.address.clone()
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(FtpSession::stat(self, path)?.is_some_and(|entry| entry.is_dir))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        FtpSession::create_dir_all(self, path)
+    }
+
+    fn create(&self, path: &Path, _modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        Ok(Box::new(FtpSession::create(self, path)?))
+    }
+
+    fn copy_within(&self, source: &Path, destination: &Path) -> Result<bool> {
+        self.copy_file(source, destination)
+    }
+
+    // Файл как есть загружается с докачкой после обрыва
+    fn send_file(
+        &self,
+        source: &Path,
+        destination: &Path,
+        _streams: usize,
+        on_start: &dyn Fn(u64),
+        on_progress: Progress,
+    ) -> Result<bool> {
+        let size = std::fs::metadata(source)
+            .with_context(|| format!("Failed to open source file: {}", source.display()))?
+            .len();
+        on_start(size);
+        self.send_resumable(source, destination, size, on_progress)?;
+        Ok(true)
+    }
+}
+
+// Загрузка с сервера. При обрыве соединение открывается заново, и передача
+// продолжается с прочитанного места (REST + RETR)
+pub struct FtpReader {
+    session: FtpSession,
+    path: PathBuf,
+    size: u64,
+    transfer: Option<(Connection, Stream)>,
+    offset: u64,
+    attempt: u32,
+    done: bool,
+}
+
+impl FtpReader {
+    fn start(&mut self) -> Result<Result<()>> {
+        let name = match command_path(&self.path) {
+            Ok(name) => name,
+            Err(e) => return Ok(Err(e)),
+        };
+        let mut connection = self.session.take()?;
+        let failed = || format!("Connection to FTP server {} failed", self.session.address);
+        if self.offset > 0 {
+            let reply = connection.command(&format!("REST {}", self.offset)).with_context(failed)?;
+            if reply.code != 350 {
+                return Ok(Err(reply.error("FTP server cannot resume the download")));
+            }
+        }
+        match connection.transfer(&format!("RETR {}", name)).with_context(failed)? {
+            Ok(data) => {
+                self.transfer = Some((connection, data));
+                Ok(Ok(()))
+            }
+            Err(e) => {
+                self.session.give_back(connection);
+                Ok(Err(e))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<Result<()>> {
+        let (mut connection, data) = self.transfer.take().unwrap();
+        // Сервер уже закрыл передачу; ее полноту подтвердит ответ на управляющем соединении
+        let _ = data.close();
+        let failed = || format!("Connection to FTP server {} failed", self.session.address);
+        let result = connection.finish("Download failed").with_context(failed)?;
+        self.session.give_back(connection);
+        // Сервер может закрыть передачу раньше времени и все равно ответить успехом
+        if result.is_ok() && self.offset < self.size {
+            anyhow::bail!("Download ended after {} of {} bytes", self.offset, self.size);
+        }
+        Ok(result)
+    }
+
+    fn retry(&mut self, error: anyhow::Error) -> io::Result<()> {
+        self.transfer = None;
+        self.attempt += 1;
+        if self.attempt > RESUME_ATTEMPTS {
+            return Err(io::Error::other(error));
+        }
+        eprintln!("{}", t!("resuming", error = format!("{:#}", error), path = self.path.display().to_string()));
+        thread::sleep(RESUME_DELAY);
+        Ok(())
+    }
+}
+
+impl Read for FtpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done {
+            if self.transfer.is_none() {
+                match self.start() {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => return Err(io::Error::other(e)),
+                    Err(e) => {
+                        self.retry(e)?;
+                        continue;
+                    }
+                }
+            }
+            let (_, data) = self.transfer.as_mut().unwrap();
+            match data.read(buf) {
+                Ok(0) => match self.finish() {
+                    Ok(Ok(())) => self.done = true,
+                    Ok(Err(e)) => return Err(io::Error::other(e)),
+                    Err(e) => self.retry(e)?,
+                },
+                Ok(read) => {
+                    self.offset += read as u64;
+                    return Ok(read);
+                }
+                Err(e) => self.retry(anyhow::Error::new(e).context("FTP data connection failed"))?,
+            }
+        }
+        Ok(0)
+    }
+}
+
+// Файл на сервере для записи потоком (со сжатием, шифрованием и т.п.); такую
+// передачу после обрыва не продолжить - источник уже прочитан
+pub struct FtpFile {
+    session: FtpSession,
+    connection: Option<Connection>,
+    data: Option<Stream>,
+}
+
+impl FtpFile {
+    pub fn close(mut self) -> Result<()> {
+        let failed = || format!("Connection to FTP server {} failed", self.session.address);
+        if let Some(data) = self.data.take() {
+            data.close().with_context(failed)?;
+        }
+        let mut connection = self.connection.take().unwrap();
+        connection.finish("Upload failed").with_context(failed)??;
+        self.session.give_back(connection);
+        Ok(())
+    }
+}
+
+impl SinkFile for FtpFile {
+    fn close(self: Box<Self>) -> Result<()> {
+        FtpFile::close(*self)
+    }
+}
+
+impl Write for FtpFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.data.as_mut() {
+            Some(data) => data.write(buf),
+            None => Err(io::Error::other("FTP upload already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use md5::{Digest, Md5};
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::PrivatePkcs8KeyDer;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::http::{agent, describe_error, is_transient, percent_encode};
+use crate::transport::{is_object_prefix, Sink, SinkFile};
+
+// Назначение в Google Cloud Storage через JSON API: каждый файл - возобновляемая
+// загрузка частями, после которой сверяется MD5 объекта
+
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+// Части загрузки должны быть кратны 256 KiB
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+const UPLOAD_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// Токен обновляется заранее, чтобы не истек посреди запроса
+const TOKEN_MARGIN: Duration = Duration::from_secs(300);
+
+// `gs://bucket/path`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GcsLocation {
+    pub bucket: String,
+    pub path: PathBuf,
+}
+
+impl GcsLocation {
+    pub fn parse(location: &Path) -> Option<Self> {
+        let rest = location.to_str()?.strip_prefix("gs://")?;
+        let (bucket, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return None;
+        }
+        Some(GcsLocation {
+            bucket: bucket.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// Ключ сервисного аккаунта (JSON из консоли Google Cloud)
+struct ServiceAccount {
+    client_email: String,
+    token_uri: String,
+    key: RsaKeyPair,
+}
+
+impl ServiceAccount {
+    fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Failed to read GCS credentials: {}", path.display()))?;
+        let json: Value = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid GCS credentials file: {}", path.display()))?;
+        let field = |name: &str| {
+            json[name]
+                .as_str()
+                .map(str::to_string)
+                .with_context(|| format!("GCS credentials file has no {}: {}", name, path.display()))
+        };
+        if json["type"].as_str() != Some("service_account") {
+            anyhow::bail!("Not a service account key: {}", path.display());
+        }
+
+        let pem = field("private_key")?;
+        let der = PrivatePkcs8KeyDer::from_pem_slice(pem.as_bytes())
+            .with_context(|| format!("Invalid private key in {}", path.display()))?;
+        let key = RsaKeyPair::from_pkcs8(der.secret_pkcs8_der())
+            .map_err(|e| anyhow::anyhow!("Invalid private key in {}: {}", path.display(), e))?;
+        Ok(ServiceAccount {
+            client_email: field("client_email")?,
+            token_uri: field("token_uri").unwrap_or_else(|_| "https://oauth2.googleapis.com/token".to_string()),
+            key,
+        })
+    }
+
+    // Подписанный JWT меняется на токен доступа (OAuth 2.0 для сервисных аккаунтов)
+    fn fetch_token(&self, agent: &ureq::Agent) -> Result<(String, Instant)> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256","typ":"JWT"}"#);
+        let claims = json!({
+            "iss": self.client_email,
+            "scope": SCOPE,
+            "aud": self.token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        let message = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let mut signature = vec![0; self.key.public().modulus_len()];
+        self.key
+            .sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), message.as_bytes(), &mut signature)
+            .map_err(|_| anyhow::anyhow!("Failed to sign GCS token request"))?;
+        let assertion = format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature));
+
+        let mut response = agent
+            .post(&self.token_uri)
+            .send_form([
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .with_context(|| format!("Failed to reach {}", self.token_uri))?;
+        if response.status() != 200 {
+            anyhow::bail!("GCS token request failed: {}", describe_error(&mut response));
+        }
+        let body: Value = response.body_mut().read_json().context("Invalid GCS token response")?;
+        let token = body["access_token"].as_str().context("GCS token response has no access_token")?;
+        let expires_in = body["expires_in"].as_u64().unwrap_or(3600);
+        Ok((token.to_string(), Instant::now() + Duration::from_secs(expires_in)))
+    }
+}
+
+// Без ключа можно работать только с эмулятором (STORAGE_EMULATOR_HOST)
+enum Credentials {
+    Anonymous,
+    ServiceAccount {
+        account: Box<ServiceAccount>,
+        token: Mutex<Option<(String, Instant)>>,
+    },
+}
+
+#[derive(Clone)]
+pub struct GcsSession {
+    agent: ureq::Agent,
+    endpoint: String,
+    bucket: String,
+    credentials: Arc<Credentials>,
+}
+
+impl GcsSession {
+    pub fn connect(bucket: &str, credentials: Option<&Path>) -> Result<Self> {
+        let emulator = std::env::var("STORAGE_EMULATOR_HOST").ok().filter(|host| !host.is_empty());
+        let endpoint = match &emulator {
+            Some(host) if host.contains("://") => host.trim_end_matches('/').to_string(),
+            Some(host) => format!("http://{}", host.trim_end_matches('/')),
+            None => DEFAULT_ENDPOINT.to_string(),
+        };
+        let credentials = match credentials {
+            Some(path) => Credentials::ServiceAccount {
+                account: Box::new(ServiceAccount::load(path)?),
+                token: Mutex::new(None),
+            },
+            None if emulator.is_some() => Credentials::Anonymous,
+            None => anyhow::bail!("GCS needs a service account key: pass --gcs-credentials or set GOOGLE_APPLICATION_CREDENTIALS"),
+        };
+
+        let session = GcsSession {
+            agent: agent(),
+            endpoint,
+            bucket: bucket.to_string(),
+            credentials: Arc::new(credentials),
+        };
+
+        // Заодно проверяем ключ и доступ к бакету до начала копирования
+        let url = format!("{}/storage/v1/b/{}", session.endpoint, percent_encode(bucket));
+        let mut response = session
+            .authorize(session.agent.get(&url))?
+            .call()
+            .with_context(|| format!("Failed to reach {}", session.endpoint))?;
+        if response.status() != 200 {
+            anyhow::bail!("Cannot access bucket gs://{}: {}", bucket, describe_error(&mut response));
+        }
+        Ok(session)
+    }
+
+    fn authorize<B>(&self, request: ureq::RequestBuilder<B>) -> Result<ureq::RequestBuilder<B>> {
+        let Credentials::ServiceAccount { account, token } = self.credentials.as_ref() else {
+            return Ok(request);
+        };
+        let mut token = token.lock().unwrap();
+        let fresh = token.as_ref().is_some_and(|(_, expires)| Instant::now() + TOKEN_MARGIN < *expires);
+        if !fresh {
+            *token = Some(account.fetch_token(&self.agent)?);
+        }
+        let (value, _) = token.as_ref().unwrap();
+        Ok(request.header("Authorization", format!("Bearer {}", value)))
+    }
+
+    // Имя объекта - путь назначения без ведущего слеша
+    fn object_name(path: &Path) -> Result<&str> {
+        path.to_str()
+            .map(|name| name.trim_start_matches('/'))
+            .filter(|name| !name.is_empty())
+            .with_context(|| format!("Invalid GCS object name: {}", path.display()))
+    }
+
+    pub fn create(&self, path: &Path) -> Result<GcsFile> {
+        let name = Self::object_name(path)?;
+        let url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=resumable&name={}",
+            self.endpoint,
+            percent_encode(&self.bucket),
+            percent_encode(name)
+        );
+        let mut response = self
+            .authorize(self.agent.post(&url))?
+            .header("X-Upload-Content-Type", "application/octet-stream")
+            .send_json(json!({}))
+            .with_context(|| format!("Failed to reach {}", self.endpoint))?;
+        if response.status() != 200 {
+            anyhow::bail!(
+                "Failed to start upload of gs://{}/{}: {}",
+                self.bucket,
+                name,
+                describe_error(&mut response)
+            );
+        }
+        let upload_url = response
+            .headers()
+            .get("location")
+            .and_then(|location| location.to_str().ok())
+            .context("GCS did not return an upload session URL")?
+            .to_string();
+
+        Ok(GcsFile {
+            session: self.clone(),
+            upload_url,
+            buffer: Vec::new(),
+            offset: 0,
+            md5: Md5::new(),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Sink for GcsSession {
+    fn name(&self) -> String {
+        format!("gs://{}", self.bucket)
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(is_object_prefix(path))
+    }
+
+    // Директории возникают из имен объектов
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn create(&self, path: &Path, _modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        Ok(Box::new(GcsSession::create(self, path)?))
+    }
+}
+
+// Объект в процессе загрузки: данные копятся до целой части и уходят в сессию загрузки
+pub struct GcsFile {
+    session: GcsSession,
+    upload_url: String,
+    buffer: Vec<u8>,
+    // Сколько байт сервер уже подтвердил
+    offset: u64,
+    md5: Md5,
+    path: PathBuf,
+}
+
+// Ответ 308 сообщает, сколько байт сохранено: `Range: bytes=0-N`
+fn committed_bytes(response: &ureq::http::Response<ureq::Body>) -> u64 {
+    response
+        .headers()
+        .get("range")
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.rsplit_once('-'))
+        .and_then(|(_, last)| last.parse::<u64>().ok())
+        .map_or(0, |last| last + 1)
+}
+
+impl GcsFile {
+    // Отправляет первые len байт буфера; total известен только у последней части.
+    // При обрыве спрашивает у сервера, сколько он получил, и досылает остальное
+    fn upload(&mut self, len: usize, total: Option<u64>) -> Result<Option<Value>> {
+        let mut sent = 0;
+        let mut attempt = 0;
+        loop {
+            let range = match (len - sent, total) {
+                (0, Some(total)) => format!("bytes */{}", total),
+                (_, total) => format!(
+                    "bytes {}-{}/{}",
+                    self.offset + sent as u64,
+                    self.offset + len as u64 - 1,
+                    total.map_or("*".to_string(), |total| total.to_string())
+                ),
+            };
+            let result = self
+                .session
+                .agent
+                .put(&self.upload_url)
+                .header("Content-Range", range)
+                .send(&self.buffer[sent..len]);
+
+            let error = match result {
+                Ok(mut response) => match response.status().as_u16() {
+                    200 | 201 => {
+                        let object = response.body_mut().read_json::<Value>().unwrap_or(Value::Null);
+                        self.advance(len);
+                        return Ok(Some(object));
+                    }
+                    308 => {
+                        let committed = committed_bytes(&response);
+                        if committed >= self.offset + len as u64 {
+                            self.advance(len);
+                            return Ok(None);
+                        }
+                        format!("only {} bytes were stored", committed)
+                    }
+                    status if is_transient(status) => describe_error(&mut response),
+                    _ => anyhow::bail!("Upload to GCS failed: {}", describe_error(&mut response)),
+                },
+                Err(e) => e.to_string(),
+            };
+
+            attempt += 1;
+            if attempt >= UPLOAD_ATTEMPTS {
+                anyhow::bail!("Upload to GCS failed: {}", error);
+            }
+            thread::sleep(RETRY_DELAY);
+            sent = self.committed()?.saturating_sub(self.offset).min(len as u64) as usize;
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.buffer.drain(..len);
+        self.offset += len as u64;
+    }
+
+    // Состояние сессии загрузки: пустой PUT с `bytes */*`
+    fn committed(&self) -> Result<u64> {
+        let mut response = self
+            .session
+            .agent
+            .put(&self.upload_url)
+            .header("Content-Range", "bytes */*")
+            .send_empty()
+            .context("Failed to query GCS upload status")?;
+        match response.status().as_u16() {
+            308 => Ok(committed_bytes(&response)),
+            _ => anyhow::bail!("Failed to query GCS upload status: {}", describe_error(&mut response)),
+        }
+    }
+
+    // Объект появляется только после последней части; MD5 объекта сверяется с отправленным
+    pub fn close(mut self) -> Result<()> {
+        let total = self.offset + self.buffer.len() as u64;
+        let object = self
+            .upload(self.buffer.len(), Some(total))?
+            .with_context(|| format!("GCS did not finish the upload of {}", self.path.display()))?;
+
+        let expected = STANDARD.encode(self.md5.finalize_reset());
+        if let Some(actual) = object["md5Hash"].as_str()
+            && actual != expected
+        {
+            anyhow::bail!("Checksum mismatch for gs://{}/{}", self.session.bucket, self.path.display());
+        }
+        Ok(())
+    }
+}
+
+impl SinkFile for GcsFile {
+    fn close(self: Box<Self>) -> Result<()> {
+        GcsFile::close(*self)
+    }
+}
+
+impl Write for GcsFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.md5.update(buf);
+        self.buffer.extend_from_slice(buf);
+        // Последнюю часть держим до close(): только в ней указывается полный размер
+        while self.buffer.len() > CHUNK_SIZE {
+            self.upload(CHUNK_SIZE, None).map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
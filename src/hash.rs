@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::cli::ChecksumAlgorithm;
+use crate::metadata::open_source;
+use crate::BUFFER_SIZE;
+
+// Хешер, который кормится теми же буферами, что идут на запись
+pub enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize_hex(self) -> String {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Sha256(hasher) => hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+}
+
+pub fn hash_file(path: &Path, algorithm: ChecksumAlgorithm) -> Result<String> {
+    let mut file = open_source(path)
+        .with_context(|| format!("Failed to open file for verification: {}", path.display()))?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; BUFFER_SIZE];
+
+    loop {
+        let bytes_read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read file for verification: {}", path.display()))?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(hasher.finalize_hex())
+}
+
+// Сумма части файла; части читаются из нескольких потоков, поэтому через pread
+pub fn hash_range(file: &File, range: Range<u64>, algorithm: ChecksumAlgorithm) -> io::Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut position = range.start;
+    while position < range.end {
+        let length = buffer.len().min((range.end - position) as usize);
+        let bytes_read = file.read_at(&mut buffer[..length], position)?;
+        if bytes_read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        hasher.update(&buffer[..bytes_read]);
+        position += bytes_read as u64;
+    }
+    Ok(hasher.finalize_hex())
+}
+
+// Сколько блоков может ждать хеширования
+const HASH_QUEUE_DEPTH: usize = 2;
+
+// Сумма в своем потоке: на быстрых NVMe BLAKE3 и особенно SHA-256 иначе считаются по очереди
+// с чтением и записью и ограничивают скорость копирования. Небольшие файлы хешируются сразу
+pub enum BackgroundHasher {
+    Inline(Hasher),
+    Threaded {
+        sender: SyncSender<Vec<u8>>,
+        // Обработанные блоки возвращаются, чтобы не выделять память под каждый
+        hashed: Receiver<Vec<u8>>,
+        handle: JoinHandle<String>,
+    },
+}
+
+impl BackgroundHasher {
+    pub fn new(algorithm: ChecksumAlgorithm, threaded: bool) -> Self {
+        let mut hasher = Hasher::new(algorithm);
+        if !threaded {
+            return BackgroundHasher::Inline(hasher);
+        }
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(HASH_QUEUE_DEPTH);
+        let (returned, hashed) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            for block in receiver {
+                hasher.update(&block);
+                let _ = returned.send(block);
+            }
+            hasher.finalize_hex()
+        });
+        BackgroundHasher::Threaded { sender, hashed, handle }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            BackgroundHasher::Inline(hasher) => hasher.update(data),
+            BackgroundHasher::Threaded { sender, hashed, .. } => {
+                let mut block = hashed.try_recv().unwrap_or_else(|_| Vec::with_capacity(data.len()));
+                block.clear();
+                block.extend_from_slice(data);
+                // Поток хеширования не падает, пока жив отправитель
+                let _ = sender.send(block);
+            }
+        }
+    }
+
+    pub fn finalize_hex(self) -> String {
+        match self {
+            BackgroundHasher::Inline(hasher) => hasher.finalize_hex(),
+            BackgroundHasher::Threaded { sender, handle, .. } => {
+                drop(sender);
+                handle.join().expect("Hash thread panicked")
+            }
+        }
+    }
+}
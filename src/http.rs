@@ -0,0 +1,100 @@
+use anyhow::Result;
+use std::thread;
+use std::time::Duration;
+
+use ureq::http::Response;
+use ureq::Body;
+
+// HTTP-клиент для облачных назначений: коды ошибок и перенаправления разбираем сами
+// (у загрузок GCS, например, 308 означает "принята часть данных")
+pub fn agent() -> ureq::Agent {
+    ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .max_redirects(0)
+        // PROPFIND, MKCOL и MOVE для WebDAV
+        .allow_non_standard_methods(true)
+        .timeout_connect(Some(Duration::from_secs(30)))
+        // Зависший сервер не должен держать копирование вечно
+        .timeout_recv_response(Some(Duration::from_secs(120)))
+        .build()
+        .into()
+}
+
+// Кодирование для пути и параметров запроса: все, кроме незарезервированных символов
+pub fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Путь в URL: сегменты кодируются по отдельности, слеши остаются
+pub fn encode_path(path: &str) -> String {
+    path.split('/').map(percent_encode).collect::<Vec<_>>().join("/")
+}
+
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Ответ сервера об ошибке для сообщения пользователю
+pub fn describe_error(response: &mut Response<Body>) -> String {
+    let status = response.status();
+    let body = response.body_mut().read_to_string().unwrap_or_default();
+    let body = body.trim();
+    if body.is_empty() {
+        status.to_string()
+    } else {
+        format!("{}: {}", status, body.chars().take(500).collect::<String>())
+    }
+}
+
+// Стоит ли повторить запрос с таким кодом
+pub fn is_transient(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+const ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// Для запросов, которые можно безопасно повторить: сбой сети и ответы 429/5xx повторяются
+pub fn send_with_retry<F>(action: &str, request: F) -> Result<Response<Body>>
+where
+    F: Fn() -> Result<Response<Body>, ureq::Error>,
+{
+    let mut attempt = 0;
+    loop {
+        let error = match request() {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(mut response) if is_transient(response.status().as_u16()) => describe_error(&mut response),
+            Ok(mut response) => anyhow::bail!("{} failed: {}", action, describe_error(&mut response)),
+            Err(e) => e.to_string(),
+        };
+        attempt += 1;
+        if attempt >= ATTEMPTS {
+            anyhow::bail!("{} failed: {}", action, error);
+        }
+        thread::sleep(RETRY_DELAY);
+    }
+}
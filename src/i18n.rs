@@ -0,0 +1,70 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+// Тексты для пользователя лежат в locales/<язык>.ftl (Fluent) и встроены в бинарник. Язык
+// выбирается, как у gettext, по LC_ALL, LC_MESSAGES или LANG; сообщение, которого нет в
+// выбранном каталоге, берется из английского. Ошибки (контекст anyhow) пока не переводятся
+const CATALOGS: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("ru", include_str!("../locales/ru.ftl")),
+];
+
+static BUNDLES: OnceLock<Vec<FluentBundle<FluentResource>>> = OnceLock::new();
+
+// t!("copy-completed"), t!("copying-files", count = 10)
+macro_rules! t {
+    ($id:literal) => {
+        $crate::i18n::message($id, None)
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::message($id, Some(&args))
+    }};
+}
+pub(crate) use t;
+
+pub fn message(id: &str, args: Option<&FluentArgs>) -> String {
+    for bundle in BUNDLES.get_or_init(load) {
+        if let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) {
+            let mut errors = Vec::new();
+            return bundle.format_pattern(pattern, args, &mut errors).into_owned();
+        }
+    }
+    id.to_string()
+}
+
+// Выбранный каталог, затем английский
+fn load() -> Vec<FluentBundle<FluentResource>> {
+    let language = language();
+    let mut bundles = Vec::new();
+    for (code, source) in CATALOGS {
+        if code != language && code != "en" {
+            continue;
+        }
+        let id: LanguageIdentifier = code.parse().expect("catalog languages are valid");
+        let mut bundle = FluentBundle::new_concurrent(vec![id]);
+        // Без невидимых символов изоляции вокруг подстановок: вывод идет в терминал
+        bundle.set_use_isolating(false);
+        let resource = FluentResource::try_new(source.to_string()).expect("bundled catalogs parse");
+        bundle.add_resource(resource).expect("bundled catalogs have unique ids");
+        if code == language {
+            bundles.insert(0, bundle);
+        } else {
+            bundles.push(bundle);
+        }
+    }
+    bundles
+}
+
+// ru_RU.UTF-8 -> ru; C и POSIX - английский
+fn language() -> String {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    locale.split(['_', '.', '@']).next().unwrap_or_default().to_lowercase()
+}
@@ -0,0 +1,185 @@
+use colored::Colorize;
+use glob::Pattern;
+use indicatif::HumanBytes;
+use std::fs;
+
+use crate::collect::CopyItem;
+use crate::i18n::t;
+use crate::tee::TEE_QUEUE_DEPTH;
+
+// Меньше буфер --max-memory не делает: дальше уменьшается число одновременных файлов
+const MIN_BUFFER_SIZE: usize = 16 * 1024;
+
+// --prioritize: подходящие под шаблоны файлы идут в начало очереди, по порядку шаблонов;
+// остальные сохраняют свой порядок. Шаблон без `/` сравнивается с именем файла
+pub fn prioritize(files: &mut [CopyItem], patterns: &[Pattern]) {
+    if patterns.is_empty() {
+        return;
+    }
+    files.sort_by_cached_key(|item| {
+        let name = item.source.rsplit('/').next().unwrap_or(&item.source);
+        patterns
+            .iter()
+            .position(|pattern| match pattern.as_str().contains('/') {
+                true => pattern.matches(&item.source),
+                false => pattern.matches(name),
+            })
+            .unwrap_or(patterns.len())
+    });
+}
+
+// Отбираем файлы по порядку, пока не превышен один из лимитов,
+// остальные возвращаем отдельно для отчета
+pub fn apply_limits(
+    files: Vec<CopyItem>,
+    max_total_bytes: Option<u64>,
+    max_files: Option<usize>,
+) -> (Vec<CopyItem>, Vec<CopyItem>) {
+    let mut planned_bytes: u64 = 0;
+    let mut limit_reached = false;
+    let mut accepted = Vec::new();
+    let mut left_out = Vec::new();
+
+    for item in files {
+        if !limit_reached {
+            let over_files = max_files.is_some_and(|max| accepted.len() >= max);
+            let over_bytes = max_total_bytes.is_some_and(|max| planned_bytes + item.size > max);
+            limit_reached = over_files || over_bytes;
+        }
+
+        if limit_reached {
+            left_out.push(item);
+        } else {
+            planned_bytes += item.size;
+            accepted.push(item);
+        }
+    }
+
+    (accepted, left_out)
+}
+
+// Сколько буферов один файл держит одновременно в худшем случае: буфер чтения, до трех блоков
+// у потока записи и до четырех у потока хеширования, очередь --also-to; файл, копируемый
+// частями (--split-above), - по буферу на поток
+pub fn buffers_per_file(also_to: bool, streams: usize) -> usize {
+    let sequential = 8 + if also_to { TEE_QUEUE_DEPTH + 1 } else { 0 };
+    sequential.max(streams)
+}
+
+// --max-memory: буферы всех файлов вместе. Сначала уменьшается буфер, и только у самого
+// маленького - число одновременных файлов. None - все и так помещается
+pub fn fit_memory(max_memory: u64, jobs: usize, buffer_size: usize, buffers_per_file: usize) -> Option<(usize, usize)> {
+    if (jobs * buffers_per_file * buffer_size) as u64 <= max_memory {
+        return None;
+    }
+    let fitting = max_memory / (jobs * buffers_per_file) as u64;
+    if fitting >= MIN_BUFFER_SIZE as u64 {
+        // Степень двойки: буфер ровно ложится на блоки дисков
+        return Some((jobs, 1 << fitting.ilog2()));
+    }
+    let jobs = (max_memory / (MIN_BUFFER_SIZE * buffers_per_file) as u64).max(1) as usize;
+    Some((jobs, MIN_BUFFER_SIZE))
+}
+
+// Дескрипторы, которые остаются прогрессу, журналам, сокетам и интерфейсу
+const FD_RESERVE: u64 = 64;
+
+// Сколько дескрипторов держит один файл: источник, копия и каждое --also-to, плюс короткие
+// открытия для проверки, атрибутов и сети
+pub fn fds_per_file(also_to: usize) -> usize {
+    4 + also_to
+}
+
+// Мягкий предел открытых файлов поднимается до жесткого; если и его не хватает на --jobs
+// файлов сразу, возвращает, сколько файлов помещается, и предел. Иначе большие --jobs
+// упираются в EMFILE
+pub fn fit_open_files(jobs: usize, fds_per_file: usize) -> Option<(usize, u64)> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    if limit.rlim_cur < limit.rlim_max {
+        let raised = libc::rlimit { rlim_cur: limit.rlim_max, ..limit };
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            limit = raised;
+        }
+    }
+    let open = fs::read_dir("/proc/self/fd").map_or(0, |entries| entries.count() as u64);
+    let available = limit.rlim_cur.saturating_sub(open + FD_RESERVE);
+    let fitting = (available / fds_per_file as u64).max(1) as usize;
+    (fitting < jobs).then_some((fitting, limit.rlim_cur))
+}
+
+pub fn report_left_out(left_out: &[CopyItem]) {
+    if left_out.is_empty() {
+        return;
+    }
+
+    let bytes: u64 = left_out.iter().map(|item| item.size).sum();
+    eprintln!(
+        "{} {}",
+        t!("warning").yellow().bold(),
+        t!("limits-left-out", count = left_out.len(), size = HumanBytes(bytes).to_string())
+    );
+    for item in left_out {
+        eprintln!("  {}", item.source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn items(sizes: &[u64]) -> Vec<CopyItem> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(n, &size)| CopyItem::with_size(Path::new(&format!("file{}", n)), vec![PathBuf::from("copy")], size))
+            .collect()
+    }
+
+    fn sizes(files: &[CopyItem]) -> Vec<u64> {
+        files.iter().map(|item| item.size).collect()
+    }
+
+    #[test]
+    fn limits_keep_the_order_and_stop_at_the_first_file_over_them() {
+        let (accepted, left_out) = apply_limits(items(&[40, 50, 20, 5]), Some(100), None);
+        // 5 байт еще поместились бы, но порядок файлов не меняется
+        assert_eq!((sizes(&accepted), sizes(&left_out)), (vec![40, 50], vec![20, 5]));
+
+        let (accepted, left_out) = apply_limits(items(&[1, 2, 3]), None, Some(2));
+        assert_eq!((sizes(&accepted), sizes(&left_out)), (vec![1, 2], vec![3]));
+
+        let (accepted, left_out) = apply_limits(items(&[60, 40]), Some(100), Some(5));
+        assert_eq!((sizes(&accepted), sizes(&left_out)), (vec![60, 40], vec![]));
+    }
+
+    #[test]
+    fn missing_limits_take_everything_and_zero_takes_nothing() {
+        let (accepted, left_out) = apply_limits(items(&[u64::MAX / 2, 7]), None, None);
+        assert_eq!((accepted.len(), left_out.len()), (2, 0));
+        let (accepted, left_out) = apply_limits(items(&[10]), Some(5), Some(0));
+        assert_eq!((accepted.len(), left_out.len()), (0, 1));
+    }
+
+    #[test]
+    fn fit_memory_shrinks_the_buffer_before_the_jobs() {
+        const MIB: usize = 1024 * 1024;
+        assert_eq!(fit_memory(1 << 30, 4, MIB, 8), None);
+        // 10M на 4 файла по 8 буферов - 320K, вниз до степени двойки
+        assert_eq!(fit_memory(10 * MIB as u64, 4, MIB, 8), Some((4, 256 * 1024)));
+        // Буфер уже минимальный - меньше файлов сразу
+        assert_eq!(fit_memory(MIB as u64, 16, MIB, 8), Some((8, MIN_BUFFER_SIZE)));
+        for (max_memory, jobs) in [(10 * MIB as u64, 4), (MIB as u64, 16), (3 * MIB as u64, 100)] {
+            let (jobs, buffer_size) = fit_memory(max_memory, jobs, MIB, 8).unwrap();
+            assert!((jobs * 8 * buffer_size) as u64 <= max_memory);
+        }
+    }
+
+    #[test]
+    fn fit_memory_keeps_one_job_when_nothing_fits() {
+        assert_eq!(fit_memory(1000, 4, 1024 * 1024, 8), Some((1, MIN_BUFFER_SIZE)));
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::i18n::t;
+use crate::session::state_dir;
+use crate::transport::{local_path, Address};
+
+// Блокировка назначения, чтобы два rcp не писали и не удаляли в одном каталоге вперемешку.
+// Файл блокировки лежит не в самом назначении, а в $XDG_STATE_HOME/rcp/locks под хешем
+// пути назначения (для удаленных - адреса); flock снимается вместе с процессом
+pub struct DestinationLock {
+    _file: File,
+}
+
+impl DestinationLock {
+    // Блокировки берутся в одном порядке, чтобы ждущие друг друга запуски не сцепились
+    pub fn acquire_all(destinations: &[PathBuf], wait: bool) -> Result<Vec<Self>> {
+        let mut keys = destinations
+            .iter()
+            .map(|destination| lock_key(destination))
+            .collect::<Result<Vec<_>>>()?;
+        keys.sort();
+        keys.dedup();
+        keys.iter().map(|key| Self::acquire(key, wait)).collect()
+    }
+
+    fn acquire(key: &str, wait: bool) -> Result<Self> {
+        let dir = state_dir()?.join("locks");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        let path = dir.join(format!("{}.lock", &blake3::hash(key.as_bytes()).to_hex()[..16]));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file: {}", path.display()))?;
+
+        if !try_lock(&file, false)? {
+            let mut owner = String::new();
+            let _ = file.read_to_string(&mut owner);
+            let owner = owner.lines().next().unwrap_or("?").to_string();
+            if !wait {
+                anyhow::bail!(t!("destination-locked", destination = key, pid = owner));
+            }
+            eprintln!("{}", t!("waiting-for-lock", destination = key, pid = owner));
+            try_lock(&file, true)?;
+        }
+
+        // Для сообщения тому, кто придет следом: PID и назначение
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}\n{}", std::process::id(), key)
+            .with_context(|| format!("Failed to write lock file: {}", path.display()))?;
+        Ok(DestinationLock { _file: file })
+    }
+}
+
+// false - блокировку держит другой процесс
+fn try_lock(file: &File, wait: bool) -> Result<bool> {
+    let operation = match wait {
+        true => libc::LOCK_EX,
+        false => libc::LOCK_EX | libc::LOCK_NB,
+    };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock => return Ok(false),
+            _ => return Err(error).context("Failed to lock the destination"),
+        }
+    }
+}
+
+// Один и тот же каталог под разными путями (относительный, через ссылку) дает один ключ;
+// еще не созданное назначение определяется по родителю. Блокируется только сам путь:
+// запуски в /data и в /data/sub друг другу не мешают, предки и потомки не проверяются
+fn lock_key(destination: &Path) -> Result<String> {
+    if Address::parse(destination)?.is_some() {
+        return Ok(destination.to_string_lossy().trim_end_matches('/').to_string());
+    }
+    let path = local_path(destination);
+    let absolute = match fs::canonicalize(&path) {
+        Ok(path) => path,
+        Err(_) => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                match fs::canonicalize(parent) {
+                    Ok(parent) => parent.join(name),
+                    Err(_) => std::path::absolute(&path)?,
+                }
+            }
+            _ => std::path::absolute(&path)?,
+        },
+    };
+    Ok(absolute.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_directory_gets_one_key() {
+        let dir = std::env::temp_dir().join(format!("rcp-lock-key-{}", std::process::id()));
+        let target = dir.join("target");
+        let link = dir.join("link");
+        fs::create_dir_all(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let key = lock_key(&target).unwrap();
+        let spellings = [
+            target.join("."),
+            dir.join("target/../target/"),
+            link.clone(),
+            PathBuf::from(format!("file://{}", target.display())),
+        ];
+        let keys: Vec<String> = spellings.iter().map(|path| lock_key(path).unwrap()).collect();
+        // Еще не созданное назначение - по родителю, в том числе через ссылку
+        let missing = (lock_key(&target.join("new")).unwrap(), lock_key(&link.join("new")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        for (path, other) in spellings.iter().zip(&keys) {
+            assert_eq!(*other, key, "{}", path.display());
+        }
+        assert_eq!(missing.0, format!("{}/new", key));
+        assert_eq!(missing.1, missing.0);
+    }
+
+    #[test]
+    fn remote_keys_ignore_the_trailing_slash() {
+        let key = lock_key(Path::new("rcp://nas/backup/")).unwrap();
+        assert_eq!(key, "rcp://nas/backup");
+        assert_eq!(lock_key(Path::new("rcp://nas/backup")).unwrap(), key);
+        // Только сам путь: подкаталог блокируется отдельно
+        assert_ne!(lock_key(Path::new("rcp://nas/backup/photos")).unwrap(), key);
+    }
+}
@@ -1,442 +1,923 @@
+mod archive;
+mod azure;
+mod bench;
+mod bisync;
+mod btrfs;
+mod chmod;
+mod cli;
+mod collect;
+mod completions;
+mod compression;
+mod config;
+mod control;
+mod copy;
+mod daemon;
+mod dav;
+mod dbus;
+mod dedup;
+mod direct;
+mod doctor;
+mod du;
+mod disks;
+mod encrypt;
+mod engine;
+mod extract;
+mod ftp;
+mod gcs;
+mod hash;
+mod http;
+mod i18n;
+mod limits;
+mod lock;
+mod man;
+mod manifest;
+mod metadata;
+mod nearby;
+mod net;
+mod owner;
+mod pipeline;
+mod plugin;
+mod progress;
+mod pseudofs;
+mod quic;
+mod reference;
+mod relay;
+mod report;
+mod rsync;
+mod schedule;
+mod session;
+mod sftp;
+mod snapshot;
+mod space;
+mod stats;
+mod systemd;
+mod tee;
+mod tls;
+mod transport;
+mod tui;
+mod watch;
+mod web;
+mod wormhole;
+
 use anyhow::{Context, Result};
-use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::os::unix::fs::symlink;
-use std::path::Path;
-use std::sync::mpsc;
-use std::thread;
-use std::time::Duration;
+use clap::Parser;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use colored::Colorize;
+use indicatif::HumanBytes;
+
+use archive::{create_archive, report_archive_created, ARCHIVE_ROOT};
+use cli::{
+    Args, AttachArgs, BarTemplate, BenchArgs, BisyncArgs, CancelArgs, ColorChoice, CompletionsArgs, DaemonArgs, DoctorArgs,
+    DuArgs, ListArgs, ManArgs, ReceiveArgs, RelayArgs, SendArgs, ServeArgs, SnapshotArgs, SubmitArgs, WatchArgs,
+};
+use collect::{collect_files, collect_remote, expand_sources, CollectOptions, CopyItem};
+use compression::Compression;
+use config::Defaults;
+use control::{Control, WorkQueue};
+use copy::{copy_item_with_progress, keeps_holes, sync_directories, CopyOptions};
+use dbus::Transfer;
+use dedup::{link_duplicates, split_duplicates, DestinationIndex};
+use disks::{network_fs, sort_by_disk_position, tune, DiskSlots, Tuning};
+use encrypt::{check_gpg_recipients, Encryption};
+use extract::extract_archives;
+use hash::hash_file;
+use i18n::t;
+use limits::{apply_limits, buffers_per_file, fds_per_file, fit_memory, fit_open_files, prioritize, report_left_out};
+use lock::DestinationLock;
+use manifest::{write_copied_list, write_manifest};
+use metadata::Metadata;
+use progress::{spawn_progress_manager, BarStyle, ProgressUpdate};
+use reference::{ReferenceDirs, ReferenceKind};
+use rsync::RsyncUrl;
+use session::SessionLog;
+use sftp::RemoteLocation;
+use space::check_free_space;
+use stats::{report_slowest, report_stats, FileTiming};
+use transport::{local_path, parse_sources, Address, EntryKind, Sink, Source};
+use tui::spawn_tui;
 
 const BUFFER_SIZE: usize = 64 * 1024;
 const MAX_CONCURRENT_FILES: usize = 10;
 const MAX_PATH_LENGTH: usize = 30;
 
-fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} <source> <destination>", args[0]);
-        std::process::exit(1);
-    }
+// Режимы, которые выбираются первым аргументом; копию файла с таким именем начинают с `--`
+const SUBCOMMANDS: &[&str] = &[
+    "attach", "bench", "bisync", "cancel", "completions", "daemon", "doctor", "du", "list", "man", "receive",
+    "relay", "send", "serve", "snapshot", "submit", "watch",
+];
 
-    let source = Path::new(&args[1]);
-    let destination = Path::new(&args[2]);
+pub fn is_subcommand(arg: &OsStr) -> bool {
+    SUBCOMMANDS.iter().any(|name| arg == *name)
+}
 
-    if !source.exists() {
-        anyhow::bail!("Source path does not exist: {}", source.display());
+// Ошибку печатаем сами: стандартное `Error: ...` из Result не переводится
+fn main() -> ExitCode {
+    match start() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{} {:#}", t!("error").red().bold(), e);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    // Собираем все файлы для копирования
-    let files_to_copy = collect_files(source, destination)?;
-    
-    if files_to_copy.is_empty() {
-        println!("No files to copy");
-        return Ok(());
+fn start() -> Result<()> {
+    if let Some(arg) = std::env::args_os().nth(1)
+        && is_subcommand(&arg)
+        && Path::new(&arg).exists()
+    {
+        let name = arg.to_string_lossy().into_owned();
+        eprintln!("{} {}", t!("warning").yellow().bold(), t!("subcommand-shadows-path", name = name));
     }
-
-    let total_files = files_to_copy.len();
-    println!("Copying {} files...", total_files);
-
-    let multi_progress = MultiProgress::new();
-    let (progress_sender, progress_receiver) = mpsc::channel();
-
-    // Запускаем менеджер прогресс-баров в отдельном потоке
-    let manager_handle = thread::spawn({
-        let multi_progress = multi_progress.clone();
-        move || progress_manager(progress_receiver, multi_progress, total_files)
-    });
-
-    // Распределяем файлы по рабочим потокам заранее
-    let worker_files = distribute_files_to_workers(&files_to_copy, MAX_CONCURRENT_FILES);
-    
-    // Создаем рабочие потоки
-    let mut worker_handles = Vec::new();
-
-    for (worker_id, files_for_worker) in worker_files.into_iter().enumerate() {
-        let progress_sender = progress_sender.clone();
-        
-        let handle = thread::spawn(move || {
-            for (i, (source_path, dest_path)) in files_for_worker.into_iter().enumerate() {
-                let global_file_id = calculate_global_id(i, worker_id, MAX_CONCURRENT_FILES);
-                if let Err(e) = copy_item_with_progress(
-                    &source_path,
-                    &dest_path,
-                    progress_sender.clone(),
-                    global_file_id as u32,
-                ) {
-                    eprintln!("Worker {}: Error copying {}: {}", worker_id, source_path, e);
+    // `rcp serve` - отдельный режим со своими аргументами
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == "serve") {
+        let args = ServeArgs::parse_from(std::env::args_os().skip(1));
+        use_color(args.output.color);
+        return net::serve(&args);
+    }
+    // `rcp send` находит получателя и дальше работает как обычное копирование
+    match std::env::args_os().nth(1) {
+        Some(arg) if arg == "send" => {
+            let args = SendArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return match &args.relay {
+                Some(relay) => {
+                    let (args, _proxy) = wormhole::send(&args, relay)?;
+                    run(args)
                 }
-            }
-        });
-        worker_handles.push(handle);
+                None => run(nearby::send(&args)?),
+            };
+        }
+        Some(arg) if arg == "receive" => {
+            let args = ReceiveArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return match &args.relay {
+                Some(relay) => wormhole::receive(&args, relay),
+                None => nearby::receive(&args),
+            };
+        }
+        Some(arg) if arg == "relay" => {
+            let args = RelayArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return relay::serve(&args);
+        }
+        Some(arg) if arg == "snapshot" => {
+            let args = SnapshotArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return snapshot::run(&args);
+        }
+        Some(arg) if arg == "watch" => {
+            let args = WatchArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return watch::watch(&args);
+        }
+        Some(arg) if arg == "bisync" => {
+            let args = BisyncArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return bisync::run(&args);
+        }
+        Some(arg) if arg == "bench" => {
+            let args = BenchArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return bench::run(&args);
+        }
+        Some(arg) if arg == "doctor" => {
+            let args = DoctorArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return doctor::run(&args);
+        }
+        Some(arg) if arg == "du" => {
+            let args = DuArgs::parse_from(std::env::args_os().skip(1));
+            use_color(args.output.color);
+            return du::run(&args);
+        }
+        Some(arg) if arg == "daemon" => {
+            let args = DaemonArgs::parse_from(std::env::args_os().skip(1));
+            return daemon::serve(&args);
+        }
+        Some(arg) if arg == "submit" => {
+            let args = SubmitArgs::parse_from(std::env::args_os().skip(1));
+            return daemon::submit(&args);
+        }
+        Some(arg) if arg == "list" => {
+            let args = ListArgs::parse_from(std::env::args_os().skip(1));
+            return daemon::list(&args);
+        }
+        Some(arg) if arg == "cancel" => {
+            let args = CancelArgs::parse_from(std::env::args_os().skip(1));
+            return daemon::cancel(&args);
+        }
+        Some(arg) if arg == "attach" => {
+            let args = AttachArgs::parse_from(std::env::args_os().skip(1));
+            return daemon::attach(&args);
+        }
+        Some(arg) if arg == "completions" => {
+            let args = CompletionsArgs::parse_from(std::env::args_os().skip(1));
+            return completions::print(&args);
+        }
+        Some(arg) if arg == "man" => {
+            let args = ManArgs::parse_from(std::env::args_os().skip(1));
+            return man::print(&args);
+        }
+        _ => {}
     }
 
-    // Ждем завершения всех рабочих потоков
-    for handle in worker_handles {
-        handle.join().unwrap();
+    let defaults = Defaults::load()?;
+    let args = defaults.parse();
+    use_color(args.output.color);
+    systemd::ready(systemd::sync_status);
+    if let Some(schedule) = &args.interval {
+        return schedule::repeat(schedule, || defaults.parse());
     }
-
-    // Завершаем менеджер прогресс-баров
-    drop(progress_sender);
-    manager_handle.join().expect("Progress manager panicked")?;
-
-    println!("{}", "Copy completed successfully!".green());
-    Ok(())
+    run(args)
 }
 
-// Распределяем файлы по рабочим потокам
-fn distribute_files_to_workers(
-    files: &[(String, std::path::PathBuf)], 
-    total_workers: usize
-) -> Vec<Vec<(String, std::path::PathBuf)>> {
-    let mut result: Vec<Vec<(String, std::path::PathBuf)>> = vec![Vec::new(); total_workers];
-    
-    for (i, (source, dest)) in files.iter().enumerate() {
-        let worker_id = i % total_workers;
-        result[worker_id].push((source.clone(), dest.clone()));
-    }
-    
-    result
+// Цвет и в наших сообщениях (colored), и в прогресс-барах (console через indicatif)
+fn use_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && io::stdout().is_terminal()
+                && io::stderr().is_terminal()
+        }
+    };
+    colored::control::set_override(enabled);
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
 }
 
-// Вычисляем глобальный ID файла
-fn calculate_global_id(local_id: usize, worker_id: usize, total_workers: usize) -> usize {
-    local_id * total_workers + worker_id
+// Копирование с отчетом о сбое (--failure-report)
+fn run(args: Args) -> Result<()> {
+    report::begin(&args)?;
+    let result = copy_files(args);
+    report::finish(&result);
+    result
 }
 
-fn collect_files(source: &Path, destination: &Path) -> Result<Vec<(String, std::path::PathBuf)>> {
-    let mut files = Vec::new();
-    
-    if source.is_file() || source.is_symlink() {
-        let source_str = source.to_string_lossy().into_owned();
-        let dest_path = if destination.is_dir() {
-            destination.join(source.file_name().unwrap())
-        } else {
-            destination.to_path_buf()
-        };
-        files.push((source_str, dest_path));
-    } else if source.is_dir() {
-        collect_files_recursive(source, destination, &mut files)?;
+fn copy_files(args: Args) -> Result<()> {
+    let destinations: Vec<PathBuf> = std::iter::once(args.destination.clone())
+        .chain(args.also_to.iter().cloned())
+        .collect();
+    // Держится до конца копирования
+    let _locks = match args.no_lock {
+        true => Vec::new(),
+        false => DestinationLock::acquire_all(&destinations, args.wait_for_lock)?,
+    };
+
+    if let Some(url) = args.sources.iter().find_map(|source| RsyncUrl::parse(source)) {
+        return pull_from_rsync(&args, &url);
+    }
+    if RsyncUrl::parse(&args.destination).is_some() {
+        anyhow::bail!("{}", t!("rsync-destination-unsupported"));
+    }
+    if args.btrfs_send {
+        return btrfs::replicate(&args);
     }
-    
-    Ok(files)
-}
-
-fn collect_files_recursive(
-    source: &Path,
-    destination: &Path,
-    files: &mut Vec<(String, std::path::PathBuf)>,
-) -> Result<()> {
-    fs::create_dir_all(destination)
-        .with_context(|| format!("Failed to create destination directory: {}", destination.display()))?;
-
-    for entry in fs::read_dir(source)? {
-        let entry = entry?;
-        let source_path = entry.path();
-        let dest_path = destination.join(entry.file_name());
 
-        // Включаем символические ссылки в список для копирования
-        if source_path.is_file() || source_path.is_symlink() {
-            let source_str = source_path.to_string_lossy().into_owned();
-            files.push((source_str, dest_path));
-        } else if source_path.is_dir() {
-            collect_files_recursive(&source_path, &dest_path, files)?;
+    // Источник и назначение выбираются по схеме адреса и сочетаются в любом порядке
+    let remote_sources = parse_sources(&args.sources)?;
+    let sources = match &remote_sources {
+        Some((_, paths)) => paths.clone(),
+        None => expand_sources(&args.sources.iter().map(|source| local_path(source)).collect::<Vec<_>>())?,
+    };
+
+    // `-` при распаковке - архив со стандартного ввода; удаленные источники проверит сервер
+    for source in &sources {
+        if remote_sources.is_some() || (args.extract && source == Path::new("-")) {
+            continue;
+        }
+        if !source.exists() && !source.is_symlink() {
+            anyhow::bail!("{}", t!("source-missing", path = source.display().to_string()));
         }
     }
 
-    Ok(())
-}
-
-fn copy_item_with_progress(
-    source: &str,
-    destination: &Path,
-    progress_sender: mpsc::Sender<ProgressUpdate>,
-    file_id: u32,
-) -> Result<()> {
-    let source_path = Path::new(source);
-    
-    if source_path.is_symlink() {
-        // Копируем символическую ссылку
-        copy_symlink(source_path, destination, progress_sender, file_id)
-    } else {
-        // Копируем обычный файл
-        copy_file_with_progress(source, destination, progress_sender, file_id)
+    let uses_reference = !args.link_dest.is_empty() || !args.compare_dest.is_empty() || !args.copy_dest.is_empty();
+    if uses_reference && Address::parse(&args.destination)?.is_some() {
+        anyhow::bail!("{}", t!("needs-local-destination", options = "--link-dest, --compare-dest, --copy-dest"));
+    }
+    if args.dedup_dest.is_some() && (remote_sources.is_some() || Address::parse(&args.destination)?.is_some()) {
+        anyhow::bail!("{}", t!("needs-local-source-and-destination", options = "--dedup-dest"));
+    }
+    if args.dedup.is_some() && (remote_sources.is_some() || Address::parse(&args.destination)?.is_some()) {
+        anyhow::bail!("{}", t!("needs-local-source-and-destination", options = "--dedup"));
+    }
+    if args.pre_snapshot && Address::parse(&args.destination)?.is_some() {
+        anyhow::bail!("{}", t!("needs-local-destination", options = "--pre-snapshot"));
+    }
+    if args.dedupe_blocks && Address::parse(&args.destination)?.is_some() {
+        anyhow::bail!("{}", t!("needs-local-destination", options = "--dedupe-blocks"));
+    }
+    let metadata = Metadata::from_args(&args);
+    if let Some(metadata) = &metadata {
+        if Address::parse(&args.destination)?.is_some() {
+            let options = "--chmod, --chown, --dest-mode, --dest-dir-mode, --preserve, --usermap, --groupmap";
+            anyhow::bail!("{}", t!("needs-local-destination", options = options));
+        }
+        if metadata.needs_source() && remote_sources.is_some() {
+            anyhow::bail!("{}", t!("needs-local-source", options = "--preserve, --usermap, --groupmap"));
+        }
+        if metadata.loses_birthtime() {
+            eprintln!("{} {}", t!("warning").yellow().bold(), t!("birthtime-unsupported"));
+        }
+    }
+    // Без --jobs число потоков и буфер подбираются по локальным дискам
+    let tuning = match (&remote_sources, Address::parse(&args.destination)?) {
+        (None, None) => tune(sources.iter().map(PathBuf::as_path).chain([args.destination.as_path()])),
+        _ => Tuning::default(),
+    };
+    let mut jobs = args.jobs.unwrap_or(tuning.jobs);
+    let mut buffer_size = args.buffer_size.map_or(tuning.buffer_size, |size| size as usize);
+    if let Some(max_memory) = args.max_memory
+        && let Some((fitting_jobs, fitting_buffer)) = fit_memory(
+            max_memory,
+            jobs,
+            buffer_size,
+            buffers_per_file(!args.also_to.is_empty(), args.streams_per_file.into()),
+        )
+    {
+        println!("{}", t!("memory-limited", size = HumanBytes(fitting_buffer as u64).to_string(), jobs = fitting_jobs));
+        (jobs, buffer_size) = (fitting_jobs, fitting_buffer);
+    }
+    if let Some((fitting_jobs, limit)) = fit_open_files(jobs, fds_per_file(args.also_to.len())) {
+        println!("{}", t!("fd-limited", limit = limit, jobs = fitting_jobs));
+        jobs = fitting_jobs;
+    }
+    if args.dedup_cache.is_some() && args.dedup_dest.is_none() && !args.dedupe_blocks {
+        anyhow::bail!("{}", t!("dedup-cache-needs-index"));
     }
-}
 
-fn copy_symlink(
-    source: &Path,
-    destination: &Path,
-    progress_sender: mpsc::Sender<ProgressUpdate>,
-    file_id: u32,
-) -> Result<()> {
-    // Получаем цель символической ссылки
-    let target = fs::read_link(source)
-        .with_context(|| format!("Failed to read symlink: {}", source.display()))?;
+    let source = match &remote_sources {
+        Some((address, _)) => {
+            if args.extract || args.to_archive.is_some() {
+                anyhow::bail!("{}", t!("remote-source-unsupported", options = "--extract, --to-archive"));
+            }
+            Some(address.connect_source(&args)?)
+        }
+        None => None,
+    };
 
-    if let Some(parent) = destination.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+    if args.extract {
+        extract_archives(&sources, &args.destination, jobs, bar_style(&args))?;
+        println!("{}", t!("extraction-completed").green());
+        return Ok(());
     }
 
-    // Удаляем существующий файл/ссылку, если он есть
-    let _ = std::fs::remove_file(destination);
+    let destination = match args.to_archive {
+        Some(_) => None,
+        None => Address::parse(&args.destination)?,
+    };
+    let sink = match &destination {
+        Some(address) => Some(connect_destination(&args, address, &sources, source.as_deref())?),
+        None => None,
+    };
+
+    // Для архива назначение - сам файл архива, имена записей строим от условного корня;
+    // для удаленного назначения - путь на сервере
+    let destinations = if args.to_archive.is_some() {
+        vec![PathBuf::from(ARCHIVE_ROOT)]
+    } else if let Some((_, root)) = &sink {
+        vec![root.clone()]
+    } else {
+        destinations.iter().map(|destination| local_path(destination)).collect()
+    };
 
-    // Создаем новую символическую ссылку
-    symlink(&target, destination)
-        .with_context(|| format!("Failed to create symlink: {}", destination.display()))?;
+    // Снимок до первой записи в назначение: при сборе файлов уже создаются директории
+    if args.pre_snapshot {
+        let snapshot = btrfs::pre_snapshot(&destinations[0])?;
+        println!("{}", t!("destination-snapshot-taken", path = snapshot.display().to_string()));
+    }
 
-    // Для символических ссылок отправляем фиктивный размер и сразу завершаем
-    let _ = progress_sender.send(ProgressUpdate::NewFile {
-        path: source.to_string_lossy().into_owned(),
-        size: 1, // Фиктивный размер для прогресс-бара
-        id: file_id,
-    });
+    // Список создаем сразу: если копировать окажется нечего, не должен остаться прошлый
+    let copied_log = match &args.log_copied {
+        Some(path) => Some(
+            File::create(path)
+                .with_context(|| t!("copied-list-failed", path = path.display().to_string()))?,
+        ),
+        None => None,
+    };
 
-    let _ = progress_sender.send(ProgressUpdate::Progress {
-        id: file_id,
-        bytes_copied: 1,
-    });
+    // Собираем все файлы для копирования
+    let collect_options = CollectOptions {
+        trailing_slash: args.trailing_slash,
+        skip_pseudo_fs: !args.include_pseudo_fs,
+        create_directories: args.to_archive.is_none() && sink.is_none(),
+        relative_to: args.relative_to.as_deref(),
+        special_files: metadata.as_ref().is_some_and(Metadata::preserves_devices),
+    };
+    let collection = match &source {
+        Some(source) => collect_remote(source.as_ref(), &sources, &destinations, collect_options)?,
+        None => collect_files(&sources, &destinations, collect_options)?,
+    };
+    let mut files = collection.files;
+    if source.is_none() {
+        sort_by_disk_position(&mut files);
+    }
+    prioritize(&mut files, &args.prioritize);
+    let (mut files_to_copy, left_out) = apply_limits(files, args.max_total_bytes, args.max_files);
+    report_left_out(&left_out);
+    
+    if files_to_copy.is_empty() {
+        eprintln!("{}", t!("no-files-to-copy"));
+        return Ok(());
+    }
 
-    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
+    let needs_checksum = args.verify || args.write_manifest.is_some();
+
+    if let Some(format) = args.to_archive {
+        let mut checksums = create_archive(
+            &collection.directories,
+            &files_to_copy,
+            format,
+            &args.destination,
+            needs_checksum.then_some(args.checksum),
+            bar_style(&args),
+        )?;
+        if let Some(manifest_path) = &args.write_manifest {
+            write_manifest(manifest_path, Path::new(ARCHIVE_ROOT), &mut checksums)?;
+        }
+        report_archive_created(&args.destination);
+        return Ok(());
+    }
 
-    Ok(())
-}
+    if args.decompress {
+        strip_compression_suffix(&mut files_to_copy);
+    }
+    if let Some(compress) = args.compress {
+        add_suffix(&mut files_to_copy, compress.compression.suffix());
+    }
+    let encryption = if !args.encrypt_to.is_empty() {
+        Some(Encryption::Age(args.encrypt_to.clone()))
+    } else if !args.encrypt_gpg.is_empty() {
+        check_gpg_recipients(&args.encrypt_gpg)?;
+        Some(Encryption::Gpg(args.encrypt_gpg.clone()))
+    } else {
+        None
+    };
+    if let Some(encryption) = &encryption {
+        add_suffix(&mut files_to_copy, encryption.suffix());
+    }
 
-fn copy_file_with_progress(
-    source: &str,
-    destination: &Path,
-    progress_sender: mpsc::Sender<ProgressUpdate>,
-    file_id: u32,
-) -> Result<()> {
-    let mut source_file = File::open(source)
-        .with_context(|| format!("Failed to open source file: {}", source))?;
-
-    let metadata = source_file.metadata()?;
-    let file_size = metadata.len();
-
-    if let Some(parent) = destination.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
-    }
-
-    let mut dest_file = File::create(destination)
-        .with_context(|| format!("Failed to create destination file: {}", destination.display()))?;
-
-    // Уведомляем о начале копирования
-    let _ = progress_sender.send(ProgressUpdate::NewFile {
-        path: source.to_string(),
-        size: file_size,
-        id: file_id,
-    });
-
-    let mut buffer = vec![0; BUFFER_SIZE];
-    let mut total_copied = 0;
-
-    loop {
-        let bytes_read = match source_file.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => n,
-            Err(e) => {
-                eprintln!("Error reading file {}: {}", source, e);
-                break;
+    // Передача на сервер rcp ведет журнал: по ID сессии ее можно продолжить
+    let session_log = match &destination {
+        Some(Address::Rcp(_)) => {
+            let log = SessionLog::open(args.session.as_deref())?;
+            report::session(&log);
+            let before = files_to_copy.len();
+            files_to_copy.retain(|item| !log.is_done(&item.destination));
+            if files_to_copy.len() < before {
+                println!("{}", t!("session-skipping", count = before - files_to_copy.len(), session = log.id()));
             }
-        };
-
-        if let Err(e) = dest_file.write_all(&buffer[..bytes_read]) {
-            eprintln!("Error writing file {}: {}", destination.display(), e);
-            break;
+            if files_to_copy.is_empty() {
+                log.remove();
+                println!("{}", t!("copy-completed").green());
+                return Ok(());
+            }
+            Some(Arc::new(log))
+        }
+        _ => None,
+    };
+
+    // Неизменные файлы из каталогов-образцов обрабатываем сразу: связываем, пропускаем
+    // или копируем локально
+    let mut reused = Vec::new();
+    if let Some(reference) = ReferenceDirs::from_args(&args, &destinations[0]) {
+        let handled = reference.apply(&mut files_to_copy, source.as_deref())?;
+        if !handled.is_empty() {
+            reference.report(handled.len());
+        }
+        if reference.kind != ReferenceKind::Compare {
+            reused = handled;
         }
-
-        total_copied += bytes_read as u64;
-
-        // Обновляем прогресс
-        let _ = progress_sender.send(ProgressUpdate::Progress {
-            id: file_id,
-            bytes_copied: total_copied,
-        });
+    }
+    // Содержимое, которое уже лежит в назначении под другим именем, не пишем заново
+    let mut index = match args.dedup_dest.is_some() || args.dedupe_blocks {
+        true => Some(DestinationIndex::build(&destinations[0], args.dedup_cache.as_deref())?),
+        false => None,
+    };
+    let network_destination = sink.is_none() && network_fs(&destinations[0]).is_some();
+    if network_destination && let Some(index) = &mut index {
+        index.without_reflinks();
+    }
+    if let (Some(mode), Some(index)) = (args.dedup_dest, &mut index) {
+        let handled = index.apply(&mut files_to_copy, mode)?;
+        index.save()?;
+        if !handled.is_empty() {
+            dedup::report(handled.len(), mode, false);
+        }
+        reused.extend(handled);
+    }
+    let duplicates = match args.dedup {
+        Some(_) => split_duplicates(&mut files_to_copy),
+        None => Vec::new(),
+    };
+    if !duplicates.is_empty() {
+        println!("{}", t!("duplicates-found", count = duplicates.len()));
     }
 
-    // Уведомляем о завершении
-    let _ = progress_sender.send(ProgressUpdate::Finished { id: file_id });
-
-    Ok(())
-}
+    if let Some(metadata) = &metadata {
+        for directory in &collection.directories {
+            metadata.unseal(directory.destinations())?;
+        }
+    }
 
-struct ActiveProgress {
-    pb: ProgressBar,
-    finished: bool,
-    id: u32,
-    path: String,
-}
+    let total_files = files_to_copy.len();
+    report::planned(&files_to_copy);
+    if let Some((sink, _)) = &sink {
+        // В объектных хранилищах директории возникают из имен объектов
+        for directory in &collection.directories {
+            sink.create_dir_all(&directory.destination)?;
+        }
+        match &session_log {
+            Some(log) => println!(
+                "{}",
+                t!("copying-files-to-session", count = total_files, destination = sink.name(), session = log.id())
+            ),
+            None => println!("{}", t!("copying-files-to", count = total_files, destination = sink.name())),
+        }
+    } else {
+        // Дыры разреженных файлов сохраняет только локальная копия байт в байт
+        let plain = source.is_none()
+            && args.compress.is_none()
+            && encryption.is_none()
+            && !args.decompress
+            && !args.direct_io;
+        let streams_per_file = args.streams_per_file.into();
+        check_free_space(&files_to_copy, &destinations, args.space_check, |item| {
+            plain && keeps_holes(item, streams_per_file, args.split_above)
+        })?;
+        if total_files > 0 {
+            println!("{}", t!("copying-files", count = total_files));
+        }
+    }
 
-fn progress_manager(
-    receiver: mpsc::Receiver<ProgressUpdate>,
-    multi_progress: MultiProgress,
-    total_files: usize,
-) -> Result<()> {
-    let mut active_bars: Vec<ActiveProgress> = Vec::new();
-    let mut completed_files = 0;
-    let mut bars_to_remove: Vec<ProgressBar> = Vec::new();
-    
-    // Главный прогресс-бар для общего прогресса
-    let main_pb = multi_progress.add(ProgressBar::new(total_files as u64));
-    main_pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>3}/{len:>3} files ({percent}%)")?
-            .progress_chars("█▓▒░"),
-    );
-    main_pb.set_message("Overall progress".to_string());
-
-    while completed_files < total_files {
-        // Сначала удаляем старые прогресс-бары
-        for pb in bars_to_remove.drain(..) {
-            multi_progress.remove(&pb);
-        }
-        
-        match receiver.recv_timeout(Duration::from_millis(100)) {
-            Ok(update) => {
-                match update {
-                    ProgressUpdate::NewFile { path, size, id } => {
-                        // Удаляем старые завершенные прогресс-бары при достижении лимита
-                        if active_bars.len() >= MAX_CONCURRENT_FILES {
-                            if let Some(idx) = active_bars.iter().position(|ap| ap.finished) {
-                                let completed = active_bars.remove(idx);
-                                bars_to_remove.push(completed.pb);
-                            }
-                        }
-                        
-                        let pb = multi_progress.add(ProgressBar::new(size));
-                        let display_path = shorten_path_safe(&path, MAX_PATH_LENGTH);
-                        
-                        pb.set_style(ProgressStyle::with_template(&format!(
-                            "{{msg:{}}} [{{elapsed_precise}}] {{bar:40.cyan/blue}} {{bytes:>8}}/{{total_bytes:>8}} {{bytes_per_sec:>10}}",
-                            MAX_PATH_LENGTH
-                        ))?
-                        .with_key("bytes_per_sec", |state: &ProgressState, w: &mut dyn std::fmt::Write| {
-                            write!(w, "{}/s", format_speed(state.per_sec())).unwrap()
-                        })
-                        .progress_chars("█▓▒░"));
-                        
-                        pb.set_message(format!("{:width$}", display_path.cyan().bold(), width = MAX_PATH_LENGTH));
-                        
-                        active_bars.push(ActiveProgress {
-                            pb,
-                            finished: false,
-                            id,
-                            path,
+    // Рабочие потоки берут файлы из общей очереди по мере освобождения
+    let control = Arc::new(Control::default());
+    control.set_limit(args.bwlimit);
+    report::copying(&control);
+    // Прогресс для окружения рабочего стола; без шины копирование идет как обычно
+    let transfer = match args.dbus {
+        true => {
+            let sources = args.sources.iter().map(|source| source.display().to_string()).collect::<Vec<_>>().join(", ");
+            let destination = args.destination.display().to_string();
+            match Transfer::export(sources, destination, &files_to_copy, &control) {
+                Ok(transfer) => Some(Arc::new(transfer)),
+                Err(e) => {
+                    eprintln!("{} {}", t!("warning").yellow().bold(), t!("dbus-unavailable", error = format!("{:#}", e)));
+                    None
+                }
+            }
+        }
+        false => None,
+    };
+    let queue = Arc::new(WorkQueue::new(&files_to_copy));
+    if source.is_none() && sink.is_none() && args.to_archive.is_none() {
+        queue.batch_small_files();
+    }
+    if args.jobs_per_hdd < jobs {
+        let local_destination = sink.is_none() && args.to_archive.is_none();
+        let slots = DiskSlots::detect(&files_to_copy, source.is_none(), local_destination, args.jobs_per_hdd);
+        if !slots.disks().is_empty() {
+            let disks = slots.disks().join(", ");
+            println!("{}", t!("hdd-limited", disks = disks, count = args.jobs_per_hdd));
+            queue.limit_disks(slots);
+        }
+    }
+    let tui = args.tui && io::stdout().is_terminal();
+    let (progress_sender, manager_handle) = match tui {
+        true => spawn_tui(total_files, jobs, Arc::clone(&queue), Arc::clone(&control)),
+        false => spawn_progress_manager(
+            total_files,
+            bar_style(&args),
+            Some(Arc::clone(&control)),
+            args.worker_bars.then(|| Arc::clone(&queue)),
+        ),
+    };
+
+    // С сервера на тот же сервер файлы копирует он сам
+    let same_server = match (&remote_sources, &destination) {
+        (Some((source, _)), Some(destination)) => source.same_server(destination),
+        _ => false,
+    };
+    let copy_options = CopyOptions {
+        file_timeout: args.file_timeout.map(Duration::from_secs),
+        checksum: needs_checksum.then_some(args.checksum),
+        verify: args.verify,
+        manifest: args.write_manifest.is_some(),
+        compress: args.compress,
+        decompress: args.decompress,
+        encryption,
+        source,
+        sink: sink.map(|(sink, _)| sink),
+        same_server,
+        streams_per_file: args.streams_per_file.into(),
+        split_above: args.split_above,
+        writeback_every: args.writeback_every,
+        fsync: args.fsync_dirs,
+        drop_cache: args.drop_cache,
+        direct_io: args.direct_io,
+        preserve_modified: uses_reference,
+        metadata: metadata.clone(),
+        buffer_size,
+        created_dirs: tuning.network.then(Arc::default),
+        stale_retries: args.retry_stale,
+        control: Arc::clone(&control),
+    };
+
+    // Рабочие потоки
+    let copy_started = Instant::now();
+    let log_copied = copied_log.is_some();
+    let timed = args.slowest.is_some() || args.stats;
+    let copy = {
+        let progress_sender = progress_sender.clone();
+        let session_log = session_log.clone();
+        let transfer = transfer.clone();
+        move |worker_id: usize, file_id: u32, item: CopyItem, report: &mut WorkerReport| {
+            let started = Instant::now();
+            match copy_item_with_progress(
+                &item,
+                progress_sender.clone(),
+                file_id,
+                &copy_options,
+            ) {
+                Ok(result) => {
+                    report::copied(&item);
+                    if let Some(transfer) = &transfer {
+                        transfer.copied();
+                    }
+                    if let Some(log) = &session_log {
+                        log.mark_done(&item.destination);
+                    }
+                    if log_copied {
+                        report.copied.push(item.destination.clone());
+                        report.copied.extend(item.also_to.iter().cloned());
+                    }
+                    if timed {
+                        report.timings.push(FileTiming {
+                            source: item.source.clone(),
+                            size: item.size,
+                            elapsed: started.elapsed(),
                         });
                     }
-                    ProgressUpdate::Progress { id, bytes_copied } => {
-                        if let Some(active_progress) = active_bars.iter_mut().find(|ap| ap.id == id) {
-                            if !active_progress.finished {
-                                active_progress.pb.set_position(bytes_copied);
-                            }
-                        }
+                    if let Some(digest) = result {
+                        report.checksums.push((item, digest));
+                    }
+                }
+                Err(e) => {
+                    let error = t!("worker-copy-failed", worker = worker_id, path = item.source.as_str(), error = format!("{:#}", e));
+                    // В полноэкранном режиме ошибки показывает сам интерфейс
+                    if !tui {
+                        eprintln!("{}", error);
                     }
-                    ProgressUpdate::Finished { id } => {
-                        if let Some(active_progress) = active_bars.iter_mut().find(|ap| ap.id == id) {
-                            active_progress.finished = true;
-                            let display_path = shorten_path_safe(&active_progress.path, MAX_PATH_LENGTH);
-                            active_progress.pb.finish_with_message(format!("{} {}", "✓".green(), display_path));
-                            completed_files += 1;
-                            main_pb.inc(1);
-                            
-                            // Помечаем прогресс-бар для удаления в следующей итерации
-                            bars_to_remove.push(active_progress.pb.clone());
-                        }
+                    report::failed(&item, &format!("{:#}", e));
+                    if let Some(transfer) = &transfer {
+                        transfer.failed();
                     }
+                    let _ = progress_sender.send(ProgressUpdate::Failed { id: file_id, error });
+                    report.failed += 1;
                 }
             }
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                // Таймаут - продолжаем проверять
-                continue;
-            }
-            Err(mpsc::RecvTimeoutError::Disconnected) => {
-                // Канал закрыт, выходим
-                break;
-            }
         }
+    };
+    let reports = engine::run(jobs, &queue, &control, copy);
+
+    // Итоги рабочих потоков
+    let mut checksums = Vec::new();
+    let mut copied = Vec::new();
+    let mut timings = Vec::new();
+    let mut failed = 0;
+    for report in reports {
+        checksums.extend(report.checksums);
+        copied.extend(report.copied);
+        timings.extend(report.timings);
+        failed += report.failed;
     }
-    
-    main_pb.finish_with_message("All files copied successfully!".green().to_string());
-    
-    // Завершаем оставшиеся прогресс-бары
-    for active_progress in active_bars {
-        if !active_progress.finished {
-            let display_path = shorten_path_safe(&active_progress.path, MAX_PATH_LENGTH);
-            active_progress.pb.finish_with_message(format!("{} {}", "✓".green(), display_path));
+    if let Some(mode) = args.dedup
+        && !duplicates.is_empty()
+    {
+        let (done, duplicate_failures) = link_duplicates(duplicates, mode, network_destination);
+        failed += duplicate_failures;
+        reused.extend(done);
+    }
+    // Права директорий - в конце, когда в них больше ничего не пишется
+    if let Some(metadata) = &metadata {
+        for directory in &collection.directories {
+            metadata.apply(Path::new(&directory.source), directory.destinations())?;
         }
     }
-    
+    if args.dedupe_blocks
+        && let Some(index) = &mut index
+    {
+        let copied: Vec<PathBuf> = files_to_copy
+            .iter()
+            .chain(&reused)
+            .map(|item| item.destination.clone())
+            .collect();
+        index.dedupe_blocks(&copied)?;
+        index.save()?;
+    }
+    if let Some(metadata) = &metadata {
+        metadata.seal()?;
+    }
+    if args.fsync_dirs && destination.is_none() {
+        let entries = files_to_copy.iter().chain(&reused).chain(&collection.directories);
+        sync_directories(entries.flat_map(CopyItem::destinations))?;
+    }
+    // Файлы из каталогов-образцов и связанные дубликаты тоже оказались в назначении
+    if copied_log.is_some() {
+        copied.extend(reused.iter().map(|item| item.destination.clone()));
+    }
+    // В манифест попадают и файлы, взятые из каталогов-образцов
+    if args.write_manifest.is_some() {
+        for item in reused {
+            let digest = hash_file(&item.destination, args.checksum)?;
+            checksums.push((item, digest));
+        }
+    }
+
+    // Завершаем менеджер прогресс-баров
+    drop(progress_sender);
+    manager_handle.join().expect("Progress manager panicked")?;
+
+    // Пропущенные до начала и оставшиеся после остановки
+    let left_out = queue.left_out();
+    if left_out > 0 {
+        eprintln!("{} {}", t!("warning").yellow().bold(), t!("files-not-copied", count = left_out));
+        report::left_out(left_out);
+        failed += left_out;
+    }
+    if let Some(transfer) = &transfer {
+        let state = match (control.is_draining(), failed) {
+            (true, _) => "stopped",
+            (false, 0) => "finished",
+            (false, _) => "failed",
+        };
+        transfer.finish(state, failed);
+    }
+
+    if let Some(manifest_path) = &args.write_manifest {
+        write_manifest(manifest_path, &destinations[0], &mut checksums)?;
+    }
+    if let (Some(file), Some(path)) = (copied_log, &args.log_copied) {
+        write_copied_list(file, path, &mut copied, args.log_copied0)?;
+    }
+    if args.stats {
+        report_stats(&timings, copy_started.elapsed());
+    }
+    if let Some(count) = args.slowest {
+        report_slowest(&mut timings, count);
+    }
+
+    if let Some(log) = session_log.and_then(Arc::into_inner) {
+        if failed > 0 {
+            eprintln!(
+                "{} {}",
+                t!("warning").yellow().bold(),
+                t!("files-not-copied-resume", count = failed, session = log.id())
+            );
+        } else {
+            log.remove();
+        }
+    }
+
+    if control.is_draining() {
+        anyhow::bail!("{}", t!("copy-stopped"));
+    }
+    println!("{}", t!("copy-completed").green());
     Ok(())
 }
 
-// Безопасная версия shorten_path для Unicode
-fn shorten_path_safe(path: &str, max_length: usize) -> String {
-    if path.len() <= max_length {
-        return path.to_string();
-    }
-    
-    let chars: Vec<char> = path.chars().collect();
-    if chars.len() <= max_length {
-        return path.to_string();
+// Итог рабочего потока
+#[derive(Default)]
+struct WorkerReport {
+    checksums: Vec<(CopyItem, String)>,
+    copied: Vec<PathBuf>,
+    timings: Vec<FileTiming>,
+    failed: usize,
+}
+
+// Сжатые и зашифрованные файлы получают суффикс формата; символические ссылки копируются как есть
+fn add_suffix(files: &mut [CopyItem], suffix: &str) {
+    for item in files.iter_mut().filter(|item| !Path::new(&item.source).is_symlink()) {
+        for destination in std::iter::once(&mut item.destination).chain(item.also_to.iter_mut()) {
+            destination.as_mut_os_string().push(suffix);
+        }
     }
-    
-    if chars.len() >= 3 {
-        if let Some(last_sep) = path.rfind(std::path::MAIN_SEPARATOR) {
-            let filename = &path[last_sep + 1..];
-            let filename_chars: Vec<char> = filename.chars().collect();
-            
-            if filename_chars.len() + 3 <= max_length {
-                return format!("...{}", filename);
-            }
+}
+
+// Распакованные файлы теряют суффикс сжатия (`app.log.gz` -> `app.log`)
+fn strip_compression_suffix(files: &mut [CopyItem]) {
+    for item in files.iter_mut().filter(|item| !Path::new(&item.source).is_symlink()) {
+        if Compression::from_suffix(Path::new(&item.source)) == Compression::None {
+            continue;
         }
-        
-        let start_chars = max_length / 2;
-        let end_chars = max_length - start_chars - 3;
-        
-        if start_chars > 0 && end_chars > 0 {
-            let start: String = chars[..start_chars].iter().collect();
-            let end: String = chars[chars.len() - end_chars..].iter().collect();
-            return format!("{}...{}", start, end);
+        for destination in std::iter::once(&mut item.destination).chain(item.also_to.iter_mut()) {
+            destination.set_extension("");
         }
     }
-    
-    if max_length > 3 {
-        let end: String = chars[chars.len() - (max_length - 3)..].iter().collect();
-        format!("...{}", end)
-    } else {
-        "...".to_string()
+}
+
+// Источник на демоне rsync: один, только в локальное назначение и без обработки
+// данных по пути - файлы пишутся так, как их прислал сервер
+fn pull_from_rsync(args: &Args, url: &RsyncUrl) -> Result<()> {
+    if args.sources.len() > 1 {
+        anyhow::bail!("{}", t!("rsync-source-combined"));
+    }
+    if url.path.is_empty() {
+        return rsync::list_modules(url);
+    }
+    if RemoteLocation::parse(&args.destination).is_some() {
+        anyhow::bail!("{}", t!("remote-to-remote-unsupported"));
+    }
+    if args.extract
+        || args.to_archive.is_some()
+        || !args.also_to.is_empty()
+        || args.verify
+        || args.write_manifest.is_some()
+        || args.log_copied.is_some()
+        || args.compress.is_some()
+        || args.decompress
+        || !args.encrypt_to.is_empty()
+        || !args.encrypt_gpg.is_empty()
+    {
+        let options = "--extract, --to-archive, --also-to, --verify, --write-manifest, --log-copied, --compress, \
+                       --decompress, --encrypt-to, --encrypt-gpg";
+        anyhow::bail!("{}", t!("rsync-source-unsupported", options = options));
     }
+
+    rsync::pull(url, &args.destination, args.max_total_bytes, args.max_files, bar_style(args))
 }
 
-fn format_speed(bytes_per_sec: f64) -> String {
-    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
-    let mut size = bytes_per_sec;
-    let mut unit_index = 0;
+fn bar_style(args: &Args) -> BarStyle {
+    let mut style = BarStyle {
+        ascii: args.ascii,
+        refresh_rate: args.refresh_rate,
+        verbose: args.verbose,
+        ..BarStyle::default()
+    };
+    for template in &args.bar_style {
+        match template {
+            BarTemplate::Overall(template) => style.overall = Some(template.clone()),
+            BarTemplate::File(template) => style.file = Some(template.clone()),
+            BarTemplate::Worker(template) => style.worker = Some(template.clone()),
+        }
+    }
+    style
+}
 
-    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+// Подключаемся к удаленному назначению. Как и scp, одиночный файл кладем внутрь
+// директории назначения, если она существует
+fn connect_destination(
+    args: &Args,
+    address: &Address,
+    sources: &[PathBuf],
+    source: Option<&dyn Source>,
+) -> Result<(Arc<dyn Sink>, PathBuf)> {
+    if !args.also_to.is_empty() || args.verify {
+        anyhow::bail!("{}", t!("remote-destination-unsupported", options = "--also-to, --verify"));
     }
 
-    format!("{:.1} {}", size, UNITS[unit_index])
+    let sink = address.connect_sink(args)?;
+    let mut root = address.path();
+
+    if let [path] = sources {
+        let (is_dir, name) = match source {
+            Some(source) => (
+                source.stat(path)?.is_some_and(|entry| entry.kind == EntryKind::Dir),
+                source.file_name(path),
+            ),
+            None => (path.is_dir(), path.file_name().map(OsString::from)),
+        };
+        if !is_dir
+            && sink.is_dir(&root)?
+            && let Some(name) = name
+        {
+            root.push(name);
+        }
+    } else if sources.len() > 1 {
+        sink.create_dir_all(&root)?;
+    }
+
+    Ok((sink, root))
 }
 
-#[derive(Debug)]
-enum ProgressUpdate {
-    NewFile {
-        path: String,
-        size: u64,
-        id: u32,
-    },
-    Progress {
-        id: u32,
-        bytes_copied: u64,
-    },
-    Finished {
-        id: u32,
-    },
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_dash_copies_a_source_named_like_a_subcommand() {
+        assert!(is_subcommand(OsStr::new("serve")));
+        assert!(!is_subcommand(OsStr::new("--")));
+        let args = Args::parse_from(["rcp", "--", "serve", "dest"]);
+        assert_eq!(args.sources, [PathBuf::from("serve")]);
+        assert_eq!(args.destination, PathBuf::from("dest"));
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+use clap_mangen::Man;
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use crate::cli::{self, ManArgs};
+
+// Страницы строятся из тех же определений clap, что и разбор аргументов, поэтому не расходятся
+// с настоящими опциями. Пакетам удобнее --dir: по странице на каждую подкоманду
+pub fn print(args: &ManArgs) -> Result<()> {
+    let mut command = cli::full_command();
+    command.build();
+    let Some(dir) = &args.dir else {
+        return render(&Man::new(command), &mut io::stdout()).context("Failed to write the man page");
+    };
+
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    for subcommand in command.get_subcommands() {
+        Man::new(subcommand.clone())
+            .generate_to(dir)
+            .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+    }
+    let path = dir.join("rcp.1");
+    let mut file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    render(&Man::new(command), &mut file).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// Как Man::render, но с разделами о конфигурации и переменных окружения, которых нет в clap
+fn render(man: &Man, out: &mut dyn Write) -> io::Result<()> {
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+    out.write_all(ENVIRONMENT_AND_FILES.as_bytes())?;
+    man.render_version_section(out)
+}
+
+const ENVIRONMENT_AND_FILES: &str = r#".SH ENVIRONMENT
+.TP
+\fBRCP_\fR\fIOPTION\fR
+Value for the copy option \fB\-\-\fR\fIoption\fR (upper case, \- replaced by _), e.g. \fBRCP_JOBS\fR or \fBRCP_BWLIMIT\fR. It overrides config.toml and is overridden by the flag itself. Flags take 1/0, true/false, yes/no or on/off.
+.TP
+\fBRCP_PROFILE\fR
+Profile to use when \fB\-\-profile\fR is not given.
+.TP
+\fBNO_COLOR\fR
+Turns colors off for \fB\-\-color auto\fR.
+.SH FILES
+.TP
+\fI/etc/rcp/config.toml\fR, \fI$XDG_CONFIG_HOME/rcp/config.toml\fR
+Default values of copy options keyed by their long names (\fBbwlimit = "20M"\fR), and \fB[profile.\fR\fINAME\fR\fB]\fR sections selected with \fB\-\-profile\fR. The user file (\fI~/.config/rcp/config.toml\fR without XDG_CONFIG_HOME) overrides the system one.
+"#;
@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::collect::CopyItem;
+use crate::i18n::t;
+
+// Формат совместим с `sha256sum -c` / `b3sum -c`, запущенными из директории назначения
+pub fn write_manifest(path: &Path, destination_root: &Path, checksums: &mut [(CopyItem, String)]) -> Result<()> {
+    checksums.sort_by(|(a, _), (b, _)| a.destination.cmp(&b.destination));
+
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create manifest: {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    for (item, digest) in checksums.iter() {
+        let relative = item
+            .destination
+            .strip_prefix(destination_root)
+            .ok()
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .unwrap_or(&item.destination);
+        writeln!(writer, "{}  {}", digest, relative.display())?;
+    }
+
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write manifest: {}", path.display()))
+}
+
+// --log-copied: пути назначения скопированных файлов для следующих шагов (chown, индексация,
+// подпись). Путь с переводом строки в построчном списке не записать - его оставляем только
+// для --log-copied0
+pub fn write_copied_list(file: File, path: &Path, copied: &mut [PathBuf], nul: bool) -> Result<()> {
+    copied.sort();
+    let mut writer = BufWriter::new(file);
+    let mut left_out = 0;
+    for destination in copied.iter() {
+        let bytes = destination.as_os_str().as_bytes();
+        if !nul && bytes.contains(&b'\n') {
+            left_out += 1;
+            continue;
+        }
+        writer.write_all(bytes)?;
+        writer.write_all(if nul { b"\0" } else { b"\n" })?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write the list of copied files: {}", path.display()))?;
+    if left_out > 0 {
+        eprintln!(
+            "{} {}",
+            t!("warning").yellow().bold(),
+            t!("paths-with-newline-left-out", count = left_out, path = path.display().to_string())
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,534 @@
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{lchown, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::chmod::{apply_chmod, ChmodRule};
+use crate::cli::{Args, Preserve};
+use crate::owner::{IdKind, Ownership};
+
+// Атрибуты, которые переносятся на копию после записи данных: владелец (owner.rs), права
+// (с преобразованиями --chmod из chmod.rs), флаги chattr, время доступа и создания и
+// специальные файлы. С --fake-super без root то, что поставить нельзя, сохраняется в
+// user.rcp.* xattrs копии (как fake-super у rsync), а при чтении источника xattrs
+// важнее настоящих атрибутов - так копию можно вернуть обратно уже от root
+
+const UID_XATTR: &str = "user.rcp.uid";
+const GID_XATTR: &str = "user.rcp.gid";
+// Имена рядом с номерами: на другой системе у тех же пользователей могут быть другие ID
+const USER_XATTR: &str = "user.rcp.user";
+const GROUP_XATTR: &str = "user.rcp.group";
+const MODE_XATTR: &str = "user.rcp.mode";
+const RDEV_XATTR: &str = "user.rcp.rdev";
+const FLAGS_XATTR: &str = "user.rcp.flags";
+const BIRTHTIME_XATTR: &str = "user.rcp.birthtime";
+
+// Время создания ставится только там, где для этого есть вызов
+const SETS_BIRTHTIME: bool = cfg!(any(target_os = "macos", windows));
+
+// Флаги chattr (linux/fs.h), которые переносятся на копию
+const SYNC_FL: u32 = 0x8;
+const IMMUTABLE_FL: u32 = 0x10;
+const APPEND_FL: u32 = 0x20;
+const NODUMP_FL: u32 = 0x40;
+const NOATIME_FL: u32 = 0x80;
+const DIRSYNC_FL: u32 = 0x10000;
+const NOCOW_FL: u32 = 0x800000;
+const PRESERVED_FLAGS: u32 = SYNC_FL | IMMUTABLE_FL | APPEND_FL | NODUMP_FL | NOATIME_FL | DIRSYNC_FL | NOCOW_FL;
+// После них в файл не записать, не сделать жесткую ссылку, а в директории - ничего не создать
+const SEALING_FLAGS: u32 = IMMUTABLE_FL | APPEND_FL;
+
+// Атрибуты источника; mode - вместе с типом файла. Имена владельца и группы известны, если
+// источник - копия с --fake-super
+#[derive(Clone, Debug)]
+pub struct Stat {
+    pub uid: u32,
+    pub gid: u32,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub mode: u32,
+    pub rdev: u64,
+}
+
+impl Stat {
+    fn read(path: &Path, fake_super: bool) -> Result<Self> {
+        let metadata = fs::symlink_metadata(path).with_context(|| format!("Failed to read attributes of {}", path.display()))?;
+        let mut stat = Stat {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            user: None,
+            group: None,
+            mode: metadata.mode(),
+            rdev: metadata.rdev(),
+        };
+        if fake_super && !metadata.is_symlink() {
+            if let Some(uid) = get_xattr(path, UID_XATTR).and_then(|value| value.parse().ok()) {
+                stat.uid = uid;
+            }
+            if let Some(gid) = get_xattr(path, GID_XATTR).and_then(|value| value.parse().ok()) {
+                stat.gid = gid;
+            }
+            stat.user = get_xattr(path, USER_XATTR);
+            stat.group = get_xattr(path, GROUP_XATTR);
+            if let Some(mode) = get_xattr(path, MODE_XATTR).and_then(|value| u32::from_str_radix(&value, 8).ok()) {
+                stat.mode = mode;
+            }
+            if let Some((major, minor)) = get_xattr(path, RDEV_XATTR).and_then(|value| parse_rdev(&value)) {
+                stat.rdev = libc::makedev(major, minor);
+            }
+        }
+        Ok(stat)
+    }
+
+    fn file_type(&self) -> u32 {
+        self.mode & libc::S_IFMT
+    }
+
+    fn is_special(&self) -> bool {
+        matches!(self.file_type(), libc::S_IFCHR | libc::S_IFBLK | libc::S_IFIFO | libc::S_IFSOCK)
+    }
+
+    fn is_device(&self) -> bool {
+        matches!(self.file_type(), libc::S_IFCHR | libc::S_IFBLK)
+    }
+}
+
+fn parse_rdev(value: &str) -> Option<(u32, u32)> {
+    let (major, minor) = value.split_once(',')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+#[derive(Clone, Default)]
+pub struct Metadata {
+    ownership: Option<Ownership>,
+    mode: bool,
+    // Права вместо оставленных umask (--dest-mode, --dest-dir-mode)
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    chmod: Vec<ChmodRule>,
+    devices: bool,
+    flags: bool,
+    atime: bool,
+    birthtime: bool,
+    fake_super: bool,
+    // Без root владельца и устройства ставить нельзя: с --fake-super они уходят в xattrs
+    root: bool,
+    // Копии, которые получат immutable и append-only, когда все остальное будет записано
+    sealed: Arc<Mutex<Vec<(PathBuf, u32)>>>,
+}
+
+impl Metadata {
+    // None - копия получает атрибуты по умолчанию
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let metadata = Metadata {
+            ownership: Ownership::from_args(args),
+            mode: args.preserve.contains(&Preserve::Mode),
+            file_mode: args.dest_mode,
+            dir_mode: args.dest_dir_mode,
+            chmod: args.chmod.iter().flatten().cloned().collect(),
+            devices: args.preserve.contains(&Preserve::Devices),
+            flags: args.preserve.contains(&Preserve::Flags),
+            atime: args.preserve.contains(&Preserve::Atime),
+            birthtime: args.preserve.contains(&Preserve::Birthtime),
+            fake_super: args.fake_super,
+            root: unsafe { libc::geteuid() } == 0,
+            sealed: Arc::default(),
+        };
+        let active = metadata.ownership.is_some()
+            || metadata.mode
+            || metadata.file_mode.is_some()
+            || metadata.dir_mode.is_some()
+            || !metadata.chmod.is_empty()
+            || metadata.devices
+            || metadata.flags
+            || metadata.atime
+            || metadata.birthtime;
+        active.then_some(metadata)
+    }
+
+    pub fn preserves_devices(&self) -> bool {
+        self.devices
+    }
+
+    // Все, кроме --chown и прав копии (--chmod, --dest-mode, --dest-dir-mode), берется у источника, а его атрибуты известны только у локального
+    pub fn needs_source(&self) -> bool {
+        self.mode || self.devices || self.flags || self.atime || self.birthtime || self.ownership.as_ref().is_some_and(Ownership::needs_source)
+    }
+
+    // Время создания некуда записать: ни вызова для него, ни --fake-super
+    pub fn loses_birthtime(&self) -> bool {
+        self.birthtime && !SETS_BIRTHTIME && !self.fake_super
+    }
+
+    // Время доступа источника до чтения: файловая система без O_NOATIME обновит его
+    pub fn source_atime(&self, source: &Path) -> Option<SystemTime> {
+        match self.atime {
+            true => fs::symlink_metadata(source).ok()?.accessed().ok(),
+            false => None,
+        }
+    }
+
+    // Вернуть время доступа прочитанному источнику; чужие файлы без root так не поправить
+    pub fn restore_atime(&self, source: &Path, accessed: Option<SystemTime>) {
+        if let Some(accessed) = accessed {
+            let _ = set_atime(source, accessed);
+        }
+    }
+
+    fn source_stat(&self, source: &Path) -> Result<Option<Stat>> {
+        match self.needs_source() {
+            true => Stat::read(source, self.fake_super).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    // Источник - устройство, FIFO или сокет (в том числе записанный через --fake-super)
+    pub fn special(&self, source: &Path) -> Result<Option<Stat>> {
+        if !self.devices {
+            return Ok(None);
+        }
+        let stat = Stat::read(source, self.fake_super)?;
+        Ok(stat.is_special().then_some(stat))
+    }
+
+    // Без root устройство с --fake-super становится пустым файлом, а тип и номер устройства
+    // записываются в xattrs вместе с остальным
+    pub fn create_special(&self, stat: &Stat, destination: &Path) -> Result<()> {
+        let _ = fs::remove_file(destination);
+        if self.fake_super && !self.root {
+            File::create(destination)
+                .with_context(|| format!("Failed to create destination file: {}", destination.display()))?;
+            return Ok(());
+        }
+        let path = CString::new(destination.as_os_str().as_bytes())?;
+        if unsafe { libc::mknod(path.as_ptr(), stat.mode, stat.rdev) } != 0 {
+            let error = io::Error::last_os_error();
+            let hint = match error.kind() {
+                io::ErrorKind::PermissionDenied if stat.is_device() => " (needs root, or --fake-super)",
+                _ => "",
+            };
+            return Err(error).with_context(|| format!("Failed to create {}{}", destination.display(), hint));
+        }
+        Ok(())
+    }
+
+    pub fn apply<'a>(&self, source: &Path, destinations: impl IntoIterator<Item = &'a Path>) -> Result<()> {
+        let stat = self.source_stat(source)?;
+        let flags = match self.flags {
+            true => read_flags(source, self.fake_super),
+            false => 0,
+        };
+        let birthtime = match self.birthtime {
+            true => read_birthtime(source, self.fake_super),
+            false => None,
+        };
+        let accessed = self.source_atime(source);
+        let (user, group) = match &self.ownership {
+            Some(ownership) => ownership.owner(stat.as_ref()),
+            None => (None, None),
+        };
+        for destination in destinations {
+            let current = fs::symlink_metadata(destination).ok();
+            let is_symlink = current.as_ref().is_some_and(|metadata| metadata.is_symlink());
+            let mode = self.mode_of(stat.as_ref(), current.as_ref());
+            if let Some(created) = birthtime.filter(|_| current.as_ref().is_some_and(|metadata| metadata.is_file())) {
+                set_birthtime(destination, created, self.fake_super)?;
+            }
+            if let Some(accessed) = accessed.filter(|_| !is_symlink) {
+                set_atime(destination, accessed)
+                    .with_context(|| format!("Failed to set access time of {}", destination.display()))?;
+            }
+            if self.fake_super && !self.root {
+                // У ссылок нет пользовательских xattrs; владелец ссылки ни на что не влияет
+                if !is_symlink {
+                    self.stash(destination, user, group, stat.as_ref(), mode)?;
+                    if flags != 0 {
+                        set_xattr(destination, FLAGS_XATTR, &format!("{:x}", flags))?;
+                    }
+                }
+                // Свои файлы должны остаться доступными владельцу, каталоги - еще и проходимыми
+                if let Some(mode) = mode.filter(|_| !is_symlink) {
+                    let owner = match current.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+                        true => 0o700,
+                        false => 0o600,
+                    };
+                    set_mode(destination, (mode & 0o777) | owner)?;
+                }
+                continue;
+            }
+            if user.is_some() || group.is_some() {
+                lchown(destination, user, group)
+                    .with_context(|| format!("Failed to change owner of {}", destination.display()))?;
+            }
+            // После chown: смена владельца сбрасывает setuid и setgid
+            if let Some(mode) = mode.filter(|_| !is_symlink) {
+                set_mode(destination, mode)?;
+            }
+            if self.flags && current.as_ref().is_some_and(|metadata| metadata.is_file() || metadata.is_dir()) {
+                set_flags(destination, flags & !SEALING_FLAGS)?;
+                if flags & SEALING_FLAGS != 0 {
+                    self.sealed.lock().unwrap().push((destination.to_path_buf(), flags));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Повторное копирование поверх копий с immutable и append-only: флаги снимаются, чтобы
+    // их можно было перезаписать, и ставятся снова в seal
+    pub fn unseal<'a>(&self, destinations: impl IntoIterator<Item = &'a Path>) -> Result<()> {
+        if !self.flags {
+            return Ok(());
+        }
+        for destination in destinations {
+            if let Ok(flags) = open_flags(destination).and_then(|file| get_flags(&file))
+                && flags & SEALING_FLAGS != 0
+            {
+                set_flags(destination, flags & PRESERVED_FLAGS & !SEALING_FLAGS)?;
+            }
+        }
+        Ok(())
+    }
+
+    // immutable и append-only - в самом конце копирования, после связывания дубликатов и
+    // дедупликации блоков
+    pub fn seal(&self) -> Result<()> {
+        for (path, flags) in self.sealed.lock().unwrap().drain(..) {
+            set_flags(&path, flags)?;
+        }
+        Ok(())
+    }
+
+    // Права копии: права источника (--preserve mode), --dest-mode/--dest-dir-mode или уже
+    // выставленные, затем --chmod
+    fn mode_of(&self, source: Option<&Stat>, current: Option<&fs::Metadata>) -> Option<u32> {
+        let is_dir = match (source, current) {
+            (Some(stat), _) => stat.file_type() == libc::S_IFDIR,
+            (None, current) => current?.is_dir(),
+        };
+        let default = match is_dir {
+            true => self.dir_mode,
+            false => self.file_mode,
+        };
+        let mode = match (source.filter(|_| self.mode), default) {
+            (Some(stat), _) => stat.mode & 0o7777,
+            (None, Some(mode)) => mode,
+            (None, None) if !self.chmod.is_empty() => current?.mode() & 0o7777,
+            (None, None) => return None,
+        };
+        Some(apply_chmod(&self.chmod, mode, is_dir))
+    }
+
+    fn stash(
+        &self,
+        destination: &Path,
+        user: Option<u32>,
+        group: Option<u32>,
+        stat: Option<&Stat>,
+        mode: Option<u32>,
+    ) -> Result<()> {
+        let ownership = self.ownership.as_ref();
+        if let Some(user) = user {
+            set_xattr(destination, UID_XATTR, &user.to_string())?;
+            if let Some(name) = ownership.and_then(|ownership| ownership.name(IdKind::User, user)) {
+                set_xattr(destination, USER_XATTR, &name)?;
+            }
+        }
+        if let Some(group) = group {
+            set_xattr(destination, GID_XATTR, &group.to_string())?;
+            if let Some(name) = ownership.and_then(|ownership| ownership.name(IdKind::Group, group)) {
+                set_xattr(destination, GROUP_XATTR, &name)?;
+            }
+        }
+        if let Some(stat) = stat.filter(|stat| mode.is_some() || stat.is_special()) {
+            let mode = stat.file_type() | mode.unwrap_or(0o644);
+            set_xattr(destination, MODE_XATTR, &format!("{:o}", mode))?;
+            if stat.is_device() {
+                let rdev = format!("{},{}", libc::major(stat.rdev), libc::minor(stat.rdev));
+                set_xattr(destination, RDEV_XATTR, &rdev)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions: {}", path.display()))
+}
+
+// Флаги источника; у ссылок, специальных файлов и на файловых системах без флагов - 0
+fn read_flags(path: &Path, fake_super: bool) -> u32 {
+    if fake_super && let Some(flags) = get_xattr(path, FLAGS_XATTR).and_then(|value| u32::from_str_radix(&value, 16).ok()) {
+        return flags & PRESERVED_FLAGS;
+    }
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() || metadata.is_dir() => {}
+        _ => return 0,
+    }
+    open_flags(path).and_then(|file| get_flags(&file)).map_or(0, |flags| flags & PRESERVED_FLAGS)
+}
+
+fn open_flags(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+        .open(path)
+}
+
+fn get_flags(file: &File) -> io::Result<u32> {
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags as u32)
+}
+
+// Переносимые флаги копии становятся как у источника, остальные не трогаются
+fn set_flags(path: &Path, flags: u32) -> Result<()> {
+    let failed = || format!("Failed to set flags on {}", path.display());
+    let file = open_flags(path).with_context(failed)?;
+    let current = match get_flags(&file) {
+        Ok(current) => current,
+        // Файловая система без флагов: переносить нечего
+        Err(_) if flags == 0 => return Ok(()),
+        Err(error) => return Err(error).with_context(failed),
+    };
+    let wanted = (current & !PRESERVED_FLAGS) | flags;
+    if wanted == current {
+        return Ok(());
+    }
+    let wanted = wanted as libc::c_int;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &wanted) } != 0 {
+        return Err(io::Error::last_os_error()).with_context(failed);
+    }
+    Ok(())
+}
+
+// Только atime: время изменения остается как есть
+fn set_atime(path: &Path, accessed: SystemTime) -> io::Result<()> {
+    let since_epoch = accessed.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let times = [
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+        },
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+    ];
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Источник читается с O_NOATIME, чтобы резервное копирование не меняло время доступа всего
+// дерева; ядро разрешает его только владельцу файла и root
+pub fn open_source(path: impl AsRef<Path>) -> io::Result<File> {
+    let path = path.as_ref();
+    match fs::OpenOptions::new().read(true).custom_flags(libc::O_NOATIME).open(path) {
+        Err(error) if error.raw_os_error() == Some(libc::EPERM) => File::open(path),
+        result => result,
+    }
+}
+
+// С --fake-super время из xattr важнее: на Linux его больше негде хранить
+fn read_birthtime(path: &Path, fake_super: bool) -> Option<SystemTime> {
+    if fake_super
+        && let Some((seconds, nanos)) = get_xattr(path, BIRTHTIME_XATTR).and_then(|value| {
+            let (seconds, nanos) = value.split_once('.')?;
+            Some((seconds.parse().ok()?, nanos.parse().ok()?))
+        })
+    {
+        return Some(SystemTime::UNIX_EPOCH + Duration::new(seconds, nanos));
+    }
+    fs::symlink_metadata(path).ok()?.created().ok()
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn set_birthtime(path: &Path, created: SystemTime, _fake_super: bool) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::FileTimesExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileTimesExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_times(fs::FileTimes::new().set_created(created)))
+        .with_context(|| format!("Failed to set creation time of {}", path.display()))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn set_birthtime(path: &Path, created: SystemTime, fake_super: bool) -> Result<()> {
+    if !fake_super {
+        return Ok(());
+    }
+    let since_epoch = created.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let value = format!("{}.{:09}", since_epoch.as_secs(), since_epoch.subsec_nanos());
+    set_xattr(path, BIRTHTIME_XATTR, &value)
+}
+
+// Сначала размер значения; если между вызовами оно выросло (ERANGE) - спрашиваем заново
+fn get_xattr(path: &Path, name: &str) -> Option<String> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let name = CString::new(name).ok()?;
+    loop {
+        let size = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return None;
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let length = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len()) };
+        if length < 0 {
+            match io::Error::last_os_error().raw_os_error() {
+                Some(libc::ERANGE) => continue,
+                _ => return None,
+            }
+        }
+        buffer.truncate(length as usize);
+        return String::from_utf8(buffer).ok();
+    }
+}
+
+fn set_xattr(path: &Path, name: &str, value: &str) -> Result<()> {
+    let failed = || format!("Failed to set {} on {}", name, path.display());
+    let c_path = CString::new(path.as_os_str().as_bytes()).with_context(failed)?;
+    let c_name = CString::new(name).with_context(failed)?;
+    if unsafe { libc::setxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr().cast(), value.len(), 0) } != 0 {
+        return Err(io::Error::last_os_error()).with_context(failed);
+    }
+    Ok(())
+}
+
+// Для обхода источника: такие файлы копируются только с --preserve devices
+pub fn is_special_file(metadata: &fs::Metadata) -> bool {
+    let file_type = metadata.file_type();
+    file_type.is_char_device() || file_type.is_block_device() || file_type.is_fifo() || file_type.is_socket()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_xattr_values_are_read_whole() {
+        let path = std::env::temp_dir().join(format!("rcp-metadata-xattr-{}", std::process::id()));
+        fs::write(&path, b"").unwrap();
+        // Имя владельца длиннее прежнего буфера в 64 байта
+        let user = "u".repeat(200);
+        set_xattr(&path, USER_XATTR, &user).unwrap();
+        let value = get_xattr(&path, USER_XATTR);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(value.as_deref(), Some(user.as_str()));
+    }
+}
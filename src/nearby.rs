@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use mdns_sd::{ResolvedService, ServiceDaemon, ServiceEvent, ServiceInfo};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::ffi::{CStr, OsString};
+use std::io::{self, BufRead, Write};
+use std::net::{IpAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cli::{Args, ReceiveArgs, SendArgs};
+use crate::i18n::t;
+use crate::net::{accept_all, receive_from_peer};
+use crate::tls::{Psk, ServerTls};
+
+// `rcp receive` объявляет себя в локальной сети через mDNS (DNS-SD), `rcp send` находит
+// получателя и передает файлы по протоколу `rcp serve` поверх TLS. Сертификат получателя
+// временный, а вместо него стороны подтверждают короткий код, который получатель показывает,
+// а отправитель вводит. Код - пароль SPAKE2 внутри сессии TLS, и общий ключ вырабатывается
+// из обмена: перехваченное у поддельного получателя нельзя перебрать офлайн
+
+const SERVICE_TYPE: &str = "_rcp._tcp.local.";
+const CODE_DIGITS: usize = 6;
+
+pub fn receive(args: &ReceiveArgs) -> Result<()> {
+    if !args.directory.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", args.directory.display());
+    }
+    let code = new_code()?;
+    let tls = Arc::new(ServerTls::ephemeral(Some(Psk::from_code(&code)))?);
+    let listener = TcpListener::bind(&args.listen).with_context(|| format!("Failed to listen on {}", args.listen))?;
+    let port = listener.local_addr().context("Failed to get listening address")?.port();
+
+    let host = host_name();
+    let name = args.name.clone().unwrap_or_else(|| host.clone());
+    let daemon = ServiceDaemon::new().context("Failed to start mDNS")?;
+    let service = ServiceInfo::new(SERVICE_TYPE, &name, &format!("{}.local.", host), "", port, None)
+        .context("Failed to describe the mDNS service")?
+        .enable_addr_auto();
+    daemon.register(service).context("Failed to announce rcp receive over mDNS")?;
+
+    println!(
+        "{}",
+        t!("receive-waiting", directory = args.directory.display().to_string(), name = name.as_str(), port = port)
+    );
+    println!("{}", t!("confirmation-code", code = code.bold().to_string()));
+
+    let result = receive_from_peer(vec![accept_all(listener)], &args.directory, tls);
+    let _ = daemon.shutdown();
+    let peer = result?;
+    println!("{}", t!("received-from", peer = peer.to_string()).green());
+    Ok(())
+}
+
+// Находим получателя, спрашиваем код и превращаем `rcp send` в обычное копирование на rcp://
+pub fn send(args: &SendArgs) -> Result<Args> {
+    let peer = discover(args.to.as_deref(), Duration::from_secs(args.discover_timeout))?;
+    let name = instance_name(&peer);
+    let address = pick_address(&peer).with_context(|| format!("Receiver {} has no usable address", name))?;
+
+    let code = match &args.code {
+        Some(code) => code.clone(),
+        None => prompt(&t!("confirmation-code-prompt", name = name.as_str()))?,
+    };
+    if code.trim().is_empty() {
+        anyhow::bail!("No confirmation code given");
+    }
+
+    let destination = match address {
+        IpAddr::V4(ip) => format!("rcp://{}:{}/", ip, peer.get_port()),
+        IpAddr::V6(ip) => format!("rcp://[{}]:{}/", ip, peer.get_port()),
+    };
+    println!("{}", t!("sending-to", name = name.as_str(), destination = destination.as_str()));
+    Ok(copy_to_peer(&args.sources, &destination, Psk::from_code(&code)))
+}
+
+// Обычные аргументы копирования на rcp:// получателя с ключом, которого нет в файле
+pub fn copy_to_peer(sources: &[PathBuf], destination: &str, psk: Psk) -> Args {
+    let mut args = Args::parse_from(
+        std::iter::once(OsString::from("rcp"))
+            .chain(sources.iter().map(OsString::from))
+            .chain(std::iter::once(OsString::from(destination))),
+    );
+    args.pairing = Some(psk);
+    args
+}
+
+// Ждем ответов в течение timeout; с --to - до первого получателя с этим именем
+fn discover(to: Option<&str>, timeout: Duration) -> Result<ResolvedService> {
+    let daemon = ServiceDaemon::new().context("Failed to start mDNS")?;
+    let events = daemon.browse(SERVICE_TYPE).context("Failed to search for receivers over mDNS")?;
+    let deadline = Instant::now() + timeout;
+    let mut found: Vec<ResolvedService> = Vec::new();
+    while let Some(left) = deadline.checked_duration_since(Instant::now())
+        && let Ok(event) = events.recv_timeout(left)
+    {
+        if let ServiceEvent::ServiceResolved(service) = event
+            && !found.iter().any(|known| known.get_fullname() == service.get_fullname())
+        {
+            if to.is_some_and(|to| instance_name(&service) == to) {
+                found = vec![*service];
+                break;
+            }
+            found.push(*service);
+        }
+    }
+    let _ = daemon.shutdown();
+
+    if let Some(to) = to {
+        found.retain(|service| instance_name(service) == to);
+        return found.pop().with_context(|| format!("Receiver \"{}\" not found on the local network", to));
+    }
+    match found.len() {
+        0 => anyhow::bail!("No receivers found on the local network; run `rcp receive` on the other machine"),
+        1 => Ok(found.remove(0)),
+        _ => {
+            for (i, service) in found.iter().enumerate() {
+                println!("{:>3}. {}", i + 1, instance_name(service));
+            }
+            let choice = prompt(&t!("send-to-prompt"))?;
+            let index = choice
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&index| (1..=found.len()).contains(&index))
+                .with_context(|| format!("Invalid choice: {}", choice.trim()))?;
+            Ok(found.remove(index - 1))
+        }
+    }
+}
+
+// `имя._rcp._tcp.local.` -> `имя`
+fn instance_name(service: &ResolvedService) -> String {
+    let fullname = service.get_fullname();
+    fullname
+        .strip_suffix(SERVICE_TYPE)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(fullname)
+        .replace("\\.", ".")
+}
+
+// Предпочитаем IPv4: адреса IPv6 link-local без зоны недостижимы
+fn pick_address(service: &ResolvedService) -> Option<IpAddr> {
+    let mut addresses: Vec<IpAddr> = service.get_addresses().iter().map(|ip| ip.to_ip_addr()).collect();
+    addresses.sort_by_key(|ip| (ip.is_loopback(), !ip.is_ipv4()));
+    addresses.into_iter().next()
+}
+
+pub fn prompt(question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).context("Failed to read from stdin")? == 0 {
+        anyhow::bail!("No answer on stdin");
+    }
+    Ok(answer.trim().to_string())
+}
+
+// `123 456`: цифры случайные, пробел - только для чтения вслух
+fn new_code() -> Result<String> {
+    let mut bytes = [0u8; 8];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate a confirmation code"))?;
+    let number = u64::from_le_bytes(bytes) % 10u64.pow(CODE_DIGITS as u32);
+    let digits = format!("{:0width$}", number, width = CODE_DIGITS);
+    Ok(format!("{} {}", &digits[..CODE_DIGITS / 2], &digits[CODE_DIGITS / 2..]))
+}
+
+fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    let name = match result {
+        0 => CStr::from_bytes_until_nul(&buffer).map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        _ => String::new(),
+    };
+    match name.split('.').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => "rcp".to_string(),
+    }
+}
@@ -0,0 +1,1677 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+use rustls::{ClientConnection, ServerConnection, StreamOwned};
+
+use crate::cli::ServeArgs;
+use crate::compression::is_compressed_format;
+use crate::extract::{join_inside, link_stays_inside, sanitize_entry_path};
+use crate::i18n::t;
+use crate::metadata::open_source;
+use crate::quic::{QuicClient, QuicServer, QuicStream};
+use crate::tls::{ClientTls, Psk, ServerTls, EXPORTER_LABEL};
+use crate::transport::{Progress, Sink, SinkFile};
+use crate::BUFFER_SIZE;
+
+// Собственный протокол `rcp serve`: кадры `тип (1 байт) + длина (4 байта BE) + данные`
+// поверх TCP или потоков QUIC. На каждую команду клиента сервер отвечает OK или ERROR; данные файла
+// идут кадрами DATA без ответов, последний кадр END несет BLAKE3 переданных байт.
+// С TLS кадры те же, а при общем ключе сразу после HELLO идет AUTH. Большие файлы
+// передаются частями (RANGE) по нескольким соединениям сразу, а COMMIT с BLAKE3 всего файла
+// завершает их. Если клиент просит
+// в HELLO сжатие и сервер его подтверждает, данные могут идти кадрами DATA_ZSTD.
+// Для дельта-передачи клиент запрашивает хеши блоков файла на сервере (SIGNATURES)
+// и шлет PATCH, за которым идут только изменившиеся блоки (BLOCK). Файлы сервер
+// принимает в `.ИМЯ.rcp-part` рядом с целевым; после обрыва клиент запрашивает хеши
+// блоков недокачанного файла (RESUME) и продолжает с конца совпавшего начала (APPEND)
+
+pub const DEFAULT_PORT: u16 = 7300;
+const PROTOCOL: &[u8] = b"rcp/1";
+
+const FRAME_HELLO: u8 = 1;
+const FRAME_OK: u8 = 2;
+const FRAME_ERROR: u8 = 3;
+const FRAME_STAT: u8 = 4;
+const FRAME_DIRECTORY: u8 = 5;
+const FRAME_SYMLINK: u8 = 6;
+const FRAME_FILE: u8 = 7;
+const FRAME_DATA: u8 = 8;
+const FRAME_END: u8 = 9;
+const FRAME_AUTH: u8 = 10;
+const FRAME_RANGE: u8 = 11;
+const FRAME_DATA_ZSTD: u8 = 12;
+const FRAME_SIGNATURES: u8 = 13;
+const FRAME_PATCH: u8 = 14;
+const FRAME_BLOCK: u8 = 15;
+const FRAME_RESUME: u8 = 16;
+const FRAME_APPEND: u8 = 17;
+const FRAME_PAKE: u8 = 18;
+const FRAME_COMMIT: u8 = 19;
+
+// Обрыв соединения посреди файла: столько раз переподключаемся и продолжаем
+const RESUME_ATTEMPTS: u32 = 5;
+const RESUME_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Блоки дельта-передачи: не больше DELTA_MAX_BLOCKS на файл, чтобы список хешей
+// помещался в один кадр
+const DELTA_MIN_BLOCK: u64 = 64 * 1024;
+const DELTA_MAX_BLOCK: u64 = 8 * 1024 * 1024;
+const DELTA_MAX_BLOCKS: u64 = 65536;
+
+// Возможность протокола, о которой договариваются в HELLO
+const FEATURE_ZSTD: &[u8] = b"zstd";
+const WIRE_COMPRESSION_LEVEL: i32 = 3;
+
+// Файлы от этого размера передаются частями параллельно (--streams-per-file)
+pub const PARALLEL_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+// Защита от мусора в потоке: кадры данных не больше BUFFER_SIZE
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+// Ответ на STAT
+const KIND_MISSING: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_DIRECTORY: u8 = 2;
+
+// Назначение `rcp://host[:port]/path`; путь отсчитывается от корня сервера
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetLocation {
+    pub address: String,
+    pub path: PathBuf,
+}
+
+impl NetLocation {
+    pub fn parse(location: &Path) -> Option<Self> {
+        let rest = location.to_str()?.strip_prefix("rcp://")?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if authority.is_empty() {
+            return None;
+        }
+
+        // `[::1]` - адрес IPv6 без порта
+        let has_port = authority.rsplit_once(':').is_some_and(|(_, port)| !port.contains(']'));
+        let address = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:{}", authority, DEFAULT_PORT)
+        };
+        Some(NetLocation {
+            address,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+pub fn write_frame(output: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = [0; 5];
+    header[0] = kind;
+    header[1..].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    output.write_all(&header)?;
+    output.write_all(payload)
+}
+
+// None - соединение закрыто между кадрами
+pub fn read_frame(input: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; 5];
+    if input.read(&mut header[..1])? == 0 {
+        return Ok(None);
+    }
+    input.read_exact(&mut header[1..])?;
+
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame too large: {} bytes", len)));
+    }
+    let mut payload = vec![0; len];
+    input.read_exact(&mut payload)?;
+    Ok(Some((header[0], payload)))
+}
+
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+// Соединение - TCP, TLS поверх него (со стороны клиента или сервера) или поток QUIC
+enum Transport {
+    Plain(TcpStream),
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+    Quic(Box<QuicStream>),
+}
+
+impl Transport {
+    // Материал TLS-сессии, к которому привязывается подтверждение общего ключа
+    fn exporter(&self) -> Result<[u8; 32]> {
+        let exporter = match self {
+            Transport::Plain(_) => anyhow::bail!("PSK authentication requires TLS"),
+            Transport::TlsClient(stream) => stream.conn.export_keying_material([0; 32], EXPORTER_LABEL, None),
+            Transport::TlsServer(stream) => stream.conn.export_keying_material([0; 32], EXPORTER_LABEL, None),
+            Transport::Quic(stream) => return stream.exporter(),
+        };
+        exporter.context("Failed to derive TLS session key material")
+    }
+}
+
+// Сессия TLS закрывается явно, иначе другая сторона примет разрыв за обрыв соединения
+impl Drop for Transport {
+    fn drop(&mut self) {
+        match self {
+            Transport::Plain(_) | Transport::Quic(_) => {}
+            Transport::TlsClient(stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+            Transport::TlsServer(stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::TlsClient(stream) => stream.read(buf),
+            Transport::TlsServer(stream) => stream.read(buf),
+            Transport::Quic(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::TlsClient(stream) => stream.write(buf),
+            Transport::TlsServer(stream) => stream.write(buf),
+            Transport::Quic(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::TlsClient(stream) => stream.flush(),
+            Transport::TlsServer(stream) => stream.flush(),
+            Transport::Quic(stream) => stream.flush(),
+        }
+    }
+}
+
+// Кадры поверх соединения: чтение буферизуется, запись копится до flush()
+struct FramedStream {
+    input: BufReader<Transport>,
+    output: Vec<u8>,
+}
+
+impl FramedStream {
+    fn new(transport: Transport) -> Self {
+        FramedStream {
+            input: BufReader::new(transport),
+            output: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        write_frame(&mut self.output, kind, payload)?;
+        if self.output.len() >= BUFFER_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        read_frame(&mut self.input)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let transport = self.input.get_mut();
+        transport.write_all(&self.output)?;
+        self.output.clear();
+        transport.flush()
+    }
+}
+
+// Соединение клиента; после ошибки ввода-вывода оно больше не используется
+struct Connection {
+    stream: FramedStream,
+    // Сервер согласился принимать сжатые данные
+    compress: bool,
+}
+
+impl Connection {
+    fn open(address: &str, tls: Option<&ClientTls>, quic: Option<&QuicClient>, compress: bool) -> Result<Self> {
+        let transport = match quic {
+            Some(quic) => Transport::Quic(Box::new(quic.open_stream()?)),
+            None => {
+                let stream = TcpStream::connect(address)
+                    .with_context(|| format!("Failed to connect to rcp server {}", address))?;
+                let _ = stream.set_nodelay(true);
+                match tls {
+                    Some(tls) => Transport::TlsClient(Box::new(tls.connect(address, stream)?)),
+                    None => Transport::Plain(stream),
+                }
+            }
+        };
+        let mut connection = Connection {
+            stream: FramedStream::new(transport),
+            compress: false,
+        };
+
+        let mut hello = PROTOCOL.to_vec();
+        if compress {
+            hello.push(b' ');
+            hello.extend_from_slice(FEATURE_ZSTD);
+        }
+        let features = connection
+            .call(FRAME_HELLO, &hello)
+            .with_context(|| format!("Failed to start session with rcp server {}", address))?
+            .map_err(|message| anyhow::anyhow!("rcp server {} refused the session: {}", address, message))?;
+        connection.compress = compress && features.split(|&byte| byte == b' ').any(|feature| feature == FEATURE_ZSTD);
+
+        if let Some(psk) = tls.and_then(|tls| tls.psk.as_ref()) {
+            let exporter = connection.stream.input.get_ref().exporter()?;
+            let psk = psk.session_key(&exporter, |message| {
+                connection
+                    .call(FRAME_PAKE, message)
+                    .with_context(|| format!("Failed to authenticate with rcp server {}", address))?
+                    .map_err(|message| anyhow::anyhow!("rcp server {} rejected the key exchange: {}", address, message))
+            })?;
+            let reply = connection
+                .call(FRAME_AUTH, &psk.tag(b"client", &exporter))
+                .with_context(|| format!("Failed to authenticate with rcp server {}", address))?
+                .map_err(|message| anyhow::anyhow!("rcp server {} rejected the PSK: {}", address, message))?;
+            if !tags_match(&reply, &psk.tag(b"server", &exporter)) {
+                anyhow::bail!("rcp server {} failed PSK authentication", address);
+            }
+        }
+        Ok(connection)
+    }
+
+    fn send(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        self.stream.send(kind, payload)
+    }
+
+    // Внешняя ошибка - сбой соединения, внутренняя - отказ сервера выполнить команду
+    fn reply(&mut self) -> io::Result<Result<Vec<u8>, String>> {
+        self.stream.flush()?;
+        match self.stream.receive()? {
+            Some((FRAME_OK, payload)) => Ok(Ok(payload)),
+            Some((FRAME_ERROR, message)) => Ok(Err(String::from_utf8_lossy(&message).into_owned())),
+            Some((kind, _)) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected frame {}", kind))),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn call(&mut self, kind: u8, payload: &[u8]) -> io::Result<Result<Vec<u8>, String>> {
+        self.send(kind, payload)?;
+        self.reply()
+    }
+}
+
+// Хеши блоков файла на сервере; ответ начинается с размера файла, чтобы пустой
+// файл отличался от отсутствующего
+pub struct Signatures {
+    block_size: u64,
+    hashes: Vec<blake3::Hash>,
+}
+
+impl Signatures {
+    fn parse(reply: &[u8], block_size: u64) -> Option<Self> {
+        let (_size, hashes) = reply.split_at_checked(8)?;
+        if hashes.len() % 32 != 0 {
+            return None;
+        }
+        Some(Signatures {
+            block_size,
+            hashes: hashes
+                .chunks_exact(32)
+                .map(|hash| blake3::Hash::from_bytes(hash.try_into().unwrap()))
+                .collect(),
+        })
+    }
+}
+
+fn delta_block_size(size: u64) -> u64 {
+    (size / DELTA_MAX_BLOCKS)
+        .next_power_of_two()
+        .clamp(DELTA_MIN_BLOCK, DELTA_MAX_BLOCK)
+}
+
+// Читает блок целиком, если файл не кончился раньше
+fn read_block(input: &mut impl Read, block: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match input.read(&mut block[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+// Сравнение MAC без утечки по времени
+pub fn tags_match(actual: &[u8], expected: &[u8; 32]) -> bool {
+    <[u8; 32]>::try_from(actual).is_ok_and(|actual| blake3::Hash::from_bytes(actual) == blake3::Hash::from_bytes(*expected))
+}
+
+// Сессия с сервером rcp. Каждый рабочий поток берет себе свободное соединение
+// (или открывает новое), поэтому файлы передаются параллельно. Поверх QUIC
+// соединения - это потоки одного соединения QUIC
+#[derive(Clone)]
+pub struct NetSession {
+    address: String,
+    tls: Option<ClientTls>,
+    quic: Option<Arc<QuicClient>>,
+    compress: bool,
+    // Передавать только изменившиеся блоки существующих файлов (--delta)
+    delta: bool,
+    idle: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl NetSession {
+    // QUIC без TLS не бывает: без --tls-ca и --psk-file сертификат сервера не проверяется
+    pub fn connect(address: &str, tls: Option<ClientTls>, use_quic: bool, compress: bool, delta: bool) -> Result<Self> {
+        let quic = if use_quic {
+            let quic_tls = match &tls {
+                Some(tls) => tls.clone(),
+                None => ClientTls::unverified()?,
+            };
+            Some(Arc::new(QuicClient::connect(address, &quic_tls)?))
+        } else {
+            None
+        };
+        let connection = Connection::open(address, tls.as_ref(), quic.as_deref(), compress)?;
+        Ok(NetSession {
+            address: address.to_string(),
+            tls,
+            quic,
+            compress,
+            delta,
+            idle: Arc::new(Mutex::new(vec![connection])),
+        })
+    }
+
+    fn take(&self) -> Result<Connection> {
+        if let Some(connection) = self.idle.lock().unwrap().pop() {
+            return Ok(connection);
+        }
+        Connection::open(&self.address, self.tls.as_ref(), self.quic.as_deref(), self.compress)
+    }
+
+    fn give_back(&self, connection: Connection) {
+        self.idle.lock().unwrap().push(connection);
+    }
+
+    fn call(&self, kind: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut connection = self.take()?;
+        let reply = connection
+            .call(kind, payload)
+            .with_context(|| format!("Connection to rcp server {} failed", self.address))?;
+        self.give_back(connection);
+        reply.map_err(|message| anyhow::anyhow!(message))
+    }
+
+    pub fn is_dir(&self, path: &Path) -> Result<bool> {
+        let reply = self
+            .call(FRAME_STAT, path_bytes(path))
+            .with_context(|| format!("Failed to stat remote path: {}", path.display()))?;
+        Ok(reply.first() == Some(&KIND_DIRECTORY))
+    }
+
+    pub fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.call(FRAME_DIRECTORY, path_bytes(path))
+            .with_context(|| format!("Failed to create remote directory: {}", path.display()))?;
+        Ok(())
+    }
+
+    // Существующий файл на месте ссылки сервер заменяет
+    pub fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        let target = path_bytes(target);
+        let mut payload = Vec::with_capacity(4 + target.len() + link.as_os_str().len());
+        payload.extend_from_slice(&(target.len() as u32).to_be_bytes());
+        payload.extend_from_slice(target);
+        payload.extend_from_slice(path_bytes(link));
+        self.call(FRAME_SYMLINK, &payload)
+            .with_context(|| format!("Failed to create remote symlink: {}", link.display()))?;
+        Ok(())
+    }
+
+    pub fn create(&self, path: &Path) -> Result<NetFile> {
+        self.open_file(FRAME_FILE, path_bytes(path), path)
+    }
+
+    // Часть файла с offset: сервер не обрезает файл, а доводит его до полного размера
+    fn create_range(&self, path: &Path, offset: u64, total: u64) -> Result<NetFile> {
+        let mut payload = Vec::with_capacity(16 + path.as_os_str().len());
+        payload.extend_from_slice(&offset.to_be_bytes());
+        payload.extend_from_slice(&total.to_be_bytes());
+        payload.extend_from_slice(path_bytes(path));
+        self.open_file(FRAME_RANGE, &payload, path)
+    }
+
+    // Все части на месте: сервер сверяет собранный файл с суммой источника и только тогда
+    // ставит его на место целевого
+    fn commit_ranges(&self, path: &Path, total: u64, digest: &blake3::Hash) -> Result<()> {
+        let mut payload = Vec::with_capacity(40 + path.as_os_str().len());
+        payload.extend_from_slice(&total.to_be_bytes());
+        payload.extend_from_slice(digest.as_bytes());
+        payload.extend_from_slice(path_bytes(path));
+        self.call(FRAME_COMMIT, &payload).map(drop)
+    }
+
+    // None - на сервере нет такого файла, сравнивать не с чем
+    pub fn signatures(&self, path: &Path, size: u64) -> Result<Option<Signatures>> {
+        let block_size = delta_block_size(size);
+        let reply = self
+            .call(FRAME_SIGNATURES, &signatures_request(path, block_size))
+            .with_context(|| format!("Failed to get block checksums of remote file: {}", path.display()))?;
+        self.parse_signatures(&reply, block_size)
+    }
+
+    // Есть ли на сервере недокачанный файл, который можно продолжить
+    pub fn has_partial(&self, path: &Path) -> Result<bool> {
+        let reply = self
+            .call(FRAME_RESUME, &signatures_request(path, DELTA_MAX_BLOCK))
+            .with_context(|| format!("Failed to check partial remote file: {}", path.display()))?;
+        Ok(!reply.is_empty())
+    }
+
+    fn parse_signatures(&self, reply: &[u8], block_size: u64) -> Result<Option<Signatures>> {
+        if reply.is_empty() {
+            return Ok(None);
+        }
+        Signatures::parse(reply, block_size)
+            .map(Some)
+            .with_context(|| format!("Malformed block checksums from rcp server {}", self.address))
+    }
+
+    // Передача, которая переживает обрыв соединения: после него переподключаемся и
+    // продолжаем с того, что сервер уже сохранил. on_progress получает число переданных байт
+    pub fn send_resumable(
+        &self,
+        source: &Path,
+        destination: &Path,
+        size: u64,
+        mut on_progress: impl FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.try_send_resumable(source, destination, size, &mut on_progress) {
+                Ok(result) => return result,
+                Err(e) if attempt < RESUME_ATTEMPTS => {
+                    attempt += 1;
+                    eprintln!("{}", t!("resuming", error = format!("{:#}", e), path = destination.display().to_string()));
+                    thread::sleep(RESUME_DELAY);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Внешняя ошибка - сбой соединения (можно повторить), внутренняя - все остальное
+    fn try_send_resumable(
+        &self,
+        source: &Path,
+        destination: &Path,
+        size: u64,
+        on_progress: &mut impl FnMut(u64) -> Result<()>,
+    ) -> Result<Result<()>> {
+        let block_size = delta_block_size(size);
+        let mut connection = self.take()?;
+        let failed = || format!("Connection to rcp server {} failed", self.address);
+        let partial = match connection
+            .call(FRAME_RESUME, &signatures_request(destination, block_size))
+            .with_context(failed)?
+        {
+            Ok(reply) => self.parse_signatures(&reply, block_size),
+            Err(message) => Err(anyhow::anyhow!(message)),
+        };
+        let partial = match partial {
+            Ok(partial) => partial,
+            Err(e) => {
+                self.give_back(connection);
+                return Ok(Err(e));
+            }
+        };
+
+        let mut file = match open_source(source) {
+            Ok(file) => file,
+            Err(e) => {
+                self.give_back(connection);
+                return Ok(Err(anyhow::Error::new(e).context(format!("Failed to open source file: {}", source.display()))));
+            }
+        };
+        let read_failed = || format!("Failed to read file: {}", source.display());
+
+        // Начало, которое уже лежит на сервере, не передаем: только целые совпавшие блоки
+        let mut hasher = blake3::Hasher::new();
+        let mut offset = 0u64;
+        if let Some(partial) = partial {
+            let mut block = vec![0; block_size as usize];
+            for hash in &partial.hashes {
+                let read = match read_block(&mut file, &mut block).with_context(read_failed) {
+                    Ok(read) => read,
+                    Err(e) => return Ok(Err(e)),
+                };
+                if read as u64 != block_size || blake3::hash(&block) != *hash {
+                    break;
+                }
+                hasher.update(&block);
+                offset += block_size;
+            }
+            if let Err(e) = file.seek(io::SeekFrom::Start(offset)).with_context(read_failed) {
+                return Ok(Err(e));
+            }
+        }
+
+        let mut payload = offset.to_be_bytes().to_vec();
+        payload.extend_from_slice(path_bytes(destination));
+        connection.send(FRAME_APPEND, &payload).with_context(failed)?;
+        let mut remote = NetFile {
+            session: self.clone(),
+            compress: connection.compress && !is_compressed_format(destination),
+            connection,
+            hasher,
+            path: destination.to_path_buf(),
+        };
+
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            if let Err(e) = on_progress(offset) {
+                return Ok(Err(e));
+            }
+            let read = match file.read(&mut buffer).with_context(read_failed) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => return Ok(Err(e)),
+            };
+            remote.write_all(&buffer[..read]).with_context(failed)?;
+            offset += read as u64;
+        }
+        Ok(remote
+            .finish()
+            .with_context(failed)?
+            .map_err(|message| anyhow::anyhow!(message))
+            .with_context(|| format!("Failed to write remote file: {}", destination.display())))
+    }
+
+    // Файл читается блоками, и на сервер уходят только блоки, чей хеш не совпал;
+    // сервер затем сверяет BLAKE3 всего файла. on_progress получает число прочитанных байт
+    pub fn send_delta(
+        &self,
+        source: &Path,
+        destination: &Path,
+        size: u64,
+        signatures: &Signatures,
+        mut on_progress: impl FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
+        let mut file = open_source(source).with_context(|| format!("Failed to open source file: {}", source.display()))?;
+        let mut header = Vec::with_capacity(8 + destination.as_os_str().len());
+        header.extend_from_slice(&size.to_be_bytes());
+        header.extend_from_slice(path_bytes(destination));
+
+        let mut connection = self.take()?;
+        let failed = || format!("Connection to rcp server {} failed", self.address);
+        connection.send(FRAME_PATCH, &header).with_context(failed)?;
+
+        let mut hasher = blake3::Hasher::new();
+        let mut block = vec![0; signatures.block_size as usize];
+        let mut offset = 0u64;
+        for index in 0.. {
+            let read = read_block(&mut file, &mut block)
+                .with_context(|| format!("Failed to read file: {}", source.display()))?;
+            if read == 0 {
+                break;
+            }
+            let block = &block[..read];
+            hasher.update(block);
+            if signatures.hashes.get(index) != Some(&blake3::hash(block)) {
+                let mut position = offset;
+                for chunk in block.chunks(BUFFER_SIZE) {
+                    let mut frame = Vec::with_capacity(8 + chunk.len());
+                    frame.extend_from_slice(&position.to_be_bytes());
+                    frame.extend_from_slice(chunk);
+                    connection.send(FRAME_BLOCK, &frame).with_context(failed)?;
+                    position += chunk.len() as u64;
+                }
+            }
+            offset += read as u64;
+            on_progress(offset)?;
+        }
+        if offset != size {
+            anyhow::bail!("File changed size while copying: {}", source.display());
+        }
+
+        let reply = connection.call(FRAME_END, hasher.finalize().as_bytes()).with_context(failed)?;
+        self.give_back(connection);
+        reply
+            .map(drop)
+            .map_err(|message| anyhow::anyhow!(message))
+            .with_context(|| format!("Failed to write remote file: {}", destination.display()))
+    }
+
+    fn open_file(&self, kind: u8, payload: &[u8], path: &Path) -> Result<NetFile> {
+        let mut connection = self.take()?;
+        connection
+            .send(kind, payload)
+            .with_context(|| format!("Connection to rcp server {} failed", self.address))?;
+        Ok(NetFile {
+            session: self.clone(),
+            compress: connection.compress && !is_compressed_format(path),
+            connection,
+            hasher: blake3::Hasher::new(),
+            path: path.to_path_buf(),
+        })
+    }
+
+    // Большой локальный файл делится на части, и каждая идет по своему соединению: на каналах
+    // с большой задержкой одно соединение TCP не успевает заполнить полосу. on_progress
+    // получает общее число отправленных байт и может прервать передачу. Сумма всего файла
+    // для COMMIT считается в своем потоке, пока идут части
+    pub fn send_in_ranges(
+        &self,
+        source: &Path,
+        destination: &Path,
+        size: u64,
+        streams: usize,
+        on_progress: impl Fn(u64) -> Result<()> + Sync,
+    ) -> Result<()> {
+        let range_len = size.div_ceil(streams as u64);
+        let sent = AtomicU64::new(0);
+        let digest = thread::scope(|scope| {
+            let digest = scope.spawn(|| -> Result<blake3::Hash> {
+                let mut file =
+                    open_source(source).with_context(|| format!("Failed to open source file: {}", source.display()))?;
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read file: {}", source.display()))?;
+                Ok(hasher.finalize())
+            });
+            let handles: Vec<_> = (0..streams as u64)
+                .map(|i| (i * range_len, ((i + 1) * range_len).min(size)))
+                .filter(|(start, end)| start < end)
+                .map(|(start, end)| {
+                    let (sent, on_progress) = (&sent, &on_progress);
+                    scope.spawn(move || self.send_range(source, destination, start..end, size, sent, on_progress))
+                })
+                .collect();
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Transfer thread panicked")))
+            })?;
+            digest.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Transfer thread panicked")))
+        })?;
+        self.commit_ranges(destination, size, &digest)
+    }
+
+    fn send_range(
+        &self,
+        source: &Path,
+        destination: &Path,
+        range: std::ops::Range<u64>,
+        size: u64,
+        sent: &AtomicU64,
+        on_progress: &(impl Fn(u64) -> Result<()> + Sync),
+    ) -> Result<()> {
+        let file = open_source(source).with_context(|| format!("Failed to open source file: {}", source.display()))?;
+        let mut remote = self.create_range(destination, range.start, size)?;
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut offset = range.start;
+
+        while offset < range.end {
+            let len = ((range.end - offset) as usize).min(BUFFER_SIZE);
+            let read = file
+                .read_at(&mut buffer[..len], offset)
+                .with_context(|| format!("Failed to read file: {}", source.display()))?;
+            if read == 0 {
+                anyhow::bail!("File shrank while copying: {}", source.display());
+            }
+            remote
+                .write_all(&buffer[..read])
+                .with_context(|| format!("Connection to rcp server {} failed", self.address))?;
+            offset += read as u64;
+            on_progress(sent.fetch_add(read as u64, Ordering::Relaxed) + read as u64)?;
+        }
+        remote.close()
+    }
+}
+
+impl Sink for NetSession {
+    fn name(&self) -> String {
+        self.address.clone()
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        NetSession::is_dir(self, path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        NetSession::create_dir_all(self, path)
+    }
+
+    fn create(&self, path: &Path, _modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        Ok(Box::new(NetSession::create(self, path)?))
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        NetSession::symlink(self, target, link)
+    }
+
+    fn send_file(
+        &self,
+        source: &Path,
+        destination: &Path,
+        streams: usize,
+        on_start: &dyn Fn(u64),
+        on_progress: Progress,
+    ) -> Result<bool> {
+        let size = fs::metadata(source)
+            .with_context(|| format!("Failed to open source file: {}", source.display()))?
+            .len();
+        on_start(size);
+        if self.delta
+            && let Some(signatures) = self.signatures(destination, size)?
+        {
+            self.send_delta(source, destination, size, &signatures, on_progress)
+                .with_context(|| format!("Failed to write file: {}", destination.display()))?;
+        } else if streams > 1 && size >= PARALLEL_MIN_SIZE && !self.has_partial(destination)? {
+            // Недокачанный в прошлый раз файл продолжаем, а не начинаем заново частями
+            self.send_in_ranges(source, destination, size, streams, on_progress)
+                .with_context(|| format!("Failed to write file: {}", destination.display()))?;
+        } else {
+            self.send_resumable(source, destination, size, on_progress)?;
+        }
+        Ok(true)
+    }
+}
+
+// Файл на сервере rcp: занимает соединение до close()
+pub struct NetFile {
+    session: NetSession,
+    connection: Connection,
+    hasher: blake3::Hasher,
+    path: PathBuf,
+    // Сжимать данные; снимается, как только данные перестают сжиматься
+    compress: bool,
+}
+
+impl NetFile {
+    // Файл записан, только если сервер получил все данные и сумма совпала
+    pub fn close(self) -> Result<()> {
+        let address = self.session.address.clone();
+        let path = self.path.clone();
+        self.finish()
+            .with_context(|| format!("Connection to rcp server {} failed", address))?
+            .map_err(|message| anyhow::anyhow!(message))
+            .with_context(|| format!("Failed to write remote file: {}", path.display()))
+    }
+
+    fn finish(mut self) -> io::Result<Result<(), String>> {
+        let digest = self.hasher.finalize();
+        let reply = self.connection.call(FRAME_END, digest.as_bytes())?;
+        self.session.give_back(self.connection);
+        Ok(reply.map(drop))
+    }
+}
+
+impl SinkFile for NetFile {
+    fn close(self: Box<Self>) -> Result<()> {
+        NetFile::close(*self)
+    }
+}
+
+impl Write for NetFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = &buf[..buf.len().min(BUFFER_SIZE)];
+        self.hasher.update(chunk);
+        if self.compress {
+            let packed = zstd::bulk::compress(chunk, WIRE_COMPRESSION_LEVEL)?;
+            if packed.len() < chunk.len() - chunk.len() / 16 {
+                self.connection.send(FRAME_DATA_ZSTD, &packed)?;
+                return Ok(chunk.len());
+            }
+            self.compress = false;
+        }
+        self.connection.send(FRAME_DATA, chunk)?;
+        Ok(chunk.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection.stream.flush()
+    }
+}
+
+// `rcp serve ROOT`: принимаем файлы от клиентов, каждое соединение в своем потоке
+pub fn serve(args: &ServeArgs) -> Result<()> {
+    if !args.root.is_dir() {
+        anyhow::bail!("Served directory does not exist: {}", args.root.display());
+    }
+    let tls = ServerTls::from_args(args)?.map(Arc::new);
+    let listener = TcpListener::bind(&args.listen).with_context(|| format!("Failed to listen on {}", args.listen))?;
+    println!(
+        "{}",
+        t!(
+            "serving",
+            root = args.root.display().to_string(),
+            address = listener.local_addr().context("Failed to get listening address")?.to_string(),
+            tls = if tls.is_some() { "yes" } else { "no" },
+            quic = if args.quic { "yes" } else { "no" },
+        )
+    );
+    if args.quic {
+        serve_quic(args, tls.clone())?;
+    }
+    if tls.is_none() {
+        eprintln!(
+            "{} {}",
+            t!("warning").yellow().bold(),
+            t!("serve-unencrypted", quic = if args.quic { "yes" } else { "no" })
+        );
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{}", t!("accept-failed", error = e.to_string()));
+                continue;
+            }
+        };
+        let root = args.root.clone();
+        let tls = tls.clone();
+        let escaping_links = args.allow_escaping_links;
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default();
+            let _ = stream.set_nodelay(true);
+            let transport = match tls.as_deref() {
+                Some(tls) => tls.accept(stream).map(|stream| Transport::TlsServer(Box::new(stream))),
+                None => Ok(Transport::Plain(stream)),
+            };
+            let psk = tls.as_ref().and_then(|tls| tls.psk.as_ref());
+            if let Err(e) = transport.and_then(|transport| handle_client(transport, &root, psk, escaping_links)) {
+                eprintln!("{} {}: {:#}", t!("error").red().bold(), peer, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+// После стольких соединений с неверным кодом `rcp receive` прекращает ждать: код короткий
+// и перебирать его по сети нельзя давать
+const MAX_PAIRING_FAILURES: usize = 3;
+
+enum PeerEvent {
+    Connected(TcpStream),
+    Finished(String, Result<()>),
+    // Соединение закрыто без единого байта - проверка доступности, а не попытка входа
+    Probed,
+}
+
+// Источник входящих соединений для `rcp receive`: прослушиваемый порт или ретранслятор
+pub type Incoming = Box<dyn Iterator<Item = TcpStream> + Send>;
+
+pub fn accept_all(listener: TcpListener) -> Incoming {
+    Box::new(std::iter::repeat_with(move || listener.accept()).filter_map(|accepted| accepted.ok().map(|(stream, _)| stream)))
+}
+
+// Прием для `rcp receive`: соединения обслуживаются как в `rcp serve`, пока отправитель
+// не закроет все свои соединения. Возвращает адрес отправителя
+pub fn receive_from_peer(incoming: Vec<Incoming>, root: &Path, tls: Arc<ServerTls>) -> Result<String> {
+    let (sender, events) = mpsc::channel();
+    for connections in incoming {
+        let accepted = sender.clone();
+        thread::spawn(move || {
+            for stream in connections {
+                if accepted.send(PeerEvent::Connected(stream)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut active = 0;
+    let mut failures = 0;
+    while let Ok(event) = events.recv() {
+        match event {
+            PeerEvent::Connected(stream) => {
+                active += 1;
+                let root = root.to_path_buf();
+                let tls = Arc::clone(&tls);
+                let finished = sender.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().map(|peer| peer.ip().to_string()).unwrap_or_default();
+                    let _ = stream.set_nodelay(true);
+                    if stream.peek(&mut [0]).is_ok_and(|read| read == 0) {
+                        let _ = finished.send(PeerEvent::Probed);
+                        return;
+                    }
+                    let result = tls
+                        .accept(stream)
+                        .and_then(|stream| handle_client(Transport::TlsServer(Box::new(stream)), &root, tls.psk.as_ref(), false));
+                    let _ = finished.send(PeerEvent::Finished(peer, result));
+                });
+            }
+            PeerEvent::Probed => active -= 1,
+            PeerEvent::Finished(peer, Ok(())) => {
+                active -= 1;
+                if active == 0 {
+                    return Ok(peer);
+                }
+            }
+            PeerEvent::Finished(peer, Err(e)) => {
+                active -= 1;
+                eprintln!("{} {}: {:#}", t!("error").red().bold(), peer, e);
+                failures += 1;
+                if failures >= MAX_PAIRING_FAILURES {
+                    anyhow::bail!("Giving up after {} failed connections", failures);
+                }
+            }
+        }
+    }
+    anyhow::bail!("Stopped accepting connections")
+}
+
+// QUIC слушает тот же адрес по UDP; без настроек TLS сервер берет временный сертификат
+fn serve_quic(args: &ServeArgs, tls: Option<Arc<ServerTls>>) -> Result<()> {
+    let tls = match tls {
+        Some(tls) => tls,
+        None => Arc::new(ServerTls::ephemeral(None)?),
+    };
+    let server = QuicServer::bind(&args.listen, &tls)?;
+    let root = args.root.clone();
+    let escaping_links = args.allow_escaping_links;
+    thread::spawn(move || {
+        server.run(move |stream: QuicStream, peer: SocketAddr| {
+            if let Err(e) = handle_client(Transport::Quic(Box::new(stream)), &root, tls.psk.as_ref(), escaping_links) {
+                eprintln!("{} {}: {:#}", t!("error").red().bold(), peer, e);
+            }
+        });
+    });
+    Ok(())
+}
+
+fn handle_client(transport: Transport, root: &Path, psk: Option<&Psk>, escaping_links: bool) -> Result<()> {
+    let mut stream = FramedStream::new(transport);
+
+    match stream.receive()? {
+        Some((FRAME_HELLO, hello)) if hello.split(|&byte| byte == b' ').next() == Some(PROTOCOL) => {
+            let compress = hello.split(|&byte| byte == b' ').any(|feature| feature == FEATURE_ZSTD);
+            stream.send(FRAME_OK, if compress { FEATURE_ZSTD } else { &[] })?
+        }
+        _ => {
+            stream.send(FRAME_ERROR, b"unsupported protocol version")?;
+            stream.flush()?;
+            anyhow::bail!("Client speaks an unsupported protocol");
+        }
+    }
+    if let Some(psk) = psk {
+        authenticate_client(&mut stream, psk)?;
+    }
+    stream.flush()?;
+
+    while let Some((kind, payload)) = stream.receive()? {
+        let result = match kind {
+            FRAME_STAT => resolve(root, &payload).map(|path| {
+                let kind = match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => KIND_DIRECTORY,
+                    Ok(_) => KIND_FILE,
+                    Err(_) => KIND_MISSING,
+                };
+                vec![kind]
+            }),
+            FRAME_DIRECTORY => resolve(root, &payload).and_then(|path| {
+                fs::create_dir_all(&path)
+                    .with_context(|| format!("Failed to create directory: {}", path.display()))
+                    .map(|_| Vec::new())
+            }),
+            FRAME_SYMLINK => create_symlink(root, &payload, escaping_links).map(|_| Vec::new()),
+            FRAME_FILE => receive_file(&mut stream, root, &payload)?.map(|_| Vec::new()),
+            FRAME_RANGE => receive_range(&mut stream, root, &payload)?.map(|_| Vec::new()),
+            FRAME_COMMIT => commit_ranges(root, &payload).map(|_| Vec::new()),
+            FRAME_SIGNATURES => block_signatures(root, &payload),
+            FRAME_PATCH => receive_patch(&mut stream, root, &payload)?.map(|_| Vec::new()),
+            FRAME_RESUME => partial_signatures(root, &payload),
+            FRAME_APPEND => receive_append(&mut stream, root, &payload)?.map(|_| Vec::new()),
+            _ => anyhow::bail!("Unexpected frame {} from client", kind),
+        };
+
+        match result {
+            Ok(reply) => stream.send(FRAME_OK, &reply)?,
+            Err(e) => stream.send(FRAME_ERROR, format!("{:#}", e).as_bytes())?,
+        }
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+// Клиент первым доказывает знание ключа, затем сервер отвечает своим MAC
+fn authenticate_client(stream: &mut FramedStream, psk: &Psk) -> Result<()> {
+    stream.flush()?;
+    let exporter = stream.input.get_ref().exporter()?;
+    let psk = psk.session_key(&exporter, |message| match stream.receive()? {
+        Some((FRAME_PAKE, reply)) => {
+            stream.send(FRAME_OK, message)?;
+            stream.flush()?;
+            Ok(reply)
+        }
+        _ => {
+            stream.send(FRAME_ERROR, b"authentication failed")?;
+            stream.flush()?;
+            anyhow::bail!("Client did not start the key exchange");
+        }
+    })?;
+    match stream.receive()? {
+        Some((FRAME_AUTH, tag)) if tags_match(&tag, &psk.tag(b"client", &exporter)) => {
+            stream.send(FRAME_OK, &psk.tag(b"server", &exporter))?;
+            Ok(())
+        }
+        _ => {
+            stream.send(FRAME_ERROR, b"authentication failed")?;
+            stream.flush()?;
+            anyhow::bail!("Client failed PSK authentication");
+        }
+    }
+}
+
+// Пути клиента - относительно корня; выйти за его пределы нельзя ни через `..`, ни через
+// символическую ссылку на пути. Последний компонент открывается с O_NOFOLLOW
+fn resolve(root: &Path, path: &[u8]) -> Result<PathBuf> {
+    let path = Path::new(OsStr::from_bytes(path));
+    let relative = sanitize_entry_path(path)
+        .with_context(|| format!("Path escapes the served directory: {}", path.display()))?;
+    join_inside(root, &relative)
+}
+
+// Ссылки наружу корня создаются только с --allow-escaping-links
+fn create_symlink(root: &Path, payload: &[u8], escaping_links: bool) -> Result<()> {
+    let (len, rest) = payload.split_at_checked(4).context("Malformed symlink request")?;
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    let (target, link) = rest.split_at_checked(len).context("Malformed symlink request")?;
+
+    let relative = Path::new(OsStr::from_bytes(link));
+    let target_path = Path::new(OsStr::from_bytes(target));
+    if !escaping_links && !link_stays_inside(relative, target_path) {
+        anyhow::bail!(
+            "Symlink {} -> {} points outside the served directory (allowed with --allow-escaping-links)",
+            relative.display(),
+            target_path.display()
+        );
+    }
+    let link = resolve(root, link)?;
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+    }
+    let _ = fs::remove_file(&link);
+    symlink(OsStr::from_bytes(target), &link)
+        .with_context(|| format!("Failed to create symlink: {}", link.display()))
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+// Недокачанный файл лежит рядом с целевым под скрытым именем
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".rcp-part");
+    path.with_file_name(name)
+}
+
+// Проверенный файл занимает место целевого
+fn complete_partial(result: Result<()>, path: &Path) -> Result<()> {
+    result?;
+    fs::rename(partial_path(path), path).with_context(|| format!("Failed to rename into place: {}", path.display()))
+}
+
+fn receive_file(stream: &mut FramedStream, root: &Path, path: &[u8]) -> io::Result<Result<()>> {
+    let mut target = None;
+    let output = resolve(root, path).and_then(|path| {
+        create_parent(&path)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(partial_path(&path))
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        target = Some(path.clone());
+        Ok((file, path))
+    });
+    let result = receive_data(stream, output, 0, u64::MAX, blake3::Hasher::new())?;
+    Ok(match target {
+        Some(path) => complete_partial(result, &path),
+        None => result,
+    })
+}
+
+// Продолжение недокачанного файла: хвост после offset отбрасывается, а сумма
+// считается с начала, которое уже лежит на диске
+fn receive_append(stream: &mut FramedStream, root: &Path, payload: &[u8]) -> io::Result<Result<()>> {
+    let Some((offset, path)) = payload.split_at_checked(8) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed append request"));
+    };
+    let offset = u64::from_be_bytes(offset.try_into().unwrap());
+
+    let mut target = None;
+    let mut hasher = blake3::Hasher::new();
+    let output = resolve(root, path).and_then(|path| {
+        create_parent(&path)?;
+        let partial = partial_path(&path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&partial)
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        if file.metadata()?.len() < offset {
+            anyhow::bail!("Partial file is shorter than the resume offset: {}", path.display());
+        }
+        file.set_len(offset)
+            .and_then(|_| io::copy(&mut (&mut file).take(offset), &mut hasher))
+            .with_context(|| format!("Failed to read partial file: {}", path.display()))?;
+        target = Some(path.clone());
+        Ok((file, path))
+    });
+    let result = receive_data(stream, output, offset, u64::MAX, hasher)?;
+    Ok(match target {
+        Some(path) => complete_partial(result, &path),
+        None => result,
+    })
+}
+
+// Части одного файла приходят по разным соединениям в любом порядке, поэтому каждая
+// открывает `.ИМЯ.rcp-part` без обрезки и выставляет ему итоговый размер. Целевой файл
+// не меняется до COMMIT
+fn receive_range(stream: &mut FramedStream, root: &Path, payload: &[u8]) -> io::Result<Result<()>> {
+    let Some((header, path)) = payload.split_at_checked(16) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed range request"));
+    };
+    let offset = u64::from_be_bytes(header[..8].try_into().unwrap());
+    let total = u64::from_be_bytes(header[8..].try_into().unwrap());
+
+    let output = resolve(root, path).and_then(|path| {
+        if offset > total {
+            anyhow::bail!("Range starts past the end of the file: {}", path.display());
+        }
+        create_parent(&path)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(partial_path(&path))
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        if file.metadata()?.len() != total {
+            file.set_len(total)
+                .with_context(|| format!("Failed to resize file: {}", path.display()))?;
+        }
+        Ok((file, path))
+    });
+    receive_data(stream, output, offset, total, blake3::Hasher::new())
+}
+
+// Собранный из частей файл сверяется с суммой, которую клиент посчитал по источнику;
+// неверный удаляется, верный занимает место целевого
+fn commit_ranges(root: &Path, payload: &[u8]) -> Result<()> {
+    let (header, path) = payload.split_at_checked(40).context("Malformed commit request")?;
+    let total = u64::from_be_bytes(header[..8].try_into().unwrap());
+    let path = resolve(root, path)?;
+    let partial = partial_path(&path);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&partial)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read back file: {}", path.display()))?;
+    if file.metadata()?.len() != total || hasher.finalize().as_bytes() != &header[8..] {
+        let _ = fs::remove_file(&partial);
+        anyhow::bail!("Checksum mismatch for {}", path.display());
+    }
+    complete_partial(Ok(()), &path)
+}
+
+fn signatures_request(path: &Path, block_size: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(8 + path.as_os_str().len());
+    payload.extend_from_slice(&block_size.to_be_bytes());
+    payload.extend_from_slice(path_bytes(path));
+    payload
+}
+
+fn parse_signatures_request(root: &Path, payload: &[u8]) -> Result<(PathBuf, u64)> {
+    let (block_size, path) = payload.split_at_checked(8).context("Malformed signatures request")?;
+    let block_size = u64::from_be_bytes(block_size.try_into().unwrap());
+    if !(DELTA_MIN_BLOCK..=DELTA_MAX_BLOCK).contains(&block_size) {
+        anyhow::bail!("Unsupported block size: {}", block_size);
+    }
+    Ok((resolve(root, path)?, block_size))
+}
+
+fn block_signatures(root: &Path, payload: &[u8]) -> Result<Vec<u8>> {
+    let (path, block_size) = parse_signatures_request(root, payload)?;
+    file_signatures(&path, block_size)
+}
+
+fn partial_signatures(root: &Path, payload: &[u8]) -> Result<Vec<u8>> {
+    let (path, block_size) = parse_signatures_request(root, payload)?;
+    file_signatures(&partial_path(&path), block_size)
+}
+
+// Пустой ответ - файла нет (или это не файл)
+fn file_signatures(path: &Path, block_size: u64) -> Result<Vec<u8>> {
+    let mut file = match OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW).open(path) {
+        Ok(file) if file.metadata().is_ok_and(|metadata| metadata.is_file()) => file,
+        _ => return Ok(Vec::new()),
+    };
+    let size = file.metadata()?.len();
+    if size.div_ceil(block_size) > DELTA_MAX_BLOCKS * 2 {
+        anyhow::bail!("Too many blocks for {}", path.display());
+    }
+
+    let mut reply = size.to_be_bytes().to_vec();
+    let mut block = vec![0; block_size as usize];
+    loop {
+        let read = read_block(&mut file, &mut block).with_context(|| format!("Failed to read file: {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        reply.extend_from_slice(blake3::hash(&block[..read]).as_bytes());
+    }
+    Ok(reply)
+}
+
+// Изменившиеся блоки пишутся в копию существующего файла в `.ИМЯ.rcp-part`; END несет
+// BLAKE3 всего нового содержимого, копия перечитывается для сверки и только после нее
+// занимает место целевого. Оборванный или неверный патч целевой файл (и его жесткие
+// ссылки) не трогает
+fn receive_patch(stream: &mut FramedStream, root: &Path, payload: &[u8]) -> io::Result<Result<()>> {
+    let Some((size, path)) = payload.split_at_checked(8) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed patch request"));
+    };
+    let size = u64::from_be_bytes(size.try_into().unwrap());
+
+    let mut target = None;
+    let output = resolve(root, path).and_then(|path| {
+        let mut original = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&path)
+            .with_context(|| format!("Failed to open file: {}", path.display()))?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(partial_path(&path))
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        target = Some(path.clone());
+        // На той же файловой системе копия делит блоки с оригиналом (FICLONE)
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::FICLONE, original.as_raw_fd()) } != 0 {
+            io::copy(&mut original, &mut file).with_context(|| format!("Failed to copy file: {}", path.display()))?;
+        }
+        file.set_permissions(original.metadata()?.permissions())
+            .and_then(|()| file.set_len(size))
+            .with_context(|| format!("Failed to resize file: {}", path.display()))?;
+        Ok((file, path))
+    });
+
+    let result = receive_blocks(stream, output);
+    let Some(path) = target else {
+        return result;
+    };
+    match result {
+        Ok(Ok(())) => Ok(complete_partial(Ok(()), &path)),
+        result => {
+            let _ = fs::remove_file(partial_path(&path));
+            result
+        }
+    }
+}
+
+fn receive_blocks(stream: &mut FramedStream, mut output: Result<(File, PathBuf)>) -> io::Result<Result<()>> {
+    loop {
+        let Some((kind, payload)) = stream.receive()? else {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        };
+        match kind {
+            FRAME_BLOCK => {
+                let Some((offset, data)) = payload.split_at_checked(8) else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed block frame"));
+                };
+                let offset = u64::from_be_bytes(offset.try_into().unwrap());
+                if let Ok((file, path)) = &mut output
+                    && let Err(e) = file.write_all_at(data, offset)
+                {
+                    output = Err(anyhow::Error::new(e).context(format!("Failed to write file: {}", path.display())));
+                }
+            }
+            FRAME_END => {
+                return Ok(output.and_then(|(mut file, path)| {
+                    let mut hasher = blake3::Hasher::new();
+                    file.rewind()
+                        .and_then(|_| io::copy(&mut file, &mut hasher))
+                        .with_context(|| format!("Failed to read back file: {}", path.display()))?;
+                    if payload != hasher.finalize().as_bytes() {
+                        anyhow::bail!("Checksum mismatch for {}", path.display());
+                    }
+                    Ok(())
+                }));
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected frame {} inside file data", kind),
+                ));
+            }
+        }
+    }
+}
+
+// Ошибку записи возвращаем отдельно: кадры данных все равно нужно дочитать до END.
+// Данные за end (концом части файла) не пишутся
+fn receive_data(
+    stream: &mut FramedStream,
+    mut output: Result<(File, PathBuf)>,
+    mut offset: u64,
+    end: u64,
+    mut hasher: blake3::Hasher,
+) -> io::Result<Result<()>> {
+
+    loop {
+        let Some((kind, payload)) = stream.receive()? else {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        };
+        match kind {
+            FRAME_DATA | FRAME_DATA_ZSTD => {
+                let payload = if kind == FRAME_DATA_ZSTD {
+                    zstd::bulk::decompress(&payload, BUFFER_SIZE)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                } else {
+                    payload
+                };
+                hasher.update(&payload);
+                if let Ok((_, path)) = &output
+                    && offset.checked_add(payload.len() as u64).is_none_or(|data_end| data_end > end)
+                {
+                    output = Err(anyhow::anyhow!("Range goes past the end of the file: {}", path.display()));
+                }
+                if let Ok((file, path)) = &mut output
+                    && let Err(e) = file.write_all_at(&payload, offset)
+                {
+                    output = Err(anyhow::Error::new(e).context(format!("Failed to write file: {}", path.display())));
+                }
+                offset = offset.saturating_add(payload.len() as u64);
+            }
+            FRAME_END => {
+                let (_, path) = match output {
+                    Ok(output) => output,
+                    Err(e) => return Ok(Err(e)),
+                };
+                if payload != hasher.finalize().as_bytes() {
+                    return Ok(Err(anyhow::anyhow!("Checksum mismatch for {}", path.display())));
+                }
+                return Ok(Ok(()));
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected frame {} inside file data", kind),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rcp-net-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Сервер на локальном порту и клиент, уже поздоровавшийся с ним
+    fn connect(root: &Path, escaping_links: bool) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = handle_client(Transport::Plain(stream), &root, None, escaping_links);
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        write_frame(&mut client, FRAME_HELLO, PROTOCOL).unwrap();
+        assert_eq!(read_frame(&mut client).unwrap().unwrap().0, FRAME_OK);
+        client
+    }
+
+    fn symlink_request(target: &str, link: &str) -> Vec<u8> {
+        let mut payload = (target.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(target.as_bytes());
+        payload.extend_from_slice(link.as_bytes());
+        payload
+    }
+
+    fn call(client: &mut TcpStream, kind: u8, payload: &[u8]) -> u8 {
+        write_frame(client, kind, payload).unwrap();
+        read_frame(client).unwrap().unwrap().0
+    }
+
+    fn send_file(client: &mut TcpStream, path: &str, data: &[u8]) -> u8 {
+        write_frame(client, FRAME_FILE, path.as_bytes()).unwrap();
+        write_frame(client, FRAME_DATA, data).unwrap();
+        call(client, FRAME_END, blake3::hash(data).as_bytes())
+    }
+
+    #[test]
+    fn symlinks_out_of_root_are_refused() {
+        let root = scratch("escaping-links");
+        let mut client = connect(&root, false);
+        assert_eq!(call(&mut client, FRAME_SYMLINK, &symlink_request("/", "x")), FRAME_ERROR);
+        assert_eq!(call(&mut client, FRAME_SYMLINK, &symlink_request("../..", "a/x")), FRAME_ERROR);
+        assert!(root.join("x").symlink_metadata().is_err());
+        assert!(root.join("a/x").symlink_metadata().is_err());
+        // `..`, который остается внутри корня, - обычная относительная ссылка
+        assert_eq!(call(&mut client, FRAME_SYMLINK, &symlink_request("../b", "a/x")), FRAME_OK);
+        assert_eq!(fs::read_link(root.join("a/x")).unwrap(), Path::new("../b"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn writes_through_symlinks_stay_in_root() {
+        let root = scratch("through-links");
+        let outside = scratch("through-links-outside");
+        let mut client = connect(&root, true);
+        let target = outside.to_str().unwrap();
+        assert_eq!(call(&mut client, FRAME_SYMLINK, &symlink_request(target, "x")), FRAME_OK);
+
+        assert_eq!(send_file(&mut client, "x/payload", b"data"), FRAME_ERROR);
+        assert_eq!(call(&mut client, FRAME_DIRECTORY, b"x/etc"), FRAME_ERROR);
+        assert_eq!(call(&mut client, FRAME_SYMLINK, &symlink_request("target", "x/link")), FRAME_ERROR);
+        let mut range = 0u64.to_be_bytes().to_vec();
+        range.extend_from_slice(&4u64.to_be_bytes());
+        range.extend_from_slice(b"x/range");
+        write_frame(&mut client, FRAME_RANGE, &range).unwrap();
+        write_frame(&mut client, FRAME_DATA, b"data").unwrap();
+        assert_eq!(call(&mut client, FRAME_END, blake3::hash(b"data").as_bytes()), FRAME_ERROR);
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn planted_partial_file_link_is_not_followed() {
+        let root = scratch("partial-link");
+        let outside = scratch("partial-link-outside");
+        let victim = outside.join("victim");
+        fs::write(&victim, b"keep").unwrap();
+        let mut client = connect(&root, true);
+        let planted = symlink_request(victim.to_str().unwrap(), ".f.rcp-part");
+        assert_eq!(call(&mut client, FRAME_SYMLINK, &planted), FRAME_OK);
+
+        let mut append = 0u64.to_be_bytes().to_vec();
+        append.extend_from_slice(b"f");
+        write_frame(&mut client, FRAME_APPEND, &append).unwrap();
+        write_frame(&mut client, FRAME_DATA, b"evil").unwrap();
+        assert_eq!(call(&mut client, FRAME_END, blake3::hash(b"evil").as_bytes()), FRAME_ERROR);
+        // Обычная передача заменяет ссылку файлом, а не пишет по ней
+        assert_eq!(send_file(&mut client, ".f.rcp-part", b"evil"), FRAME_OK);
+        assert_eq!(fs::read(&victim).unwrap(), b"keep");
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn locations_get_the_default_port() {
+        let parse = |location: &str| {
+            NetLocation::parse(Path::new(location)).map(|location| (location.address, location.path))
+        };
+        let default = |host: &str| format!("{}:{}", host, DEFAULT_PORT);
+        assert_eq!(parse("rcp://nas/backup/2024"), Some((default("nas"), PathBuf::from("backup/2024"))));
+        assert_eq!(parse("rcp://nas:9000/backup"), Some(("nas:9000".to_string(), PathBuf::from("backup"))));
+        assert_eq!(parse("rcp://[::1]/x"), Some((default("[::1]"), PathBuf::from("x"))));
+        assert_eq!(parse("rcp://[::1]:9000"), Some(("[::1]:9000".to_string(), PathBuf::new())));
+
+        assert_eq!(parse("rcp:///backup"), None);
+        assert_eq!(parse("sftp://nas/backup"), None);
+        assert_eq!(parse("nas:backup"), None);
+    }
+
+    fn patch_request(path: &str, size: u64) -> Vec<u8> {
+        let mut payload = size.to_be_bytes().to_vec();
+        payload.extend_from_slice(path.as_bytes());
+        payload
+    }
+
+    fn block(offset: u64, data: &[u8]) -> Vec<u8> {
+        let mut payload = offset.to_be_bytes().to_vec();
+        payload.extend_from_slice(data);
+        payload
+    }
+
+    #[test]
+    fn cut_off_patch_leaves_the_file_unchanged() {
+        let root = scratch("patch-cut");
+        let original = vec![7; 3 * DELTA_MIN_BLOCK as usize];
+        fs::write(root.join("f"), &original).unwrap();
+        fs::hard_link(root.join("f"), root.join("link")).unwrap();
+
+        let mut client = connect(&root, false);
+        write_frame(&mut client, FRAME_PATCH, &patch_request("f", DELTA_MIN_BLOCK)).unwrap();
+        write_frame(&mut client, FRAME_BLOCK, &block(0, &[1; 4096])).unwrap();
+        // Обрыв посреди патча: сервер дочитывает соединение до конца и закрывает его
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(read_frame(&mut client).map_or(true, |frame| frame.is_none()));
+
+        assert_eq!(fs::read(root.join("f")).unwrap(), original);
+        assert_eq!(fs::read(root.join("link")).unwrap(), original);
+        assert!(!root.join(".f.rcp-part").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn patch_is_applied_only_after_the_checksum_matches() {
+        let root = scratch("patch-checksum");
+        let original = vec![7; 2 * DELTA_MIN_BLOCK as usize];
+        fs::write(root.join("f"), &original).unwrap();
+        fs::hard_link(root.join("f"), root.join("link")).unwrap();
+        let mut patched = original.clone();
+        patched[..4096].fill(1);
+        patched.truncate(DELTA_MIN_BLOCK as usize + 10);
+
+        let mut client = connect(&root, false);
+        write_frame(&mut client, FRAME_PATCH, &patch_request("f", patched.len() as u64)).unwrap();
+        write_frame(&mut client, FRAME_BLOCK, &block(0, &[1; 4096])).unwrap();
+        assert_eq!(call(&mut client, FRAME_END, blake3::hash(b"something else").as_bytes()), FRAME_ERROR);
+        assert_eq!(fs::read(root.join("f")).unwrap(), original);
+        assert!(!root.join(".f.rcp-part").exists());
+
+        write_frame(&mut client, FRAME_PATCH, &patch_request("f", patched.len() as u64)).unwrap();
+        write_frame(&mut client, FRAME_BLOCK, &block(0, &[1; 4096])).unwrap();
+        assert_eq!(call(&mut client, FRAME_END, blake3::hash(&patched).as_bytes()), FRAME_OK);
+        assert_eq!(fs::read(root.join("f")).unwrap(), patched);
+        // Жесткая ссылка на старый файл его и сохраняет
+        assert_eq!(fs::read(root.join("link")).unwrap(), original);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn range_request(path: &str, offset: u64, total: u64) -> Vec<u8> {
+        let mut payload = offset.to_be_bytes().to_vec();
+        payload.extend_from_slice(&total.to_be_bytes());
+        payload.extend_from_slice(path.as_bytes());
+        payload
+    }
+
+    fn send_range(client: &mut TcpStream, path: &str, offset: u64, total: u64, data: &[u8]) -> u8 {
+        write_frame(client, FRAME_RANGE, &range_request(path, offset, total)).unwrap();
+        write_frame(client, FRAME_DATA, data).unwrap();
+        call(client, FRAME_END, blake3::hash(data).as_bytes())
+    }
+
+    fn commit_request(path: &str, data: &[u8]) -> Vec<u8> {
+        let mut payload = (data.len() as u64).to_be_bytes().to_vec();
+        payload.extend_from_slice(blake3::hash(data).as_bytes());
+        payload.extend_from_slice(path.as_bytes());
+        payload
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_refused() {
+        let root = scratch("range-bounds");
+        let mut client = connect(&root, false);
+        assert_eq!(send_range(&mut client, "f", 8, 10, b"abc"), FRAME_ERROR);
+        assert_eq!(send_range(&mut client, "f", 11, 10, b""), FRAME_ERROR);
+        assert_eq!(send_range(&mut client, "f", u64::MAX - 1, u64::MAX, b"abc"), FRAME_ERROR);
+        assert!(!root.join("f").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ranges_replace_the_file_only_after_the_commit() {
+        let root = scratch("range-commit");
+        let original = b"old content!".to_vec();
+        let new = b"new content!".to_vec();
+        fs::write(root.join("f"), &original).unwrap();
+
+        let mut client = connect(&root, false);
+        // Вторая часть так и не пришла: файл того же размера остается прежним
+        assert_eq!(send_range(&mut client, "f", 0, 12, &new[..6]), FRAME_OK);
+        assert_eq!(fs::read(root.join("f")).unwrap(), original);
+        assert_eq!(call(&mut client, FRAME_COMMIT, &commit_request("f", &new)), FRAME_ERROR);
+        assert_eq!(fs::read(root.join("f")).unwrap(), original);
+
+        assert_eq!(send_range(&mut client, "f", 6, 12, &new[6..]), FRAME_OK);
+        assert_eq!(send_range(&mut client, "f", 0, 12, &new[..6]), FRAME_OK);
+        assert_eq!(call(&mut client, FRAME_COMMIT, &commit_request("f", &new)), FRAME_OK);
+        assert_eq!(fs::read(root.join("f")).unwrap(), new);
+        assert!(!root.join(".f.rcp-part").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
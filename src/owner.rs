@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::cli::{Args, Preserve};
+use crate::metadata::Stat;
+
+// Владелец файлов в назначении. --chown задает его всем файлам, --preserve ownership
+// переносит владельца источника, а --usermap и --groupmap при этом переводят ID: данные
+// переезжают между системами, где у одних и тех же пользователей разные номера. Без этих
+// опций владелец - тот, кто копирует. Владелец копии с --fake-super записан и по имени: без
+// --numeric-ids на другой системе он находится по имени
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IdKind {
+    User,
+    Group,
+}
+
+impl IdKind {
+    fn describe(self) -> &'static str {
+        match self {
+            IdKind::User => "user",
+            IdKind::Group => "group",
+        }
+    }
+}
+
+// USER:GROUP, USER или :GROUP; имена или номера
+#[derive(Clone, Debug)]
+pub struct Chown {
+    user: Option<u32>,
+    group: Option<u32>,
+}
+
+impl FromStr for Chown {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let (user, group) = text.split_once(':').unwrap_or((text, ""));
+        if user.is_empty() && group.is_empty() {
+            return Err("expected USER, USER:GROUP or :GROUP".to_string());
+        }
+        let resolve = |kind, name: &str| match name.is_empty() {
+            true => Ok(None),
+            false => resolve_id(kind, name).map(Some),
+        };
+        Ok(Chown {
+            user: resolve(IdKind::User, user)?,
+            group: resolve(IdKind::Group, group)?,
+        })
+    }
+}
+
+// Правило перевода: ID источника (имя или шаблон имени с * и ?, номер, диапазон N-M) -> ID копии
+#[derive(Clone, Debug)]
+pub struct IdRule {
+    from: IdMatch,
+    to: u32,
+}
+
+#[derive(Clone, Debug)]
+enum IdMatch {
+    Name(glob::Pattern),
+    Range(u32, u32),
+}
+
+// FROM:TO[,FROM:TO...]
+pub fn parse_usermap(text: &str) -> Result<Vec<IdRule>, String> {
+    parse_rules(IdKind::User, text)
+}
+
+pub fn parse_groupmap(text: &str) -> Result<Vec<IdRule>, String> {
+    parse_rules(IdKind::Group, text)
+}
+
+fn parse_rules(kind: IdKind, text: &str) -> Result<Vec<IdRule>, String> {
+    text.split(',')
+        .map(|rule| {
+            let (from, to) = rule
+                .split_once(':')
+                .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+                .ok_or_else(|| format!("expected FROM:TO, got {}", rule))?;
+            let from = match from.split_once('-') {
+                Some((low, high)) if is_number(low) && is_number(high) => {
+                    IdMatch::Range(parse_number(low)?, parse_number(high)?)
+                }
+                _ if is_number(from) => {
+                    let id = parse_number(from)?;
+                    IdMatch::Range(id, id)
+                }
+                _ => IdMatch::Name(glob::Pattern::new(from).map_err(|e| format!("invalid pattern {}: {}", from, e))?),
+            };
+            Ok(IdRule { from, to: resolve_id(kind, to)? })
+        })
+        .collect()
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    text.parse().map_err(|_| format!("invalid ID: {}", text))
+}
+
+// Имя или номер -> номер в этой системе
+fn resolve_id(kind: IdKind, name: &str) -> Result<u32, String> {
+    if is_number(name) {
+        return parse_number(name);
+    }
+    lookup_id(kind, name).ok_or_else(|| format!("no {} named {}", kind.describe(), name))
+}
+
+#[derive(Clone, Default)]
+pub struct Ownership {
+    chown: Option<Chown>,
+    // Переводы подразумевают перенос владельца, как в rsync
+    user: bool,
+    group: bool,
+    usermap: Vec<IdRule>,
+    groupmap: Vec<IdRule>,
+    numeric_ids: bool,
+    // ID источника -> ID копии: имена не ищем заново для каждого файла
+    mapped: Arc<Mutex<HashMap<(IdKind, u32), u32>>>,
+}
+
+impl Ownership {
+    // None - владельца не трогаем
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let preserve = args.preserve.contains(&Preserve::Ownership);
+        if args.chown.is_none() && !preserve && args.usermap.is_empty() && args.groupmap.is_empty() {
+            return None;
+        }
+        Some(Ownership {
+            chown: args.chown.clone(),
+            user: preserve || !args.usermap.is_empty(),
+            group: preserve || !args.groupmap.is_empty(),
+            usermap: args.usermap.iter().flatten().cloned().collect(),
+            groupmap: args.groupmap.iter().flatten().cloned().collect(),
+            numeric_ids: args.numeric_ids,
+            mapped: Arc::default(),
+        })
+    }
+
+    // Перенос владельца требует владельца источника, а его знает только локальный источник
+    pub fn needs_source(&self) -> bool {
+        self.user || self.group
+    }
+
+    // Владелец и группа копии; None - не менять
+    pub fn owner(&self, source: Option<&Stat>) -> (Option<u32>, Option<u32>) {
+        let chown = self.chown.as_ref();
+        let user = chown.and_then(|chown| chown.user).or_else(|| {
+            let source = source.filter(|_| self.user)?;
+            Some(self.map(IdKind::User, source.uid, source.user.as_deref()))
+        });
+        let group = chown.and_then(|chown| chown.group).or_else(|| {
+            let source = source.filter(|_| self.group)?;
+            Some(self.map(IdKind::Group, source.gid, source.group.as_deref()))
+        });
+        (user, group)
+    }
+
+    // Имя для записи рядом с ID; с --numeric-ids переносятся только номера
+    pub fn name(&self, kind: IdKind, id: u32) -> Option<String> {
+        match self.numeric_ids {
+            true => None,
+            false => lookup_name(kind, id),
+        }
+    }
+
+    // Первое подходящее правило; ID без правила остается как в источнике. Записанное имя
+    // (копия с --fake-super с другой системы) важнее номера
+    fn map(&self, kind: IdKind, id: u32, recorded: Option<&str>) -> u32 {
+        let recorded = recorded.filter(|_| !self.numeric_ids);
+        if recorded.is_none()
+            && let Some(&mapped) = self.mapped.lock().unwrap().get(&(kind, id))
+        {
+            return mapped;
+        }
+        let id = recorded.and_then(|name| lookup_id(kind, name)).unwrap_or(id);
+        let rules = match kind {
+            IdKind::User => &self.usermap,
+            IdKind::Group => &self.groupmap,
+        };
+        let name = match recorded {
+            Some(name) => Some(name.to_string()),
+            None => rules
+                .iter()
+                .any(|rule| matches!(rule.from, IdMatch::Name(_)))
+                .then(|| lookup_name(kind, id))
+                .flatten(),
+        };
+        let mapped = rules
+            .iter()
+            .find(|rule| match &rule.from {
+                IdMatch::Range(low, high) => (*low..=*high).contains(&id),
+                IdMatch::Name(pattern) => name.as_deref().is_some_and(|name| pattern.matches(name)),
+            })
+            .map_or(id, |rule| rule.to);
+        if recorded.is_none() {
+            self.mapped.lock().unwrap().insert((kind, id), mapped);
+        }
+        mapped
+    }
+}
+
+pub fn lookup_id(kind: IdKind, name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    unsafe {
+        match kind {
+            IdKind::User => {
+                let mut entry: libc::passwd = std::mem::zeroed();
+                let mut found = std::ptr::null_mut();
+                libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found);
+                (!found.is_null()).then_some(entry.pw_uid)
+            }
+            IdKind::Group => {
+                let mut entry: libc::group = std::mem::zeroed();
+                let mut found = std::ptr::null_mut();
+                libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found);
+                (!found.is_null()).then_some(entry.gr_gid)
+            }
+        }
+    }
+}
+
+pub fn lookup_name(kind: IdKind, id: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    unsafe {
+        let name = match kind {
+            IdKind::User => {
+                let mut entry: libc::passwd = std::mem::zeroed();
+                let mut found = std::ptr::null_mut();
+                libc::getpwuid_r(id, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found);
+                if found.is_null() {
+                    return None;
+                }
+                entry.pw_name
+            }
+            IdKind::Group => {
+                let mut entry: libc::group = std::mem::zeroed();
+                let mut found = std::ptr::null_mut();
+                libc::getgrgid_r(id, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found);
+                if found.is_null() {
+                    return None;
+                }
+                entry.gr_name
+            }
+        };
+        Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ownership(usermap: &str, groupmap: &str) -> Ownership {
+        let rules = |kind, text: &str| match text.is_empty() {
+            true => Vec::new(),
+            false => parse_rules(kind, text).unwrap(),
+        };
+        Ownership {
+            user: !usermap.is_empty(),
+            group: !groupmap.is_empty(),
+            usermap: rules(IdKind::User, usermap),
+            groupmap: rules(IdKind::Group, groupmap),
+            ..Default::default()
+        }
+    }
+
+    fn stat(uid: u32, gid: u32, user: Option<&str>) -> Stat {
+        Stat {
+            uid,
+            gid,
+            user: user.map(str::to_string),
+            group: None,
+            mode: 0o100644,
+            rdev: 0,
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let ownership = ownership("1000-1999:5000,1500:6000,0:7000", "");
+        assert_eq!(ownership.owner(Some(&stat(1500, 100, None))), (Some(5000), None));
+        assert_eq!(ownership.owner(Some(&stat(0, 100, None))), (Some(7000), None));
+        // ID без правила остается как в источнике
+        assert_eq!(ownership.owner(Some(&stat(2500, 100, None))), (Some(2500), None));
+    }
+
+    #[test]
+    fn names_match_by_pattern() {
+        // root есть в любой системе с номером 0
+        let ownership = ownership("r??t:4242", "ro*:4343");
+        assert_eq!(ownership.owner(Some(&stat(0, 0, None))), (Some(4242), Some(4343)));
+        assert_eq!(ownership.owner(Some(&stat(4000000, 4000000, None))), (Some(4000000), Some(4000000)));
+    }
+
+    #[test]
+    fn chown_wins_over_maps() {
+        let mut ownership = ownership("0:777", "0:888");
+        ownership.chown = Some(":6".parse().unwrap());
+        assert_eq!(ownership.owner(Some(&stat(0, 0, None))), (Some(777), Some(6)));
+        ownership.chown = Some("5:6".parse().unwrap());
+        assert_eq!(ownership.owner(Some(&stat(0, 0, None))), (Some(5), Some(6)));
+    }
+
+    #[test]
+    fn recorded_names_win_over_numbers() {
+        let mut ownership = ownership("0:777", "");
+        assert_eq!(ownership.owner(Some(&stat(1234, 0, Some("root")))), (Some(777), None));
+        ownership.numeric_ids = true;
+        assert_eq!(ownership.owner(Some(&stat(1234, 0, Some("root")))), (Some(1234), None));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for rules in ["", "1000", ":5", "5:", "1000:5,", "[a:5", "99999999999:5", "1000:no-such-user-rcp"] {
+            assert!(parse_usermap(rules).is_err(), "{:?}", rules);
+        }
+        assert!(":".parse::<Chown>().is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+// Сколько прочитанных блоков может ждать записи: пока пишется один, читается следующий
+const PIPELINE_QUEUE_DEPTH: usize = 1;
+
+// Запись копии в отдельном потоке: чтение следующего блока идет одновременно с записью
+// текущего, а не по очереди. Выигрыш - когда источник и назначение на разных устройствах.
+// Ошибку записи возвращает следующий write или finish
+pub struct PipelinedWriter<W: Write + Send + 'static> {
+    // Небольшой файл пишется сразу, без потока
+    direct: Option<W>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    // Записанные блоки возвращаются, чтобы не выделять память под каждый
+    written: Option<Receiver<Vec<u8>>>,
+    handle: Option<JoinHandle<io::Result<W>>>,
+}
+
+impl<W: Write + Send + 'static> PipelinedWriter<W> {
+    pub fn new(inner: W, threaded: bool) -> Self {
+        if !threaded {
+            return PipelinedWriter { direct: Some(inner), sender: None, written: None, handle: None };
+        }
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(PIPELINE_QUEUE_DEPTH);
+        let (returned, written) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut inner = inner;
+            for block in receiver {
+                inner.write_all(&block)?;
+                let _ = returned.send(block);
+            }
+            Ok(inner)
+        });
+        PipelinedWriter { direct: None, sender: Some(sender), written: Some(written), handle: Some(handle) }
+    }
+
+    // Дожидается записи всех блоков и возвращает обернутый writer
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.direct.take() {
+            Some(inner) => Ok(inner),
+            None => self.join(),
+        }
+    }
+
+    fn join(&mut self) -> io::Result<W> {
+        self.sender = None;
+        match self.handle.take() {
+            Some(handle) => handle.join().unwrap_or_else(|_| Err(io::Error::other("write thread panicked"))),
+            None => Err(io::Error::other("write thread has already stopped")),
+        }
+    }
+
+    // Поток записи завершился раньше времени - значит, на ошибке
+    fn stopped(&mut self) -> io::Error {
+        match self.join() {
+            Ok(_) => io::Error::other("write thread has stopped"),
+            Err(e) => e,
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Write for PipelinedWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(inner) = &mut self.direct {
+            return inner.write(data);
+        }
+        let mut block = match self.written.as_ref().map(Receiver::try_recv) {
+            Some(Ok(block)) => block,
+            Some(Err(TryRecvError::Empty)) => Vec::with_capacity(data.len()),
+            _ => return Err(self.stopped()),
+        };
+        block.clear();
+        block.extend_from_slice(data);
+        match self.sender.as_ref().map(|sender| sender.send(block)) {
+            Some(Ok(())) => Ok(data.len()),
+            _ => Err(self.stopped()),
+        }
+    }
+
+    // Блоки в очереди дописывает finish
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.direct {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for PipelinedWriter<W> {
+    // Брошенная запись (пропуск файла) тоже дожидается потока: файл удаляется уже после нее
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
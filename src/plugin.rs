@@ -0,0 +1,382 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::transport::{Entry, EntryKind, Sink, SinkFile, Source};
+use crate::BUFFER_SIZE;
+
+// Внешние модули для схем, которых rcp не знает: для `foo://...` ищется программа
+// `rcp-backend-foo` в PATH. С ней rcp говорит строками JSON через stdin/stdout, по одному
+// запросу за раз; для параллельной работы запускается несколько экземпляров. stderr
+// программы выводится как есть.
+//
+// Запрос - объект с полем `op`, ответ - `{"ok": true, ...}` или
+// `{"ok": false, "error": "..."}`. Пути - абсолютные пути из адреса (`/dir/file`):
+//
+//   hello    {"op":"hello","version":1,"url":"foo://host"}  -> {"ok":true}
+//   stat     {"op":"stat","path":P}    -> {"ok":true,"entry":{"kind":"file","size":N}} или "entry":null;
+//            kind - file, dir, symlink или other; ссылка описывается сама, а не ее цель.
+//            Необязательное "mtime" (секунды) нужно --link-dest, --compare-dest и --copy-dest
+//   list     {"op":"list","path":P}    -> {"ok":true,"entries":[{"name":"a","kind":"dir","size":0}]}
+//   readlink {"op":"readlink","path":P} -> {"ok":true,"target":"..."}
+//   read     {"op":"read","path":P}    -> {"ok":true,"size":N}, затем строки {"data":"base64"}
+//            и в конце {"eof":true} либо {"ok":false,"error":"..."}
+//   mkdir    {"op":"mkdir","path":P}   -> {"ok":true}; с родителями, существующая - не ошибка
+//   write    {"op":"write","path":P,"mtime":секунды|null} -> {"ok":true}, затем rcp шлет
+//            {"data":"base64"} и {"eof":true}; ответ {"ok":true} - файл сохранен
+//   symlink  {"op":"symlink","target":T,"path":P} -> {"ok":true}
+//   copy     {"op":"copy","from":P,"path":Q} -> {"ok":true}: копия на самом сервере, когда
+//            источник и назначение - один адрес; отказ - файл пойдет через rcp
+//
+// Неизвестная операция - ответ с ошибкой: модуль может поддерживать только чтение или запись
+
+const PROTOCOL_VERSION: u64 = 1;
+const PROGRAM_PREFIX: &str = "rcp-backend-";
+
+// Исполняемый `rcp-backend-<scheme>` из PATH
+pub fn find_backend(scheme: &str) -> Option<PathBuf> {
+    let name = format!("{}{}", PROGRAM_PREFIX, scheme);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0))
+}
+
+// `scheme://authority/path` для внешнего модуля
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginLocation {
+    pub program: PathBuf,
+    // `scheme://authority`: по нему модуль подключается к своему серверу
+    pub server: String,
+    pub path: PathBuf,
+}
+
+impl PluginLocation {
+    pub fn parse(location: &Path, program: PathBuf) -> Option<Self> {
+        let location = location.to_str()?;
+        let (scheme, rest) = location.split_once("://")?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        Some(PluginLocation {
+            program,
+            server: format!("{}://{}", scheme, authority),
+            path: PathBuf::from(format!("/{}", path)),
+        })
+    }
+}
+
+fn parse_entry(value: &Value) -> Result<Entry> {
+    let kind = match value["kind"].as_str() {
+        Some("file") => EntryKind::File,
+        Some("dir") => EntryKind::Dir,
+        Some("symlink") => EntryKind::Symlink,
+        Some("other") => EntryKind::Other,
+        _ => anyhow::bail!("Invalid entry from backend: {}", value),
+    };
+    let size = if kind == EntryKind::File { value["size"].as_u64().unwrap_or(0) } else { 0 };
+    let modified = value["mtime"].as_u64().map(|seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+    Ok(Entry { kind, size, modified })
+}
+
+// Запущенный экземпляр модуля
+struct Process {
+    child: Child,
+    input: BufWriter<ChildStdin>,
+    output: BufReader<ChildStdout>,
+}
+
+impl Process {
+    fn spawn(session: &PluginSession) -> Result<Self> {
+        let failed = || format!("Failed to start backend {}", session.program.display());
+        let mut child = Command::new(&session.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(failed)?;
+        let mut process = Process {
+            input: BufWriter::new(child.stdin.take().unwrap()),
+            output: BufReader::new(child.stdout.take().unwrap()),
+            child,
+        };
+        process
+            .call(&json!({ "op": "hello", "version": PROTOCOL_VERSION, "url": session.server }))
+            .with_context(failed)?
+            .with_context(failed)?;
+        Ok(process)
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        serde_json::to_writer(&mut self.input, message)?;
+        self.input.write_all(b"\n")?;
+        self.input.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<Value> {
+        let mut line = String::new();
+        if self.output.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "backend exited"));
+        }
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Внешняя ошибка - сбой обмена с модулем, внутренняя - отказ модуля
+    fn call(&mut self, request: &Value) -> io::Result<Result<Value>> {
+        self.send(request)?;
+        let response = self.receive()?;
+        Ok(check(response))
+    }
+}
+
+fn check(response: Value) -> Result<Value> {
+    match response["ok"].as_bool() {
+        Some(true) => Ok(response),
+        _ => anyhow::bail!("{}", response["error"].as_str().unwrap_or("backend request failed")),
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Clone)]
+pub struct PluginSession {
+    program: PathBuf,
+    server: String,
+    idle: Arc<Mutex<Vec<Process>>>,
+}
+
+impl PluginSession {
+    // Первый экземпляр запускается сразу: так ошибка модуля видна до начала копирования
+    pub fn connect(location: &PluginLocation) -> Result<Self> {
+        let session = PluginSession {
+            program: location.program.clone(),
+            server: location.server.clone(),
+            idle: Arc::new(Mutex::new(Vec::new())),
+        };
+        let process = Process::spawn(&session)?;
+        session.give_back(process);
+        Ok(session)
+    }
+
+    fn take(&self) -> Result<Process> {
+        if let Some(process) = self.idle.lock().unwrap().pop() {
+            return Ok(process);
+        }
+        Process::spawn(self)
+    }
+
+    fn give_back(&self, process: Process) {
+        self.idle.lock().unwrap().push(process);
+    }
+
+    // Экземпляр со сбоем обмена в пул не возвращается
+    fn call(&self, request: Value) -> Result<Value> {
+        let mut process = self.take()?;
+        let response = process
+            .call(&request)
+            .with_context(|| format!("Backend {} failed", self.program.display()))?;
+        self.give_back(process);
+        response
+    }
+
+    fn describe_path(&self, path: &Path) -> String {
+        format!("{}{}", self.server, path.display())
+    }
+}
+
+impl Source for PluginSession {
+    fn describe(&self, path: &Path) -> String {
+        self.describe_path(path)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Option<Entry>> {
+        let response = self
+            .call(json!({ "op": "stat", "path": path }))
+            .with_context(|| format!("Failed to stat {}", self.describe_path(path)))?;
+        match &response["entry"] {
+            Value::Null => Ok(None),
+            entry => parse_entry(entry).map(Some),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Entry)>> {
+        let response = self
+            .call(json!({ "op": "list", "path": path }))
+            .with_context(|| format!("Failed to list remote directory: {}", self.describe_path(path)))?;
+        let mut entries = Vec::new();
+        for entry in response["entries"].as_array().into_iter().flatten() {
+            let name = entry["name"].as_str().filter(|name| !name.is_empty() && !name.contains('/'));
+            let name = name.with_context(|| format!("Invalid entry from backend: {}", entry))?;
+            entries.push((path.join(name), parse_entry(entry)?));
+        }
+        Ok(entries)
+    }
+
+    fn open(&self, path: &Path) -> Result<(Box<dyn Read>, u64)> {
+        let mut process = self.take()?;
+        let response = process
+            .call(&json!({ "op": "read", "path": path }))
+            .with_context(|| format!("Backend {} failed", self.program.display()))?;
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                self.give_back(process);
+                return Err(e.context(format!("Failed to open {}", self.describe_path(path))));
+            }
+        };
+        let reader = PluginReader {
+            session: self.clone(),
+            process: Some(process),
+            buffer: Vec::new(),
+            position: 0,
+        };
+        Ok((Box::new(reader), response["size"].as_u64().unwrap_or(0)))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        let response = self
+            .call(json!({ "op": "readlink", "path": path }))
+            .with_context(|| format!("Failed to read symlink: {}", self.describe_path(path)))?;
+        response["target"]
+            .as_str()
+            .map(PathBuf::from)
+            .context("Backend returned no symlink target")
+    }
+}
+
+impl Sink for PluginSession {
+    fn name(&self) -> String {
+        self.server.clone()
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(Source::stat(self, path)?.is_some_and(|entry| entry.kind == EntryKind::Dir))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.call(json!({ "op": "mkdir", "path": path }))
+            .with_context(|| format!("Failed to create remote directory {}", self.describe_path(path)))?;
+        Ok(())
+    }
+
+    fn create(&self, path: &Path, modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        let mtime = modified
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs());
+        let mut process = self.take()?;
+        let response = process
+            .call(&json!({ "op": "write", "path": path, "mtime": mtime }))
+            .with_context(|| format!("Backend {} failed", self.program.display()))?;
+        if let Err(e) = response {
+            self.give_back(process);
+            return Err(e.context(format!("Failed to create remote file: {}", self.describe_path(path))));
+        }
+        Ok(Box::new(PluginFile {
+            session: self.clone(),
+            process: Some(process),
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+        }))
+    }
+
+    fn copy_within(&self, source: &Path, destination: &Path) -> Result<bool> {
+        Ok(self.call(json!({ "op": "copy", "from": source, "path": destination })).is_ok())
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.call(json!({ "op": "symlink", "target": target, "path": link }))
+            .with_context(|| format!("Failed to create symlink: {}", self.describe_path(link)))?;
+        Ok(())
+    }
+}
+
+// Чтение файла: модуль присылает данные строками, пока не пришлет конец
+pub struct PluginReader {
+    session: PluginSession,
+    // None - файл прочитан, экземпляр возвращен в пул
+    process: Option<Process>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Read for PluginReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            let Some(process) = self.process.as_mut() else {
+                return Ok(0);
+            };
+            let message = process.receive()?;
+            if message["eof"].as_bool() == Some(true) {
+                self.session.give_back(self.process.take().unwrap());
+                return Ok(0);
+            }
+            let Some(data) = message["data"].as_str() else {
+                // Отказ модуля посреди файла обмен не ломает, а непонятный ответ - ломает
+                let process = self.process.take().unwrap();
+                if let Err(e) = check(message) {
+                    self.session.give_back(process);
+                    return Err(io::Error::other(format!("{:#}", e)));
+                }
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid message from backend"));
+            };
+            self.buffer = STANDARD.decode(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.position = 0;
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+// Запись файла: данные уходят модулю частями по BUFFER_SIZE
+pub struct PluginFile {
+    session: PluginSession,
+    process: Option<Process>,
+    buffer: Vec<u8>,
+}
+
+impl PluginFile {
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let process = self.process.as_mut().ok_or_else(|| io::Error::other("Backend upload already finished"))?;
+        let data = STANDARD.encode(&self.buffer);
+        self.buffer.clear();
+        process.send(&json!({ "data": data }))
+    }
+}
+
+impl SinkFile for PluginFile {
+    fn close(mut self: Box<Self>) -> Result<()> {
+        let failed = format!("Backend {} failed", self.session.program.display());
+        self.send_buffer().context(failed.clone())?;
+        let mut process = self.process.take().context("Backend upload already finished")?;
+        process.call(&json!({ "eof": true })).context(failed)??;
+        self.session.give_back(process);
+        Ok(())
+    }
+}
+
+impl Write for PluginFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(BUFFER_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == BUFFER_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,624 @@
+use anyhow::Result;
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use indicatif::{HumanBytes, ProgressDrawTarget};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, IsTerminal};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::control::{Control, WorkQueue};
+use crate::i18n::t;
+use crate::{MAX_CONCURRENT_FILES, MAX_PATH_LENGTH};
+
+// Самое низкое ограничение скорости, до которого опускает `-`
+const MIN_LIMIT: u64 = 64 * 1024;
+// Чаще рабочие потоки о прогрессе файла не сообщают: быстрее все равно не нарисовать
+const REPORT_INTERVAL: Duration = Duration::from_millis(50);
+pub const DEFAULT_REFRESH_RATE: u8 = 20;
+
+const OVERALL_TEMPLATE: &str =
+    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>3}/{len:>3} files ({percent}%) {prefix}";
+
+// Вид прогресс-баров: свои шаблоны indicatif (--bar-style), только ASCII (--ascii),
+// частота перерисовки в секунду (--refresh-rate) и строка над ними на каждый файл (-v)
+#[derive(Clone, Debug)]
+pub struct BarStyle {
+    pub overall: Option<String>,
+    pub file: Option<String>,
+    pub worker: Option<String>,
+    pub ascii: bool,
+    pub refresh_rate: u8,
+    pub verbose: bool,
+}
+
+impl Default for BarStyle {
+    fn default() -> Self {
+        BarStyle {
+            overall: None,
+            file: None,
+            worker: None,
+            ascii: false,
+            refresh_rate: DEFAULT_REFRESH_RATE,
+            verbose: false,
+        }
+    }
+}
+
+impl BarStyle {
+    fn overall_style(&self) -> Result<ProgressStyle> {
+        let style = ProgressStyle::with_template(self.overall.as_deref().unwrap_or(OVERALL_TEMPLATE))?;
+        Ok(self.characters(style))
+    }
+
+    fn file_style(&self) -> Result<ProgressStyle> {
+        let template = match &self.file {
+            Some(template) => template.clone(),
+            None => format!(
+                "{{msg:{}}} [{{elapsed_precise}}] {{bar:40.cyan/blue}} {{bytes:>8}}/{{total_bytes:>8}} {{bytes_per_sec:>10}}",
+                MAX_PATH_LENGTH
+            ),
+        };
+        let style = ProgressStyle::with_template(&template)?.with_key("bytes_per_sec", bytes_per_sec);
+        Ok(self.characters(style))
+    }
+
+    fn worker_style(&self) -> Result<ProgressStyle> {
+        let template = match &self.worker {
+            Some(template) => template.clone(),
+            None => format!("{{prefix}} {{msg:{}}} {{bytes:>10}} {{bytes_per_sec:>12}}", MAX_PATH_LENGTH),
+        };
+        let style = ProgressStyle::with_template(&template)?.with_key("bytes_per_sec", bytes_per_sec);
+        Ok(self.characters(style))
+    }
+
+    fn characters(&self, style: ProgressStyle) -> ProgressStyle {
+        match self.ascii {
+            true => style.progress_chars("#=-.").tick_chars("|/-\\ "),
+            false => style.progress_chars("█▓▒░"),
+        }
+    }
+
+    fn done(&self) -> String {
+        (if self.ascii { "+" } else { "✓" }).green().to_string()
+    }
+
+    fn failed(&self) -> String {
+        (if self.ascii { "x" } else { "✗" }).red().to_string()
+    }
+}
+
+struct ActiveProgress {
+    pb: ProgressBar,
+    finished: bool,
+    id: u32,
+    path: String,
+}
+
+fn bytes_per_sec(state: &ProgressState, w: &mut dyn std::fmt::Write) {
+    write!(w, "{}/s", format_speed(state.per_sec())).unwrap()
+}
+
+// Запускаем менеджер прогресс-баров в отдельном потоке. С control он еще и слушает клавиши,
+// с очередью (--worker-bars) рисует полосу на рабочий поток вместо полосы на файл
+pub fn spawn_progress_manager(
+    total_files: usize,
+    style: BarStyle,
+    control: Option<Arc<Control>>,
+    workers: Option<Arc<WorkQueue>>,
+) -> (mpsc::Sender<ProgressUpdate>, thread::JoinHandle<Result<()>>) {
+    let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::stderr_with_hz(style.refresh_rate));
+    let (progress_sender, progress_receiver) = mpsc::channel();
+    let manager_handle = thread::spawn(move || {
+        progress_manager(progress_receiver, multi_progress, total_files, style, control, workers)
+    });
+    (progress_sender, manager_handle)
+}
+
+fn progress_manager(
+    receiver: mpsc::Receiver<ProgressUpdate>,
+    multi_progress: MultiProgress,
+    total_files: usize,
+    style: BarStyle,
+    control: Option<Arc<Control>>,
+    workers: Option<Arc<WorkQueue>>,
+) -> Result<()> {
+    let mut active_bars: Vec<ActiveProgress> = Vec::new();
+    let mut bars_to_remove: Vec<ProgressBar> = Vec::new();
+    
+    // Главный прогресс-бар для общего прогресса
+    let main_pb = multi_progress.add(ProgressBar::new(total_files as u64));
+    main_pb.set_style(style.overall_style()?);
+    main_pb.set_message(t!("overall-progress"));
+    let mut worker_bars = workers.map(WorkerBars::new);
+
+    let keyboard = control.and_then(|control| Keyboard::new().map(|keyboard| (keyboard, control)));
+    if keyboard.is_some() {
+        let _ = multi_progress.println(
+            t!("progress-keys").dimmed().to_string(),
+        );
+    }
+
+    // Работаем, пока живы отправители: общее число файлов может расти по ходу
+    // (например, при распаковке архива из потока)
+    loop {
+        // Сначала удаляем старые прогресс-бары
+        for pb in bars_to_remove.drain(..) {
+            multi_progress.remove(&pb);
+        }
+
+        if let Some((keyboard, control)) = &keyboard {
+            let running = match &worker_bars {
+                Some(worker_bars) => worker_bars.running(),
+                None => active_bars
+                    .iter()
+                    .filter(|ap| !ap.finished)
+                    .map(|ap| (ap.id, ap.pb.per_sec()))
+                    .collect(),
+            };
+            for key in keyboard.read() {
+                handle_key(key, control, &running, keyboard);
+            }
+            main_pb.set_prefix(status(control, style.ascii));
+        }
+        
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(update) => {
+                if let Some(worker_bars) = &mut worker_bars {
+                    worker_bars.update(update, &multi_progress, &main_pb, &style)?;
+                    continue;
+                }
+                match update {
+                    ProgressUpdate::NewFile { path, size, id } => {
+                        // Удаляем старые завершенные прогресс-бары при достижении лимита
+                        if active_bars.len() >= MAX_CONCURRENT_FILES
+                            && let Some(idx) = active_bars.iter().position(|ap| ap.finished)
+                        {
+                            let completed = active_bars.remove(idx);
+                            bars_to_remove.push(completed.pb);
+                        }
+                        
+                        let pb = multi_progress.add(ProgressBar::new(size));
+                        let display_path = shorten_path(&path, MAX_PATH_LENGTH);
+                        
+                        pb.set_style(style.file_style()?);
+                        
+                        pb.set_message(display_path.cyan().bold().to_string());
+                        
+                        active_bars.push(ActiveProgress {
+                            pb,
+                            finished: false,
+                            id,
+                            path,
+                        });
+                    }
+                    ProgressUpdate::Progress { id, bytes_copied } => {
+                        if let Some(active_progress) = active_bars.iter_mut().find(|ap| ap.id == id)
+                            && !active_progress.finished
+                        {
+                            active_progress.pb.set_position(bytes_copied);
+                        }
+                    }
+                    ProgressUpdate::Finished { id } => {
+                        if let Some(active_progress) = active_bars.iter_mut().find(|ap| ap.id == id) {
+                            if style.verbose {
+                                let size = active_progress.pb.length().unwrap_or_default();
+                                log_completed(&multi_progress, &style, &active_progress.path, size, active_progress.pb.elapsed());
+                            }
+                            active_progress.finished = true;
+                            let display_path = shorten_path(&active_progress.path, MAX_PATH_LENGTH - 2);
+                            active_progress.pb.finish_with_message(format!("{} {}", style.done(), display_path));
+                            main_pb.inc(1);
+                            
+                            // Помечаем прогресс-бар для удаления в следующей итерации
+                            bars_to_remove.push(active_progress.pb.clone());
+                        }
+                    }
+                    ProgressUpdate::Queued { files } => {
+                        main_pb.inc_length(files);
+                    }
+                    ProgressUpdate::Failed { id, .. } => {
+                        // Файл мог упасть еще до появления своего прогресс-бара
+                        let already_finished = match active_bars.iter_mut().find(|ap| ap.id == id) {
+                            Some(active_progress) if active_progress.finished => true,
+                            Some(active_progress) => {
+                                active_progress.finished = true;
+                                let display_path = shorten_path(&active_progress.path, MAX_PATH_LENGTH - 2);
+                                active_progress.pb.abandon_with_message(format!("{} {}", style.failed(), display_path));
+                                bars_to_remove.push(active_progress.pb.clone());
+                                false
+                            }
+                            None => false,
+                        };
+
+                        if !already_finished {
+                            main_pb.inc(1);
+                        }
+                    }
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // Таймаут - продолжаем проверять
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                // Канал закрыт, выходим
+                break;
+            }
+        }
+    }
+    
+    main_pb.finish_with_message(t!("all-files-copied").green().to_string());
+    if let Some(worker_bars) = worker_bars {
+        worker_bars.finish(&style);
+    }
+    
+    // Завершаем оставшиеся прогресс-бары
+    for active_progress in active_bars {
+        if !active_progress.finished {
+            let display_path = shorten_path(&active_progress.path, MAX_PATH_LENGTH - 2);
+            active_progress.pb.finish_with_message(format!("{} {}", style.done(), display_path));
+        }
+    }
+    
+    Ok(())
+}
+
+// running - копирующиеся сейчас файлы и их скорость
+fn handle_key(key: u8, control: &Control, running: &[(u32, f64)], keyboard: &Keyboard) {
+    match key {
+        b'p' | b'P' => {
+            control.toggle_pause();
+        }
+        // Самый медленный из копирующихся сейчас файлов
+        b's' | b'S' => {
+            if let Some((id, _)) = running.iter().min_by(|a, b| a.1.total_cmp(&b.1)) {
+                control.skip(*id);
+            }
+        }
+        b'q' | b'Q' => control.abort(),
+        // Ctrl-C: как q, а если копирование уже прерывается - выходим сразу
+        3 => {
+            if control.is_stopped() {
+                keyboard.restore();
+                std::process::exit(130);
+            }
+            control.abort();
+        }
+        b'+' | b'=' => {
+            if let Some(limit) = control.limit() {
+                control.set_limit(Some(limit + limit / 4));
+            }
+        }
+        // Без ограничения начинаем с текущей скорости
+        b'-' | b'_' => {
+            let current = control
+                .limit()
+                .unwrap_or_else(|| running.iter().map(|(_, speed)| speed).sum::<f64>() as u64);
+            control.set_limit(Some((current - current / 5).max(MIN_LIMIT)));
+        }
+        _ => {}
+    }
+}
+
+fn status(control: &Control, ascii: bool) -> String {
+    let sign = if ascii { "<=" } else { "≤" };
+    let limit = control.limit().map(|limit| format!("{} {}/s", sign, format_speed(limit as f64)));
+    let state = if control.is_stopped() {
+        Some(t!("state-stopping").red())
+    } else if control.is_draining() {
+        Some(t!("state-draining").yellow())
+    } else if control.is_paused() {
+        Some(t!("state-paused").yellow())
+    } else {
+        None
+    };
+    match (limit, state) {
+        (Some(limit), Some(state)) => format!("{} {}", limit, state),
+        (Some(limit), None) => limit,
+        (None, Some(state)) => state.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+// Терминал без построчного ввода и эха, чтобы клавиши приходили сразу. Вывод не трогаем, иначе
+// поедут прогресс-бары; Ctrl-C тоже приходит символом, чтобы остановка была мягкой
+struct Keyboard {
+    original: libc::termios,
+}
+
+impl Keyboard {
+    fn new() -> Option<Self> {
+        // Фоновому процессу терминал не принадлежит
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() || unsafe { libc::tcgetpgrp(0) != libc::getpgrp() } {
+            return None;
+        }
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(0, &mut original) } != 0 {
+            return None;
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(0, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(Keyboard { original })
+    }
+
+    // Не блокируется: VMIN и VTIME равны нулю
+    fn read(&self) -> Vec<u8> {
+        let mut buffer = [0u8; 32];
+        let length = unsafe { libc::read(0, buffer.as_mut_ptr().cast(), buffer.len()) };
+        buffer[..length.max(0) as usize].to_vec()
+    }
+
+    fn restore(&self) {
+        unsafe { libc::tcsetattr(0, libc::TCSANOW, &self.original) };
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+// --worker-bars: полоса на рабочий поток с текущим файлом, байтами за все время и скоростью.
+// Тысячи мелких файлов проносятся слишком быстро, чтобы полосы на файл можно было прочитать
+struct WorkerBars {
+    queue: Arc<WorkQueue>,
+    bars: BTreeMap<usize, WorkerBar>,
+    // Копирующийся файл -> рабочий поток, путь, размер и начало копирования
+    files: HashMap<u32, (usize, String, u64, Instant)>,
+}
+
+struct WorkerBar {
+    pb: ProgressBar,
+    // Байты рабочего потока до начала текущего файла
+    base: u64,
+    done: u64,
+    failed: u64,
+}
+
+impl WorkerBars {
+    fn new(queue: Arc<WorkQueue>) -> Self {
+        WorkerBars {
+            queue,
+            bars: BTreeMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn update(
+        &mut self,
+        update: ProgressUpdate,
+        multi_progress: &MultiProgress,
+        main_pb: &ProgressBar,
+        style: &BarStyle,
+    ) -> Result<()> {
+        match update {
+            ProgressUpdate::NewFile { path, size, id } => {
+                let Some(worker) = self.queue.worker_of(id) else {
+                    return Ok(());
+                };
+                let worker_style = style.worker_style()?;
+                let bar = self.bars.entry(worker).or_insert_with(|| {
+                    let pb = multi_progress.add(ProgressBar::no_length());
+                    pb.set_style(worker_style);
+                    WorkerBar { pb, base: 0, done: 0, failed: 0 }
+                });
+                bar.base = bar.pb.position();
+                bar.pb.set_message(shorten_path(&path, MAX_PATH_LENGTH).cyan().bold().to_string());
+                self.files.insert(id, (worker, path, size, Instant::now()));
+                self.refresh(worker);
+            }
+            ProgressUpdate::Progress { id, bytes_copied } => {
+                if let Some((worker, ..)) = self.files.get(&id)
+                    && let Some(bar) = self.bars.get(worker)
+                {
+                    bar.pb.set_position(bar.base + bytes_copied);
+                }
+            }
+            ProgressUpdate::Finished { id } => {
+                if let Some((worker, path, size, started)) = self.files.remove(&id) {
+                    if style.verbose {
+                        log_completed(multi_progress, style, &path, size, started.elapsed());
+                    }
+                    if let Some(bar) = self.bars.get_mut(&worker) {
+                        bar.pb.set_position(bar.base + size);
+                        bar.done += 1;
+                    }
+                    self.refresh(worker);
+                    main_pb.inc(1);
+                }
+            }
+            ProgressUpdate::Failed { id, .. } => {
+                if let Some((worker, ..)) = self.files.remove(&id) {
+                    if let Some(bar) = self.bars.get_mut(&worker) {
+                        bar.failed += 1;
+                    }
+                    self.refresh(worker);
+                }
+                main_pb.inc(1);
+            }
+            ProgressUpdate::Queued { files } => main_pb.inc_length(files),
+        }
+        Ok(())
+    }
+
+    fn refresh(&self, worker: usize) {
+        if let Some(bar) = self.bars.get(&worker) {
+            let mut prefix = format!("{:>2} {:>6} {}", worker + 1, bar.done, t!("files-column"));
+            if bar.failed > 0 {
+                prefix.push_str(&t!("worker-failed-count", count = bar.failed).red().to_string());
+            }
+            bar.pb.set_prefix(prefix);
+        }
+    }
+
+    fn running(&self) -> Vec<(u32, f64)> {
+        self.files
+            .iter()
+            .filter_map(|(id, (worker, ..))| Some((*id, self.bars.get(worker)?.pb.per_sec())))
+            .collect()
+    }
+
+    fn finish(self, style: &BarStyle) {
+        for bar in self.bars.into_values() {
+            bar.pb.finish_with_message(format!("{} {}", style.done(), t!("worker-finished")));
+        }
+    }
+}
+
+// -v: постоянная строка о скопированном файле над прогресс-барами. Без терминала полосы не
+// рисуются, и println у MultiProgress ничего не выводит - тогда печатаем сами
+fn log_completed(multi_progress: &MultiProgress, style: &BarStyle, path: &str, size: u64, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let line = format!(
+        "{} {}",
+        style.done(),
+        t!(
+            "file-copied",
+            path = path,
+            size = HumanBytes(size).to_string(),
+            seconds = format!("{:.1}", seconds),
+            rate = format_speed(size as f64 / seconds.max(0.001)),
+        )
+    );
+    if multi_progress.is_hidden() {
+        println!("{}", line);
+    } else {
+        let _ = multi_progress.println(line);
+    }
+}
+
+// Прореживает ProgressUpdate::Progress одного файла: на быстром диске блок копируется за
+// микросекунды, и сообщение на каждый забивало бы канал. Последнее значение не нужно -
+// Finished и так дорисовывает полосу до конца
+#[derive(Default)]
+pub struct ProgressThrottle {
+    last: Cell<Option<Instant>>,
+}
+
+impl ProgressThrottle {
+    pub fn ready(&self) -> bool {
+        let now = Instant::now();
+        if self.last.get().is_some_and(|last| now.duration_since(last) < REPORT_INTERVAL) {
+            return false;
+        }
+        self.last.set(Some(now));
+        true
+    }
+}
+
+// Сокращает путь до max_width колонок терминала: CJK и эмодзи занимают по две колонки, а
+// кластер графем (буква с диакритикой, эмодзи с модификаторами) не разрывается
+pub fn shorten_path(path: &str, max_width: usize) -> String {
+    if path.width() <= max_width {
+        return path.to_string();
+    }
+    // По возможности оставляем имя файла целиком
+    if let Some(last_sep) = path.rfind(std::path::MAIN_SEPARATOR) {
+        let filename = &path[last_sep + 1..];
+        if filename.width() + 3 <= max_width {
+            return format!("...{}", filename);
+        }
+    }
+    if max_width <= 3 {
+        return "...".to_string();
+    }
+    let budget = max_width - 3;
+    let start = leading(path, budget / 2);
+    // Широкий символ мог не поместиться в начало - отдаем остаток концу
+    let end = trailing(path, budget - start.width());
+    format!("{}...{}", start, end)
+}
+
+// Самое длинное начало text не шире width колонок
+fn leading(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += grapheme.width();
+        if used > width {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+fn trailing(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true).rev() {
+        used += grapheme.width();
+        if used > width {
+            return &text[index + grapheme.len()..];
+        }
+    }
+    text
+}
+
+pub fn format_speed(bytes_per_sec: f64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut size = bytes_per_sec;
+    let mut unit_index = 0;
+
+    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit_index])
+}
+
+#[derive(Debug)]
+pub enum ProgressUpdate {
+    NewFile {
+        path: String,
+        size: u64,
+        id: u32,
+    },
+    Progress {
+        id: u32,
+        bytes_copied: u64,
+    },
+    Finished {
+        id: u32,
+    },
+    Failed {
+        id: u32,
+        error: String,
+    },
+    // Файлы, о которых стало известно уже после запуска
+    Queued {
+        files: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_paths_stay_and_long_ones_keep_the_file_name() {
+        assert_eq!(shorten_path("/a/b.txt", 20), "/a/b.txt");
+        assert_eq!(shorten_path("/very/long/directory/name/file.txt", 15), "...file.txt");
+        assert_eq!(shorten_path("abcdefghijklmnopqrstuvwxyz", 10), "abc...wxyz");
+        assert_eq!(shorten_path("/x/longname", 3), "...");
+    }
+
+    #[test]
+    fn wide_characters_and_graphemes_are_not_split() {
+        // Иероглиф - две колонки: в начало из трех помещается один
+        let shortened = shorten_path("文件文件文件文件文件", 10);
+        assert_eq!(shortened, "文...文件");
+        assert!(shortened.width() <= 10);
+        // Буква с комбинируемым ударением - одна графема
+        assert_eq!(shorten_path(&"e\u{301}".repeat(10), 5), "e\u{301}...e\u{301}");
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Виртуальные файловые системы, копировать которые бессмысленно или опасно
+const PSEUDO_FS_TYPES: &[&str] = &[
+    "proc", "sysfs", "devtmpfs", "devpts", "cgroup", "cgroup2", "securityfs", "debugfs",
+    "tracefs", "pstore", "bpf", "configfs", "fusectl", "hugetlbfs", "mqueue", "efivarfs",
+    "autofs", "binfmt_misc", "rpc_pipefs", "nsfs", "selinuxfs",
+];
+
+// tmpfs считаем виртуальной только в служебных точках монтирования,
+// а не, например, в /tmp с пользовательскими данными
+const PSEUDO_TMPFS_MOUNTS: &[&str] = &["/run", "/dev/shm"];
+
+static MOUNT_TYPES: OnceLock<HashMap<PathBuf, String>> = OnceLock::new();
+
+// Возвращает тип файловой системы, если директория лежит на виртуальной ФС
+pub fn pseudo_fs_type(dir: &Path) -> Option<String> {
+    let canonical = fs::canonicalize(dir).ok()?;
+
+    // Ближайшая точка монтирования, содержащая директорию
+    let (mount_point, fs_type) = canonical
+        .ancestors()
+        .find_map(|ancestor| mount_types().get_key_value(ancestor))?;
+
+    let is_pseudo = PSEUDO_FS_TYPES.contains(&fs_type.as_str())
+        || (fs_type == "tmpfs" && PSEUDO_TMPFS_MOUNTS.iter().any(|mount| mount_point == Path::new(mount)));
+
+    is_pseudo.then(|| fs_type.clone())
+}
+
+// Тип файловой системы директории (ext4, btrfs, ...) по ближайшей точке монтирования
+pub fn fs_type(dir: &Path) -> Option<String> {
+    let canonical = fs::canonicalize(dir).ok()?;
+    canonical.ancestors().find_map(|ancestor| mount_types().get(ancestor)).cloned()
+}
+
+fn mount_types() -> &'static HashMap<PathBuf, String> {
+    MOUNT_TYPES.get_or_init(|| {
+        fs::read_to_string("/proc/self/mountinfo")
+            .map(|content| parse_mountinfo(&content))
+            .unwrap_or_default()
+    })
+}
+
+// Формат строки: id parent maj:min root mount_point options [optional...] - fs_type source super_options
+fn parse_mountinfo(content: &str) -> HashMap<PathBuf, String> {
+    let mut mounts = HashMap::new();
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let Some(separator) = fields.iter().position(|field| *field == "-") else {
+            continue;
+        };
+        if fields.len() < 5 || separator + 1 >= fields.len() {
+            continue;
+        }
+
+        // Поздние монтирования перекрывают ранние в той же точке
+        mounts.insert(
+            PathBuf::from(unescape_mount_path(fields[4])),
+            fields[separator + 1].to_string(),
+        );
+    }
+
+    mounts
+}
+
+// Пробелы и спецсимволы в mountinfo экранированы как \040
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && let Some(code) = std::str::from_utf8(&bytes[i + 1..i + 4])
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        {
+            result.push(code);
+            i += 4;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mountinfo_maps_mount_points_to_types() {
+        let content = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid shared:12 master:1 - proc proc rw
+24 22 0:22 / /mnt/my\\040disk rw - vfat /dev/sdb1 rw
+broken line without separator
+25 22 0:23 / /mnt/my\\040disk rw - ntfs3 /dev/sdb1 rw
+26 22 0:24 / /tmp rw -
+";
+        let mounts = parse_mountinfo(content);
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(mounts[Path::new("/")], "ext4");
+        // Необязательные поля (shared:, master:) не сбивают разбор
+        assert_eq!(mounts[Path::new("/proc")], "proc");
+        // Позднее монтирование в той же точке перекрывает раннее
+        assert_eq!(mounts[Path::new("/mnt/my disk")], "ntfs3");
+    }
+
+    #[test]
+    fn mount_paths_are_unescaped() {
+        assert_eq!(unescape_mount_path("/mnt/a\\040b\\011c\\134d"), "/mnt/a b\tc\\d");
+        // Неполный или не восьмеричный код остается как есть
+        assert_eq!(unescape_mount_path("/mnt/x\\04"), "/mnt/x\\04");
+        assert_eq!(unescape_mount_path("/mnt/x\\9zz"), "/mnt/x\\9zz");
+    }
+}
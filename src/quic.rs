@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use crate::i18n::t;
+use crate::tls::{ClientTls, ServerTls, EXPORTER_LABEL};
+
+// QUIC (quinn) как транспорт rcp://: одно соединение на сессию, а каждое соединение
+// рабочего потока - отдельный двунаправленный поток QUIC внутри него. quinn асинхронный,
+// поэтому держим свой runtime tokio и блокируемся на нем из обычных потоков
+
+// Код закрытия соединения после успешной сессии
+const CLOSE_DONE: quinn::VarInt = quinn::VarInt::from_u32(0);
+
+// Без пакетов соединение закрывается по таймауту, а рабочие потоки могут надолго
+// задуматься над большим файлом
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+fn runtime() -> Result<Arc<Runtime>> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .context("Failed to start QUIC runtime")?;
+    Ok(Arc::new(runtime))
+}
+
+fn resolve(address: &str) -> Result<SocketAddr> {
+    address
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", address))?
+        .next()
+        .with_context(|| format!("No addresses found for {}", address))
+}
+
+// Поток QUIC с блокирующими Read и Write
+pub struct QuicStream {
+    runtime: Arc<Runtime>,
+    connection: quinn::Connection,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+impl QuicStream {
+    // Материал сессии общий для всех потоков соединения
+    pub fn exporter(&self) -> Result<[u8; 32]> {
+        let mut exporter = [0; 32];
+        self.connection
+            .export_keying_material(&mut exporter, EXPORTER_LABEL, &[])
+            .map_err(|_| anyhow::anyhow!("Failed to derive QUIC session key material"))?;
+        Ok(exporter)
+    }
+}
+
+// Поток закрывается явно, чтобы другая сторона увидела конец данных, а не сброс
+impl Drop for QuicStream {
+    fn drop(&mut self) {
+        let _ = self.send.finish();
+    }
+}
+
+impl Read for QuicStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.runtime.block_on(self.recv.read(buf)) {
+            Ok(Some(len)) => Ok(len),
+            Ok(None) => Ok(0),
+            // Клиент закрывает соединение целиком, не дожидаясь конца каждого потока
+            Err(quinn::ReadError::ConnectionLost(quinn::ConnectionError::ApplicationClosed(close)))
+                if close.error_code == CLOSE_DONE =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Write for QuicStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(self.runtime.block_on(self.send.write(buf))?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Клиентское соединение QUIC; потоки открываются по одному на соединение протокола rcp
+pub struct QuicClient {
+    runtime: Arc<Runtime>,
+    endpoint: quinn::Endpoint,
+    connection: quinn::Connection,
+}
+
+impl QuicClient {
+    pub fn connect(address: &str, tls: &ClientTls) -> Result<Self> {
+        let runtime = runtime()?;
+        let remote = resolve(address)?;
+        let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+        let _guard = runtime.enter();
+        let mut endpoint = quinn::Endpoint::client(local.parse().unwrap()).context("Failed to open UDP socket")?;
+        let crypto = QuicClientConfig::try_from(tls.config()).context("TLS configuration is not usable for QUIC")?;
+        let mut transport = quinn::TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE));
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+        endpoint.set_default_client_config(config);
+
+        let connecting = endpoint
+            .connect(remote, ClientTls::server_host(address))
+            .with_context(|| format!("Failed to connect to rcp server {} over QUIC", address))?;
+        let connection = runtime
+            .block_on(connecting)
+            .with_context(|| format!("Failed to connect to rcp server {} over QUIC", address))?;
+        Ok(QuicClient {
+            runtime,
+            endpoint,
+            connection,
+        })
+    }
+
+    pub fn open_stream(&self) -> Result<QuicStream> {
+        let (send, recv) = self
+            .runtime
+            .block_on(self.connection.open_bi())
+            .context("Failed to open QUIC stream")?;
+        Ok(QuicStream {
+            runtime: Arc::clone(&self.runtime),
+            connection: self.connection.clone(),
+            send,
+            recv,
+        })
+    }
+}
+
+// Дожидаемся, пока сервер узнает о закрытии, иначе он досидит до таймаута
+impl Drop for QuicClient {
+    fn drop(&mut self) {
+        self.connection.close(CLOSE_DONE, b"done");
+        self.runtime.block_on(self.endpoint.wait_idle());
+    }
+}
+
+// Серверная сторона: каждое соединение и каждый поток в нем обслуживаются в своем потоке ОС
+pub struct QuicServer {
+    runtime: Arc<Runtime>,
+    endpoint: quinn::Endpoint,
+}
+
+impl QuicServer {
+    pub fn bind(listen: &str, tls: &ServerTls) -> Result<Self> {
+        let runtime = runtime()?;
+        let address = resolve(listen)?;
+        let crypto = QuicServerConfig::try_from(tls.config()).context("TLS configuration is not usable for QUIC")?;
+
+        let endpoint = {
+            let _guard = runtime.enter();
+            quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), address)
+                .with_context(|| format!("Failed to listen on {} (UDP)", listen))?
+        };
+        Ok(QuicServer { runtime, endpoint })
+    }
+
+    pub fn run<F>(self, handle: F)
+    where
+        F: Fn(QuicStream, SocketAddr) + Send + Sync + 'static,
+    {
+        let handle = Arc::new(handle);
+        while let Some(incoming) = self.runtime.block_on(self.endpoint.accept()) {
+            let runtime = Arc::clone(&self.runtime);
+            let handle = Arc::clone(&handle);
+            thread::spawn(move || {
+                let peer = incoming.remote_address();
+                let connection = match runtime.block_on(async move { incoming.await }) {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        eprintln!("{}", t!("quic-accept-failed", peer = peer.to_string(), error = e.to_string()));
+                        return;
+                    }
+                };
+                while let Ok((send, recv)) = runtime.block_on(connection.accept_bi()) {
+                    let stream = QuicStream {
+                        runtime: Arc::clone(&runtime),
+                        connection: connection.clone(),
+                        send,
+                        recv,
+                    };
+                    let handle = Arc::clone(&handle);
+                    thread::spawn(move || handle(stream, peer));
+                }
+            });
+        }
+    }
+}
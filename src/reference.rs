@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::cli::Args;
+use crate::collect::CopyItem;
+use crate::i18n::t;
+use crate::transport::{EntryKind, Source};
+
+// --link-dest, --compare-dest и --copy-dest: каталоги с той же структурой, что у назначения
+// (прошлые копии, эталон, заранее привезенная копия). Файл, который лежит там по тому же
+// пути с тем же размером и временем изменения, что у источника, не передается: он
+// становится жесткой ссылкой, пропускается или копируется оттуда локально
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReferenceKind {
+    Link,
+    Compare,
+    Copy,
+}
+
+impl ReferenceKind {
+    fn flag(self) -> &'static str {
+        match self {
+            ReferenceKind::Link => "--link-dest",
+            ReferenceKind::Compare => "--compare-dest",
+            ReferenceKind::Copy => "--copy-dest",
+        }
+    }
+}
+
+pub struct ReferenceDirs {
+    pub kind: ReferenceKind,
+    destination: PathBuf,
+    dirs: Vec<PathBuf>,
+    // Допустимая разница времени изменения (--modify-window)
+    modify_window: Duration,
+}
+
+impl ReferenceDirs {
+    // Флаги взаимоисключающие, как в rsync
+    pub fn from_args(args: &Args, destination: &Path) -> Option<Self> {
+        let (kind, dirs) = [
+            (ReferenceKind::Link, &args.link_dest),
+            (ReferenceKind::Compare, &args.compare_dest),
+            (ReferenceKind::Copy, &args.copy_dest),
+        ]
+        .into_iter()
+        .find(|(_, dirs)| !dirs.is_empty())?;
+
+        // Отсутствующий каталог - не ошибка: у первой копии прошлой нет
+        let dirs = dirs
+            .iter()
+            .filter(|dir| {
+                let exists = dir.is_dir();
+                if !exists {
+                    eprintln!(
+                        "{} {}",
+                        t!("warning").yellow().bold(),
+                        t!("reference-dir-missing", flag = kind.flag(), path = dir.display().to_string())
+                    );
+                }
+                exists
+            })
+            .cloned()
+            .collect();
+        Some(ReferenceDirs {
+            kind,
+            destination: destination.to_path_buf(),
+            dirs,
+            modify_window: Duration::from_secs(args.modify_window),
+        })
+    }
+
+    // Размер и время изменения источника; у удаленного источника время может быть неизвестно
+    fn source_state(item: &CopyItem, source: Option<&dyn Source>) -> Result<Option<(u64, Option<SystemTime>)>> {
+        Ok(match source {
+            Some(source) => source
+                .stat(Path::new(&item.source))?
+                .filter(|entry| entry.kind == EntryKind::File)
+                .map(|entry| (entry.size, entry.modified)),
+            None => fs::symlink_metadata(&item.source)
+                .ok()
+                .filter(Metadata::is_file)
+                .map(|metadata| (metadata.len(), metadata.modified().ok())),
+        })
+    }
+
+    fn find(&self, item: &CopyItem, size: u64, modified: SystemTime) -> Option<(PathBuf, Metadata)> {
+        let relative = item.destination.strip_prefix(&self.destination).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+        self.dirs.iter().find_map(|dir| {
+            let candidate = dir.join(relative);
+            fs::symlink_metadata(&candidate)
+                .ok()
+                .filter(|metadata| {
+                    metadata.is_file()
+                        && metadata.len() == size
+                        && metadata.modified().is_ok_and(|candidate| same_time(candidate, modified, self.modify_window))
+                })
+                .map(|metadata| (candidate, metadata))
+        })
+    }
+
+    // Убирает из списка на копирование файлы, найденные в каталогах, и возвращает их
+    pub fn apply(&self, files: &mut Vec<CopyItem>, source: Option<&dyn Source>) -> Result<Vec<CopyItem>> {
+        let mut handled = Vec::new();
+        let mut rest = Vec::new();
+        let mut without_times = false;
+        for item in files.drain(..) {
+            let found = match Self::source_state(&item, source)? {
+                Some((size, Some(modified))) => self.find(&item, size, modified),
+                Some((_, None)) => {
+                    without_times = true;
+                    None
+                }
+                None => None,
+            };
+            let done = match (found, self.kind) {
+                (Some(_), ReferenceKind::Compare) => true,
+                (Some((reference, metadata)), ReferenceKind::Link) => link(&reference, &metadata, &item.destination)?,
+                (Some((reference, metadata)), ReferenceKind::Copy) => copy(&reference, &metadata, &item.destination)?,
+                (None, _) => false,
+            };
+            if done {
+                handled.push(item);
+            } else {
+                rest.push(item);
+            }
+        }
+        *files = rest;
+
+        if without_times && let Some(source) = source {
+            eprintln!(
+                "{} {}",
+                t!("warning").yellow().bold(),
+                t!("reference-no-times", source = source.describe(Path::new("")), flag = self.kind.flag())
+            );
+        }
+        Ok(handled)
+    }
+
+    pub fn report(&self, count: usize) {
+        match self.kind {
+            ReferenceKind::Link => println!("{}", t!("reference-linked", count = count)),
+            ReferenceKind::Compare => println!("{}", t!("reference-compared", count = count)),
+            ReferenceKind::Copy => println!("{}", t!("reference-copied", count = count)),
+        }
+    }
+}
+
+// Как в rsync: если у одной из сторон время только в секундах (SFTP, FAT), доли не сравниваются;
+// с --modify-window достаточно разницы не больше окна
+fn same_time(first: SystemTime, second: SystemTime, window: Duration) -> bool {
+    let since_epoch = |time: SystemTime| time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let (first, second) = (since_epoch(first), since_epoch(second));
+    if !window.is_zero() {
+        first.abs_diff(second) <= window
+    } else if first.subsec_nanos() == 0 || second.subsec_nanos() == 0 {
+        first.as_secs() == second.as_secs()
+    } else {
+        first == second
+    }
+}
+
+// Между файловыми системами жестких ссылок нет - такой файл просто копируется
+pub fn link(reference: &Path, metadata: &Metadata, destination: &Path) -> Result<bool> {
+    if !prepare_destination(metadata, destination)? {
+        return Ok(true);
+    }
+    match fs::hard_link(reference, destination) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to link {} to {}", destination.display(), reference.display())),
+    }
+}
+
+fn copy(reference: &Path, metadata: &Metadata, destination: &Path) -> Result<bool> {
+    if !prepare_destination(metadata, destination)? {
+        return Ok(true);
+    }
+    fs::copy(reference, destination)
+        .with_context(|| format!("Failed to copy {} to {}", reference.display(), destination.display()))?;
+    let modified = metadata.modified()?;
+    File::options()
+        .write(true)
+        .open(destination)
+        .and_then(|file| file.set_modified(modified))
+        .with_context(|| format!("Failed to set modification time: {}", destination.display()))?;
+    Ok(true)
+}
+
+// Убирает старый файл назначения; false - это и есть файл из каталога (каталог указывает
+// на само назначение), делать ничего не нужно
+pub fn prepare_destination(metadata: &Metadata, destination: &Path) -> Result<bool> {
+    match fs::symlink_metadata(destination) {
+        Ok(existing) if existing.dev() == metadata.dev() && existing.ino() == metadata.ino() => return Ok(false),
+        Ok(_) => fs::remove_file(destination)
+            .with_context(|| format!("Failed to replace destination file: {}", destination.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to stat destination file: {}", destination.display())),
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::RelayArgs;
+use crate::i18n::t;
+
+// `rcp relay`: точка встречи для `rcp send --relay` / `rcp receive --relay`, когда машины
+// не видят друг друга напрямую. Клиент шлет строку `rcp-relay/1 КАНАЛ СТОРОНА`
+// (сторона - send или receive) и ждет `ok`: ретранслятор соединяет его с первым ждущим
+// клиентом другой стороны на том же канале и дальше только перекачивает байты в обе
+// стороны. Содержимое зашифровано концами, ретранслятор видит лишь номера каналов
+
+pub const DEFAULT_PORT: u16 = 7301;
+const PROTOCOL: &str = "rcp-relay/1";
+const MAX_HEADER_LEN: usize = 256;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+// Ждущих соединений на всем ретрансляторе не больше этого числа
+const MAX_WAITING: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Send,
+    Receive,
+}
+
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Side::Send => "send",
+            Side::Receive => "receive",
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "send" => Some(Side::Send),
+            "receive" => Some(Side::Receive),
+            _ => None,
+        }
+    }
+}
+
+type Waiting = Arc<Mutex<HashMap<String, Vec<(Side, TcpStream)>>>>;
+
+pub fn serve(args: &RelayArgs) -> Result<()> {
+    let listener = TcpListener::bind(&args.listen).with_context(|| format!("Failed to listen on {}", args.listen))?;
+    println!(
+        "{}",
+        t!("relaying", address = listener.local_addr().context("Failed to get listening address")?.to_string())
+    );
+
+    let waiting: Waiting = Arc::default();
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{}", t!("accept-failed", error = e.to_string()));
+                continue;
+            }
+        };
+        let waiting = Arc::clone(&waiting);
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default();
+            if let Err(e) = join(stream, &waiting) {
+                eprintln!("{} {}: {:#}", t!("error").red().bold(), peer, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn join(mut stream: TcpStream, waiting: &Waiting) -> Result<()> {
+    stream.set_read_timeout(Some(HEADER_TIMEOUT))?;
+    let header = read_line(&mut stream)?;
+    stream.set_read_timeout(None)?;
+    let (channel, side) = match header.split(' ').collect::<Vec<_>>()[..] {
+        [PROTOCOL, channel, side] if is_valid_channel(channel) => {
+            (channel.to_string(), Side::parse(side).context("Unknown relay side")?)
+        }
+        _ => {
+            let _ = stream.write_all(b"error unsupported protocol\n");
+            anyhow::bail!("Client speaks an unsupported protocol");
+        }
+    };
+
+    let mut peer = {
+        let mut waiting = waiting.lock().unwrap();
+        let queue = waiting.entry(channel.clone()).or_default();
+        // Ждавшие и уже ушедшие клиенты выбывают из очереди
+        queue.retain(|(_, stream)| is_connected(stream));
+        match queue.iter().position(|(waiting_side, _)| *waiting_side != side) {
+            Some(index) => {
+                let peer = queue.remove(index).1;
+                waiting.retain(|_, queue| !queue.is_empty());
+                peer
+            }
+            None => {
+                let total: usize = waiting.values().map(Vec::len).sum();
+                if total >= MAX_WAITING {
+                    let _ = stream.write_all(b"error relay is busy\n");
+                    anyhow::bail!("Too many waiting connections");
+                }
+                waiting.entry(channel).or_default().push((side, stream));
+                return Ok(());
+            }
+        }
+    };
+    peer.write_all(b"ok\n")?;
+    stream.write_all(b"ok\n")?;
+    pipe(stream, peer)?;
+    Ok(())
+}
+
+// Неблокирующий peek: 0 байт - другая сторона закрыла соединение
+fn is_connected(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let connected = match stream.peek(&mut [0]) {
+        Ok(read) => read > 0,
+        Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+    };
+    connected && stream.set_nonblocking(false).is_ok()
+}
+
+fn is_valid_channel(channel: &str) -> bool {
+    !channel.is_empty() && channel.len() <= 64 && channel.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+// Строка до `\n` побайтно: за заголовком сразу могут идти данные
+fn read_line(stream: &mut TcpStream) -> Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        if stream.read(&mut byte).context("Failed to read relay header")? == 0 {
+            anyhow::bail!("Connection closed before the relay header");
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        if line.len() >= MAX_HEADER_LEN {
+            anyhow::bail!("Relay header is too long");
+        }
+        line.push(byte[0]);
+    }
+    String::from_utf8(line).context("Relay header is not UTF-8")
+}
+
+// Перекачка в обе стороны; конец потока с одной стороны закрывает запись в другую
+pub fn pipe(first: TcpStream, second: TcpStream) -> Result<()> {
+    let (mut first_in, mut second_out) = (first.try_clone()?, second.try_clone()?);
+    let forward = thread::spawn(move || {
+        let _ = io::copy(&mut first_in, &mut second_out);
+        let _ = second_out.shutdown(Shutdown::Write);
+    });
+    let (mut second_in, mut first_out) = (second, first);
+    let _ = io::copy(&mut second_in, &mut first_out);
+    let _ = first_out.shutdown(Shutdown::Write);
+    let _ = forward.join();
+    Ok(())
+}
+
+// Клиент: `host[:port]`, ждем пару на канале сколько потребуется
+pub fn connect(relay: &str, channel: &str, side: Side) -> Result<TcpStream> {
+    let address = if relay.rsplit_once(':').is_some_and(|(_, port)| !port.contains(']')) {
+        relay.to_string()
+    } else {
+        format!("{}:{}", relay, DEFAULT_PORT)
+    };
+    let mut stream =
+        TcpStream::connect(&address).with_context(|| format!("Failed to connect to relay {}", address))?;
+    stream
+        .write_all(format!("{} {} {}\n", PROTOCOL, channel, side.as_str()).as_bytes())
+        .with_context(|| format!("Failed to talk to relay {}", address))?;
+    match read_line(&mut stream)?.as_str() {
+        "ok" => {}
+        reply => anyhow::bail!(
+            "Relay {} refused the connection: {}",
+            address,
+            reply.strip_prefix("error ").unwrap_or(reply)
+        ),
+    }
+    let _ = stream.set_nodelay(true);
+    Ok(stream)
+}
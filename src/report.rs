@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::cli::Args;
+use crate::collect::CopyItem;
+use crate::control::Control;
+use crate::i18n::t;
+use crate::session::{state_dir, SessionLog};
+use crate::systemd;
+
+// Отчет о запуске, который закончился ошибкой, прерыванием или нескопированными файлами:
+// что собирались копировать, что скопировано, что нет и где журнал сессии. Лежит по
+// известному пути, чтобы планировщики могли реагировать на сбой, не разбирая stderr;
+// после успешного запуска удаляется
+struct Run {
+    path: PathBuf,
+    started: SystemTime,
+    command: Vec<String>,
+    sources: Vec<String>,
+    destination: String,
+    planned_files: usize,
+    planned_bytes: u64,
+    copied_files: usize,
+    copied_bytes: u64,
+    failed: Vec<(String, String)>,
+    left_out: usize,
+    session: Option<(String, PathBuf)>,
+    // Идущее копирование, которое сигнал останавливает
+    control: Option<Arc<Control>>,
+    signal: Option<i32>,
+}
+
+static RUN: Mutex<Option<Run>> = Mutex::new(None);
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+static SIGNALS: Once = Once::new();
+
+fn update(change: impl FnOnce(&mut Run)) {
+    if let Some(run) = RUN.lock().unwrap().as_mut() {
+        change(run);
+    }
+}
+
+pub fn begin(args: &Args) -> Result<()> {
+    let path = match &args.failure_report {
+        Some(path) => path.clone(),
+        None => state_dir()?.join("failure.json"),
+    };
+    SIGNALS.call_once(handle_signals);
+    *RUN.lock().unwrap() = Some(Run {
+        path,
+        started: SystemTime::now(),
+        command: std::env::args().collect(),
+        sources: args.sources.iter().map(|source| source.to_string_lossy().into_owned()).collect(),
+        destination: args.destination.to_string_lossy().into_owned(),
+        planned_files: 0,
+        planned_bytes: 0,
+        copied_files: 0,
+        copied_bytes: 0,
+        failed: Vec::new(),
+        left_out: 0,
+        session: None,
+        control: None,
+        signal: None,
+    });
+    Ok(())
+}
+
+pub fn planned(files: &[CopyItem]) {
+    update(|run| {
+        run.planned_files = files.len();
+        run.planned_bytes = files.iter().map(|item| item.size).sum();
+    });
+}
+
+pub fn session(log: &SessionLog) {
+    update(|run| run.session = Some((log.id().to_string(), log.path().to_path_buf())));
+}
+
+pub fn copying(control: &Arc<Control>) {
+    update(|run| run.control = Some(Arc::clone(control)));
+}
+
+pub fn copied(item: &CopyItem) {
+    systemd::copied(item.size);
+    update(|run| {
+        run.copied_files += 1;
+        run.copied_bytes += item.size;
+    });
+}
+
+pub fn failed(item: &CopyItem, error: &str) {
+    systemd::failed();
+    update(|run| run.failed.push((item.source.clone(), error.to_string())));
+}
+
+pub fn left_out(count: usize) {
+    update(|run| run.left_out = count);
+}
+
+// Идущее копирование: сколько файлов в нем и какая доля байт записана
+pub fn progress() -> Option<(usize, f64)> {
+    let run = RUN.lock().unwrap();
+    let run = run.as_ref()?;
+    let control = run.control.as_ref()?;
+    let progress = match run.planned_bytes {
+        0 => 0.0,
+        bytes => (control.written() as f64 / bytes as f64).min(1.0),
+    };
+    Some((run.planned_files, progress))
+}
+
+// Итог запуска: при успехе старый отчет удаляется, иначе пишется новый. Прерванный
+// сигналом процесс здесь и завершается, иначе `rcp watch` и --interval пошли бы дальше
+pub fn finish(result: &Result<()>) {
+    let Some(run) = RUN.lock().unwrap().take() else {
+        return;
+    };
+    systemd::refresh();
+    let stopped = run.control.as_ref().is_some_and(|control| control.is_draining());
+    let status = match result {
+        Ok(()) if run.failed.is_empty() && run.left_out == 0 => None,
+        Ok(()) => Some("incomplete"),
+        Err(_) if stopped || run.signal.is_some() => Some("interrupted"),
+        Err(_) => Some("error"),
+    };
+    let error = result.as_ref().err().map(|e| format!("{:#}", e));
+    match status {
+        Some(status) => run.save(status, error.as_deref()),
+        None => {
+            let _ = fs::remove_file(&run.path);
+        }
+    }
+    if let Some(signal) = run.signal {
+        if let Some(error) = error {
+            eprintln!("{} {}", t!("error").red().bold(), error);
+        }
+        std::process::exit(128 + signal);
+    }
+}
+
+impl Run {
+    fn save(&self, status: &str, error: Option<&str>) {
+        match self.write(status, error) {
+            Ok(()) => eprintln!("{}", t!("failure-report-written", path = self.path.display().to_string())),
+            Err(e) => eprintln!("{} {:#}", t!("warning").yellow().bold(), e),
+        }
+    }
+
+    fn write(&self, status: &str, error: Option<&str>) -> Result<()> {
+        let seconds = |time: SystemTime| time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let report = json!({
+            "status": status,
+            "error": error,
+            "signal": self.signal,
+            "pid": std::process::id(),
+            "started": seconds(self.started),
+            "finished": seconds(SystemTime::now()),
+            "command": self.command,
+            "sources": self.sources,
+            "destination": self.destination,
+            "planned": { "files": self.planned_files, "bytes": self.planned_bytes },
+            "copied": { "files": self.copied_files, "bytes": self.copied_bytes },
+            "failed": self.failed.iter().map(|(source, error)| json!({ "source": source, "error": error })).collect::<Vec<_>>(),
+            "not_started": self.left_out,
+            "session": self.session.as_ref().map(|(id, journal)| json!({ "id": id, "journal": journal })),
+        });
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        }
+        // Через временный файл, чтобы читатель не увидел отчет наполовину
+        let temporary = temporary_path(&self.path);
+        fs::write(&temporary, serde_json::to_vec_pretty(&report)?)
+            .and_then(|()| fs::rename(&temporary, &self.path))
+            .with_context(|| format!("Failed to write failure report: {}", self.path.display()))
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    SIGNAL.store(signal, Ordering::Relaxed);
+}
+
+// SIGINT, SIGTERM и SIGHUP останавливают копирование так же, как клавиша q дважды: начатые
+// файлы бросаются, отчет пишется. Вне копирования и по второму сигналу отчет пишется сразу
+fn handle_signals() {
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+    }
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_millis(100));
+        let signal = SIGNAL.swap(0, Ordering::Relaxed);
+        if signal == 0 {
+            continue;
+        }
+        let mut run = RUN.lock().unwrap();
+        if let Some(run) = run.as_mut()
+            && run.signal.is_none()
+            && let Some(control) = &run.control
+        {
+            run.signal = Some(signal);
+            control.abort();
+            control.abort();
+            continue;
+        }
+        if let Some(run) = run.as_mut() {
+            run.signal = Some(signal);
+            run.save("interrupted", None);
+        }
+        std::process::exit(128 + signal);
+    });
+}
@@ -0,0 +1,843 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::collect::CopyItem;
+use crate::extract::{apply_metadata, join_inside, sanitize_entry_path};
+use crate::i18n::t;
+use crate::limits::{apply_limits, report_left_out};
+use crate::progress::{spawn_progress_manager, BarStyle, ProgressThrottle, ProgressUpdate};
+
+// Клиент демона rsync (`rsync://host[:port]/module/path`, `host::module/path`), только
+// прием файлов целиком. Говорим на протоколе 27: его до сих пор принимают серверы rsync 3.x,
+// а список файлов в нем еще без varint-кодирования и инкрементальной рекурсии
+
+const PROTOCOL_VERSION: i32 = 27;
+const DEFAULT_PORT: u16 = 873;
+
+// После выбора модуля все данные от сервера идут кадрами: 4 байта LE,
+// в старшем байте тег сообщения + MPLEX_BASE, в остальных длина
+const MPLEX_BASE: u32 = 7;
+const MSG_DATA: u32 = 0;
+const MSG_ERROR_XFER: u32 = 1;
+const MSG_INFO: u32 = 2;
+const MSG_ERROR: u32 = 3;
+const MSG_WARNING: u32 = 4;
+const MSG_ERROR_SOCKET: u32 = 5;
+const MSG_ERROR_UTF8: u32 = 8;
+
+// Флаги записи в списке файлов (протокол < 28)
+const XMIT_SAME_MODE: u8 = 0x02;
+const XMIT_SAME_NAME: u8 = 0x20;
+const XMIT_LONG_NAME: u8 = 0x40;
+const XMIT_SAME_TIME: u8 = 0x80;
+
+// Конец фазы передачи
+const NDX_DONE: i32 = -1;
+// После данных файла сервер присылает его MD4
+const FILE_SUM_LEN: usize = 16;
+// Защита от мусора в потоке
+const MAX_NAME_LEN: usize = 4096;
+const MAX_TOKEN_LEN: usize = 1024 * 1024;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+// Источник на демоне rsync; путь включает имя модуля, пустой путь - список модулей
+pub struct RsyncUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl RsyncUrl {
+    pub fn parse(source: &Path) -> Option<RsyncUrl> {
+        let source = source.to_str()?;
+        let (authority, path) = match source.strip_prefix("rsync://") {
+            Some(rest) => rest.split_once('/').unwrap_or((rest, "")),
+            None => {
+                let (host, path) = source.split_once("::")?;
+                if host.contains('/') {
+                    return None;
+                }
+                (host, path)
+            }
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(RsyncUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    fn module(&self) -> &str {
+        self.path.split('/').next().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RsyncUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rsync://{}:{}/{}", self.host, self.port, self.path)
+    }
+}
+
+struct FileEntry {
+    name: PathBuf,
+    size: u64,
+    mode: u32,
+    modified: i32,
+    link_target: Option<PathBuf>,
+}
+
+impl FileEntry {
+    fn kind(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    // Приветствие `@RSYNCD: <версия>` в обе стороны
+    fn open(url: &RsyncUrl) -> Result<Self> {
+        let stream = TcpStream::connect((url.host.as_str(), url.port))
+            .with_context(|| format!("Failed to connect to rsync server {}:{}", url.host, url.port))?;
+        let writer = stream.try_clone().context("Failed to set up rsync connection")?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+            writer,
+        };
+
+        let greeting = connection.read_line()?;
+        let version = greeting
+            .strip_prefix("@RSYNCD: ")
+            .and_then(|rest| rest.split(['.', ' ']).next())
+            .and_then(|version| version.parse::<i32>().ok())
+            .with_context(|| format!("Unexpected rsync server greeting: {}", greeting))?;
+        if version < PROTOCOL_VERSION {
+            anyhow::bail!("rsync server protocol {} is too old (need {})", version, PROTOCOL_VERSION);
+        }
+        connection.write_line(&format!("@RSYNCD: {}.0", PROTOCOL_VERSION))?;
+        Ok(connection)
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        self.reader.read_until(b'\n', &mut line).context("Failed to read from rsync server")?;
+        if line.is_empty() {
+            anyhow::bail!("rsync server closed the connection");
+        }
+        while line.last().is_some_and(|byte| *byte == b'\n' || *byte == b'\r') {
+            line.pop();
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        self.writer
+            .write_all(format!("{}\n", line).as_bytes())
+            .context("Failed to write to rsync server")
+    }
+
+    // Ответ на выбор модуля: до `@RSYNCD: OK` сервер может прислать приветственное сообщение
+    fn select_module(&mut self, module: &str) -> Result<()> {
+        self.write_line(module)?;
+        loop {
+            let line = self.read_line()?;
+            if line == "@RSYNCD: OK" {
+                return Ok(());
+            }
+            if line.starts_with("@RSYNCD: AUTHREQD") {
+                anyhow::bail!("rsync module {} requires authentication, which is not supported", module);
+            }
+            if let Some(error) = line.strip_prefix("@ERROR") {
+                anyhow::bail!("rsync server: {}", error.trim_start_matches(':').trim());
+            }
+            if line == "@RSYNCD: EXIT" {
+                anyhow::bail!("rsync server closed module {}", module);
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+// Список модулей сервера: `rcp rsync://host/ dest` только печатает его
+pub fn list_modules(url: &RsyncUrl) -> Result<()> {
+    let mut connection = Connection::open(url)?;
+    connection.write_line("")?;
+    loop {
+        let line = connection.read_line()?;
+        if line == "@RSYNCD: EXIT" {
+            return Ok(());
+        }
+        if let Some(error) = line.strip_prefix("@ERROR") {
+            anyhow::bail!("rsync server: {}", error.trim_start_matches(':').trim());
+        }
+        println!("{}", line);
+    }
+}
+
+// Забираем дерево с демона: сервер присылает список файлов, мы запрашиваем нужные
+// с пустыми контрольными суммами блоков, и он передает их целиком. Имена раскладываются
+// как у rsync: `module/dir` создает `dest/dir`, `module/dir/` - содержимое прямо в `dest`
+pub fn pull(
+    url: &RsyncUrl,
+    destination: &Path,
+    max_total_bytes: Option<u64>,
+    max_files: Option<usize>,
+    style: BarStyle,
+) -> Result<()> {
+    let mut connection = Connection::open(url)?;
+    connection.select_module(url.module())?;
+
+    // Аргументы серверной стороны, как их передал бы `rsync -rl`; конец - пустая строка
+    for arg in ["--server", "--sender", "-lr", ".", url.path.as_str(), ""] {
+        connection.write_line(arg)?;
+    }
+
+    let Connection { reader, mut writer } = connection;
+    let mut input = reader;
+    let checksum_seed = read_i32(&mut input).context("Failed to start rsync transfer")?;
+    // Пустой список фильтров
+    writer.write_all(&0i32.to_le_bytes()).context("Failed to write to rsync server")?;
+
+    let mut input = Demultiplexer { inner: input, remaining: 0 };
+    let entries = receive_file_list(&mut input).context("Failed to receive rsync file list")?;
+
+    // Одиночный файл ложится в существующую директорию или под именем назначения
+    let single_file = matches!(entries.as_slice(), [entry] if entry.kind() == S_IFREG) && !destination.is_dir();
+    let local_path = |entry: &FileEntry| -> Option<PathBuf> {
+        if single_file {
+            return Some(destination.to_path_buf());
+        }
+        // Путь через ссылку, оставшуюся в назначении, мог бы увести запись наружу
+        sanitize_entry_path(&entry.name).and_then(|relative| join_inside(destination, &relative).ok())
+    };
+    if !single_file {
+        fs::create_dir_all(destination)
+            .with_context(|| format!("Failed to create directory: {}", destination.display()))?;
+    }
+
+    let mut directories = Vec::new();
+    let mut symlinks = Vec::new();
+    let mut files = Vec::new();
+    let mut indices = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(path) = local_path(entry) else {
+            eprintln!("{}", t!("rsync-unsafe-path", path = entry.name.display().to_string()));
+            continue;
+        };
+        match (entry.kind(), &entry.link_target) {
+            (S_IFDIR, _) => {
+                fs::create_dir_all(&path)
+                    .with_context(|| format!("Failed to create directory: {}", path.display()))?;
+                directories.push((path, entry));
+            }
+            // Ссылки создаем после данных: иначе сервер прислал бы ссылку `a` на чужую
+            // директорию, а следом файл `a/x`, и он записался бы за пределы назначения
+            (S_IFLNK, Some(target)) => symlinks.push((entry, target)),
+            (S_IFREG, _) => {
+                let item = CopyItem::with_size(&entry.name, vec![path], entry.size);
+                indices.insert(item.source.clone(), index as i32);
+                files.push(item);
+            }
+            _ => eprintln!("{}", t!("rsync-special-file", path = entry.name.display().to_string())),
+        }
+    }
+
+    let (files, left_out) = apply_limits(files, max_total_bytes, max_files);
+    report_left_out(&left_out);
+    let requests: Vec<i32> = files.iter().map(|item| indices[&item.source]).collect();
+    let destinations: HashMap<i32, &CopyItem> = requests.iter().copied().zip(&files).collect();
+
+    println!("{}", t!("copying-files-from", count = files.len(), host = url.host.as_str()));
+
+    // Запросы пишет отдельный поток: сервер начинает отвечать, не дочитав их,
+    // и при большом списке обе стороны иначе встали бы на полных буферах
+    let request_writer = writer.try_clone().context("Failed to set up rsync connection")?;
+    let request_thread = thread::spawn(move || -> io::Result<()> {
+        let mut output = BufWriter::new(request_writer);
+        for index in requests {
+            // Заголовок контрольных сумм без блоков: файла у нас нет, нужен целиком
+            for value in [index, 0, 0, 0, 0] {
+                output.write_all(&value.to_le_bytes())?;
+            }
+        }
+        output.write_all(&NDX_DONE.to_le_bytes())?;
+        output.flush()
+    });
+    let mut request_thread = Some(request_thread);
+
+    let (progress_sender, manager_handle) = spawn_progress_manager(files.len(), style, None, None);
+    let mut failed = 0;
+    let mut next_id = 0;
+
+    loop {
+        let index = read_i32(&mut input).context("Failed to read from rsync server")?;
+        if index == NDX_DONE {
+            // Первый конец фазы - эхо нашего; повторной передачи нам не нужно, завершаем
+            // и вторую фазу. Следующий NDX_DONE - конец передачи
+            let Some(request_thread) = request_thread.take() else {
+                break;
+            };
+            request_thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("rsync request thread panicked")))
+                .context("Failed to send file requests to rsync server")?;
+            writer.write_all(&NDX_DONE.to_le_bytes()).context("Failed to write to rsync server")?;
+            continue;
+        }
+
+        let item = *destinations
+            .get(&index)
+            .with_context(|| format!("rsync server sent an unrequested file index {}", index))?;
+        let entry = &entries[index as usize];
+
+        let id = next_id;
+        next_id += 1;
+        let _ = progress_sender.send(ProgressUpdate::NewFile {
+            path: item.source.clone(),
+            size: entry.size,
+            id,
+        });
+        let throttle = ProgressThrottle::default();
+        match receive_file(&mut input, &item.destination, checksum_seed, &|bytes_copied| {
+            if throttle.ready() {
+                let _ = progress_sender.send(ProgressUpdate::Progress { id, bytes_copied });
+            }
+        })
+        .with_context(|| format!("Failed to receive {} from rsync server", item.source))?
+        {
+            Ok(()) => {
+                let modified = UNIX_EPOCH + Duration::from_secs(entry.modified.max(0) as u64);
+                apply_metadata(&item.destination, Some(entry.mode), Some(modified))?;
+                let _ = progress_sender.send(ProgressUpdate::Finished { id });
+            }
+            Err(e) => {
+                let error = t!("copy-failed", path = item.source.as_str(), error = format!("{:#}", e));
+                eprintln!("{}", error);
+                let _ = progress_sender.send(ProgressUpdate::Failed { id, error });
+                failed += 1;
+            }
+        }
+    }
+
+    // Статистика сервера (прочитано, записано, общий размер) и наше прощание
+    for _ in 0..3 {
+        read_longint(&mut input).context("Failed to read rsync transfer statistics")?;
+    }
+    writer.write_all(&NDX_DONE.to_le_bytes()).context("Failed to write to rsync server")?;
+
+    drop(progress_sender);
+    manager_handle.join().expect("Progress manager panicked")?;
+
+    // Путь проверяем заново: ссылка могла лечь под другую, созданную перед ней
+    for (entry, target) in symlinks {
+        let result = sanitize_entry_path(&entry.name)
+            .context("Unsafe path")
+            .and_then(|relative| join_inside(destination, &relative))
+            .and_then(|path| {
+                if path.is_symlink() {
+                    let _ = fs::remove_file(&path);
+                }
+                std::os::unix::fs::symlink(target, &path)
+                    .with_context(|| format!("Failed to create symlink: {}", path.display()))
+            });
+        if let Err(e) = result {
+            let path = entry.name.display().to_string();
+            eprintln!("{}", t!("symlink-failed", path = path, error = format!("{:#}", e)));
+            failed += 1;
+        }
+    }
+
+    // Время директорий выставляем в конце, после записи файлов в них
+    for (path, entry) in directories.iter().rev() {
+        let modified: SystemTime = UNIX_EPOCH + Duration::from_secs(entry.modified.max(0) as u64);
+        apply_metadata(path, Some(entry.mode), Some(modified))?;
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} files failed to transfer", failed);
+    }
+    println!("{}", t!("copy-completed").green());
+    Ok(())
+}
+
+// Список файлов: каждая запись сжата относительно предыдущей (общий префикс имени,
+// то же время и режим). Обе стороны сортируют его, и номера файлов в запросах - это
+// позиции в отсортированном списке; до протокола 29 порядок - побайтовый, как у strcmp
+fn receive_file_list(input: &mut impl Read) -> io::Result<Vec<FileEntry>> {
+    let mut entries = Vec::new();
+    let mut last_name: Vec<u8> = Vec::new();
+    let mut last_mode = 0;
+    let mut last_modified = 0;
+
+    loop {
+        let flags = read_u8(input)?;
+        if flags == 0 {
+            break;
+        }
+
+        let prefix_len = if flags & XMIT_SAME_NAME != 0 { read_u8(input)? as usize } else { 0 };
+        let suffix_len = if flags & XMIT_LONG_NAME != 0 { read_len(input)? } else { read_u8(input)? as usize };
+        if prefix_len > last_name.len() || prefix_len + suffix_len > MAX_NAME_LEN {
+            return Err(invalid_data("invalid file name length in file list"));
+        }
+        let mut name = last_name[..prefix_len].to_vec();
+        name.resize(prefix_len + suffix_len, 0);
+        input.read_exact(&mut name[prefix_len..])?;
+
+        let size = read_longint(input)?;
+        let modified = if flags & XMIT_SAME_TIME != 0 { last_modified } else { read_i32(input)? };
+        let mode = if flags & XMIT_SAME_MODE != 0 { last_mode } else { read_i32(input)? as u32 };
+        let link_target = if mode & S_IFMT == S_IFLNK {
+            let mut target = vec![0; read_len(input)?];
+            input.read_exact(&mut target)?;
+            Some(PathBuf::from(OsStr::from_bytes(&target)))
+        } else {
+            None
+        };
+
+        entries.push(FileEntry {
+            name: PathBuf::from(OsStr::from_bytes(&name)),
+            size: u64::try_from(size).map_err(|_| invalid_data("negative file size in file list"))?,
+            mode,
+            modified,
+            link_target,
+        });
+        last_name = name;
+        last_mode = mode;
+        last_modified = modified;
+    }
+
+    // Флаг ошибок чтения на стороне сервера; о самих ошибках он уже написал сообщениями
+    let _io_error = read_i32(input)?;
+
+    entries.sort_by(|a, b| a.name.as_os_str().as_bytes().cmp(b.name.as_os_str().as_bytes()));
+    entries.dedup_by(|a, b| a.name == b.name);
+    Ok(entries)
+}
+
+// Данные файла: положительный токен - столько байт литералом, ноль - конец. Ошибку
+// записи возвращаем отдельно: поток все равно нужно дочитать, иначе собьется протокол
+fn receive_file(
+    input: &mut impl Read,
+    path: &Path,
+    checksum_seed: i32,
+    progress: &dyn Fn(u64),
+) -> io::Result<Result<()>> {
+    // Эхо нашего заголовка контрольных сумм
+    for _ in 0..4 {
+        read_i32(input)?;
+    }
+
+    // Ссылку на месте файла заменяем, а не пишем по ней
+    if path.is_symlink() {
+        let _ = fs::remove_file(path);
+    }
+    let mut output = File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .with_context(|| format!("Failed to create file: {}", path.display()));
+    // Сумма протокола 27: MD4 от затравки сессии и данных файла
+    let mut sum = Md4::new();
+    sum.update(&checksum_seed.to_le_bytes());
+    let mut buffer = Vec::new();
+    let mut received = 0;
+    loop {
+        let token = read_i32(input)?;
+        if token == 0 {
+            break;
+        }
+        let len = usize::try_from(token)
+            .ok()
+            .filter(|len| *len <= MAX_TOKEN_LEN)
+            .ok_or_else(|| invalid_data("unexpected block reference in whole-file transfer"))?;
+        buffer.resize(len, 0);
+        input.read_exact(&mut buffer)?;
+        received += len as u64;
+        sum.update(&buffer);
+
+        if let Ok(file) = output.as_mut()
+            && let Err(e) = file.write_all(&buffer)
+        {
+            output = Err(anyhow::Error::new(e).context(format!("Failed to write file: {}", path.display())));
+        }
+        progress(received);
+    }
+
+    let mut file_sum = [0; FILE_SUM_LEN];
+    input.read_exact(&mut file_sum)?;
+
+    // Испорченный файл не оставляем: он выглядел бы скопированным
+    if output.is_ok() && sum.finish() != file_sum {
+        let _ = fs::remove_file(path);
+        return Ok(Err(anyhow::anyhow!("Checksum mismatch for {}", path.display())));
+    }
+    Ok(output.map(drop))
+}
+
+// MD4 (RFC 1320) - сумма файла в протоколе rsync; отдельного крейта ради нее не тянем
+struct Md4 {
+    state: [u32; 4],
+    block: [u8; 64],
+    filled: usize,
+    length: u64,
+}
+
+impl Md4 {
+    fn new() -> Self {
+        Md4 { state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476], block: [0; 64], filled: 0, length: 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let take = data.len().min(64 - self.filled);
+            self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_le_bytes());
+        let mut digest = [0; 16];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let x: Vec<u32> = block.chunks_exact(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+        let [mut a, mut b, mut c, mut d] = self.state;
+
+        for &i in &[0, 4, 8, 12] {
+            let f = |a: u32, b: u32, c: u32, d: u32, k: usize, s: u32| {
+                a.wrapping_add((b & c) | (!b & d)).wrapping_add(x[k]).rotate_left(s)
+            };
+            a = f(a, b, c, d, i, 3);
+            d = f(d, a, b, c, i + 1, 7);
+            c = f(c, d, a, b, i + 2, 11);
+            b = f(b, c, d, a, i + 3, 19);
+        }
+        for &i in &[0, 1, 2, 3] {
+            let g = |a: u32, b: u32, c: u32, d: u32, k: usize, s: u32| {
+                a.wrapping_add((b & c) | (b & d) | (c & d)).wrapping_add(x[k]).wrapping_add(0x5a827999).rotate_left(s)
+            };
+            a = g(a, b, c, d, i, 3);
+            d = g(d, a, b, c, i + 4, 5);
+            c = g(c, d, a, b, i + 8, 9);
+            b = g(b, c, d, a, i + 12, 13);
+        }
+        for &i in &[0, 2, 1, 3] {
+            let h = |a: u32, b: u32, c: u32, d: u32, k: usize, s: u32| {
+                a.wrapping_add(b ^ c ^ d).wrapping_add(x[k]).wrapping_add(0x6ed9eba1).rotate_left(s)
+            };
+            a = h(a, b, c, d, i, 3);
+            d = h(d, a, b, c, i + 8, 9);
+            c = h(c, d, a, b, i + 4, 11);
+            b = h(b, c, d, a, i + 12, 15);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+// Поток данных из мультиплексированных кадров; текстовые сообщения сервера печатаем
+struct Demultiplexer<R: Read> {
+    inner: R,
+    remaining: usize,
+}
+
+impl<R: Read> Read for Demultiplexer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            let header = read_i32(&mut self.inner)? as u32;
+            let tag = (header >> 24).wrapping_sub(MPLEX_BASE);
+            let len = (header & 0x00FF_FFFF) as usize;
+            if tag == MSG_DATA {
+                self.remaining = len;
+                continue;
+            }
+
+            let mut message = vec![0; len];
+            self.inner.read_exact(&mut message)?;
+            match tag {
+                MSG_ERROR_XFER | MSG_ERROR | MSG_ERROR_SOCKET | MSG_ERROR_UTF8 => {
+                    eprint!("{}", String::from_utf8_lossy(&message).red())
+                }
+                MSG_INFO | MSG_WARNING => eprint!("{}", String::from_utf8_lossy(&message)),
+                _ => {}
+            }
+        }
+
+        let len = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut value = [0; 1];
+    input.read_exact(&mut value)?;
+    Ok(value[0])
+}
+
+fn read_i32(input: &mut impl Read) -> io::Result<i32> {
+    let mut value = [0; 4];
+    input.read_exact(&mut value)?;
+    Ok(i32::from_le_bytes(value))
+}
+
+fn read_len(input: &mut impl Read) -> io::Result<usize> {
+    usize::try_from(read_i32(input)?)
+        .ok()
+        .filter(|len| *len <= MAX_NAME_LEN)
+        .ok_or_else(|| invalid_data("invalid length in file list"))
+}
+
+// Размер до протокола 30: 4 байта, а -1 означает, что дальше идут все 8
+fn read_longint(input: &mut impl Read) -> io::Result<i64> {
+    let value = read_i32(input)?;
+    if value != -1 {
+        return Ok(i64::from(value));
+    }
+    let mut value = [0; 8];
+    input.read_exact(&mut value)?;
+    Ok(i64::from_le_bytes(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rcp-rsync-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn md4(data: &[u8]) -> String {
+        let mut sum = Md4::new();
+        sum.update(data);
+        sum.finish().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    enum Entry<'a> {
+        Link(&'a str, &'a str),
+        File(&'a str, &'a [u8]),
+    }
+
+    // Поток данных файла, как его шлет сервер: эхо заголовка, литерал, конец и сумма
+    fn file_data(seed: i32, data: &[u8], sum: Option<[u8; 16]>) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for _ in 0..4 {
+            stream.extend_from_slice(&0i32.to_le_bytes());
+        }
+        if !data.is_empty() {
+            stream.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            stream.extend_from_slice(data);
+        }
+        stream.extend_from_slice(&0i32.to_le_bytes());
+        let sum = sum.unwrap_or_else(|| {
+            let mut md4 = Md4::new();
+            md4.update(&seed.to_le_bytes());
+            md4.update(data);
+            md4.finish()
+        });
+        stream.extend_from_slice(&sum);
+        stream
+    }
+
+    fn write_frame(stream: &mut TcpStream, data: &[u8]) {
+        let header = ((MPLEX_BASE + MSG_DATA) << 24) | data.len() as u32;
+        stream.write_all(&header.to_le_bytes()).unwrap();
+        stream.write_all(data).unwrap();
+    }
+
+    // Демон rsync на локальном порту, который отдает entries целиком, как их запросят
+    fn serve(entries: Vec<Entry<'static>>) -> RsyncUrl {
+        const SEED: i32 = 0x1234;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"@RSYNCD: 27.0\n").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            reader.read_line(&mut line).unwrap();
+            stream.write_all(b"@RSYNCD: OK\n").unwrap();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if line == "\n" {
+                    break;
+                }
+            }
+            stream.write_all(&SEED.to_le_bytes()).unwrap();
+            read_i32(&mut reader).unwrap();
+
+            let mut list = Vec::new();
+            for entry in &entries {
+                let (name, size, mode) = match entry {
+                    Entry::Link(name, _) => (name, 0, S_IFLNK | 0o777),
+                    Entry::File(name, data) => (name, data.len(), S_IFREG | 0o644),
+                };
+                list.push(XMIT_LONG_NAME);
+                list.extend_from_slice(&(name.len() as i32).to_le_bytes());
+                list.extend_from_slice(name.as_bytes());
+                list.extend_from_slice(&(size as i32).to_le_bytes());
+                list.extend_from_slice(&0i32.to_le_bytes());
+                list.extend_from_slice(&(mode as i32).to_le_bytes());
+                if let Entry::Link(_, target) = entry {
+                    list.extend_from_slice(&(target.len() as i32).to_le_bytes());
+                    list.extend_from_slice(target.as_bytes());
+                }
+            }
+            // Конец списка и флаг ошибок чтения
+            list.push(0);
+            list.extend_from_slice(&0i32.to_le_bytes());
+            write_frame(&mut stream, &list);
+
+            let mut reply = Vec::new();
+            loop {
+                let index = read_i32(&mut reader).unwrap();
+                if index == NDX_DONE {
+                    break;
+                }
+                for _ in 0..4 {
+                    read_i32(&mut reader).unwrap();
+                }
+                let Entry::File(_, data) = entries[index as usize] else {
+                    panic!("symlink requested");
+                };
+                reply.extend_from_slice(&index.to_le_bytes());
+                reply.extend_from_slice(&file_data(SEED, data, None));
+            }
+            reply.extend_from_slice(&NDX_DONE.to_le_bytes());
+            reply.extend_from_slice(&NDX_DONE.to_le_bytes());
+            for _ in 0..3 {
+                reply.extend_from_slice(&0i32.to_le_bytes());
+            }
+            write_frame(&mut stream, &reply);
+            let _ = io::copy(&mut reader, &mut io::sink());
+        });
+        RsyncUrl { host: "127.0.0.1".to_string(), port, path: "module/".to_string() }
+    }
+
+    #[test]
+    fn md4_matches_rfc_1320() {
+        assert_eq!(md4(b""), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        assert_eq!(md4(b"abc"), "a448017aaf21d8525fc10ae87aa6729d");
+        assert_eq!(md4(b"message digest"), "d9130a8164549fe818874806e1c7014b");
+        let digits = b"12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+        assert_eq!(md4(digits), "e33b4ddc9c38f2199c3e7b164fcc0536");
+    }
+
+    #[test]
+    fn checksum_mismatch_fails_the_file() {
+        let dir = scratch("checksum");
+        let path = dir.join("file");
+        let stream = file_data(7, b"data", None);
+        assert!(receive_file(&mut stream.as_slice(), &path, 7, &|_| {}).unwrap().is_ok());
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+
+        let stream = file_data(7, b"data", Some([0; 16]));
+        assert!(receive_file(&mut stream.as_slice(), &path, 7, &|_| {}).unwrap().is_err());
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symlink_in_place_of_file_is_replaced() {
+        let dir = scratch("replace-link");
+        let outside = scratch("replace-link-outside");
+        std::os::unix::fs::symlink(outside.join("victim"), dir.join("file")).unwrap();
+        let stream = file_data(0, b"data", None);
+        assert!(receive_file(&mut stream.as_slice(), &dir.join("file"), 0, &|_| {}).unwrap().is_ok());
+        assert!(!outside.join("victim").exists());
+        assert!(!dir.join("file").is_symlink());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn file_behind_symlink_stays_in_destination() {
+        let dir = scratch("pull-escape");
+        let outside = scratch("pull-escape-outside");
+        let target: &'static str = Box::leak(outside.to_str().unwrap().to_string().into_boxed_str());
+        let url = serve(vec![Entry::Link("a", target), Entry::File("a/payload", b"evil")]);
+
+        let destination = dir.join("out");
+        assert!(pull(&url, &destination, None, None, BarStyle::default()).is_err());
+        assert!(!outside.join("payload").exists());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn symlink_left_in_destination_is_not_followed() {
+        let dir = scratch("pull-old-link");
+        let outside = scratch("pull-old-link-outside");
+        std::os::unix::fs::symlink(&outside, dir.join("a")).unwrap();
+        let url = serve(vec![Entry::File("a/payload", b"evil"), Entry::File("b", b"good")]);
+
+        pull(&url, &dir, None, None, BarStyle::default()).unwrap();
+        assert!(!outside.join("payload").exists());
+        assert_eq!(fs::read(dir.join("b")).unwrap(), b"good");
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+}
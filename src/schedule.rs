@@ -0,0 +1,234 @@
+use anyhow::Result;
+use colored::Colorize;
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cli::Args;
+use crate::i18n::t;
+
+// --interval: rcp остается запущенным и повторяет копирование по расписанию. Расписание -
+// либо промежуток между началами запусков (`30s`, `15m`, `2h`, `1d`), либо строка cron из пяти
+// полей по местному времени (`0 3 * * 1-5`). Ошибка одного запуска не останавливает следующие
+
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if text.split_whitespace().count() == 5 {
+            return text.parse().map(Schedule::Cron);
+        }
+        let (number, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("expected a duration such as 15m or a cron line, got `{}`", text))?;
+        let seconds = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(format!("unknown duration unit `{}`; use s, m, h or d", unit)),
+        };
+        if number == 0 {
+            return Err("the interval must be longer than zero".to_string());
+        }
+        Ok(Schedule::Every(Duration::from_secs(number * seconds)))
+    }
+}
+
+impl Schedule {
+    // Начало следующего запуска; started - начало предыдущего
+    fn next(&self, started: SystemTime) -> SystemTime {
+        match self {
+            Schedule::Every(interval) => started + *interval,
+            Schedule::Cron(cron) => cron
+                .next_after(SystemTime::now())
+                .expect("cron lines that never fire are rejected when parsing"),
+        }
+    }
+}
+
+pub fn repeat(schedule: &Schedule, parse: impl Fn() -> Args) -> Result<()> {
+    // По cron первый запуск ждет своего времени, с интервалом - начинается сразу
+    if let Schedule::Cron(_) = schedule {
+        wait_until(schedule.next(SystemTime::now()));
+    }
+    for number in 1.. {
+        let started = SystemTime::now();
+        println!("{}", t!("run-started", number = number, time = LocalTime(started).to_string()).bold());
+        let clock = Instant::now();
+        let succeeded = run_and_report(parse());
+        let elapsed = clock.elapsed();
+        let seconds = format!("{:.1}", elapsed.as_secs_f64());
+        if succeeded {
+            println!("{}", t!("run-finished", number = number, seconds = seconds).green());
+        } else {
+            println!("{}", t!("run-failed", number = number, seconds = seconds).red());
+        }
+
+        let next = schedule.next(started).max(SystemTime::now());
+        println!("{}", t!("next-run", time = LocalTime(next).to_string()));
+        wait_until(next);
+    }
+    Ok(())
+}
+
+// Один проход копирования для повторяющихся режимов (--interval, `rcp watch`): ошибка
+// печатается, а не завершает процесс
+pub fn run_and_report(args: Args) -> bool {
+    match crate::run(args) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{} {:#}", t!("error").red().bold(), e);
+            false
+        }
+    }
+}
+
+fn wait_until(time: SystemTime) {
+    // Часы могут перевести, поэтому спим короткими отрезками и сверяемся с ними
+    while let Ok(left) = time.duration_since(SystemTime::now()) {
+        thread::sleep(left.min(Duration::from_secs(60)));
+    }
+}
+
+// Поле cron - битовая маска допустимых значений
+#[derive(Clone, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Как в cron: если ограничены и число, и день недели, подходит любое из двух
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err("a cron line has five fields: minute hour day month weekday".to_string());
+        };
+        let mut weekday_mask = parse_field(weekdays, 0, 7)?;
+        // 7 - тоже воскресенье
+        if weekday_mask & (1 << 7) != 0 {
+            weekday_mask |= 1;
+        }
+        let cron = Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_mask,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        };
+        match cron.next_after(SystemTime::now()) {
+            Some(_) => Ok(cron),
+            None => Err(format!("the cron line `{}` never fires", text)),
+        }
+    }
+}
+
+// `*`, `5`, `1-5`, `*/15`, `0-30/10` и их списки через запятую
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&step| step > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| format!("invalid step in cron field `{}`", field))?;
+        let number = |text: &str| {
+            text.parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("`{}` is out of range {}-{} in cron field `{}`", text, min, max, field))
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("empty range in cron field `{}`", field));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl Cron {
+    fn matches(&self, tm: &libc::tm) -> bool {
+        let day = self.days & (1 << tm.tm_mday) != 0;
+        let weekday = self.weekdays & (1 << tm.tm_wday) != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        };
+        self.minutes & (1 << tm.tm_min) != 0
+            && self.hours & (1 << tm.tm_hour) != 0
+            && self.months & (1 << (tm.tm_mon + 1)) != 0
+            && day_matches
+    }
+
+    // Перебираем минуты вперед; None - за 4 года не сработает ни разу (`0 0 31 2 *`)
+    fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let seconds = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let mut minute = seconds / 60 + 1;
+        let limit = minute + 4 * 366 * 24 * 60;
+        while minute < limit {
+            let tm = local_tm(minute * 60);
+            if self.matches(&tm) {
+                return Some(SystemTime::UNIX_EPOCH + Duration::from_secs(minute as u64 * 60));
+            }
+            // Неподходящий час пропускаем целиком
+            minute += if self.hours & (1 << tm.tm_hour) == 0 { 60 - tm.tm_min as i64 } else { 1 };
+        }
+        None
+    }
+}
+
+fn local_tm(seconds: i64) -> libc::tm {
+    let time = seconds as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    tm
+}
+
+struct LocalTime(SystemTime);
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let tm = local_tm(seconds);
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        )
+    }
+}
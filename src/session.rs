@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Журнал передачи на сервер rcp: пути назначения уже переданных файлов, по строке
+// на файл. Повторный запуск с тем же ID пропускает их, а недокачанные файлы сервер
+// хранит сам и передача продолжается с места обрыва
+pub struct SessionLog {
+    id: String,
+    path: PathBuf,
+    file: Mutex<File>,
+    done: HashSet<PathBuf>,
+}
+
+// $XDG_STATE_HOME/rcp, по умолчанию ~/.local/state/rcp
+pub fn state_dir() -> Result<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?).join(".local/state"),
+    };
+    Ok(state.join("rcp"))
+}
+
+fn new_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&now.to_le_bytes());
+    hasher.update(&std::process::id().to_le_bytes());
+    hasher.finalize().to_hex()[..16].to_string()
+}
+
+impl SessionLog {
+    // Без ID начинается новая сессия
+    pub fn open(id: Option<&str>) -> Result<Self> {
+        let id = match id {
+            Some(id) if id.is_empty() || id.contains(['/', '\0']) || id.starts_with('.') => {
+                anyhow::bail!("Invalid session ID: {}", id)
+            }
+            Some(id) => id.to_string(),
+            None => new_id(),
+        };
+        let dir = state_dir()?.join("sessions");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        let path = dir.join(&id);
+
+        let done = match fs::read(&path) {
+            Ok(contents) => contents
+                .split(|&byte| byte == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open session log: {}", path.display()))?;
+
+        Ok(SessionLog {
+            id,
+            path,
+            file: Mutex::new(file),
+            done,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_done(&self, destination: &Path) -> bool {
+        self.done.contains(destination)
+    }
+
+    // Ошибка записи журнала не мешает копированию: в худшем случае файл передастся снова
+    pub fn mark_done(&self, destination: &Path) {
+        let mut line = destination.as_os_str().as_bytes().to_vec();
+        if line.contains(&b'\n') {
+            return;
+        }
+        line.push(b'\n');
+        let _ = self.file.lock().unwrap().write_all(&line);
+    }
+
+    // Сессия завершена целиком - журнал больше не нужен
+    pub fn remove(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
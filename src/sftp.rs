@@ -0,0 +1,858 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::http::percent_decode;
+use crate::transport::{Entry, EntryKind, Sink, SinkFile, Source};
+
+// Клиент SFTP (версия 3) поверх `ssh host -s sftp`: ключи, агент и ~/.ssh/config
+// работают так же, как у ssh/scp. Одно соединение общее для всех рабочих потоков,
+// ответы раздаются по номерам запросов, поэтому файлы пишутся параллельно
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_LSTAT: u8 = 7;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_MKDIR: u8 = 14;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_READLINK: u8 = 19;
+const SSH_FXP_SYMLINK: u8 = 20;
+const SSH_FXP_EXTENDED: u8 = 200;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+
+const SSH_FXF_READ: u32 = 0x01;
+const SSH_FXF_WRITE: u32 = 0x02;
+const SSH_FXF_CREAT: u32 = 0x08;
+const SSH_FXF_TRUNC: u32 = 0x10;
+
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x01;
+const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x02;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x08;
+const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x8000_0000;
+
+// Серверы OpenSSH принимают до 256 KiB данных в пакете, берем с запасом
+const MAX_WRITE_CHUNK: usize = 32 * 1024;
+const MAX_READ_CHUNK: u32 = 32 * 1024;
+// Сколько запросов чтения или записи одного файла может ждать ответа
+const MAX_IN_FLIGHT: usize = 16;
+// Защита от мусора в потоке: пакеты SFTP заметно меньше
+const MAX_PACKET_LEN: usize = 4 * 1024 * 1024;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+// Путь в стиле scp: `[user@]host:path`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteLocation {
+    pub host: String,
+    pub path: PathBuf,
+}
+
+impl RemoteLocation {
+    // Как и scp, считаем удаленным путь с `:` до первого `/`; существующий
+    // локальный файл с двоеточием в имени остается локальным
+    pub fn parse(location: &Path) -> Option<Self> {
+        if let Some(url) = location.to_str()?.strip_prefix("sftp://") {
+            return Self::parse_url(url);
+        }
+        if location.exists() {
+            return None;
+        }
+        let (host, path) = location.to_str()?.split_once(':')?;
+        // Хост с `-` в начале ssh принял бы за свой ключ (`-oProxyCommand=...`)
+        if host.is_empty() || host.contains('/') || host.starts_with('-') {
+            return None;
+        }
+        // Пустой путь - домашняя директория, как `scp file host:`
+        let path = if path.is_empty() { "." } else { path };
+        Some(RemoteLocation {
+            host: host.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl RemoteLocation {
+    // `sftp://[user@]host[:port]/path`: путь абсолютный, `/~/path` - от домашней директории.
+    // Порт ssh понимает в адресе вида `ssh://host:port`
+    fn parse_url(url: &str) -> Option<Self> {
+        let (authority, path) = url.split_once('/').unwrap_or((url, ""));
+        let (_, host) = authority.rsplit_once('@').unwrap_or(("", authority));
+        if host.is_empty() || authority.starts_with('-') {
+            return None;
+        }
+        let has_port = host.rsplit_once(':').is_some_and(|(_, port)| !port.contains(']'));
+        let host = if has_port { format!("ssh://{}", authority) } else { authority.to_string() };
+        let path = match path.strip_prefix("~/") {
+            Some(path) if !path.is_empty() => percent_decode(path),
+            Some(_) => ".".to_string(),
+            None if path == "~" => ".".to_string(),
+            None => format!("/{}", percent_decode(path)),
+        };
+        Some(RemoteLocation {
+            host,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl std::fmt::Display for RemoteLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.path.display())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Attributes {
+    pub size: Option<u64>,
+    pub permissions: Option<u32>,
+    pub modified: Option<u32>,
+}
+
+impl Attributes {
+    fn file_type(&self) -> Option<u32> {
+        self.permissions.map(|mode| mode & S_IFMT)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == Some(S_IFDIR)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == Some(S_IFREG)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == Some(S_IFLNK)
+    }
+}
+
+
+struct Response {
+    kind: u8,
+    body: Vec<u8>,
+}
+
+struct Connection {
+    input: Mutex<ChildStdin>,
+    pending: Arc<Mutex<HashMap<u32, Sender<Response>>>>,
+    next_id: AtomicU32,
+    child: Mutex<Child>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Все запросы к этому моменту подтверждены, ssh можно просто завершить
+        if let Ok(mut child) = self.child.lock() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SftpSession {
+    connection: Arc<Connection>,
+    rsh: String,
+    host: String,
+    // Сервер копирует файлы сам (расширение copy-data, OpenSSH 9.0+)
+    copy_data: bool,
+}
+
+impl SftpSession {
+    // rsh - команда запуска ssh (--rsh), к ней добавляются хост и подсистема sftp. Как и у
+    // sftp из OpenSSH, хост идет после `--`: ключи после него ssh уже не разбирает
+    pub fn connect(rsh: &str, host: &str) -> Result<Self> {
+        let mut parts = rsh.split_whitespace();
+        let program = parts.next().context("Empty remote shell command")?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .args(["-s", "--", host, "sftp"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", program))?;
+
+        let mut input = child.stdin.take().context("Failed to open ssh stdin")?;
+        let mut output = child.stdout.take().context("Failed to open ssh stdout")?;
+
+        let mut init = Vec::new();
+        put_u32(&mut init, 3);
+        write_packet(&mut input, SSH_FXP_INIT, &init)
+            .with_context(|| format!("Failed to start SFTP session with {}", host))?;
+        // ssh уже напечатал причину (ключ, хост, отключенная подсистема sftp)
+        let (kind, version) = read_packet(&mut output)
+            .map_err(|_| anyhow::anyhow!("Failed to start SFTP session with {}: connection closed", host))?;
+        if kind != SSH_FXP_VERSION {
+            anyhow::bail!("Unexpected SFTP handshake reply from {}", host);
+        }
+        // За номером версии идут пары "имя расширения - данные"
+        let mut decoder = Decoder::new(&version);
+        decoder.u32()?;
+        let mut copy_data = false;
+        while let Ok(name) = decoder.bytes()
+            && decoder.bytes().is_ok()
+        {
+            copy_data |= name == b"copy-data";
+        }
+
+        let pending: Arc<Mutex<HashMap<u32, Sender<Response>>>> = Arc::new(Mutex::new(HashMap::new()));
+        spawn_dispatcher(output, Arc::clone(&pending));
+
+        Ok(SftpSession {
+            connection: Arc::new(Connection {
+                input: Mutex::new(input),
+                pending,
+                next_id: AtomicU32::new(1),
+                child: Mutex::new(child),
+            }),
+            rsh: rsh.to_string(),
+            host: host.to_string(),
+            copy_data,
+        })
+    }
+
+    // Отправляет запрос, не дожидаясь ответа
+    fn send(&self, kind: u8, payload: &[u8]) -> Result<Receiver<Response>> {
+        let id = self.connection.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel();
+        self.connection.pending.lock().unwrap().insert(id, sender);
+
+        let mut packet = Vec::with_capacity(payload.len() + 4);
+        put_u32(&mut packet, id);
+        packet.extend_from_slice(payload);
+        let mut input = self.connection.input.lock().unwrap();
+        write_packet(&mut *input, kind, &packet).context("SFTP connection lost")?;
+        Ok(receiver)
+    }
+
+    fn request(&self, kind: u8, payload: &[u8]) -> Result<Response> {
+        self.send(kind, payload)?.recv().map_err(|_| anyhow::anyhow!("SFTP connection closed"))
+    }
+
+    pub fn stat(&self, path: &Path) -> Result<Option<Attributes>> {
+        let response = self.request(SSH_FXP_STAT, &path_payload(path))?;
+        match response.kind {
+            SSH_FXP_ATTRS => Ok(Some(Decoder::new(&response.body).attributes()?)),
+            _ => match status_code(&response)? {
+                (SSH_FX_NO_SUCH_FILE, _) => Ok(None),
+                (_, message) => anyhow::bail!("stat {}: {}", path.display(), message),
+            },
+        }
+    }
+
+    // Без перехода по символическим ссылкам
+    pub fn lstat(&self, path: &Path) -> Result<Option<Attributes>> {
+        let response = self.request(SSH_FXP_LSTAT, &path_payload(path))?;
+        match response.kind {
+            SSH_FXP_ATTRS => Ok(Some(Decoder::new(&response.body).attributes()?)),
+            _ => match status_code(&response)? {
+                (SSH_FX_NO_SUCH_FILE, _) => Ok(None),
+                (_, message) => anyhow::bail!("Failed to read remote metadata {}: {}", path.display(), message),
+            },
+        }
+    }
+
+    // Содержимое директории без `.` и `..`
+    pub fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Attributes)>> {
+        let handle = self.open_handle(SSH_FXP_OPENDIR, &path_payload(path))
+            .with_context(|| format!("Failed to read remote directory: {}", path.display()))?;
+        let mut handle_payload = Vec::new();
+        put_bytes(&mut handle_payload, &handle);
+
+        let mut entries = Vec::new();
+        loop {
+            let response = self.request(SSH_FXP_READDIR, &handle_payload)?;
+            if response.kind != SSH_FXP_NAME {
+                match status_code(&response)? {
+                    (SSH_FX_EOF, _) => break,
+                    (_, message) => anyhow::bail!("Failed to read remote directory {}: {}", path.display(), message),
+                }
+            }
+
+            let mut decoder = Decoder::new(&response.body);
+            for _ in 0..decoder.u32()? {
+                let name = decoder.bytes()?;
+                decoder.bytes()?; // longname в стиле ls -l
+                let attributes = decoder.attributes()?;
+                if name != b"." && name != b".." {
+                    entries.push((path.join(OsStr::from_bytes(name)), attributes));
+                }
+            }
+        }
+
+        expect_ok(self.request(SSH_FXP_CLOSE, &handle_payload)?)?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+
+    pub fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        let response = self.request(SSH_FXP_READLINK, &path_payload(path))?;
+        if response.kind != SSH_FXP_NAME {
+            let (_, message) = status_code(&response)?;
+            anyhow::bail!("Failed to read remote symlink {}: {}", path.display(), message);
+        }
+        let mut decoder = Decoder::new(&response.body);
+        decoder.u32()?;
+        Ok(PathBuf::from(OsStr::from_bytes(decoder.bytes()?)))
+    }
+
+    pub fn open(&self, path: &Path) -> Result<RemoteReader> {
+        let mut payload = path_payload(path);
+        put_u32(&mut payload, SSH_FXF_READ);
+        put_u32(&mut payload, 0);
+        let handle = self
+            .open_handle(SSH_FXP_OPEN, &payload)
+            .with_context(|| format!("Failed to open remote file: {}", path.display()))?;
+
+        Ok(RemoteReader {
+            session: self.clone(),
+            handle,
+            next_offset: 0,
+            in_flight: VecDeque::new(),
+            buffer: Vec::new(),
+            position: 0,
+            eof: false,
+        })
+    }
+
+    fn open_handle(&self, kind: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let response = self.request(kind, payload)?;
+        if response.kind != SSH_FXP_HANDLE {
+            let (_, message) = status_code(&response)?;
+            anyhow::bail!("{}", message);
+        }
+        Ok(Decoder::new(&response.body).bytes()?.to_vec())
+    }
+
+    pub fn mkdir(&self, path: &Path) -> Result<()> {
+        let mut payload = path_payload(path);
+        put_u32(&mut payload, 0);
+        expect_ok(self.request(SSH_FXP_MKDIR, &payload)?)
+            .with_context(|| format!("Failed to create remote directory: {}", path.display()))
+    }
+
+    // Аналог create_dir_all: создаем недостающих предков по порядку
+    pub fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        for ancestor in path.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()) {
+            match self.stat(ancestor)? {
+                Some(attributes) if attributes.is_dir() => break,
+                Some(_) => anyhow::bail!("Remote path exists and is not a directory: {}", ancestor.display()),
+                None => missing.push(ancestor),
+            }
+        }
+        for directory in missing.into_iter().rev() {
+            // Параллельный запуск мог успеть создать директорию раньше нас
+            if let Err(e) = self.mkdir(directory)
+                && !self.stat(directory)?.is_some_and(|attributes| attributes.is_dir())
+            {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        expect_ok(self.request(SSH_FXP_REMOVE, &path_payload(path))?)
+            .with_context(|| format!("Failed to remove remote file: {}", path.display()))
+    }
+
+    pub fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        // OpenSSH исторически принимает аргументы в обратном порядке (цель, затем ссылка),
+        // остальные серверы в этом ему следуют
+        let mut payload = path_payload(target);
+        put_bytes(&mut payload, link.as_os_str().as_bytes());
+        expect_ok(self.request(SSH_FXP_SYMLINK, &payload)?)
+            .with_context(|| format!("Failed to create remote symlink: {}", link.display()))
+    }
+
+    // Копия файла без передачи данных через эту машину: расширение copy-data, иначе `cp`
+    // на сервере через ssh. false - сервер так не умеет
+    pub fn copy_file(&self, source: &Path, destination: &Path) -> Result<bool> {
+        if !self.copy_data {
+            return self.copy_with_cp(source, destination);
+        }
+
+        let mut payload = path_payload(source);
+        put_u32(&mut payload, SSH_FXF_READ);
+        put_u32(&mut payload, 0);
+        let reader = self
+            .open_handle(SSH_FXP_OPEN, &payload)
+            .with_context(|| format!("Failed to open remote file: {}", source.display()))?;
+        let mut payload = path_payload(destination);
+        put_u32(&mut payload, SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_TRUNC);
+        put_u32(&mut payload, 0);
+        let writer = self
+            .open_handle(SSH_FXP_OPEN, &payload)
+            .with_context(|| format!("Failed to create remote file: {}", destination.display()))?;
+
+        // Длина 0 - до конца файла
+        let mut payload = Vec::new();
+        put_bytes(&mut payload, b"copy-data");
+        put_bytes(&mut payload, &reader);
+        put_u64(&mut payload, 0);
+        put_u64(&mut payload, 0);
+        put_bytes(&mut payload, &writer);
+        put_u64(&mut payload, 0);
+        let copied = expect_ok(self.request(SSH_FXP_EXTENDED, &payload)?);
+
+        for handle in [reader, writer] {
+            let mut payload = Vec::new();
+            put_bytes(&mut payload, &handle);
+            expect_ok(self.request(SSH_FXP_CLOSE, &payload)?)
+                .with_context(|| format!("Failed to write remote file: {}", destination.display()))?;
+        }
+        copied.with_context(|| format!("Failed to copy {} on {}", source.display(), self.host))?;
+        Ok(true)
+    }
+
+    // Ошибка `cp` (нет такой команды, сервер без shell) - не ошибка копирования: файл
+    // просто пойдет через эту машину. Результат сверяем по размеру
+    fn copy_with_cp(&self, source: &Path, destination: &Path) -> Result<bool> {
+        let Some(size) = self.lstat(source)?.and_then(|attributes| attributes.size) else {
+            return Ok(false);
+        };
+        let mut parts = self.rsh.split_whitespace();
+        let program = parts.next().context("Empty remote shell command")?;
+        let status = Command::new(program)
+            .args(parts)
+            .args(["--", &self.host, "cp", "--", &shell_quote(source), &shell_quote(destination)])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if !status.is_ok_and(|status| status.success()) {
+            return Ok(false);
+        }
+        Ok(self.stat(destination)?.and_then(|attributes| attributes.size) == Some(size))
+    }
+
+    pub fn create(&self, path: &Path) -> Result<RemoteFile> {
+        let mut payload = path_payload(path);
+        put_u32(&mut payload, SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_TRUNC);
+        put_u32(&mut payload, 0);
+        let handle = self
+            .open_handle(SSH_FXP_OPEN, &payload)
+            .with_context(|| format!("Failed to create remote file: {}", path.display()))?;
+
+        Ok(RemoteFile {
+            session: self.clone(),
+            handle,
+            offset: 0,
+            in_flight: VecDeque::new(),
+        })
+    }
+}
+
+fn entry(attributes: &Attributes) -> Entry {
+    let kind = if attributes.is_dir() {
+        EntryKind::Dir
+    } else if attributes.is_symlink() {
+        EntryKind::Symlink
+    } else if attributes.is_file() {
+        EntryKind::File
+    } else {
+        EntryKind::Other
+    };
+    let size = if kind == EntryKind::File { attributes.size.unwrap_or(0) } else { 0 };
+    let modified = attributes
+        .modified
+        .map(|seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.into()));
+    Entry { kind, size, modified }
+}
+
+impl Source for SftpSession {
+    fn describe(&self, path: &Path) -> String {
+        format!("{}:{}", self.host, path.display())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Option<Entry>> {
+        Ok(self.lstat(path)?.as_ref().map(entry))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Entry)>> {
+        Ok(SftpSession::read_dir(self, path)?
+            .into_iter()
+            .map(|(path, attributes)| (path, entry(&attributes)))
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> Result<(Box<dyn Read>, u64)> {
+        let size = self.lstat(path)?.and_then(|attributes| attributes.size).unwrap_or(0);
+        Ok((Box::new(SftpSession::open(self, path)?), size))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        SftpSession::read_link(self, path)
+    }
+}
+
+impl Sink for SftpSession {
+    fn name(&self) -> String {
+        self.host.clone()
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(self.stat(path)?.is_some_and(|attributes| attributes.is_dir()))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        SftpSession::create_dir_all(self, path)
+    }
+
+    fn create(&self, path: &Path, _modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>> {
+        Ok(Box::new(SftpSession::create(self, path)?))
+    }
+
+    fn copy_within(&self, source: &Path, destination: &Path) -> Result<bool> {
+        self.copy_file(source, destination)
+    }
+
+    // Существующий файл на месте ссылки удаляем, как и локально
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        let _ = self.remove(link);
+        SftpSession::symlink(self, target, link)
+    }
+}
+
+// Файл на удаленной стороне: запись идет конвейером, подтверждения
+// проверяются, когда очередь заполнена, и при закрытии
+pub struct RemoteFile {
+    session: SftpSession,
+    handle: Vec<u8>,
+    offset: u64,
+    in_flight: VecDeque<Receiver<Response>>,
+}
+
+impl RemoteFile {
+    fn wait_oldest(&mut self) -> Result<()> {
+        if let Some(receiver) = self.in_flight.pop_front() {
+            let response = receiver.recv().map_err(|_| anyhow::anyhow!("SFTP connection closed"))?;
+            expect_ok(response)?;
+        }
+        Ok(())
+    }
+
+    // Дожидаемся всех подтверждений записи и закрываем файл
+    pub fn close(mut self) -> Result<()> {
+        while !self.in_flight.is_empty() {
+            self.wait_oldest()?;
+        }
+        let mut payload = Vec::new();
+        put_bytes(&mut payload, &self.handle);
+        expect_ok(self.session.request(SSH_FXP_CLOSE, &payload)?)
+    }
+}
+
+impl SinkFile for RemoteFile {
+    fn close(self: Box<Self>) -> Result<()> {
+        RemoteFile::close(*self)
+    }
+}
+
+impl Write for RemoteFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.chunks(MAX_WRITE_CHUNK) {
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                self.wait_oldest().map_err(io::Error::other)?;
+            }
+            let mut payload = Vec::with_capacity(chunk.len() + self.handle.len() + 16);
+            put_bytes(&mut payload, &self.handle);
+            put_u64(&mut payload, self.offset);
+            put_bytes(&mut payload, chunk);
+            let receiver = self.session.send(SSH_FXP_WRITE, &payload).map_err(io::Error::other)?;
+            self.in_flight.push_back(receiver);
+            self.offset += chunk.len() as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Файл на сервере для чтения: запросы следующих блоков отправляются заранее,
+// чтобы задержка сети не делила скорость на число запросов
+pub struct RemoteReader {
+    session: SftpSession,
+    handle: Vec<u8>,
+    next_offset: u64,
+    in_flight: VecDeque<(u64, u32, Receiver<Response>)>,
+    buffer: Vec<u8>,
+    position: usize,
+    eof: bool,
+}
+
+impl RemoteReader {
+    fn request_block(&mut self, offset: u64, len: u32) -> Result<Receiver<Response>> {
+        let mut payload = Vec::with_capacity(self.handle.len() + 16);
+        put_bytes(&mut payload, &self.handle);
+        put_u64(&mut payload, offset);
+        put_u32(&mut payload, len);
+        self.session.send(SSH_FXP_READ, &payload)
+    }
+
+    // Данные блока или None в конце файла
+    fn receive_block(receiver: Receiver<Response>) -> Result<Option<Vec<u8>>> {
+        let response = receiver.recv().map_err(|_| anyhow::anyhow!("SFTP connection closed"))?;
+        if response.kind == SSH_FXP_DATA {
+            return Ok(Some(Decoder::new(&response.body).bytes()?.to_vec()));
+        }
+        match status_code(&response)? {
+            (SSH_FX_EOF, _) => Ok(None),
+            (_, message) => anyhow::bail!("{}", message),
+        }
+    }
+
+    fn fill_buffer(&mut self) -> Result<()> {
+        while !self.eof && self.in_flight.len() < MAX_IN_FLIGHT {
+            let receiver = self.request_block(self.next_offset, MAX_READ_CHUNK)?;
+            self.in_flight.push_back((self.next_offset, MAX_READ_CHUNK, receiver));
+            self.next_offset += u64::from(MAX_READ_CHUNK);
+        }
+
+        let Some((offset, len, receiver)) = self.in_flight.pop_front() else {
+            return Ok(());
+        };
+        let Some(mut data) = Self::receive_block(receiver)? else {
+            // Остальные заранее запрошенные блоки тоже за концом файла
+            self.eof = true;
+            self.in_flight.clear();
+            return Ok(());
+        };
+
+        // Сервер вправе вернуть меньше запрошенного - дочитываем хвост блока,
+        // иначе в данных образуется дыра перед следующим запросом
+        while data.len() < len as usize {
+            let missing_offset = offset + data.len() as u64;
+            let receiver = self.request_block(missing_offset, len - data.len() as u32)?;
+            match Self::receive_block(receiver)? {
+                Some(rest) if !rest.is_empty() => data.extend_from_slice(&rest),
+                _ => {
+                    self.eof = true;
+                    self.in_flight.clear();
+                    break;
+                }
+            }
+        }
+
+        self.buffer = data;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.buffer.len() {
+            if self.eof && self.in_flight.is_empty() {
+                return Ok(0);
+            }
+            self.fill_buffer().map_err(io::Error::other)?;
+            if self.position >= self.buffer.len() {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Drop for RemoteReader {
+    fn drop(&mut self) {
+        // Ответы на незабранные запросы отбросит диспетчер, закрытие не ждем
+        let mut payload = Vec::new();
+        put_bytes(&mut payload, &self.handle);
+        let _ = self.session.send(SSH_FXP_CLOSE, &payload);
+    }
+}
+
+// Читает ответы сервера и раздает их ожидающим запросам; при обрыве
+// соединения все ожидающие получают ошибку
+fn spawn_dispatcher(mut output: ChildStdout, pending: Arc<Mutex<HashMap<u32, Sender<Response>>>>) {
+    thread::spawn(move || {
+        while let Ok((kind, body)) = read_packet(&mut output) {
+            if body.len() < 4 {
+                break;
+            }
+            let id = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+            if let Some(sender) = pending.lock().unwrap().remove(&id) {
+                let _ = sender.send(Response { kind, body: body[4..].to_vec() });
+            }
+        }
+        pending.lock().unwrap().clear();
+    });
+}
+
+fn write_packet(output: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(payload.len() + 5);
+    put_u32(&mut packet, payload.len() as u32 + 1);
+    packet.push(kind);
+    packet.extend_from_slice(payload);
+    output.write_all(&packet)?;
+    output.flush()
+}
+
+fn read_packet(input: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length == 0 || length > MAX_PACKET_LEN {
+        return Err(io::Error::other("invalid SFTP packet length"));
+    }
+    let mut packet = vec![0; length];
+    input.read_exact(&mut packet)?;
+    Ok((packet[0], packet[1..].to_vec()))
+}
+
+// ssh склеивает аргументы в командную строку для shell сервера
+pub fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, value: &[u8]) {
+    put_u32(buffer, value.len() as u32);
+    buffer.extend_from_slice(value);
+}
+
+fn path_payload(path: &Path) -> Vec<u8> {
+    let mut payload = Vec::new();
+    put_bytes(&mut payload, path.as_os_str().as_bytes());
+    payload
+}
+
+fn status_code(response: &Response) -> Result<(u32, String)> {
+    if response.kind != SSH_FXP_STATUS {
+        anyhow::bail!("Unexpected SFTP response type {}", response.kind);
+    }
+    let mut decoder = Decoder::new(&response.body);
+    let code = decoder.u32()?;
+    // Старые серверы присылают статус без текста
+    let message = decoder
+        .bytes()
+        .map(|message| String::from_utf8_lossy(message).into_owned())
+        .unwrap_or_default();
+    Ok((code, message))
+}
+
+fn expect_ok(response: Response) -> Result<()> {
+    match status_code(&response)? {
+        (SSH_FX_OK, _) => Ok(()),
+        (code, message) if message.is_empty() => anyhow::bail!("SFTP error code {}", code),
+        (_, message) => anyhow::bail!("{}", message),
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Decoder { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            anyhow::bail!("Truncated SFTP packet");
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok((u64::from(self.u32()?) << 32) | u64::from(self.u32()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn attributes(&mut self) -> Result<Attributes> {
+        let flags = self.u32()?;
+        let mut attributes = Attributes::default();
+        if flags & SSH_FILEXFER_ATTR_SIZE != 0 {
+            attributes.size = Some(self.u64()?);
+        }
+        if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+            self.u32()?;
+            self.u32()?;
+        }
+        if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+            attributes.permissions = Some(self.u32()?);
+        }
+        if flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+            self.u32()?;
+            attributes.modified = Some(self.u32()?);
+        }
+        if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.bytes()?;
+                self.bytes()?;
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_that_look_like_ssh_options_are_not_remote() {
+        let location = RemoteLocation::parse(Path::new("backup@nas:data/2024")).unwrap();
+        assert_eq!((location.host.as_str(), location.path.as_path()), ("backup@nas", Path::new("data/2024")));
+        let location = RemoteLocation::parse(Path::new("sftp://nas:2222/~/data")).unwrap();
+        assert_eq!((location.host.as_str(), location.path.as_path()), ("ssh://nas:2222", Path::new("data")));
+
+        assert!(RemoteLocation::parse(Path::new("-oProxyCommand=touch pwned:file")).is_none());
+        assert!(RemoteLocation::parse(Path::new("sftp://-oProxyCommand=touch pwned/file")).is_none());
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+use crate::cli::{Args, SnapshotArgs};
+use crate::i18n::t;
+
+// `rcp snapshot`: каждая копия - каталог `ГГГГ-ММ-ДД_ЧЧММСС` (местное время) в корне копий.
+// Копия пишется в `ИМЯ.partial` с --link-dest на последнюю завершенную и получает свое имя
+// только после успешного копирования, так что прерванная копия не станет основой следующей
+
+const PARTIAL_SUFFIX: &str = ".partial";
+
+struct Snapshot {
+    name: String,
+    // Дней от 1970-01-01
+    day: i64,
+}
+
+pub fn run(args: &SnapshotArgs) -> Result<()> {
+    let root = &args.backup_root;
+    fs::create_dir_all(root).with_context(|| format!("Failed to create directory: {}", root.display()))?;
+    let (mut snapshots, partial) = list(root)?;
+
+    let name = now_name();
+    if snapshots.iter().any(|snapshot| snapshot.name == name) {
+        anyhow::bail!("Snapshot {} already exists", root.join(&name).display());
+    }
+    let target = root.join(format!("{}{}", name, PARTIAL_SUFFIX));
+    // Уже скопированное прерванной копией не пропадает: каталог продолжает новая
+    if let Some(partial) = partial {
+        fs::rename(root.join(&partial), &target)
+            .with_context(|| format!("Failed to rename {} to {}", partial, target.display()))?;
+        println!("{}", t!("snapshot-continuing", name = partial.as_str()));
+    }
+
+    let mut copy = vec![OsString::from("rcp")];
+    if let Some(latest) = snapshots.last() {
+        copy.push("--link-dest".into());
+        copy.push(root.join(&latest.name).into());
+    }
+    copy.push("--".into());
+    copy.extend(args.sources.iter().map(OsString::from));
+    copy.push(target.clone().into());
+    crate::run(Args::parse_from(copy))?;
+
+    let finished = root.join(&name);
+    fs::rename(&target, &finished)
+        .with_context(|| format!("Failed to rename {} to {}", target.display(), finished.display()))?;
+    println!("{}", t!("snapshot-created", path = finished.display().to_string()).green());
+
+    let day = parse_name(&name).context("Unexpected snapshot name")?;
+    snapshots.push(Snapshot { name, day });
+    prune(root, snapshots, args)
+}
+
+// Завершенные копии от старых к новым и самая новая прерванная; прочее в корне не трогаем
+fn list(root: &Path) -> Result<(Vec<Snapshot>, Option<String>)> {
+    let mut snapshots = Vec::new();
+    let mut partial: Option<String> = None;
+    for entry in fs::read_dir(root).with_context(|| format!("Failed to read directory: {}", root.display()))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        match name.strip_suffix(PARTIAL_SUFFIX) {
+            Some(base) if parse_name(base).is_some() => {
+                if partial.as_ref().is_none_or(|newest| *newest < name) {
+                    partial = Some(name);
+                }
+            }
+            Some(_) => {}
+            None => {
+                if let Some(day) = parse_name(&name) {
+                    snapshots.push(Snapshot { name, day });
+                }
+            }
+        }
+    }
+    // Имена сортируются в хронологическом порядке
+    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((snapshots, partial))
+}
+
+// Как в restic: дни и недели считаются только те, в которых есть копии; самая новая копия
+// остается всегда. Без --keep-* ничего не удаляется
+fn prune(root: &Path, mut snapshots: Vec<Snapshot>, args: &SnapshotArgs) -> Result<()> {
+    if args.keep_daily.is_none() && args.keep_weekly.is_none() {
+        return Ok(());
+    }
+    snapshots.reverse();
+
+    let mut keep: HashSet<&str> = snapshots.first().map(|snapshot| snapshot.name.as_str()).into_iter().collect();
+    for (limit, length) in [(args.keep_daily, 1), (args.keep_weekly, 7)] {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let mut seen = HashSet::new();
+        for snapshot in &snapshots {
+            if seen.len() == limit {
+                break;
+            }
+            // Неделя начинается с понедельника; 1970-01-01 - четверг
+            if seen.insert((snapshot.day + 3).div_euclid(length)) {
+                keep.insert(&snapshot.name);
+            }
+        }
+    }
+
+    for snapshot in snapshots.iter().filter(|snapshot| !keep.contains(snapshot.name.as_str())) {
+        let path = root.join(&snapshot.name);
+        fs::remove_dir_all(&path).with_context(|| format!("Failed to remove snapshot {}", path.display()))?;
+        println!("{}", t!("snapshot-removed", name = snapshot.name.as_str()));
+    }
+    Ok(())
+}
+
+pub fn now_name() -> String {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02}_{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+// `ГГГГ-ММ-ДД_ЧЧММСС` -> день копии
+fn parse_name(name: &str) -> Option<i64> {
+    let bytes = name.as_bytes();
+    let digits_at = [0, 1, 2, 3, 5, 6, 8, 9, 11, 12, 13, 14, 15, 16];
+    if bytes.len() != 17
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes[10] != b'_'
+        || !digits_at.iter().all(|&i| bytes[i].is_ascii_digit())
+    {
+        return None;
+    }
+    let year: i64 = name[0..4].parse().ok()?;
+    let month: i64 = name[5..7].parse().ok()?;
+    let day: i64 = name[8..10].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+// Алгоритм Говарда Хиннанта для пролептического григорианского календаря
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use indicatif::HumanBytes;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::cli::SpaceCheck;
+use crate::collect::CopyItem;
+use crate::i18n::t;
+
+// keeps_holes - останутся ли дыры разреженного файла дырами в основном назначении. Файлы,
+// которые станут жесткими ссылками на уже лежащие в назначении (--link-dest, --dedup-dest,
+// --dedup), в files уже не входят: места они не занимают
+pub fn check_free_space(
+    files: &[CopyItem],
+    destinations: &[PathBuf],
+    mode: SpaceCheck,
+    keeps_holes: impl Fn(&CopyItem) -> bool,
+) -> Result<()> {
+    if mode == SpaceCheck::Off {
+        return Ok(());
+    }
+
+    // Назначения на одной файловой системе суммируем
+    let mut per_device: Vec<(u64, &Path, u64)> = Vec::new();
+    for (index, destination) in destinations.iter().enumerate() {
+        let mount_point = existing_ancestor(destination);
+        let device = fs::metadata(mount_point).map_or(0, |metadata| metadata.dev());
+        let required = required_bytes(files, index, &keeps_holes);
+
+        match per_device.iter_mut().find(|(dev, _, _)| *dev == device) {
+            Some((_, _, total)) => *total += required,
+            None => per_device.push((device, mount_point, required)),
+        }
+    }
+
+    for (_, mount_point, required) in per_device {
+        check_device(mount_point, required, mode)?;
+    }
+
+    Ok(())
+}
+
+fn check_device(mount_point: &Path, required: u64, mode: SpaceCheck) -> Result<()> {
+    let available = available_bytes(mount_point)
+        .with_context(|| format!("Failed to query free space on {}", mount_point.display()))?;
+
+    if required <= available {
+        return Ok(());
+    }
+
+    match mode {
+        SpaceCheck::Refuse => anyhow::bail!(
+            "Not enough free space on {}: need {}, available {} (use --space-check warn to copy anyway)",
+            mount_point.display(),
+            HumanBytes(required),
+            HumanBytes(available)
+        ),
+        _ => {
+            eprintln!(
+                "{} {}",
+                t!("warning").yellow().bold(),
+                t!(
+                    "not-enough-space",
+                    path = mount_point.display().to_string(),
+                    required = HumanBytes(required).to_string(),
+                    available = HumanBytes(available).to_string(),
+                )
+            );
+            Ok(())
+        }
+    }
+}
+
+// Перезаписываемые файлы освобождают свое место, его вычитаем из требуемого
+fn required_bytes(files: &[CopyItem], destination_index: usize, keeps_holes: &impl Fn(&CopyItem) -> bool) -> u64 {
+    files
+        .iter()
+        .filter_map(|item| Some((item, item.destinations().nth(destination_index)?)))
+        .map(|(item, destination)| {
+            let existing = fs::symlink_metadata(destination)
+                .ok()
+                .filter(|metadata| metadata.is_file())
+                .map_or(0, |metadata| allocated_bytes(&metadata));
+            // Дополнительные назначения (--also-to) всегда пишутся целиком
+            let size = match destination_index == 0 && keeps_holes(item) {
+                true => fs::metadata(&item.source).map_or(item.size, |metadata| allocated_bytes(&metadata)),
+                false => item.size,
+            };
+            size.saturating_sub(existing)
+        })
+        .sum()
+}
+
+// Место, которое файл занимает на диске: у разреженного файла дыры не в счет
+pub fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+    (metadata.blocks() * 512).min(metadata.len())
+}
+
+// Назначение может еще не существовать - берем ближайший существующий предок
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+fn available_bytes(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let stat = unsafe { stat.assume_init() };
+    // Разрядность полей statvfs зависит от платформы
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn holes_are_not_counted_when_the_copy_keeps_them() {
+        let dir = std::env::temp_dir().join(format!("rcp-space-sparse-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source");
+        let file = File::create(&source).unwrap();
+        file.write_all_at(&[7; 8192], 1 << 20).unwrap();
+        file.set_len(4 << 20).unwrap();
+        let allocated = allocated_bytes(&file.metadata().unwrap());
+        let files = [CopyItem::with_size(&source, vec![dir.join("copy"), dir.join("also")], 4 << 20)];
+
+        assert_eq!(required_bytes(&files, 0, &|_| true), allocated);
+        assert_eq!(required_bytes(&files, 0, &|_| false), 4 << 20);
+        // --also-to пишется целиком
+        assert_eq!(required_bytes(&files, 1, &|_| true), 4 << 20);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
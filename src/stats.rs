@@ -0,0 +1,106 @@
+use colored::Colorize;
+use indicatif::HumanBytes;
+use std::time::Duration;
+
+use crate::i18n::t;
+use crate::progress::format_speed;
+
+// Границы корзин гистограммы размеров для --stats
+const SIZE_BUCKETS: [(u64, &str); 4] = [
+    (4 * 1024, "< 4 KiB"),
+    (1024 * 1024, "< 1 MiB"),
+    (100 * 1024 * 1024, "< 100 MiB"),
+    (1024 * 1024 * 1024, "< 1 GiB"),
+];
+
+// Файлы меньше этого в список по скорости не попадают: их время - в основном накладные
+// расходы на открытие и создание, а не чтение
+const MIN_THROUGHPUT_SIZE: u64 = 1024 * 1024;
+
+pub struct FileTiming {
+    pub source: String,
+    pub size: u64,
+    pub elapsed: Duration,
+}
+
+impl FileTiming {
+    fn rate(&self) -> f64 {
+        self.size as f64 / self.elapsed.as_secs_f64().max(0.001)
+    }
+}
+
+// --slowest: самые долгие файлы и файлы с самой низкой скоростью - так виден один плохой
+// сектор или патологический файл, который держит все копирование
+pub fn report_slowest(timings: &mut [FileTiming], count: usize) {
+    if timings.is_empty() || count == 0 {
+        return;
+    }
+
+    timings.sort_by_key(|timing| std::cmp::Reverse(timing.elapsed));
+    println!("{}", t!("slowest-files", count = count.min(timings.len())).bold());
+    for timing in timings.iter().take(count) {
+        print_timing(timing);
+    }
+
+    let mut by_rate: Vec<&FileTiming> = timings.iter().filter(|timing| timing.size >= MIN_THROUGHPUT_SIZE).collect();
+    if by_rate.is_empty() {
+        return;
+    }
+    by_rate.sort_by(|a, b| a.rate().total_cmp(&b.rate()));
+    println!("{}", t!("lowest-throughput", size = HumanBytes(MIN_THROUGHPUT_SIZE).to_string()).bold());
+    for timing in by_rate.into_iter().take(count) {
+        print_timing(timing);
+    }
+}
+
+fn print_timing(timing: &FileTiming) {
+    println!(
+        "  {:>8.2}s {:>11} {:>12} {}",
+        timing.elapsed.as_secs_f64(),
+        HumanBytes(timing.size).to_string(),
+        format!("{}/s", format_speed(timing.rate())),
+        timing.source
+    );
+}
+
+// --stats: итог копирования и распределение размеров файлов. По нему видно, чего в наборе
+// больше - мелких файлов, где все решает число потоков, или крупных, где важнее буфер
+pub fn report_stats(timings: &[FileTiming], elapsed: Duration) {
+    let bytes: u64 = timings.iter().map(|timing| timing.size).sum();
+    println!(
+        "{}",
+        t!(
+            "stats-total",
+            count = timings.len(),
+            size = HumanBytes(bytes).to_string(),
+            seconds = format!("{:.1}", elapsed.as_secs_f64()),
+            rate = format_speed(bytes as f64 / elapsed.as_secs_f64().max(0.001)),
+        )
+    );
+    if timings.is_empty() {
+        return;
+    }
+
+    let mut buckets = [(0usize, 0u64); SIZE_BUCKETS.len() + 1];
+    for timing in timings {
+        let index = SIZE_BUCKETS
+            .iter()
+            .position(|&(limit, _)| timing.size < limit)
+            .unwrap_or(SIZE_BUCKETS.len());
+        buckets[index].0 += 1;
+        buckets[index].1 += timing.size;
+    }
+    println!("{}", t!("file-sizes").bold());
+    let unit = t!("files-column");
+    let labels = SIZE_BUCKETS.iter().map(|&(_, label)| label).chain(["≥ 1 GiB"]);
+    for (label, (files, size)) in labels.zip(buckets) {
+        println!(
+            "  {:<10} {:>8} {} {:>4.0}% {:>11}",
+            label,
+            files,
+            unit,
+            files as f64 * 100.0 / timings.len() as f64,
+            HumanBytes(size).to_string()
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use indicatif::{HumanBytes, HumanCount};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::i18n::t;
+use crate::report;
+
+// Уведомления systemd (sd_notify) для rcp, запущенного службой с Type=notify: READY=1, когда
+// режим готов к работе, строка STATUS= с ходом синхронизации для `systemctl status` и
+// WATCHDOG=1, если у службы задан WatchdogSec=. Без NOTIFY_SOCKET ничего не делается
+
+// Строка состояния обновляется не чаще, чем раз в это время
+const STATUS_INTERVAL: Duration = Duration::from_secs(2);
+
+// Итоги всех запусков процесса: `rcp watch` и --interval копируют много раз
+static SYNCED_FILES: AtomicU64 = AtomicU64::new(0);
+static SYNCED_BYTES: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    status: Box<dyn Fn() -> String + Send + Sync>,
+}
+
+impl Notifier {
+    fn send(&self, message: &str) {
+        let _ = self.socket.send_to_addr(message.as_bytes(), &self.address);
+    }
+}
+
+// Сообщает, что служба запущена, и дальше сама обновляет STATUS= строкой от status
+pub fn ready(status: impl Fn() -> String + Send + Sync + 'static) {
+    let Some((socket, address)) = connect() else {
+        return;
+    };
+    if NOTIFIER.set(Notifier { socket, address, status: Box::new(status) }).is_err() {
+        return;
+    }
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    let status = (notifier.status)();
+    notifier.send(&format!("READY=1\nSTATUS={}\n", status));
+
+    // Сторожевой таймер ждет сигнала вдвое чаще своего срока
+    let watchdog = watchdog_interval().map(|interval| interval / 2);
+    let tick = watchdog.map_or(STATUS_INTERVAL, |watchdog| watchdog.min(STATUS_INTERVAL));
+    thread::spawn(move || {
+        let mut last_status = status;
+        let mut last_watchdog = Instant::now();
+        loop {
+            thread::sleep(tick);
+            if watchdog.is_some_and(|watchdog| last_watchdog.elapsed() + tick / 2 >= watchdog) {
+                notifier.send("WATCHDOG=1\n");
+                last_watchdog = Instant::now();
+            }
+            let status = (notifier.status)();
+            if status != last_status {
+                notifier.send(&format!("STATUS={}\n", status));
+                last_status = status;
+            }
+        }
+    });
+}
+
+// Сразу, не дожидаясь потока: итог запуска, после которого процесс может и выйти
+pub fn refresh() {
+    if let Some(notifier) = NOTIFIER.get() {
+        notifier.send(&format!("STATUS={}\n", (notifier.status)()));
+    }
+}
+
+pub fn copied(size: u64) {
+    SYNCED_FILES.fetch_add(1, Ordering::Relaxed);
+    SYNCED_BYTES.fetch_add(size, Ordering::Relaxed);
+}
+
+pub fn failed() {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+// Строка состояния копирования: итоги всех запусков и ход идущего
+pub fn sync_status() -> String {
+    let files = SYNCED_FILES.load(Ordering::Relaxed);
+    let synced = t!(
+        "systemd-synced",
+        count = files,
+        files = HumanCount(files).to_string(),
+        size = HumanBytes(SYNCED_BYTES.load(Ordering::Relaxed)).to_string(),
+        errors = ERRORS.load(Ordering::Relaxed)
+    );
+    match report::progress() {
+        Some((total, progress)) => {
+            let copying = t!(
+                "systemd-copying",
+                count = total,
+                files = HumanCount(total as u64).to_string(),
+                percent = (progress * 100.0) as u32
+            );
+            format!("{}. {}", copying, synced)
+        }
+        None => synced,
+    }
+}
+
+// NOTIFY_SOCKET - путь или абстрактное имя с '@' в начале
+fn connect() -> Option<(UnixDatagram, SocketAddr)> {
+    let path = std::env::var("NOTIFY_SOCKET").ok().filter(|path| !path.is_empty())?;
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()).ok()?,
+        None => SocketAddr::from_pathname(&path).ok()?,
+    };
+    Some((UnixDatagram::unbound().ok()?, address))
+}
+
+// WATCHDOG_USEC действует, только если WATCHDOG_PID не задан или это мы
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok().filter(|&usec| usec > 0)?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+
+// Сколько блоков может ждать записи в каждое дополнительное назначение
+pub const TEE_QUEUE_DEPTH: usize = 4;
+
+struct TeeBranch {
+    path: PathBuf,
+    sender: SyncSender<Arc<[u8]>>,
+    handle: JoinHandle<std::io::Result<()>>,
+}
+
+// Параллельная запись уже прочитанных блоков в дополнительные назначения (--also-to):
+// источник читается один раз, каждое назначение пишет свой поток
+pub struct Tee {
+    branches: Vec<TeeBranch>,
+}
+
+impl Tee {
+    // fsync - данные каждого назначения в конце сбрасываются на диск (--fsync-dirs)
+    pub fn create(destinations: &[PathBuf], fsync: bool) -> Result<Self> {
+        let mut branches = Vec::with_capacity(destinations.len());
+
+        for path in destinations {
+            let mut file = create_destination(path)?;
+            let (sender, receiver) = mpsc::sync_channel::<Arc<[u8]>>(TEE_QUEUE_DEPTH);
+            let handle = thread::spawn(move || {
+                for chunk in receiver {
+                    file.write_all(&chunk)?;
+                }
+                match fsync {
+                    true => file.sync_data(),
+                    false => Ok(()),
+                }
+            });
+            branches.push(TeeBranch { path: path.clone(), sender, handle });
+        }
+
+        Ok(Tee { branches })
+    }
+
+    pub fn write(&self, chunk: &[u8]) {
+        if self.branches.is_empty() {
+            return;
+        }
+
+        let chunk: Arc<[u8]> = Arc::from(chunk);
+        for branch in &self.branches {
+            // Если поток записи упал, ошибку вернет finish()
+            let _ = branch.sender.send(chunk.clone());
+        }
+    }
+
+    // Дожидаемся окончания записи во все назначения
+    pub fn finish(self) -> Vec<(PathBuf, anyhow::Error)> {
+        let mut errors = Vec::new();
+
+        for branch in self.branches {
+            drop(branch.sender);
+            let result = match branch.handle.join() {
+                Ok(result) => result.map_err(anyhow::Error::from),
+                Err(_) => Err(anyhow::anyhow!("writer thread panicked")),
+            };
+            if let Err(e) = result {
+                errors.push((branch.path, e));
+            }
+        }
+
+        errors
+    }
+}
+
+fn create_destination(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+    }
+
+    File::create(path).with_context(|| format!("Failed to create destination file: {}", path.display()))
+}
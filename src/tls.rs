@@ -0,0 +1,390 @@
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, ServerConfig, ServerConnection,
+    SignatureScheme, StreamOwned,
+};
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::fmt;
+use std::fs;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cli::{Args, ServeArgs};
+
+// TLS для `rcp serve` и rcp://: сертификат сервера (и клиента, если сервер их требует)
+// либо общий ключ (--psk-file). Внешних PSK TLS 1.3 в rustls нет, поэтому ключ проверяется
+// поверх TLS: стороны обмениваются MAC от экспортированного материала сессии, а посредник
+// с двумя разными сессиями такой MAC не подделает
+
+// Метка материала сессии, от которого считается MAC общего ключа
+pub const EXPORTER_LABEL: &[u8] = b"EXPORTER-rcp-psk";
+
+// Общий ключ из файла; пробельные символы по краям не считаются
+#[derive(Clone)]
+pub struct Psk {
+    key: [u8; 32],
+    // Ключ из короткого кода `rcp receive`: MAC от него перебирается офлайн, поэтому
+    // сам код служит только паролем SPAKE2, а MAC считается от выработанного ключа
+    code: bool,
+}
+
+impl Psk {
+    pub fn load(path: &Path) -> Result<Self> {
+        let secret = fs::read(path).with_context(|| format!("Failed to read PSK file: {}", path.display()))?;
+        let secret = secret.trim_ascii();
+        if secret.is_empty() {
+            anyhow::bail!("PSK file is empty: {}", path.display());
+        }
+        Ok(Psk { key: blake3::derive_key("rcp serve psk v1", secret), code: false })
+    }
+
+    // Код подтверждения `rcp receive`: пробелы при вводе не важны
+    pub fn from_code(code: &str) -> Self {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        Psk { key: blake3::derive_key("rcp pairing code v1", code.as_bytes()), code: true }
+    }
+
+    // Ключ, о котором стороны договорились по коду-фразе (`rcp send --relay`)
+    pub fn from_key(key: &[u8; 32]) -> Self {
+        Psk { key: blake3::derive_key("rcp wormhole psk v1", key), code: false }
+    }
+
+    // Ключ для MAC этой сессии. Для кода - обмен SPAKE2, привязанный к сессии TLS: посредник
+    // или поддельный получатель узнает о коде не больше, чем дает одна попытка на соединение.
+    // exchange отправляет наше сообщение и возвращает сообщение другой стороны
+    pub fn session_key(&self, exporter: &[u8; 32], exchange: impl FnOnce(&[u8]) -> Result<Vec<u8>>) -> Result<Psk> {
+        if !self.code {
+            return Ok(self.clone());
+        }
+        let identity = [b"rcp pairing".as_slice(), exporter].concat();
+        let (spake, message) = Spake2::<Ed25519Group>::start_symmetric(&Password::new(self.key), &Identity::new(&identity));
+        let reply = exchange(&message)?;
+        let shared = spake
+            .finish(&reply)
+            .map_err(|e| anyhow::anyhow!("Key exchange failed: {:?}", e))?;
+        Ok(Psk { key: blake3::derive_key("rcp pairing key v1", &shared), code: false })
+    }
+
+    // side - кто подтверждает ключ: клиент и сервер отвечают разными MAC
+    pub fn tag(&self, side: &[u8], exporter: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update(side);
+        hasher.update(exporter);
+        *hasher.finalize().as_bytes()
+    }
+}
+
+impl fmt::Debug for Psk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Psk(..)")
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates: {}", path.display()))?;
+    if certificates.is_empty() {
+        anyhow::bail!("No certificates found in {}", path.display());
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).with_context(|| format!("Failed to read private key: {}", path.display()))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots
+            .add(certificate)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+// Клиентская сторона: доверенный CA (--tls-ca) или общий ключ; без них TLS не включается
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+    pub psk: Option<Psk>,
+}
+
+impl ClientTls {
+    pub fn from_args(args: &Args) -> Result<Option<Self>> {
+        let psk = match &args.pairing {
+            Some(psk) => Some(psk.clone()),
+            None => args.psk_file.as_deref().map(Psk::load).transpose()?,
+        };
+        if args.tls_ca.is_none() && psk.is_none() {
+            if args.tls_cert.is_some() {
+                anyhow::bail!("--tls-cert needs --tls-ca or --psk-file to authenticate the server");
+            }
+            return Ok(None);
+        }
+
+        let identity = args.tls_cert.as_deref().zip(args.tls_key.as_deref());
+        Self::build(args.tls_ca.as_deref(), identity, psk).map(Some)
+    }
+
+    // QUIC без настроек TLS: канал шифруется, но сервер не проверяется - как и TCP без TLS
+    pub fn unverified() -> Result<Self> {
+        Self::build(None, None, None)
+    }
+
+    fn build(ca: Option<&Path>, identity: Option<(&Path, &Path)>, psk: Option<Psk>) -> Result<Self> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .context("Failed to set up TLS")?;
+        // С общим ключом сервер может жить на самоподписанном сертификате: подлинность
+        // подтверждает MAC после рукопожатия
+        let builder = match ca {
+            Some(ca) => builder.with_root_certificates(load_roots(ca)?),
+            None => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(UnverifiedServerCert(provider()))),
+        };
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certificates(cert)?, load_private_key(key)?)
+                .context("Invalid client certificate or key")?,
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(ClientTls {
+            config: Arc::new(config),
+            psk,
+        })
+    }
+
+    pub fn config(&self) -> Arc<ClientConfig> {
+        Arc::clone(&self.config)
+    }
+
+    // Серверы с обычным сертификатом (FTPS): проверка по --tls-ca или по корневым
+    // сертификатам Mozilla
+    pub fn public(ca: Option<&Path>) -> Result<Arc<ClientConfig>> {
+        let roots = match ca {
+            Some(ca) => load_roots(ca)?,
+            None => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .context("Failed to set up TLS")?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Arc::new(config))
+    }
+
+    // Имя для проверки сертификата - хост из `host:port`
+    pub fn server_host(address: &str) -> &str {
+        address
+            .rsplit_once(':')
+            .map_or(address, |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+    }
+
+    pub fn connect(&self, address: &str, stream: TcpStream) -> Result<StreamOwned<ClientConnection, TcpStream>> {
+        let host = Self::server_host(address);
+        let name = ServerName::try_from(host.to_string())
+            .with_context(|| format!("Invalid server name for TLS: {}", host))?;
+        let connection =
+            ClientConnection::new(Arc::clone(&self.config), name).context("Failed to start TLS session")?;
+        let mut stream = StreamOwned::new(connection, stream);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .with_context(|| format!("TLS handshake with {} failed", address))?;
+        }
+        Ok(stream)
+    }
+}
+
+// Серверная сторона: сертификат из файлов или, при одном общем ключе, временный самоподписанный
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+    pub psk: Option<Psk>,
+}
+
+impl ServerTls {
+    pub fn from_args(args: &ServeArgs) -> Result<Option<Self>> {
+        let psk = args.psk_file.as_deref().map(Psk::load).transpose()?;
+        let (certificates, key) = match (&args.tls_cert, &args.tls_key, &psk) {
+            (Some(cert), Some(key), _) => (load_certificates(cert)?, load_private_key(key)?),
+            (None, None, Some(_)) => self_signed()?,
+            (None, None, None) if args.tls_client_ca.is_none() => return Ok(None),
+            _ => anyhow::bail!("--tls-cert and --tls-key must be given together"),
+        };
+        Self::build(certificates, key, args.tls_client_ca.as_deref(), psk).map(Some)
+    }
+
+    // QUIC без настроек TLS и `rcp receive`: временный самоподписанный сертификат,
+    // клиенты проверяются только общим ключом, если он есть
+    pub fn ephemeral(psk: Option<Psk>) -> Result<Self> {
+        let (certificates, key) = self_signed()?;
+        Self::build(certificates, key, None, psk)
+    }
+
+    fn build(
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        client_ca: Option<&Path>,
+        psk: Option<Psk>,
+    ) -> Result<Self> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .context("Failed to set up TLS")?;
+        let builder = match client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(ca)?), provider())
+                    .build()
+                    .context("Failed to set up client certificate verification")?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certificates, key)
+            .context("Invalid server certificate or key")?;
+
+        Ok(ServerTls {
+            config: Arc::new(config),
+            psk,
+        })
+    }
+
+    pub fn config(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.config)
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> Result<StreamOwned<ServerConnection, TcpStream>> {
+        let connection = ServerConnection::new(Arc::clone(&self.config)).context("Failed to start TLS session")?;
+        let mut stream = StreamOwned::new(connection, stream);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock).context("TLS handshake failed")?;
+        }
+        Ok(stream)
+    }
+}
+
+fn self_signed() -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certified = rcgen::generate_simple_self_signed(vec!["rcp".to_string()])
+        .context("Failed to generate a self-signed certificate")?;
+    let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+    Ok((vec![certified.cert.der().clone()], key.into()))
+}
+
+// Сертификат сервера не проверяется по цепочке - только подпись рукопожатия; подлинность
+// сервера подтверждает общий ключ после рукопожатия, а без ключа (QUIC без TLS) - ничто
+#[derive(Debug)]
+struct UnverifiedServerCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for UnverifiedServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // Обе стороны обмениваются сообщениями SPAKE2 через каналы, как через соединение
+    fn pair(client: Psk, server: Psk, exporter: [u8; 32]) -> (Psk, Psk) {
+        let (to_server, from_client) = mpsc::channel::<Vec<u8>>();
+        let (to_client, from_server) = mpsc::channel::<Vec<u8>>();
+        let server = thread::spawn(move || {
+            server
+                .session_key(&exporter, |message| {
+                    let reply = from_client.recv()?;
+                    to_client.send(message.to_vec())?;
+                    Ok(reply)
+                })
+                .unwrap()
+        });
+        let client = client
+            .session_key(&exporter, |message| {
+                to_server.send(message.to_vec())?;
+                Ok(from_server.recv()?)
+            })
+            .unwrap();
+        (client, server.join().unwrap())
+    }
+
+    #[test]
+    fn same_code_gives_same_session_key() {
+        let exporter = [7; 32];
+        let (client, server) = pair(Psk::from_code("123 456"), Psk::from_code("123456"), exporter);
+        assert_eq!(client.tag(b"client", &exporter), server.tag(b"client", &exporter));
+    }
+
+    #[test]
+    fn wrong_code_gives_different_session_keys() {
+        let exporter = [7; 32];
+        let (client, server) = pair(Psk::from_code("123 456"), Psk::from_code("123 457"), exporter);
+        assert_ne!(client.tag(b"client", &exporter), server.tag(b"client", &exporter));
+    }
+
+    #[test]
+    fn code_is_not_the_mac_key() {
+        // MAC, который видит поддельный получатель, считается не от кода: его нельзя сверить с перебором
+        let exporter = [7; 32];
+        let code = Psk::from_code("123 456");
+        let (client, _) = pair(code.clone(), Psk::from_code("123 456"), exporter);
+        assert_ne!(client.tag(b"client", &exporter), code.tag(b"client", &exporter));
+    }
+
+    #[test]
+    fn sessions_get_different_keys() {
+        let (first, _) = pair(Psk::from_code("123 456"), Psk::from_code("123 456"), [1; 32]);
+        let (second, _) = pair(Psk::from_code("123 456"), Psk::from_code("123 456"), [2; 32]);
+        assert_ne!(first.tag(b"client", &[0; 32]), second.tag(b"client", &[0; 32]));
+    }
+}
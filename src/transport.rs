@@ -0,0 +1,304 @@
+use anyhow::Result;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::azure::{AzureLocation, AzureSession};
+use crate::cli::Args;
+use crate::dav::{DavLocation, DavSession};
+use crate::ftp::{FtpLocation, FtpSession};
+use crate::gcs::{GcsLocation, GcsSession};
+use crate::http::percent_decode;
+use crate::net::{NetLocation, NetSession};
+use crate::plugin::{find_backend, PluginLocation, PluginSession};
+use crate::sftp::{RemoteLocation, SftpSession};
+use crate::tls::ClientTls;
+use crate::web::{is_url, WebSession};
+
+// Источники и назначения за пределами локального диска. Модуль выбирается по схеме
+// адреса, а копирование работает с ним только через Source и Sink, поэтому любой
+// источник сочетается с любым назначением. Новый протокол - это модуль с реализацией
+// трейтов и строка в Address, а схемы, которых rcp не знает, обслуживают внешние
+// программы (см. plugin)
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    // Устройства, сокеты и т.п. не копируются
+    Other,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub size: u64,
+    // Время изменения, если источник его сообщает
+    pub modified: Option<SystemTime>,
+}
+
+impl Entry {
+    pub fn file(size: u64) -> Self {
+        Entry { kind: EntryKind::File, size, modified: None }
+    }
+
+    pub fn dir() -> Self {
+        Entry { kind: EntryKind::Dir, size: 0, modified: None }
+    }
+}
+
+// Прогресс передачи: сколько байт файла передано; ошибка отменяет передачу
+pub type Progress<'a> = &'a (dyn Fn(u64) -> Result<()> + Sync);
+
+pub trait Source: Send + Sync {
+    // Путь источника для сообщений (`host:path`, `ftp://host/path`)
+    fn describe(&self, path: &Path) -> String;
+
+    // Символическая ссылка описывается сама, а не ее цель; None - пути нет
+    fn stat(&self, path: &Path) -> Result<Option<Entry>>;
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Entry)>>;
+
+    // Вместе с потоком возвращает размер файла
+    fn open(&self, path: &Path) -> Result<(Box<dyn Read>, u64)>;
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        anyhow::bail!("Symlinks are not supported: {}", self.describe(path))
+    }
+
+    // Имя файла в директории назначения
+    fn file_name(&self, path: &Path) -> Option<OsString> {
+        path.file_name().map(OsString::from)
+    }
+
+    // Источник может сам скачать файл в локальный путь (например, частями в несколько
+    // соединений). false - не для этого файла, он копируется потоком через open;
+    // on_start получает размер перед началом передачи
+    fn download(
+        &self,
+        _path: &Path,
+        _destination: &Path,
+        _streams: usize,
+        _on_start: &dyn Fn(u64),
+        _on_progress: Progress,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+pub trait Sink: Send + Sync {
+    // Сервер для сообщений (`host`, `gs://bucket`)
+    fn name(&self) -> String;
+
+    fn is_dir(&self, path: &Path) -> Result<bool>;
+
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    // Время изменения известно только у локального источника
+    fn create(&self, path: &Path, modified: Option<SystemTime>) -> Result<Box<dyn SinkFile>>;
+
+    fn symlink(&self, _target: &Path, link: &Path) -> Result<()> {
+        anyhow::bail!("Symlinks cannot be stored on {}: {}", self.name(), link.display())
+    }
+
+    // Копия файла на том же сервере, откуда он читается, без передачи данных через эту
+    // машину. false - сервер так не умеет, файл копируется потоком
+    fn copy_within(&self, _source: &Path, _destination: &Path) -> Result<bool> {
+        Ok(false)
+    }
+
+    // Локальный файл как есть назначение может передать своим способом (докачка, части,
+    // дельта). false - файл пишется потоком через create; on_start получает размер
+    fn send_file(
+        &self,
+        _source: &Path,
+        _destination: &Path,
+        _streams: usize,
+        _on_start: &dyn Fn(u64),
+        _on_progress: Progress,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+// Файл в назначении считается записанным только после close()
+pub trait SinkFile: Write + Send {
+    fn close(self: Box<Self>) -> Result<()>;
+}
+
+// Адрес вне локального диска. `host:path` без схемы - SFTP, как у scp
+pub enum Address {
+    Sftp(RemoteLocation),
+    Rcp(NetLocation),
+    Gcs(GcsLocation),
+    Azure(AzureLocation),
+    Dav(DavLocation),
+    Ftp(FtpLocation),
+    Web(String),
+    Plugin(PluginLocation),
+}
+
+impl Address {
+    pub fn parse(location: &Path) -> Result<Option<Self>> {
+        let text = location.to_string_lossy();
+        let scheme = text.split_once("://").map(|(scheme, _)| scheme).filter(|scheme| {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        });
+        let address = match scheme {
+            None | Some("sftp") => RemoteLocation::parse(location).map(Address::Sftp),
+            Some("file" | "rsync") => None,
+            Some("rcp") => NetLocation::parse(location).map(Address::Rcp),
+            Some("gs") => GcsLocation::parse(location).map(Address::Gcs),
+            Some("az") => AzureLocation::parse(location).map(Address::Azure),
+            Some("dav" | "davs") => DavLocation::parse(location).map(Address::Dav),
+            Some("ftp" | "ftps") => FtpLocation::parse(location).map(Address::Ftp),
+            Some("http" | "https") if is_url(location) => Some(Address::Web(text.to_string())),
+            Some(scheme) => match find_backend(scheme) {
+                Some(program) => PluginLocation::parse(location, program).map(Address::Plugin),
+                None => anyhow::bail!(
+                    "Unsupported scheme {}://: {} (no rcp-backend-{} found in PATH)",
+                    scheme,
+                    text,
+                    scheme
+                ),
+            },
+        };
+        match address {
+            Some(address) => Ok(Some(address)),
+            None if scheme.is_some_and(|scheme| scheme != "file" && scheme != "rsync") => {
+                anyhow::bail!("Invalid address: {}", text)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Тот же сервер и те же учетные данные: с источника на назначение можно копировать
+    // на самом сервере
+    pub fn same_server(&self, other: &Address) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other) && self.server() == other.server()
+    }
+
+    // Источники одного запуска должны быть на одном сервере
+    fn server(&self) -> String {
+        match self {
+            Address::Sftp(location) => location.host.clone(),
+            Address::Rcp(location) => location.address.clone(),
+            Address::Gcs(location) => location.bucket.clone(),
+            Address::Azure(location) => location.container.clone(),
+            Address::Dav(location) => format!("{:?}@{}", location.user, location.server),
+            Address::Ftp(location) => format!("{:?}@{}/{}", location.user, location.address, location.tls),
+            // Каждый URL - самостоятельный файл
+            Address::Web(_) => String::new(),
+            Address::Plugin(location) => location.server.clone(),
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Address::Sftp(location) => location.path.clone(),
+            Address::Rcp(location) => location.path.clone(),
+            Address::Gcs(location) => location.path.clone(),
+            Address::Azure(location) => location.path.clone(),
+            Address::Dav(location) => location.path.clone(),
+            Address::Ftp(location) => location.path.clone(),
+            Address::Web(url) => PathBuf::from(url),
+            Address::Plugin(location) => location.path.clone(),
+        }
+    }
+
+    pub fn connect_source(&self, args: &Args) -> Result<Arc<dyn Source>> {
+        Ok(match self {
+            Address::Sftp(location) => Arc::new(SftpSession::connect(&args.rsh, &location.host)?),
+            Address::Dav(location) => Arc::new(DavSession::connect(
+                location,
+                args.dav_user.as_deref(),
+                args.dav_password.as_deref(),
+            )?),
+            Address::Ftp(location) => Arc::new(FtpSession::connect(
+                location,
+                args.ftp_password.as_deref(),
+                args.tls_ca.as_deref(),
+            )?),
+            Address::Web(_) => Arc::new(WebSession::new()),
+            Address::Plugin(location) => Arc::new(PluginSession::connect(location)?),
+            Address::Rcp(_) => anyhow::bail!("rcp:// can only be used as a destination"),
+            Address::Gcs(_) => anyhow::bail!("gs:// can only be used as a destination"),
+            Address::Azure(_) => anyhow::bail!("az:// can only be used as a destination"),
+        })
+    }
+
+    pub fn connect_sink(&self, args: &Args) -> Result<Arc<dyn Sink>> {
+        Ok(match self {
+            Address::Sftp(location) => Arc::new(SftpSession::connect(&args.rsh, &location.host)?),
+            Address::Rcp(location) => Arc::new(NetSession::connect(
+                &location.address,
+                ClientTls::from_args(args)?,
+                args.quic,
+                args.wire_compress,
+                args.delta,
+            )?),
+            Address::Gcs(location) => Arc::new(GcsSession::connect(&location.bucket, args.gcs_credentials.as_deref())?),
+            Address::Azure(location) => Arc::new(AzureSession::connect(
+                &location.container,
+                args.azure_account.as_deref(),
+                args.azure_endpoint.as_deref(),
+                args.azure_sas.as_deref(),
+            )?),
+            Address::Dav(location) => Arc::new(DavSession::connect(
+                location,
+                args.dav_user.as_deref(),
+                args.dav_password.as_deref(),
+            )?),
+            Address::Ftp(location) => Arc::new(FtpSession::connect(
+                location,
+                args.ftp_password.as_deref(),
+                args.tls_ca.as_deref(),
+            )?),
+            Address::Plugin(location) => Arc::new(PluginSession::connect(location)?),
+            Address::Web(_) => anyhow::bail!("http:// and https:// can only be used as sources"),
+        })
+    }
+}
+
+// Директорий в объектных хранилищах нет, поэтому "директория" - путь с завершающим
+// слешем или пустой (корень бакета)
+pub fn is_object_prefix(path: &Path) -> bool {
+    path.as_os_str().is_empty() || path.as_os_str().as_encoded_bytes().ends_with(b"/")
+}
+
+// `file:///path` - обычный локальный путь
+pub fn local_path(location: &Path) -> PathBuf {
+    match location.to_str().and_then(|location| location.strip_prefix("file://")) {
+        Some(path) => PathBuf::from(percent_decode(path)),
+        None => location.to_path_buf(),
+    }
+}
+
+// Удаленные источники: все с одного сервера, смешивать их с локальными нельзя.
+// Вместе с адресом сервера возвращает пути на нем
+pub fn parse_sources(sources: &[PathBuf]) -> Result<Option<(Address, Vec<PathBuf>)>> {
+    let addresses = sources
+        .iter()
+        .map(|source| Address::parse(source))
+        .collect::<Result<Vec<_>>>()?;
+    let Some(first) = addresses.iter().flatten().next() else {
+        return Ok(None);
+    };
+
+    let mut paths = Vec::new();
+    for (source, address) in sources.iter().zip(&addresses) {
+        let Some(address) = address else {
+            anyhow::bail!("Cannot mix local and remote sources: {}", source.display());
+        };
+        if !address.same_server(first) {
+            anyhow::bail!("All remote sources must be on the same server: {}", source.display());
+        }
+        paths.push(address.path());
+    }
+
+    Ok(addresses.into_iter().flatten().next().map(|address| (address, paths)))
+}
@@ -0,0 +1,459 @@
+use anyhow::Result;
+use indicatif::HumanBytes;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::control::{Control, WorkQueue};
+use crate::i18n::t;
+use crate::progress::{format_speed, shorten_path, ProgressUpdate};
+
+// --tui: полноэкранный интерфейс вместо прогресс-баров - таблица начатых и ожидающих файлов,
+// скорость рабочих потоков и ошибки. Клавишами файл можно приостановить, пропустить или
+// переставить в очереди
+
+const TICK: Duration = Duration::from_millis(100);
+// Скорость файла пересчитываем не чаще, иначе она скачет
+const SPEED_WINDOW: Duration = Duration::from_millis(500);
+const ERROR_LINES: u16 = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    // Начатые, затем ожидающие в порядке очереди
+    Queue,
+    Path,
+    Size,
+    Progress,
+    Speed,
+}
+
+impl SortColumn {
+    fn next(self) -> Self {
+        match self {
+            SortColumn::Queue => SortColumn::Path,
+            SortColumn::Path => SortColumn::Size,
+            SortColumn::Size => SortColumn::Progress,
+            SortColumn::Progress => SortColumn::Speed,
+            SortColumn::Speed => SortColumn::Queue,
+        }
+    }
+}
+
+struct ActiveFile {
+    path: String,
+    size: u64,
+    copied: u64,
+    speed: f64,
+    sample: (Instant, u64),
+}
+
+// Строка таблицы: начатый или ожидающий файл
+struct FileRow {
+    id: u32,
+    active: bool,
+    path: String,
+    size: u64,
+    copied: u64,
+    speed: f64,
+}
+
+impl FileRow {
+    fn progress(&self) -> f64 {
+        match self.size {
+            0 => 1.0,
+            size => self.copied as f64 / size as f64,
+        }
+    }
+}
+
+struct Tui {
+    queue: Arc<WorkQueue>,
+    control: Arc<Control>,
+    total_files: u64,
+    workers: usize,
+    done: u64,
+    failed: u64,
+    skipped: u64,
+    finished_bytes: u64,
+    started: Instant,
+    active: BTreeMap<u32, ActiveFile>,
+    skipping: HashSet<u32>,
+    errors: Vec<String>,
+    sort: SortColumn,
+    descending: bool,
+    rows: Vec<FileRow>,
+    // Выделение держится за файл, а не за номер строки: строки пересортировываются
+    selected: Option<u32>,
+    table: TableState,
+}
+
+pub fn spawn_tui(
+    total_files: usize,
+    workers: usize,
+    queue: Arc<WorkQueue>,
+    control: Arc<Control>,
+) -> (mpsc::Sender<ProgressUpdate>, thread::JoinHandle<Result<()>>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let mut tui = Tui {
+            queue,
+            control,
+            total_files: total_files as u64,
+            workers,
+            done: 0,
+            failed: 0,
+            skipped: 0,
+            finished_bytes: 0,
+            started: Instant::now(),
+            active: BTreeMap::new(),
+            skipping: HashSet::new(),
+            errors: Vec::new(),
+            sort: SortColumn::Queue,
+            descending: false,
+            rows: Vec::new(),
+            selected: None,
+            table: TableState::default(),
+        };
+        let mut terminal = ratatui::init();
+        let result = tui.run(&mut terminal, &receiver);
+        ratatui::restore();
+        tui.print_summary();
+        result
+    });
+    (sender, handle)
+}
+
+impl Tui {
+    // Работаем, пока живы отправители, как и обычный менеджер прогресса
+    fn run(&mut self, terminal: &mut DefaultTerminal, receiver: &mpsc::Receiver<ProgressUpdate>) -> Result<()> {
+        loop {
+            loop {
+                match receiver.try_recv() {
+                    Ok(update) => self.update(update),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            self.refresh();
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let mut timeout = TICK;
+            while event::poll(timeout)? {
+                if let Event::Key(key) = event::read()?
+                    && key.kind == KeyEventKind::Press
+                {
+                    self.key(key);
+                }
+                timeout = Duration::ZERO;
+            }
+        }
+    }
+
+    fn update(&mut self, update: ProgressUpdate) {
+        match update {
+            ProgressUpdate::NewFile { path, size, id } => {
+                self.active.insert(
+                    id,
+                    ActiveFile {
+                        path,
+                        size,
+                        copied: 0,
+                        speed: 0.0,
+                        sample: (Instant::now(), 0),
+                    },
+                );
+            }
+            ProgressUpdate::Progress { id, bytes_copied } => {
+                if let Some(file) = self.active.get_mut(&id) {
+                    file.copied = bytes_copied;
+                    let elapsed = file.sample.0.elapsed();
+                    if elapsed >= SPEED_WINDOW {
+                        file.speed = bytes_copied.saturating_sub(file.sample.1) as f64 / elapsed.as_secs_f64();
+                        file.sample = (Instant::now(), bytes_copied);
+                    }
+                }
+            }
+            ProgressUpdate::Finished { id } => {
+                if let Some(file) = self.active.remove(&id) {
+                    self.finished_bytes += file.size;
+                }
+                self.done += 1;
+            }
+            ProgressUpdate::Failed { id, error } => {
+                self.active.remove(&id);
+                // Прерванные клавишей - не ошибки
+                if self.skipping.remove(&id) || self.control.is_stopped() {
+                    self.skipped += 1;
+                } else {
+                    self.failed += 1;
+                    self.errors.push(error);
+                }
+            }
+            ProgressUpdate::Queued { files } => self.total_files += files,
+        }
+    }
+
+    fn refresh(&mut self) {
+        // Файл на паузе или в зависшем чтении не присылает прогресс - его скорость нулевая
+        for file in self.active.values_mut() {
+            if file.sample.0.elapsed() > 2 * SPEED_WINDOW {
+                file.speed = 0.0;
+                file.sample = (Instant::now(), file.copied);
+            }
+        }
+
+        let active = self.active.iter().map(|(&id, file)| FileRow {
+            id,
+            active: true,
+            path: file.path.clone(),
+            size: file.size,
+            copied: file.copied,
+            speed: file.speed,
+        });
+        let queued = self.queue.pending().into_iter().map(|(id, path, size)| FileRow {
+            id,
+            active: false,
+            path,
+            size,
+            copied: 0,
+            speed: 0.0,
+        });
+        self.rows = active.chain(queued).collect();
+        match self.sort {
+            SortColumn::Queue => {}
+            SortColumn::Path => self.rows.sort_by(|a, b| a.path.cmp(&b.path)),
+            SortColumn::Size => self.rows.sort_by_key(|row| row.size),
+            SortColumn::Progress => self.rows.sort_by(|a, b| a.progress().total_cmp(&b.progress())),
+            SortColumn::Speed => self.rows.sort_by(|a, b| a.speed.total_cmp(&b.speed)),
+        }
+        if self.descending {
+            self.rows.reverse();
+        }
+
+        // Файл ушел из таблицы - выделение остается на том же месте
+        let index = self
+            .selected
+            .and_then(|id| self.rows.iter().position(|row| row.id == id))
+            .or_else(|| self.table.selected().map(|index| index.min(self.rows.len().saturating_sub(1))))
+            .filter(|_| !self.rows.is_empty())
+            .or((!self.rows.is_empty()).then_some(0));
+        self.selected = index.map(|index| self.rows[index].id);
+        self.table.select(index);
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        let selected = self.table.selected().and_then(|index| self.rows.get(index)).map(|row| (row.id, row.active));
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Home => self.move_selection(isize::MIN / 2),
+            KeyCode::End => self.move_selection(isize::MAX / 2),
+            KeyCode::Char(' ') => {
+                if let Some((id, true)) = selected {
+                    self.control.toggle_file_pause(id);
+                }
+            }
+            KeyCode::Char('p') => {
+                self.control.toggle_pause();
+            }
+            KeyCode::Char('s') => match selected {
+                Some((id, true)) => {
+                    self.skipping.insert(id);
+                    self.control.skip(id);
+                }
+                Some((id, false)) if self.queue.remove(id) => self.skipped += 1,
+                _ => {}
+            },
+            KeyCode::Char('t') => {
+                if let Some((id, false)) = selected {
+                    self.queue.move_to_front(id);
+                }
+            }
+            KeyCode::Char('e') => {
+                if let Some((id, false)) = selected {
+                    self.queue.move_to_back(id);
+                }
+            }
+            KeyCode::Char('o') => self.sort = self.sort.next(),
+            KeyCode::Char('r') => self.descending = !self.descending,
+            KeyCode::Char('q') | KeyCode::Esc => self.control.abort(),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.control.abort(),
+            _ => {}
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let index = current.saturating_add(delta).clamp(0, self.rows.len() as isize - 1) as usize;
+        self.selected = Some(self.rows[index].id);
+        self.table.select(Some(index));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, errors, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(ERROR_LINES + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [files, workers] = Layout::horizontal([Constraint::Min(40), Constraint::Length(44)]).areas(body);
+
+        frame.render_widget(self.overall(), header);
+        self.draw_files(frame, files);
+        frame.render_widget(self.workers(), workers);
+
+        let start = self.errors.len().saturating_sub(ERROR_LINES as usize);
+        let lines: Vec<Line> = self.errors[start..].iter().map(|error| Line::from(error.as_str()).red()).collect();
+        let title = format!(" {} ", t!("tui-errors", count = self.errors.len()));
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), errors);
+        frame.render_widget(Line::from(t!("tui-keys")).dim(), footer);
+    }
+
+    fn overall(&self) -> Gauge<'static> {
+        let finished = self.done + self.failed + self.skipped;
+        let ratio = match self.total_files {
+            0 => 1.0,
+            total => (finished as f64 / total as f64).min(1.0),
+        };
+        let copied = self.finished_bytes + self.active.values().map(|file| file.copied).sum::<u64>();
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs();
+        let mut label = format!(
+            "{}/{} {} · {} · {}/s · {:02}:{:02}:{:02}",
+            finished,
+            self.total_files,
+            t!("files-column"),
+            HumanBytes(copied),
+            format_speed(copied as f64 / elapsed.as_secs_f64().max(0.001)),
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        if self.failed > 0 {
+            label.push_str(&format!(" · {}", t!("tui-failed", count = self.failed)));
+        }
+        if self.skipped > 0 {
+            label.push_str(&format!(" · {}", t!("tui-skipped", count = self.skipped)));
+        }
+        if self.control.is_stopped() {
+            label.push_str(&format!(" · {}", t!("state-stopping")));
+        } else if self.control.is_draining() {
+            label.push_str(&format!(" · {}", t!("tui-draining")));
+        } else if self.control.is_paused() {
+            label.push_str(&format!(" · {}", t!("state-paused")));
+        }
+        Gauge::default()
+            .block(Block::bordered().title(" rcp "))
+            .gauge_style(Style::new().cyan())
+            .ratio(ratio)
+            .label(label)
+    }
+
+    fn draw_files(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        // Остальные колонки, промежутки между ними и рамка
+        let path_width = (area.width as usize).saturating_sub(40).max(10);
+        let rows = self.rows.iter().map(|row| {
+            let status = match (row.active, self.control.is_file_paused(row.id)) {
+                (true, true) => "⏸",
+                (true, false) => "▶",
+                (false, _) => "·",
+            };
+            let (done, speed) = match row.active {
+                true => (
+                    format!("{:>3}%", (row.progress() * 100.0) as u32),
+                    format!("{}/s", format_speed(row.speed)),
+                ),
+                false => (String::new(), String::new()),
+            };
+            Row::new([
+                status.to_string(),
+                shorten_path(&row.path, path_width),
+                HumanBytes(row.size).to_string(),
+                done,
+                speed,
+            ])
+        });
+
+        let arrow = if self.descending { " ▼" } else { " ▲" };
+        let titles = [
+            (SortColumn::Queue, String::new()),
+            (SortColumn::Path, t!("tui-column-file")),
+            (SortColumn::Size, t!("tui-column-size")),
+            (SortColumn::Progress, t!("tui-column-done")),
+            (SortColumn::Speed, t!("tui-column-speed")),
+        ]
+        .map(|(column, title)| match column == self.sort {
+            true => format!("{}{}", title, arrow),
+            false => title,
+        });
+        let title = format!(
+            " {} ",
+            t!("tui-files", copying = self.active.len(), queued = self.rows.len() - self.active.len())
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(2),
+                Constraint::Fill(1),
+                Constraint::Length(11),
+                Constraint::Length(6),
+                Constraint::Length(13),
+            ],
+        )
+        .header(Row::new(titles).bold())
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn workers(&self) -> Paragraph<'static> {
+        let mut by_worker = vec![None; self.workers];
+        for (id, worker) in self.queue.running() {
+            if let Some(slot) = by_worker.get_mut(worker) {
+                *slot = Some(id);
+            }
+        }
+        let lines: Vec<Line> = by_worker
+            .iter()
+            .enumerate()
+            .map(|(worker, id)| match id.and_then(|id| self.active.get(&id)) {
+                Some(file) => {
+                    let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy();
+                    Line::from(format!(
+                        "{:>2} {:>13} {}",
+                        worker + 1,
+                        format!("{}/s", format_speed(file.speed)),
+                        shorten_path(&name, 24)
+                    ))
+                }
+                None => Line::from(format!("{:>2} {:>13}", worker + 1, t!("tui-idle"))).dim(),
+            })
+            .collect();
+        Paragraph::new(lines).block(Block::bordered().title(format!(" {} ", t!("tui-workers"))))
+    }
+
+    // Ошибки на экране терминала пропадут вместе с ним - повторяем их после выхода
+    fn print_summary(&self) {
+        for error in &self.errors {
+            eprintln!("{}", error);
+        }
+        println!(
+            "{}",
+            t!("tui-summary", done = self.done, total = self.total_files, failed = self.failed, skipped = self.skipped)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::{Args, WatchArgs};
+use crate::i18n::t;
+use crate::schedule::run_and_report;
+use crate::systemd;
+use crate::transport::Address;
+
+// `rcp watch`: первичная синхронизация, затем изменения в источнике (inotify) повторяются в
+// назначении. События копятся, пока источник не затихнет на --debounce, после чего по каждому
+// затронутому пути смотрим, что с ним сейчас: существует - копируем, нет - удаляем из назначения.
+// Так порядок событий внутри пачки неважен
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DONT_FOLLOW
+    | libc::IN_ONLYDIR;
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn watch(args: &WatchArgs) -> Result<()> {
+    let source = fs::canonicalize(&args.source)
+        .with_context(|| format!("Source path does not exist: {}", args.source.display()))?;
+    if !source.is_dir() {
+        anyhow::bail!("rcp watch needs a source directory: {}", source.display());
+    }
+    let local_destination = Address::parse(&args.destination)?.is_none();
+
+    // Наблюдение ставим до первичной синхронизации, чтобы не потерять изменения во время нее
+    let mut inotify = Inotify::new()?;
+    inotify.add_tree(&source)?;
+    // Первичная синхронизация бывает долгой; ее ход systemd увидит в строке состояния
+    systemd::ready(systemd::sync_status);
+
+    let mut contents = source.clone().into_os_string();
+    contents.push("/");
+    run_and_report(copy_args(args, vec![PathBuf::from(contents)], None));
+    println!("{}", t!("watching", path = source.display().to_string()));
+
+    let debounce = Duration::from_millis(args.debounce);
+    let mut warned_about_deletes = false;
+    loop {
+        let mut touched = BTreeSet::new();
+        inotify.read(&mut touched, None)?;
+        while inotify.read(&mut touched, Some(debounce))? {}
+        inotify.finish_batch();
+
+        let (present, gone): (Vec<PathBuf>, Vec<PathBuf>) = outermost(touched)
+            .into_iter()
+            .partition(|relative| source.join(relative).symlink_metadata().is_ok());
+
+        if !gone.is_empty() {
+            if local_destination {
+                for relative in &gone {
+                    if let Err(e) = remove(&args.destination.join(relative)) {
+                        eprintln!("{} {:#}", t!("error").red().bold(), e);
+                    } else {
+                        println!("{}", t!("removed", path = relative.display().to_string()));
+                    }
+                }
+            } else if !warned_about_deletes {
+                warned_about_deletes = true;
+                eprintln!("{} {}", t!("warning").yellow().bold(), t!("watch-remote-deletes"));
+            }
+        }
+
+        if !present.is_empty() {
+            let sources = present.iter().map(|relative| source.join(relative)).collect();
+            run_and_report(copy_args(args, sources, Some(&source)));
+        }
+    }
+}
+
+fn copy_args(args: &WatchArgs, sources: Vec<PathBuf>, relative_to: Option<&Path>) -> Args {
+    let mut copy: Vec<OsString> = vec!["rcp".into(), "--rsh".into(), args.rsh.clone().into(), "--".into()];
+    copy.extend(sources.into_iter().map(PathBuf::into_os_string));
+    copy.push(args.destination.clone().into_os_string());
+    let mut copy = Args::parse_from(copy);
+    copy.relative_to = relative_to.map(Path::to_path_buf);
+    copy
+}
+
+// Путь внутри уже затронутой директории копируется или удаляется вместе с ней
+fn outermost(touched: BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut result: Vec<PathBuf> = Vec::new();
+    for path in touched {
+        if !result.last().is_some_and(|parent| path.starts_with(parent)) {
+            result.push(path);
+        }
+    }
+    result
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            fs::remove_dir_all(path).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        Ok(_) => fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to stat {}", path.display())),
+    }
+}
+
+struct Inotify {
+    file: File,
+    root: PathBuf,
+    // Дескриптор наблюдения -> директория относительно корня
+    watches: HashMap<i32, PathBuf>,
+    // Переименования внутри дерева: cookie -> старый путь директории
+    moved_from: HashMap<u32, PathBuf>,
+}
+
+impl Inotify {
+    fn new() -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to start inotify");
+        }
+        Ok(Inotify {
+            file: unsafe { File::from_raw_fd(fd) },
+            root: PathBuf::new(),
+            watches: HashMap::new(),
+            moved_from: HashMap::new(),
+        })
+    }
+
+    fn add_tree(&mut self, root: &Path) -> Result<()> {
+        self.root = root.to_path_buf();
+        self.add_dir(Path::new(""))
+    }
+
+    fn add_dir(&mut self, relative: &Path) -> Result<()> {
+        let mut pending = vec![relative.to_path_buf()];
+        while let Some(relative) = pending.pop() {
+            let path = self.root.join(&relative);
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+            if wd < 0 {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    // Директорию успели удалить или заменить файлом
+                    Some(libc::ENOENT | libc::ENOTDIR) => continue,
+                    Some(libc::ENOSPC) => {
+                        return Err(error).context("Too many directories to watch; raise fs.inotify.max_user_watches");
+                    }
+                    _ => return Err(error).with_context(|| format!("Failed to watch {}", path.display())),
+                }
+            }
+            self.watches.insert(wd, relative.clone());
+
+            let Ok(entries) = fs::read_dir(&path) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    pending.push(relative.join(entry.file_name()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Директория, уехавшая за пределы дерева: ее события больше не наши
+    fn finish_batch(&mut self) {
+        for old in std::mem::take(&mut self.moved_from).into_values() {
+            let gone: Vec<i32> = self
+                .watches
+                .iter()
+                .filter(|(_, watched)| watched.starts_with(&old))
+                .map(|(&wd, _)| wd)
+                .collect();
+            for wd in gone {
+                unsafe { libc::inotify_rm_watch(self.file.as_raw_fd(), wd) };
+                self.watches.remove(&wd);
+            }
+        }
+    }
+
+    // Добавляет в touched пути из событий; false - за timeout событий не было
+    fn read(&mut self, touched: &mut BTreeSet<PathBuf>, timeout: Option<Duration>) -> Result<bool> {
+        let mut poll = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis().min(i32::MAX as u128) as i32);
+        loop {
+            match unsafe { libc::poll(&mut poll, 1, timeout) } {
+                0 => return Ok(false),
+                result if result > 0 => break,
+                _ => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error).context("Failed to wait for inotify events");
+                    }
+                }
+            }
+        }
+
+        let mut buffer = vec![0u8; EVENT_BUFFER_SIZE];
+        let length = self.file.read(&mut buffer).context("Failed to read inotify events")?;
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header <= length {
+            let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            let name = &buffer[offset + header..offset + header + event.len as usize];
+            let name = OsStr::from_bytes(name.split(|&byte| byte == 0).next().unwrap_or_default());
+            offset += header + event.len as usize;
+            self.handle(&event, name, touched)?;
+        }
+        Ok(true)
+    }
+
+    fn handle(&mut self, event: &libc::inotify_event, name: &OsStr, touched: &mut BTreeSet<PathBuf>) -> Result<()> {
+        // Очередь переполнилась - что именно изменилось, неизвестно, синхронизируем все дерево
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            eprintln!("{} {}", t!("warning").yellow().bold(), t!("watch-overflow"));
+            touched.insert(PathBuf::new());
+            return Ok(());
+        }
+        if event.mask & libc::IN_IGNORED != 0 {
+            self.watches.remove(&event.wd);
+            return Ok(());
+        }
+        let Some(dir) = self.watches.get(&event.wd) else {
+            return Ok(());
+        };
+        let path = dir.join(name);
+        let is_dir = event.mask & libc::IN_ISDIR != 0;
+
+        if is_dir && event.mask & libc::IN_MOVED_FROM != 0 {
+            self.moved_from.insert(event.cookie, path.clone());
+        }
+        if is_dir && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+            match self.moved_from.remove(&event.cookie).filter(|_| event.cookie != 0) {
+                // Наблюдения переехавшей директории остаются, меняются только пути
+                Some(old) => {
+                    for watched in self.watches.values_mut() {
+                        if let Ok(rest) = watched.strip_prefix(&old) {
+                            *watched = path.join(rest);
+                        }
+                    }
+                }
+                None => self.add_dir(&path)?,
+            }
+        }
+        touched.insert(path);
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use ureq::http::Response;
+use ureq::{Body, ResponseExt};
+
+use crate::http::{agent, describe_error, is_transient, percent_decode, send_with_retry};
+use crate::i18n::t;
+use crate::net::PARALLEL_MIN_SIZE;
+use crate::transport::{Entry, Progress, Source};
+use crate::BUFFER_SIZE;
+
+// Источники http:// и https://: размер и поддержка диапазонов узнаются по HEAD, большой
+// файл скачивается частями (Range) по нескольким соединениям одновременно, а оборванная
+// часть докачивается с места обрыва
+
+const MAX_REDIRECTS: u32 = 10;
+const ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+pub fn is_url(source: &Path) -> bool {
+    source
+        .to_str()
+        .is_some_and(|source| source.starts_with("http://") || source.starts_with("https://"))
+}
+
+// Имя файла для URL - последний сегмент пути без параметров запроса
+pub fn file_name(url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    let (_, path) = url.split_once("://")?.1.split_once('/')?;
+    let name = percent_decode(path.rsplit('/').next()?);
+    (!matches!(name.as_str(), "" | "." | "..") && !name.contains('/')).then_some(name)
+}
+
+#[derive(Clone, Debug)]
+pub struct WebEntry {
+    // Адрес после перенаправлений: части запрашиваются сразу по нему
+    pub url: String,
+    pub size: Option<u64>,
+    pub ranges: bool,
+    // ETag или Last-Modified для If-Range: файл не должен смениться между частями
+    validator: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct WebSession {
+    agent: ureq::Agent,
+}
+
+impl WebSession {
+    pub fn new() -> Self {
+        WebSession { agent: agent() }
+    }
+
+    // Размер и диапазоны относятся к файлу как есть, поэтому сжатие при передаче не просим
+    fn get(&self, url: &str) -> ureq::RequestBuilder<ureq::typestate::WithoutBody> {
+        self.agent
+            .get(url)
+            .header("Accept-Encoding", "identity")
+            .config()
+            .max_redirects(MAX_REDIRECTS)
+            .build()
+    }
+
+    pub fn stat(&self, url: &str) -> Result<WebEntry> {
+        let response = send_with_retry(&format!("HEAD {}", url), || {
+            self.agent
+                .head(url)
+                .header("Accept-Encoding", "identity")
+                .config()
+                .max_redirects(MAX_REDIRECTS)
+                .build()
+                .call()
+        })?;
+        let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok());
+        let validator = header("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+            .or_else(|| header("Last-Modified"))
+            .map(str::to_string);
+        Ok(WebEntry {
+            url: response.get_uri().to_string(),
+            size: content_length(&response),
+            ranges: header("Accept-Ranges").is_some_and(|ranges| ranges.eq_ignore_ascii_case("bytes")),
+            validator,
+        })
+    }
+
+    // Файл одним запросом; вместе с потоком возвращает размер, если сервер его сообщил
+    pub fn open(&self, url: &str) -> Result<(ureq::BodyReader<'static>, u64)> {
+        let response = send_with_retry(&format!("Download of {}", url), || self.get(url).call())?;
+        let size = content_length(&response).unwrap_or(0);
+        Ok((response.into_body().into_reader(), size))
+    }
+
+    // Части качаются в отдельных потоках и пишутся прямо на свои места в файле
+    pub fn download_in_ranges(
+        &self,
+        entry: &WebEntry,
+        destination: &Path,
+        size: u64,
+        streams: usize,
+        on_progress: impl Fn(u64) -> Result<()> + Sync,
+    ) -> Result<()> {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
+        }
+        let file = File::create(destination)
+            .with_context(|| format!("Failed to create destination file: {}", destination.display()))?;
+        file.set_len(size)
+            .with_context(|| format!("Failed to allocate destination file: {}", destination.display()))?;
+
+        let range_len = size.div_ceil(streams as u64);
+        let received = AtomicU64::new(0);
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..streams as u64)
+                .map(|i| (i * range_len, ((i + 1) * range_len).min(size)))
+                .filter(|(start, end)| start < end)
+                .map(|(start, end)| {
+                    let (file, received, on_progress) = (&file, &received, &on_progress);
+                    scope.spawn(move || self.download_range(entry, file, start..end, received, on_progress))
+                })
+                .collect();
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Transfer thread panicked")))
+            })
+        })
+        .with_context(|| format!("Failed to write file: {}", destination.display()))
+    }
+
+    fn download_range(
+        &self,
+        entry: &WebEntry,
+        file: &File,
+        range: std::ops::Range<u64>,
+        received: &AtomicU64,
+        on_progress: &(impl Fn(u64) -> Result<()> + Sync),
+    ) -> Result<()> {
+        let mut offset = range.start;
+        let mut attempt = 0;
+        while offset < range.end {
+            let error = match self.fetch_range(entry, file, offset..range.end, received, on_progress) {
+                Ok(Ok(end)) => {
+                    offset = end;
+                    continue;
+                }
+                Ok(Err(e)) => return Err(e),
+                Err((end, e)) => {
+                    // Продвинулись - счетчик попыток начинается заново
+                    if end > offset {
+                        attempt = 0;
+                    }
+                    offset = end;
+                    e
+                }
+            };
+            attempt += 1;
+            if attempt >= ATTEMPTS {
+                return Err(error);
+            }
+            eprintln!("{}", t!("resuming-at", error = format!("{:#}", error), offset = offset));
+            thread::sleep(RETRY_DELAY);
+        }
+        Ok(())
+    }
+
+    // Ok - часть получена целиком или внутренняя ошибка окончательная; Err - сбой,
+    // после которого стоит продолжить с достигнутого смещения
+    fn fetch_range(
+        &self,
+        entry: &WebEntry,
+        file: &File,
+        range: std::ops::Range<u64>,
+        received: &AtomicU64,
+        on_progress: &(impl Fn(u64) -> Result<()> + Sync),
+    ) -> Result<Result<u64>, (u64, anyhow::Error)> {
+        let mut offset = range.start;
+        let mut request = self
+            .get(&entry.url)
+            .header("Range", &format!("bytes={}-{}", range.start, range.end - 1));
+        if let Some(validator) = &entry.validator {
+            request = request.header("If-Range", validator);
+        }
+        let mut response = match request.call() {
+            Ok(response) => response,
+            Err(e) => return Err((offset, anyhow::Error::new(e).context(format!("Download of {} failed", entry.url)))),
+        };
+        let status = response.status().as_u16();
+        if status != 206 {
+            let error = describe_error(&mut response);
+            return match status {
+                // Весь файл вместо части: не совпал If-Range, то есть файл на сервере сменился
+                200 => Ok(Err(anyhow::anyhow!("{} changed on the server during download", entry.url))),
+                _ if is_transient(status) => Err((offset, anyhow::anyhow!("Download of {} failed: {}", entry.url, error))),
+                _ => Ok(Err(anyhow::anyhow!("Download of {} failed: {}", entry.url, error))),
+            };
+        }
+        let starts_at_offset = response
+            .headers()
+            .get("Content-Range")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(&format!("bytes {}-", range.start)));
+        if !starts_at_offset {
+            return Ok(Err(anyhow::anyhow!("{} returned a different range than requested", entry.url)));
+        }
+
+        let mut body = response.into_body().into_reader();
+        let mut buffer = vec![0; BUFFER_SIZE];
+        while offset < range.end {
+            let len = ((range.end - offset) as usize).min(BUFFER_SIZE);
+            let read = match body.read(&mut buffer[..len]) {
+                Ok(0) => return Err((offset, anyhow::anyhow!("Download of {} ended early", entry.url))),
+                Ok(read) => read,
+                Err(e) => return Err((offset, anyhow::Error::new(e).context(format!("Download of {} failed", entry.url)))),
+            };
+            if let Err(e) = file.write_all_at(&buffer[..read], offset) {
+                return Ok(Err(anyhow::Error::new(e).context("Failed to write downloaded data")));
+            }
+            offset += read as u64;
+            if let Err(e) = on_progress(received.fetch_add(read as u64, Ordering::Relaxed) + read as u64) {
+                return Ok(Err(e));
+            }
+        }
+        Ok(Ok(offset))
+    }
+}
+
+// Каждый URL - один файл; имя в директории назначения - последний сегмент пути
+impl Source for WebSession {
+    fn describe(&self, path: &Path) -> String {
+        path.display().to_string()
+    }
+
+    fn stat(&self, path: &Path) -> Result<Option<Entry>> {
+        let entry = WebSession::stat(self, &path.to_string_lossy())?;
+        Ok(Some(Entry::file(entry.size.unwrap_or(0))))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, Entry)>> {
+        anyhow::bail!("Cannot list {}", path.display())
+    }
+
+    fn open(&self, path: &Path) -> Result<(Box<dyn Read>, u64)> {
+        let (reader, size) = WebSession::open(self, &path.to_string_lossy())?;
+        Ok((Box::new(reader), size))
+    }
+
+    fn file_name(&self, path: &Path) -> Option<OsString> {
+        file_name(&path.to_string_lossy()).map(OsString::from)
+    }
+
+    // Большой файл с поддержкой диапазонов качается частями в несколько соединений
+    fn download(
+        &self,
+        path: &Path,
+        destination: &Path,
+        streams: usize,
+        on_start: &dyn Fn(u64),
+        on_progress: Progress,
+    ) -> Result<bool> {
+        let entry = WebSession::stat(self, &path.to_string_lossy())?;
+        let Some(size) = entry.size.filter(|&size| entry.ranges && size >= PARALLEL_MIN_SIZE) else {
+            return Ok(false);
+        };
+        on_start(size);
+        self.download_in_ranges(&entry, destination, size, streams, on_progress)?;
+        Ok(true)
+    }
+}
+
+fn content_length(response: &Response<Body>) -> Option<u64> {
+    response
+        .headers()
+        .get("Content-Length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|length| length.parse().ok())
+}
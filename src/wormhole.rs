@@ -0,0 +1,262 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use ring::rand::{SecureRandom, SystemRandom};
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::{Args, ReceiveArgs, SendArgs};
+use crate::i18n::t;
+use crate::nearby::{copy_to_peer, prompt};
+use crate::net::{accept_all, read_frame, receive_from_peer, tags_match, write_frame, Incoming};
+use crate::relay::{self, pipe, Side};
+use crate::tls::{Psk, ServerTls};
+
+// `rcp send --relay` / `rcp receive --relay` в духе magic-wormhole: отправитель печатает
+// код-фразу `НОМЕР-слово-слово`, получатель ее вводит. По номеру обе стороны встречаются
+// на ретрансляторе и проводят через него SPAKE2 с фразой в качестве пароля: подслушавший
+// или подменивший ретранслятор фразу не узнает и перебрать ее может только одной попыткой
+// на запуск. Из общего ключа получается PSK для TLS протокола `rcp serve`, поэтому
+// дальше все как у `rcp send` по mDNS. Получатель сообщает свои адреса; если напрямую
+// отправитель до него не достает, соединения идут через ретранслятор по каналу из ключа
+
+const FRAME_PAKE: u8 = 1;
+const FRAME_CONFIRM: u8 = 2;
+const FRAME_HINTS: u8 = 3;
+
+// Номер канала - от 1 до 999, слова - по 8 бит каждое
+const MAX_CHANNEL: u64 = 999;
+const CODE_WORDS: usize = 2;
+const DIRECT_TIMEOUT: Duration = Duration::from_secs(2);
+const RELAY_RETRY: Duration = Duration::from_secs(1);
+const PROXY_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alert", "alpha", "amber",
+    "angle", "apple", "april", "arena", "armor", "arrow", "aspen", "atlas", "attic", "audio",
+    "autumn", "avenue", "bacon", "badge", "bagel", "baker", "bamboo", "banana", "banjo", "barn",
+    "basil", "basket", "beach", "beacon", "bean", "bear", "beaver", "bell", "berry", "bicycle",
+    "binder", "birch", "bishop", "blade", "blanket", "bloom", "blossom", "blue", "bonus", "book",
+    "border", "bottle", "boxer", "brain", "brave", "bread", "breeze", "brick", "bridge", "broom",
+    "bubble", "bucket", "buffalo", "bugle", "button", "cabin", "cable", "cactus", "camel", "camera",
+    "canal", "candle", "canoe", "canyon", "carbon", "carpet", "carrot", "castle", "cedar", "cello",
+    "chalk", "cherry", "chess", "circle", "citrus", "clay", "cliff", "clock", "cloud", "clover",
+    "cobalt", "cocoa", "comet", "copper", "coral", "cotton", "cougar", "coyote", "crane", "crater",
+    "crayon", "cricket", "crystal", "cupcake", "dagger", "daisy", "dancer", "delta", "denim", "desert",
+    "diamond", "dolphin", "donkey", "dragon", "dream", "drum", "eagle", "easel", "echo", "elbow",
+    "ember", "emerald", "engine", "falcon", "feather", "fennel", "ferry", "fiddle", "finch", "flame",
+    "flute", "forest", "fossil", "fountain", "fox", "galaxy", "garden", "garlic", "gecko", "ginger",
+    "glacier", "globe", "goblin", "gopher", "granite", "grape", "gravel", "guitar", "hammer", "harbor",
+    "harvest", "hazel", "helmet", "heron", "hippo", "honey", "hornet", "iceberg", "igloo", "indigo",
+    "island", "ivory", "jacket", "jaguar", "jasmine", "jelly", "jigsaw", "jungle", "kayak", "kettle",
+    "kiwi", "koala", "ladder", "lagoon", "lantern", "laser", "lemon", "lettuce", "lilac", "lily",
+    "linen", "lizard", "lobster", "locket", "lotus", "lunar", "magnet", "mango", "maple", "marble",
+    "meadow", "melon", "meteor", "mint", "mirror", "mitten", "monkey", "mosaic", "moss", "motor",
+    "muffin", "nectar", "needle", "nickel", "noodle", "nutmeg", "oasis", "ocean", "olive", "onion",
+    "orbit", "orchid", "otter", "owl", "oyster", "paddle", "palace", "panda", "paper", "parrot",
+    "peach", "pebble", "pepper", "piano", "pickle", "pilot", "pine", "planet", "plum", "pocket",
+    "polar", "pony", "poppy", "potato", "prism", "puffin", "pumpkin", "puzzle", "quartz", "quill",
+    "rabbit", "radar", "radish", "raven", "ribbon", "river", "robin", "rocket", "ruby", "saddle",
+    "salmon", "sandal", "satin", "scarf", "shadow", "shell",
+];
+
+// Вместе с аргументами копирования - прокси до ретранслятора, если он понадобился:
+// его нужно держать до конца копирования
+pub fn send(args: &SendArgs, relay: &str) -> Result<(Args, Option<RelayProxy>)> {
+    let code = match &args.code {
+        Some(code) => code.trim().to_string(),
+        None => new_code()?,
+    };
+    let channel = channel_of(&code)?;
+    println!("{}", t!("wormhole-code", code = code.bold().to_string()));
+    println!("{}", t!("wormhole-code-hint", relay = relay));
+
+    let mut stream = relay::connect(relay, channel, Side::Send)?;
+    let key = exchange(&mut stream, &code, Side::Send)?;
+    let hints = receive_message(&mut stream, FRAME_HINTS)?;
+    drop(stream);
+
+    let (destination, proxy) = match direct_route(&String::from_utf8_lossy(&hints)) {
+        Some(address) => {
+            println!("{}", t!("wormhole-direct", address = address.to_string()));
+            (format!("rcp://{}/", address), None)
+        }
+        None => {
+            println!("{}", t!("wormhole-relayed", relay = relay));
+            let proxy = RelayProxy::start(relay, data_channel(&key))?;
+            (format!("rcp://127.0.0.1:{}/", proxy.port), Some(proxy))
+        }
+    };
+    Ok((copy_to_peer(&args.sources, &destination, Psk::from_key(&key)), proxy))
+}
+
+pub fn receive(args: &ReceiveArgs, relay: &str) -> Result<()> {
+    if !args.directory.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", args.directory.display());
+    }
+    let code = match &args.code {
+        Some(code) => code.trim().to_string(),
+        None => prompt(&t!("wormhole-code-prompt"))?,
+    };
+    let channel = channel_of(&code)?;
+
+    let mut stream = relay::connect(relay, channel, Side::Receive)?;
+    let key = exchange(&mut stream, &code, Side::Receive)?;
+    let listener = TcpListener::bind(&args.listen).with_context(|| format!("Failed to listen on {}", args.listen))?;
+    let port = listener.local_addr().context("Failed to get listening address")?.port();
+    write_frame(&mut stream, FRAME_HINTS, hints(port).as_bytes()).context("Failed to send addresses to the sender")?;
+    drop(stream);
+
+    // Через ретранслятор отправитель может открыть сколько угодно соединений: одно
+    // всегда ждет на канале, а как только его занимают, открывается следующее
+    let relay = relay.to_string();
+    let channel = data_channel(&key);
+    let standby: Incoming = Box::new(std::iter::from_fn(move || {
+        loop {
+            match relay::connect(&relay, &channel, Side::Receive) {
+                Ok(stream) => return Some(stream),
+                Err(e) => {
+                    eprintln!("{} {:#}", t!("warning").yellow().bold(), e);
+                    thread::sleep(RELAY_RETRY);
+                }
+            }
+        }
+    }));
+
+    println!("{}", t!("wormhole-receiving", directory = args.directory.display().to_string()));
+    let tls = Arc::new(ServerTls::ephemeral(Some(Psk::from_key(&key)))?);
+    receive_from_peer(vec![accept_all(listener), standby], &args.directory, tls)?;
+    println!("{}", t!("wormhole-received").green());
+    Ok(())
+}
+
+// SPAKE2 и взаимное подтверждение ключа: неверная фраза обнаруживается сразу
+fn exchange(stream: &mut TcpStream, code: &str, side: Side) -> Result<[u8; 32]> {
+    let (spake, message) =
+        Spake2::<Ed25519Group>::start_symmetric(&Password::new(code.as_bytes()), &Identity::new(b"rcp wormhole"));
+    write_frame(stream, FRAME_PAKE, &message).context("Failed to send the key exchange message")?;
+    let reply = receive_message(stream, FRAME_PAKE)?;
+    let shared = spake
+        .finish(&reply)
+        .map_err(|e| anyhow::anyhow!("Key exchange failed: {:?}", e))?;
+    let key = blake3::derive_key("rcp wormhole key v1", &shared);
+
+    let other = match side {
+        Side::Send => Side::Receive,
+        Side::Receive => Side::Send,
+    };
+    write_frame(stream, FRAME_CONFIRM, &confirmation(&key, side)).context("Failed to confirm the key")?;
+    let reply = receive_message(stream, FRAME_CONFIRM)?;
+    if !tags_match(&reply, &confirmation(&key, other)) {
+        anyhow::bail!("Wrong code: the other side used a different code phrase");
+    }
+    Ok(key)
+}
+
+fn confirmation(key: &[u8; 32], side: Side) -> [u8; 32] {
+    *blake3::keyed_hash(key, side.as_str().as_bytes()).as_bytes()
+}
+
+// Канал для соединений после знакомства знают только две стороны
+fn data_channel(key: &[u8; 32]) -> String {
+    blake3::keyed_hash(key, b"channel").to_hex()[..32].to_string()
+}
+
+fn receive_message(stream: &mut TcpStream, kind: u8) -> Result<Vec<u8>> {
+    match read_frame(stream).context("Connection to the other side failed")? {
+        Some((received, payload)) if received == kind => Ok(payload),
+        Some(_) => anyhow::bail!("Unexpected message from the other side"),
+        None => anyhow::bail!("The other side closed the connection"),
+    }
+}
+
+// `417-orbit-mango`: номер канала и два случайных слова
+fn new_code() -> Result<String> {
+    let mut bytes = [0u8; 8 + CODE_WORDS];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate a code phrase"))?;
+    let (number, words) = bytes.split_at(8);
+    let channel = u64::from_le_bytes(number.try_into().unwrap()) % MAX_CHANNEL + 1;
+    let words: Vec<&str> = words.iter().map(|&byte| WORDS[byte as usize]).collect();
+    Ok(format!("{}-{}", channel, words.join("-")))
+}
+
+fn channel_of(code: &str) -> Result<&str> {
+    match code.split_once('-') {
+        Some((channel, words)) if !channel.is_empty() && channel.bytes().all(|byte| byte.is_ascii_digit()) && !words.is_empty() => {
+            Ok(channel)
+        }
+        _ => anyhow::bail!("Invalid code: {} (expected something like 7-orbit-mango)", code),
+    }
+}
+
+// `ПОРТ адрес адрес ...`: адреса интерфейсов, кроме loopback и IPv6 link-local без зоны
+fn hints(port: u16) -> String {
+    let mut addresses: Vec<IpAddr> = if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .iter()
+        .filter(|interface| !interface.is_loopback() && !interface.is_link_local())
+        .map(|interface| interface.ip())
+        .collect();
+    addresses.sort_by_key(|ip| !ip.is_ipv4());
+    std::iter::once(port.to_string())
+        .chain(addresses.iter().map(IpAddr::to_string))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Первый адрес получателя, до которого удалось достучаться; пробное соединение
+// закрывается без данных, и получатель его не считает
+fn direct_route(hints: &str) -> Option<SocketAddr> {
+    let mut hints = hints.split(' ');
+    let port: u16 = hints.next()?.parse().ok()?;
+    hints
+        .filter_map(|ip| ip.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, port))
+        .find(|address| TcpStream::connect_timeout(address, DIRECT_TIMEOUT).is_ok())
+}
+
+// Локальный порт, каждое соединение с которым уходит на ретранслятор; TLS при этом
+// остается сквозным между сторонами
+pub struct RelayProxy {
+    port: u16,
+    active: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl RelayProxy {
+    fn start(relay: &str, channel: String) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").context("Failed to open a local port for the relay")?;
+        let port = listener.local_addr().context("Failed to get listening address")?.port();
+        let active: Arc<(Mutex<usize>, Condvar)> = Arc::default();
+        let relay = relay.to_string();
+        let counter = Arc::clone(&active);
+        thread::spawn(move || {
+            for local in listener.incoming().flatten() {
+                *counter.0.lock().unwrap() += 1;
+                let relay = relay.clone();
+                let channel = channel.clone();
+                let counter = Arc::clone(&counter);
+                thread::spawn(move || {
+                    let result = relay::connect(&relay, &channel, Side::Send).and_then(|remote| pipe(local, remote));
+                    if let Err(e) = result {
+                        eprintln!("{} {:#}", t!("error").red().bold(), e);
+                    }
+                    *counter.0.lock().unwrap() -= 1;
+                    counter.1.notify_all();
+                });
+            }
+        });
+        Ok(RelayProxy { port, active })
+    }
+}
+
+// Последние байты сессии (close_notify TLS) должны дойти до получателя до выхода
+impl Drop for RelayProxy {
+    fn drop(&mut self) {
+        let (active, done) = &*self.active;
+        let _ = done.wait_timeout_while(active.lock().unwrap(), PROXY_DRAIN_TIMEOUT, |active| *active > 0);
+    }
+}